@@ -0,0 +1,117 @@
+// time the interpreter loop on tight 10 million iteration loops.
+// run with `cargo bench --bench dispatch`, the table-dispatch feature doesn't matter here since every
+// config below says which engine it wants
+use std::time::Instant;
+
+use beef::{Config, Context, ExecutionHooks, Instruction, OpCode, VmError};
+
+const ITERATIONS: i64 = 10_000_000;
+
+fn ix(opcode: OpCode, operands: &[i64]) -> Instruction {
+    Instruction { opcode, operands: operands.to_vec() }
+}
+
+type Runner = fn(&mut Context) -> Result<i64, VmError>;
+
+const CHECKED: Runner = |context| context.run(false);
+const VERIFIED: Runner = Context::run_verified;
+// hooks that do nothing, what's left is the cost of calling them at all
+const HOOKED: Runner = |context| {
+    context.set_hooks(Box::new(NoHooks));
+    context.run(false)
+};
+
+struct NoHooks;
+
+impl ExecutionHooks for NoHooks {}
+
+// best of 5 runs of `program` with r1 = ITERATIONS, reported per executed instruction
+fn bench(name: &str, program: Vec<Instruction>, config: Config, run: Runner, per_iteration: u64, expected: i64) {
+    let mut best = f64::MAX;
+    for _ in 0..5 {
+        let mut context = Context::new_with_config(program.clone(), config.clone());
+        context.set_register(1, ITERATIONS).unwrap();
+        let start = Instant::now();
+        assert_eq!(run(&mut context), Ok(expected));
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    let instructions = (ITERATIONS as u64 * per_iteration) as f64;
+    println!("{}: {} iterations in {:.3}s, {:.2} ns/instruction", name, ITERATIONS, best, best * 1e9 / instructions);
+}
+
+fn main() {
+    let matched = Config { table_dispatch: false, ..Config::default() };
+    let table = Config { table_dispatch: true, ..Config::default() };
+
+    let countdown = vec![
+        ix(OpCode::DecReg, &[1]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::JumpNotZero, &[0]),
+        ix(OpCode::Exit, &[1]),
+    ];
+    bench("countdown", countdown.clone(), matched.clone(), CHECKED, 3, 0);
+    bench("countdown, verified", countdown, matched.clone(), VERIFIED, 3, 0);
+
+    // r0 = r0 * 3 + r1 every iteration, all stack arithmetic
+    let arithmetic = vec![
+        ix(OpCode::LoadReg, &[0]),
+        ix(OpCode::MulImm, &[3]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::Add, &[]),
+        ix(OpCode::StoreReg, &[0]),
+        ix(OpCode::DecReg, &[1]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::JumpNotZero, &[0]),
+        ix(OpCode::Exit, &[0]),
+    ];
+    bench("arithmetic", arithmetic.clone(), matched.clone(), CHECKED, 8, arithmetic_result());
+    bench("arithmetic, table", arithmetic, table.clone(), CHECKED, 8, arithmetic_result());
+
+    // a call and return per iteration, the callee adds r1 to r0
+    let calls = vec![
+        ix(OpCode::Call, &[5]),
+        ix(OpCode::DecReg, &[1]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::JumpNotZero, &[0]),
+        ix(OpCode::Exit, &[0]),
+        ix(OpCode::LoadReg, &[0]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::Add, &[]),
+        ix(OpCode::StoreReg, &[0]),
+        ix(OpCode::Return, &[]),
+    ];
+    let expected = ITERATIONS * (ITERATIONS + 1) / 2;
+    bench("calls", calls.clone(), matched.clone(), CHECKED, 9, expected);
+    bench("calls, table", calls.clone(), table.clone(), CHECKED, 9, expected);
+    bench("calls, empty hooks", calls, matched.clone(), HOOKED, 9, expected);
+
+    // the factorial loop from main counting r1 down, two of its sequences fuse
+    let factorial = vec![
+        ix(OpCode::Push, &[1]),
+        ix(OpCode::StoreReg, &[0]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::Push, &[0]),
+        ix(OpCode::JumpEq, &[14]),
+        ix(OpCode::LoadReg, &[0]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::Mul, &[]),
+        ix(OpCode::StoreReg, &[0]),
+        ix(OpCode::LoadReg, &[1]),
+        ix(OpCode::Push, &[1]),
+        ix(OpCode::Sub, &[]),
+        ix(OpCode::StoreReg, &[1]),
+        ix(OpCode::Jump, &[2]),
+        ix(OpCode::Exit, &[0]),
+    ];
+    let expected = (1..=ITERATIONS).fold(1i64, |acc, i| acc.wrapping_mul(i));
+    bench("factorial", factorial.clone(), matched.clone(), CHECKED, 12, expected);
+    bench("factorial, table", factorial.clone(), table, CHECKED, 12, expected);
+    bench("factorial, verified", factorial.clone(), matched, VERIFIED, 12, expected);
+    let fused = Config { superinstructions: true, table_dispatch: false, ..Config::default() };
+    bench("factorial, superinstructions", factorial.clone(), fused.clone(), CHECKED, 12, expected);
+    bench("factorial, superinstructions + verified", factorial, fused, VERIFIED, 12, expected);
+}
+
+fn arithmetic_result() -> i64 {
+    (1..=ITERATIONS).rev().fold(0i64, |acc, i| acc.wrapping_mul(3).wrapping_add(i))
+}
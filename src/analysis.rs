@@ -0,0 +1,244 @@
+// static stack depth analysis: every basic block reachable from pc 0 gets the range of operand
+// stack depths it can be entered with, so an instruction that can run short of values is found
+// without running the program. Calls are treated as one instruction with a configurable net
+// effect, callee bodies aren't followed. Code only reached through Call, CallIndirect or JumpDyn
+// isn't analyzed. A TryPush handler is entered with the depth at its TryPush plus the thrown code
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+use crate::cfg::{cfg, EdgeKind};
+use crate::error::StackError;
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+// after this many updates to a block's entry depth, a max that's still growing is taken as unbounded
+// and a min that's still shrinking as 0, so loops that keep pushing or popping still finish
+const WIDEN_AFTER: u32 = 3;
+
+// knobs for analyze_stack_with, StackConfig::default() is what analyze_stack uses
+#[derive(Debug, Clone, Default)]
+pub struct StackConfig {
+    pub call_effect: i64, // what a Call leaves on the stack once it returns, on top of popping its arguments
+}
+
+// a range of stack depths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Depth {
+    pub min: usize,
+    pub max: Option<usize>, // None when some path keeps growing the stack
+}
+
+impl Depth {
+    fn exact(depth: usize) -> Self {
+        Depth { min: depth, max: Some(depth) }
+    }
+
+    fn plus(self, pushed: usize) -> Depth {
+        Depth { min: self.min + pushed, max: self.max.map(|max| max + pushed) }
+    }
+
+    fn join(self, other: Depth) -> Depth {
+        let max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+        Depth { min: self.min.min(other.min), max }
+    }
+}
+
+impl fmt::Display for Depth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..={}", self.min, max),
+            None => write!(f, "{}..", self.min),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackWarning {
+    // paths into the block at pc arrive with different depths, legal but usually a codegen slip
+    JoinMismatch { pc: usize, depth: Depth },
+}
+
+impl fmt::Display for StackWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackWarning::JoinMismatch { pc, depth } => {
+                write!(f, "pc={}: paths meet here with different stack depths ({})", pc, depth)
+            },
+        }
+    }
+}
+
+// depths of one analyzed block, pcs start..end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDepth {
+    pub start: usize,
+    pub end: usize,
+    pub entry: Depth,
+    pub exit: Depth, // after the last instruction, before any jump lands
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackReport {
+    pub blocks: Vec<BlockDepth>, // in pc order, only the analyzed ones
+    pub max_depth: Option<usize>, // deepest the stack gets, None if unbounded
+    pub warnings: Vec<StackWarning>,
+}
+
+pub fn analyze_stack(program: &[Instruction]) -> Result<StackReport, Vec<StackError>> {
+    analyze_stack_with(program, &StackConfig::default())
+}
+
+// errors are every instruction some path can reach with too few values, in pc order
+pub fn analyze_stack_with(program: &[Instruction], config: &StackConfig) -> Result<StackReport, Vec<StackError>> {
+    let graph = cfg(program);
+    let index: HashMap<usize, usize> = graph.blocks.iter().enumerate().map(|(index, block)| (block.start, index)).collect();
+    // next block and what the edge itself pushes
+    let mut successors = vec![Vec::new(); graph.blocks.len()];
+    for edge in graph.edges.iter().filter(|edge| edge.kind != EdgeKind::Call) {
+        let pushed = usize::from(edge.kind == EdgeKind::Handler);
+        successors[index[&edge.from]].push((index[&edge.to], pushed));
+    }
+
+    let mut entries: Vec<Option<Depth>> = vec![None; graph.blocks.len()];
+    let mut exits: Vec<Option<Depth>> = vec![None; graph.blocks.len()];
+    let mut peaks: Vec<Option<usize>> = vec![None; graph.blocks.len()];
+    let mut updates = vec![0; graph.blocks.len()];
+    let mut shortfalls = BTreeMap::new();
+    let mut pending = Vec::new();
+    if !graph.blocks.is_empty() {
+        entries[0] = Some(Depth::exact(0));
+        pending.push(0);
+    }
+
+    while let Some(block) = pending.pop() {
+        let (start, end) = (graph.blocks[block].start, graph.blocks[block].end);
+        let mut depth = entries[block].expect("only entered blocks are pending");
+        let mut peak = depth.max;
+        for (pc, instruction) in program.iter().enumerate().take(end).skip(start) {
+            depth = apply(pc, instruction, depth, config, &mut shortfalls);
+            peak = peak.zip(depth.max).map(|(a, b)| a.max(b));
+        }
+        exits[block] = Some(depth);
+        peaks[block] = peak;
+
+        for &(next, pushed) in &successors[block] {
+            let depth = depth.plus(pushed);
+            let joined = entries[next].map_or(depth, |entry| entry.join(depth));
+            if entries[next] == Some(joined) {
+                continue;
+            }
+            updates[next] += 1;
+            let widened = match entries[next] {
+                Some(entry) if updates[next] > WIDEN_AFTER => Depth {
+                    min: if joined.min < entry.min { 0 } else { joined.min },
+                    max: if joined.max != entry.max { None } else { joined.max },
+                },
+                _ => joined,
+            };
+            entries[next] = Some(widened);
+            pending.push(next);
+        }
+    }
+
+    if !shortfalls.is_empty() {
+        let errors = shortfalls.into_iter().map(|(pc, (needed, depth))| StackError::Underflow {
+            pc,
+            opcode: program[pc].opcode,
+            needed,
+            depth,
+        });
+        return Err(errors.collect());
+    }
+
+    let mut incoming: Vec<Vec<Depth>> = vec![Vec::new(); graph.blocks.len()];
+    if !graph.blocks.is_empty() {
+        incoming[0].push(Depth::exact(0));
+    }
+    for (block, next) in successors.iter().enumerate() {
+        if let Some(exit) = exits[block] {
+            next.iter().for_each(|&(next, pushed)| incoming[next].push(exit.plus(pushed)));
+        }
+    }
+
+    let mut report = StackReport { blocks: Vec::new(), max_depth: Some(0), warnings: Vec::new() };
+    for (block, range) in graph.blocks.iter().enumerate() {
+        let (Some(entry), Some(exit)) = (entries[block], exits[block]) else { continue };
+        if incoming[block].windows(2).any(|pair| pair[0] != pair[1]) {
+            report.warnings.push(StackWarning::JoinMismatch { pc: range.start, depth: entry });
+        }
+        report.max_depth = report.max_depth.zip(peaks[block]).map(|(a, b)| a.max(b));
+        report.blocks.push(BlockDepth { start: range.start, end: range.end, entry, exit });
+    }
+    Ok(report)
+}
+
+// depth after one instruction. Falling short is recorded in `shortfalls` as pc -> (needed, shallowest
+// depth seen there) and the analysis carries on as if the values had been there
+fn apply(
+    pc: usize,
+    instruction: &Instruction,
+    depth: Depth,
+    config: &StackConfig,
+    shortfalls: &mut BTreeMap<usize, (usize, usize)>,
+) -> Depth {
+    let (needed, low, high) = effect(instruction, config);
+    if depth.min < needed {
+        let (_, shallowest) = shortfalls.entry(pc).or_insert((needed, depth.min));
+        *shallowest = (*shallowest).min(depth.min);
+    }
+    let after = |depth: usize, net: i64| (depth.max(needed) as i64).saturating_add(net).max(0) as usize;
+    Depth { min: after(depth.min, low), max: depth.max.map(|max| after(max, high)) }
+}
+
+// values the instruction needs on the stack, and the least and most it changes the depth by
+fn effect(instruction: &Instruction, config: &StackConfig) -> (usize, i64, i64) {
+    let operand = |position: usize| instruction.operands.get(position).copied();
+    let count = |position: usize| operand(position).map_or(0, |value| usize::try_from(value).unwrap_or(0));
+    let (needed, net) = match instruction.opcode {
+        OpCode::Push | OpCode::FPush | OpCode::LoadReg | OpCode::PopM | OpCode::LoadLocal => (0, 1),
+        OpCode::Load | OpCode::Load8 | OpCode::Load16 | OpCode::Load32 | OpCode::Load64 => (0, 1),
+        OpCode::GetPC | OpCode::Depth | OpCode::GetCallDepth | OpCode::Rand | OpCode::Steps | OpCode::TimeMs => (0, 1),
+        OpCode::Recv => (0, 1),
+        OpCode::Pop | OpCode::StoreReg | OpCode::Free | OpCode::PushM | OpCode::StoreLocal => (1, -1),
+        OpCode::Store | OpCode::Store8 | OpCode::Store16 | OpCode::Store32 | OpCode::Store64 => (1, -1),
+        OpCode::Print | OpCode::PrintChar | OpCode::FPrint | OpCode::Switch | OpCode::JumpDyn => (1, -1),
+        OpCode::JumpZero | OpCode::JumpNotZero | OpCode::Assert | OpCode::Throw | OpCode::Send => (1, -1),
+        OpCode::Dup => (1, 1),
+        OpCode::Over => (2, 1),
+        OpCode::Swap => (2, 0),
+        OpCode::Rot => (3, 0),
+        OpCode::Pick => (count(0).saturating_add(1), 1),
+        OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::Min | OpCode::Max => (2, -1),
+        OpCode::MulHi | OpCode::MulHiU => (2, -1),
+        OpCode::And | OpCode::Or | OpCode::Xor | OpCode::Shl | OpCode::Shr | OpCode::Sar => (2, -1),
+        OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le | OpCode::Gt | OpCode::Ge => (2, -1),
+        OpCode::DivU | OpCode::ModU | OpCode::LtU | OpCode::LeU | OpCode::GtU | OpCode::GeU => (2, -1),
+        OpCode::FAdd | OpCode::FSub | OpCode::FMul | OpCode::FDiv | OpCode::FEq | OpCode::FLt | OpCode::FGt => (2, -1),
+        OpCode::AddImm | OpCode::SubImm | OpCode::MulImm | OpCode::Neg | OpCode::Abs | OpCode::Not => (1, 0),
+        OpCode::LoadInd | OpCode::Alloc | OpCode::IntToFloat | OpCode::FloatToInt | OpCode::RandRange => (1, 0),
+        OpCode::StoreInd => (2, -2),
+        OpCode::MemSet | OpCode::MemCpy => (3, -3),
+        OpCode::Select => (3, -2),
+        OpCode::AtomicAdd => (1, 0),
+        OpCode::AtomicCas => (2, -1),
+        OpCode::JumpEq | OpCode::JumpGt | OpCode::JumpLt | OpCode::JumpNe | OpCode::JumpGe | OpCode::JumpLe => (2, -2),
+        OpCode::JumpRelEq | OpCode::JumpRelNe | OpCode::JumpRelGt | OpCode::JumpRelLt => (2, -2),
+        OpCode::JumpRelGe | OpCode::JumpRelLe => (2, -2),
+        OpCode::Call => (0, config.call_effect),
+        OpCode::CallIndirect => (1, config.call_effect - 1),
+        OpCode::CallN => (count(1), config.call_effect - count(1) as i64),
+        OpCode::Syscall => (count(1), 1 - count(1) as i64),
+        OpCode::Read => return (0, 1, 2),
+        OpCode::Yield if operand(0) == Some(1) => (1, -1),
+        OpCode::Return if operand(0) == Some(1) => (1, 0),
+        OpCode::Halt | OpCode::Exit if operand(0).is_none() => (1, -1),
+        OpCode::FExit => (1, -1),
+        _ => (0, 0),
+    };
+    (needed, net, net)
+}
@@ -0,0 +1,381 @@
+// text assembly, one instruction per line:
+//
+//   push 5        ; comments run to the end of the line
+//   storereg r1   ; rN is register N, written as a plain number
+//   push -0x10    ; decimal or hex, hex may spell out all 64 bits (0xffffffffffffffff == -1)
+//   fpush 2.5     ; fpush reads decimals as floats (3, -2e3, inf, NaN), hex is the raw f64 bit pattern
+// loop:           ; a label names the next instruction, it can also share its line
+//   jumpeq done   ; labels work for jump, call and switch targets, forward references included
+//
+// mnemonics are OpCode names in any case, operands are separated by whitespace or commas.
+// A label on a relative jump assembles to the offset from that jump
+//
+// strings live in the program's string table, in double quotes with \" \\ \n \t \r and \0 escapes:
+//
+// .string "usage: ..."      ; appends an entry, the first .string is index 0, the next 1 and so on
+//   printstr 0              ; by index
+//   printstr "done\n"       ; or by value, interned: reuses an equal entry or appends one
+//   assert "sum is wrong"   ; Assert's message works the same way
+//
+// only assemble_program keeps the table, assemble returns just the instructions
+//
+// macros are expanded before anything else, so they can hold labels and call other macros:
+//
+// .macro add_regs dst a b   ; parameters are replaced wherever they appear as a whole token
+//   loadreg a
+//   loadreg b
+//   add
+//   storereg dst
+// .endmacro
+//   add_regs r0 r1 r2       ; invoked like an instruction
+//
+// labels defined inside a macro are local to each expansion, a macro can't invoke itself even
+// indirectly. Errors inside an expansion point at the line in the macro body
+
+use crate::error::{AsmError, AsmErrorKind};
+use crate::instruction::{DebugInfo, Instruction, OpCode, Program};
+use crate::prelude::*;
+
+// an operand before labels are resolved
+enum Operand<'a> {
+    Value(i64),
+    Label { column: usize, name: &'a str },
+}
+
+struct Parsed<'a> {
+    line: usize,
+    opcode: OpCode,
+    operands: Vec<Operand<'a>>,
+}
+
+pub fn assemble(src: &str) -> Result<Vec<Instruction>, AsmError> {
+    assemble_with_lines(src).map(|(program, _)| program.instructions)
+}
+
+// assemble into a Program with its string table, and debug info mapping each instruction back to its line in `file`
+pub fn assemble_program(src: &str, file: &str) -> Result<Program, AsmError> {
+    let (mut program, lines) = assemble_with_lines(src)?;
+    program.debug_info = Some(DebugInfo { file: file.to_string(), lines });
+    Ok(program)
+}
+
+// the instructions and strings, plus the source line each instruction came from
+fn assemble_with_lines(src: &str) -> Result<(Program, Vec<usize>), AsmError> {
+    let mnemonics: HashMap<String, OpCode> =
+        OpCode::ALL.iter().map(|&opcode| (format!("{:?}", opcode).to_lowercase(), opcode)).collect();
+
+    let lines = expand_macros(src)?;
+
+    // first pass: parse everything and note where each label lands
+    let mut program = Program::default();
+    let mut parsed = Vec::new();
+    let mut labels: HashMap<&str, (usize, usize)> = HashMap::new(); // name -> (pc, line)
+    for (line_number, code) in &lines {
+        let line_number = *line_number;
+        let error = |column, token: &str, kind| AsmError { line: line_number, column, token: token.to_string(), kind };
+        let mut tokens = tokens(code).into_iter().peekable();
+
+        if let Some((column, _)) = tokens.next_if(|&(_, token)| token == ".string") {
+            let (column, token) = tokens.next().unwrap_or((column, ".string"));
+            let text = parse_string(token).ok_or_else(|| error(column, token, AsmErrorKind::InvalidString))?;
+            if let Some((column, extra)) = tokens.next() {
+                return Err(error(column, extra, AsmErrorKind::InvalidString));
+            }
+            program.strings.push(text);
+            continue;
+        }
+
+        while let Some((column, label)) = tokens.next_if(|(_, token)| token.ends_with(':')) {
+            let name = &label[..label.len() - 1];
+            if !is_label(name) {
+                return Err(error(column, name, AsmErrorKind::InvalidLabel));
+            }
+            if let Some(&(_, first_line)) = labels.get(name) {
+                return Err(error(column, name, AsmErrorKind::DuplicateLabel { first_line }));
+            }
+            labels.insert(name, (parsed.len(), line_number));
+        }
+
+        let Some((column, mnemonic)) = tokens.next() else {
+            continue;
+        };
+        let opcode = *mnemonics.get(&mnemonic.to_lowercase()).ok_or_else(|| error(column, mnemonic, AsmErrorKind::UnknownMnemonic))?;
+        let parse = if opcode == OpCode::FPush { parse_float } else { parse_operand };
+        let operands = tokens
+            .enumerate()
+            .map(|(position, (column, token))| {
+                if token.starts_with('"') {
+                    let text = parse_string(token).ok_or_else(|| error(column, token, AsmErrorKind::InvalidString))?;
+                    if !opcode.is_string_operand(position) {
+                        return Err(error(column, token, AsmErrorKind::InvalidOperand));
+                    }
+                    return Ok(Operand::Value(program.intern(&text) as i64));
+                }
+                match parse(token) {
+                    Some(value) => Ok(Operand::Value(value)),
+                    None if !is_label(token) => Err(error(column, token, AsmErrorKind::InvalidOperand)),
+                    None if opcode.is_target_operand(position) || (opcode.is_relative_jump() && position == 0) => {
+                        Ok(Operand::Label { column, name: token })
+                    },
+                    None => Err(error(column, token, AsmErrorKind::LabelNotAllowed { opcode })),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let expected = opcode.operand_count();
+        if !expected.contains(&operands.len()) {
+            return Err(error(column, mnemonic, AsmErrorKind::OperandCount { opcode, expected, found: operands.len() }));
+        }
+        parsed.push(Parsed { line: line_number, opcode, operands });
+    }
+
+    // second pass: every label is known now
+    let lines = parsed.iter().map(|parsed| parsed.line).collect();
+    program.instructions = parsed
+        .into_iter()
+        .enumerate()
+        .map(|(pc, Parsed { line, opcode, operands })| {
+            let operands = operands
+                .into_iter()
+                .map(|operand| match operand {
+                    Operand::Value(value) => Ok(value),
+                    Operand::Label { column, name } => match labels.get(name) {
+                        Some(&(target, _)) if opcode.is_relative_jump() => Ok(target as i64 - pc as i64),
+                        Some(&(target, _)) => Ok(target as i64),
+                        None => Err(AsmError { line, column, token: name.to_string(), kind: AsmErrorKind::UndefinedLabel }),
+                    },
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Instruction { opcode, operands })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((program, lines))
+}
+
+struct Macro<'a> {
+    line: usize,
+    params: Vec<&'a str>,
+    body: Vec<(usize, &'a str)>, // (line, code without the comment)
+}
+
+// source lines with macro definitions taken out and invocations replaced by their bodies,
+// each paired with the line number errors should report
+fn expand_macros(src: &str) -> Result<Vec<(usize, String)>, AsmError> {
+    let mut macros: HashMap<&str, Macro> = HashMap::new();
+    let mut top_level = Vec::new();
+    let mut open: Option<(&str, Macro)> = None;
+    for (index, line) in src.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |column, token: &str, kind| AsmError { line: line_number, column, token: token.to_string(), kind };
+        let code = strip_comment(line);
+        let words = tokens(code);
+        match words.first() {
+            Some(&(column, ".macro")) => {
+                if let Some((name, _)) = open {
+                    return Err(error(column, name, AsmErrorKind::UnterminatedMacro));
+                }
+                let (column, name) = *words.get(1).ok_or_else(|| error(column, ".macro", AsmErrorKind::InvalidMacro))?;
+                let is_mnemonic = OpCode::ALL.iter().any(|opcode| format!("{:?}", opcode).eq_ignore_ascii_case(name));
+                if !is_label(name) || is_mnemonic {
+                    return Err(error(column, name, AsmErrorKind::InvalidMacro));
+                }
+                if let Some(first) = macros.get(name) {
+                    return Err(error(column, name, AsmErrorKind::DuplicateMacro { first_line: first.line }));
+                }
+                let params = words[2..].iter().map(|&(_, param)| param).collect();
+                open = Some((name, Macro { line: line_number, params, body: Vec::new() }));
+            },
+            Some(&(column, ".endmacro")) => {
+                let (name, definition) = open.take().ok_or_else(|| error(column, ".endmacro", AsmErrorKind::InvalidMacro))?;
+                macros.insert(name, definition);
+            },
+            Some(&(column, directive)) if directive.starts_with('.') && !directive.ends_with(':') && directive != ".string" => {
+                return Err(error(column, directive, AsmErrorKind::UnknownDirective));
+            },
+            _ => match open.as_mut() {
+                Some((_, definition)) => definition.body.push((line_number, code)),
+                None => top_level.push((line_number, code)),
+            },
+        }
+    }
+    if let Some((name, definition)) = open {
+        return Err(AsmError { line: definition.line, column: 1, token: name.to_string(), kind: AsmErrorKind::UnterminatedMacro });
+    }
+
+    let mut out = Vec::new();
+    let mut expansions = 0;
+    for (line, code) in top_level {
+        expand_line(line, code, &macros, &mut Vec::new(), &mut expansions, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn expand_line<'a>(
+    line: usize,
+    code: &str,
+    macros: &HashMap<&'a str, Macro<'a>>,
+    active: &mut Vec<&'a str>, // macros being expanded, outermost first
+    expansions: &mut usize,
+    out: &mut Vec<(usize, String)>,
+) -> Result<(), AsmError> {
+    let words = tokens(code);
+    let labels = words.iter().take_while(|(_, token)| token.ends_with(':')).count();
+    let Some(&(column, name)) = words.get(labels) else {
+        out.push((line, code.to_string()));
+        return Ok(());
+    };
+    let Some((&name, definition)) = macros.get_key_value(name) else {
+        out.push((line, code.to_string()));
+        return Ok(());
+    };
+    let error = |token: &str, kind| AsmError { line, column, token: token.to_string(), kind };
+    if active.contains(&name) {
+        return Err(error(name, AsmErrorKind::RecursiveMacro));
+    }
+    let args = &words[labels + 1..];
+    if args.len() != definition.params.len() {
+        return Err(error(name, AsmErrorKind::MacroArguments { expected: definition.params.len(), found: args.len() }));
+    }
+
+    // labels in front of the invocation name the first instruction of the expansion
+    if labels > 0 {
+        let labels: Vec<&str> = words[..labels].iter().map(|&(_, label)| label).collect();
+        out.push((line, labels.join(" ")));
+    }
+
+    *expansions += 1;
+    let id = *expansions;
+    let locals: Vec<&str> = definition
+        .body
+        .iter()
+        .flat_map(|(_, code)| tokens(code).into_iter().take_while(|(_, token)| token.ends_with(':')))
+        .map(|(_, label)| &label[..label.len() - 1])
+        .collect();
+    let substitute = |token: &str| -> String {
+        let (word, colon) = match token.strip_suffix(':') {
+            Some(word) => (word, ":"),
+            None => (token, ""),
+        };
+        if let Some(position) = definition.params.iter().position(|param| *param == word) {
+            format!("{}{}", args[position].1, colon)
+        } else if locals.contains(&word) {
+            format!("{}.{}.{}{}", name, id, word, colon)
+        } else {
+            token.to_string()
+        }
+    };
+
+    active.push(name);
+    for &(body_line, body) in &definition.body {
+        let expanded: Vec<String> = tokens(body).into_iter().map(|(_, token)| substitute(token)).collect();
+        expand_line(body_line, &expanded.join(" "), macros, active, expansions, out)?;
+    }
+    active.pop();
+    Ok(())
+}
+
+// the line up to its comment, a ; inside a string literal doesn't start one
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (offset, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..offset],
+            _ => {},
+        }
+    }
+    line
+}
+
+// (1-based column, text) of each whitespace or comma separated token. A token starting with a quote runs
+// to the closing quote, separators included, or to the end of the line when it isn't closed
+fn tokens(code: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (column, (offset, c)) in code.char_indices().enumerate() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {},
+            }
+            continue;
+        }
+        let separator = c.is_whitespace() || c == ',';
+        match start {
+            Some((token_column, token_offset)) if separator => {
+                tokens.push((token_column, &code[token_offset..offset]));
+                start = None;
+            },
+            None if !separator => {
+                start = Some((column + 1, offset));
+                quoted = c == '"';
+            },
+            _ => {},
+        }
+    }
+    if let Some((column, offset)) = start {
+        tokens.push((column, &code[offset..]));
+    }
+    tokens
+}
+
+// identifiers that couldn't be mistaken for a number or register
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_well = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '.');
+    starts_well && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.') && parse_operand(name).is_none()
+}
+
+fn parse_operand(token: &str) -> Option<i64> {
+    let token = token.strip_prefix(['r', 'R']).filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit())).unwrap_or(token);
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) if !hex.starts_with(['+', '-']) => u64::from_str_radix(hex, 16).ok()? as i64,
+        Some(_) => return None,
+        None if digits.starts_with(['+', '-']) => return None,
+        // parsed with the sign so i64::MIN works
+        None => return format!("{}{}", if negative { "-" } else { "" }, digits).parse().ok(),
+    };
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+// FPush's operand: its bit pattern when written in hex, otherwise a float
+fn parse_float(token: &str) -> Option<i64> {
+    let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+    if unsigned.starts_with("0x") || unsigned.starts_with("0X") {
+        return parse_operand(token);
+    }
+    token.parse::<f64>().ok().map(|value| value.to_bits() as i64)
+}
+
+// a double quoted literal with its escapes applied
+fn parse_string(token: &str) -> Option<String> {
+    let body = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        text.push(match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(text)
+}
@@ -0,0 +1,220 @@
+// programs from Rust without hand-counted jump targets:
+//
+//   ProgramBuilder::new().push(3).label("loop").dec_reg(1).load_reg(1).jump_not_zero("loop").exit_reg(1).build()
+//
+// jumps and calls name labels, which may be defined before or after use. build() resolves them and
+// checks operand counts and register numbers, so mistakes show up before anything runs
+
+use crate::context::REGISTER_COUNT;
+use crate::error::BuildError;
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+enum Operand {
+    Value(i64),
+    Label(String),
+}
+
+#[derive(Default)]
+pub struct ProgramBuilder {
+    instructions: Vec<(OpCode, Vec<Operand>)>,
+    labels: HashMap<String, usize>,
+    duplicate: Option<BuildError>, // first label defined twice, reported by build
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // name the next instruction
+    pub fn label(mut self, name: &str) -> Self {
+        let pc = self.instructions.len();
+        if let Some(&first) = self.labels.get(name) {
+            self.duplicate.get_or_insert(BuildError::DuplicateLabel { label: name.to_string(), first, second: pc });
+        } else {
+            self.labels.insert(name.to_string(), pc);
+        }
+        self
+    }
+
+    // any opcode with literal operands
+    pub fn op(mut self, opcode: OpCode, operands: &[i64]) -> Self {
+        self.instructions.push((opcode, operands.iter().map(|&value| Operand::Value(value)).collect()));
+        self
+    }
+
+    // escape hatch, taken as is apart from the build time checks
+    pub fn raw(self, instruction: Instruction) -> Self {
+        self.op(instruction.opcode, &instruction.operands)
+    }
+
+    // opcode whose first operand is a label, followed by literal operands
+    fn with_target(mut self, opcode: OpCode, label: &str, rest: &[i64]) -> Self {
+        let mut operands = vec![Operand::Label(label.to_string())];
+        operands.extend(rest.iter().map(|&value| Operand::Value(value)));
+        self.instructions.push((opcode, operands));
+        self
+    }
+
+    pub fn build(self) -> Result<Vec<Instruction>, BuildError> {
+        if let Some(error) = self.duplicate {
+            return Err(error);
+        }
+
+        let mut program = Vec::with_capacity(self.instructions.len());
+        for (pc, (opcode, operands)) in self.instructions.into_iter().enumerate() {
+            let expected = opcode.operand_count();
+            if !expected.contains(&operands.len()) {
+                return Err(BuildError::OperandCount { pc, opcode, expected, found: operands.len() });
+            }
+            let operands = operands
+                .into_iter()
+                .map(|operand| match operand {
+                    Operand::Value(value) => Ok(value),
+                    Operand::Label(label) => match self.labels.get(&label) {
+                        Some(&target) => Ok(target as i64),
+                        None => Err(BuildError::UndefinedLabel { label, pc }),
+                    },
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for position in opcode.register_operands(operands.len()) {
+                let register = operands[position];
+                if usize::try_from(register).map_or(true, |register| register >= REGISTER_COUNT) {
+                    return Err(BuildError::InvalidRegister { pc, opcode, register });
+                }
+            }
+            program.push(Instruction { opcode, operands });
+        }
+        Ok(program)
+    }
+
+    // stack
+    pub fn push(self, value: i64) -> Self { self.op(OpCode::Push, &[value]) }
+    pub fn pop(self) -> Self { self.op(OpCode::Pop, &[]) }
+    pub fn dup(self) -> Self { self.op(OpCode::Dup, &[]) }
+    pub fn swap(self) -> Self { self.op(OpCode::Swap, &[]) }
+    pub fn over(self) -> Self { self.op(OpCode::Over, &[]) }
+    pub fn rot(self) -> Self { self.op(OpCode::Rot, &[]) }
+    pub fn pick(self, depth: i64) -> Self { self.op(OpCode::Pick, &[depth]) }
+
+    // arithmetic, bitwise and comparisons on the stack
+    pub fn add(self) -> Self { self.op(OpCode::Add, &[]) }
+    pub fn sub(self) -> Self { self.op(OpCode::Sub, &[]) }
+    pub fn mul(self) -> Self { self.op(OpCode::Mul, &[]) }
+    pub fn div(self) -> Self { self.op(OpCode::Div, &[]) }
+    pub fn modulo(self) -> Self { self.op(OpCode::Mod, &[]) }
+    pub fn mul_hi(self) -> Self { self.op(OpCode::MulHi, &[]) }
+    pub fn mul_hi_u(self) -> Self { self.op(OpCode::MulHiU, &[]) }
+    pub fn add_imm(self, value: i64) -> Self { self.op(OpCode::AddImm, &[value]) }
+    pub fn sub_imm(self, value: i64) -> Self { self.op(OpCode::SubImm, &[value]) }
+    pub fn mul_imm(self, value: i64) -> Self { self.op(OpCode::MulImm, &[value]) }
+    pub fn negate(self) -> Self { self.op(OpCode::Neg, &[]) }
+    pub fn abs(self) -> Self { self.op(OpCode::Abs, &[]) }
+    pub fn min(self) -> Self { self.op(OpCode::Min, &[]) }
+    pub fn max(self) -> Self { self.op(OpCode::Max, &[]) }
+    pub fn and(self) -> Self { self.op(OpCode::And, &[]) }
+    pub fn or(self) -> Self { self.op(OpCode::Or, &[]) }
+    pub fn xor(self) -> Self { self.op(OpCode::Xor, &[]) }
+    pub fn bit_not(self) -> Self { self.op(OpCode::Not, &[]) }
+    pub fn shl(self) -> Self { self.op(OpCode::Shl, &[]) }
+    pub fn shr(self) -> Self { self.op(OpCode::Shr, &[]) }
+    pub fn sar(self) -> Self { self.op(OpCode::Sar, &[]) }
+    pub fn eq(self) -> Self { self.op(OpCode::Eq, &[]) }
+    pub fn ne(self) -> Self { self.op(OpCode::Ne, &[]) }
+    pub fn lt(self) -> Self { self.op(OpCode::Lt, &[]) }
+    pub fn le(self) -> Self { self.op(OpCode::Le, &[]) }
+    pub fn gt(self) -> Self { self.op(OpCode::Gt, &[]) }
+    pub fn ge(self) -> Self { self.op(OpCode::Ge, &[]) }
+    pub fn div_u(self) -> Self { self.op(OpCode::DivU, &[]) }
+    pub fn mod_u(self) -> Self { self.op(OpCode::ModU, &[]) }
+    pub fn lt_u(self) -> Self { self.op(OpCode::LtU, &[]) }
+    pub fn le_u(self) -> Self { self.op(OpCode::LeU, &[]) }
+    pub fn gt_u(self) -> Self { self.op(OpCode::GtU, &[]) }
+    pub fn ge_u(self) -> Self { self.op(OpCode::GeU, &[]) }
+    pub fn select(self) -> Self { self.op(OpCode::Select, &[]) } // a b cond -> cond != 0 ? a : b
+
+    // floats
+    pub fn fpush(self, value: f64) -> Self { self.op(OpCode::FPush, &[value.to_bits() as i64]) }
+    pub fn fadd(self) -> Self { self.op(OpCode::FAdd, &[]) }
+    pub fn fsub(self) -> Self { self.op(OpCode::FSub, &[]) }
+    pub fn fmul(self) -> Self { self.op(OpCode::FMul, &[]) }
+    pub fn fdiv(self) -> Self { self.op(OpCode::FDiv, &[]) }
+    pub fn feq(self) -> Self { self.op(OpCode::FEq, &[]) }
+    pub fn flt(self) -> Self { self.op(OpCode::FLt, &[]) }
+    pub fn fgt(self) -> Self { self.op(OpCode::FGt, &[]) }
+    pub fn int_to_float(self) -> Self { self.op(OpCode::IntToFloat, &[]) }
+    pub fn float_to_int(self) -> Self { self.op(OpCode::FloatToInt, &[]) }
+    pub fn fprint(self) -> Self { self.op(OpCode::FPrint, &[]) }
+
+    // registers
+    pub fn load_reg(self, reg: i64) -> Self { self.op(OpCode::LoadReg, &[reg]) }
+    pub fn store_reg(self, reg: i64) -> Self { self.op(OpCode::StoreReg, &[reg]) }
+    pub fn add_reg(self, dst: i64, a: i64, b: i64) -> Self { self.op(OpCode::AddReg, &[dst, a, b]) }
+    pub fn sub_reg(self, dst: i64, a: i64, b: i64) -> Self { self.op(OpCode::SubReg, &[dst, a, b]) }
+    pub fn mul_reg(self, dst: i64, a: i64, b: i64) -> Self { self.op(OpCode::MulReg, &[dst, a, b]) }
+    pub fn div_reg(self, dst: i64, a: i64, b: i64) -> Self { self.op(OpCode::DivReg, &[dst, a, b]) }
+    pub fn mov_reg(self, dst: i64, src: i64) -> Self { self.op(OpCode::MovReg, &[dst, src]) }
+    pub fn inc_reg(self, reg: i64) -> Self { self.op(OpCode::IncReg, &[reg]) }
+    pub fn dec_reg(self, reg: i64) -> Self { self.op(OpCode::DecReg, &[reg]) }
+
+    // memory, the _at forms address registers[reg] + offset
+    pub fn load(self, addr: i64) -> Self { self.op(OpCode::Load, &[addr]) }
+    pub fn load_at(self, reg: i64, offset: i64) -> Self { self.op(OpCode::Load, &[reg, offset]) }
+    pub fn store(self, addr: i64) -> Self { self.op(OpCode::Store, &[addr]) }
+    pub fn store_at(self, reg: i64, offset: i64) -> Self { self.op(OpCode::Store, &[reg, offset]) }
+    pub fn load_ind(self) -> Self { self.op(OpCode::LoadInd, &[]) }
+    pub fn store_ind(self) -> Self { self.op(OpCode::StoreInd, &[]) }
+    pub fn atomic_add(self, addr: i64) -> Self { self.op(OpCode::AtomicAdd, &[addr]) }
+    pub fn atomic_cas(self, addr: i64) -> Self { self.op(OpCode::AtomicCas, &[addr]) }
+
+    // control flow, targets are labels
+    pub fn jump(self, label: &str) -> Self { self.with_target(OpCode::Jump, label, &[]) }
+    pub fn jump_eq(self, label: &str) -> Self { self.with_target(OpCode::JumpEq, label, &[]) }
+    pub fn jump_ne(self, label: &str) -> Self { self.with_target(OpCode::JumpNe, label, &[]) }
+    pub fn jump_gt(self, label: &str) -> Self { self.with_target(OpCode::JumpGt, label, &[]) }
+    pub fn jump_lt(self, label: &str) -> Self { self.with_target(OpCode::JumpLt, label, &[]) }
+    pub fn jump_ge(self, label: &str) -> Self { self.with_target(OpCode::JumpGe, label, &[]) }
+    pub fn jump_le(self, label: &str) -> Self { self.with_target(OpCode::JumpLe, label, &[]) }
+    pub fn jump_zero(self, label: &str) -> Self { self.with_target(OpCode::JumpZero, label, &[]) }
+    pub fn jump_not_zero(self, label: &str) -> Self { self.with_target(OpCode::JumpNotZero, label, &[]) }
+
+    // calls and frames
+    pub fn call(self, label: &str) -> Self { self.with_target(OpCode::Call, label, &[]) }
+    pub fn call_with_locals(self, label: &str, locals: i64) -> Self { self.with_target(OpCode::Call, label, &[locals]) }
+    pub fn call_n(self, label: &str, argc: i64) -> Self { self.with_target(OpCode::CallN, label, &[argc]) }
+    pub fn tail_call(self, label: &str) -> Self { self.with_target(OpCode::TailCall, label, &[]) }
+    pub fn ret(self) -> Self { self.op(OpCode::Return, &[]) }
+    pub fn ret_values(self, count: i64) -> Self { self.op(OpCode::Return, &[count]) }
+    pub fn enter(self, locals: i64) -> Self { self.op(OpCode::Enter, &[locals]) }
+    pub fn load_local(self, index: i64) -> Self { self.op(OpCode::LoadLocal, &[index]) }
+    pub fn store_local(self, index: i64) -> Self { self.op(OpCode::StoreLocal, &[index]) }
+    pub fn try_push(self, handler: &str) -> Self { self.with_target(OpCode::TryPush, handler, &[]) }
+    pub fn try_pop(self) -> Self { self.op(OpCode::TryPop, &[]) }
+    pub fn throw(self) -> Self { self.op(OpCode::Throw, &[]) }
+
+    // host
+    pub fn syscall(self, number: i64, argc: i64) -> Self { self.op(OpCode::Syscall, &[number, argc]) }
+    pub fn trap(self, code: i64) -> Self { self.op(OpCode::Trap, &[code]) }
+    pub fn print(self) -> Self { self.op(OpCode::Print, &[]) }
+    pub fn print_char(self) -> Self { self.op(OpCode::PrintChar, &[]) }
+    pub fn print_str(self, index: i64) -> Self { self.op(OpCode::PrintStr, &[index]) }
+    pub fn read(self) -> Self { self.op(OpCode::Read, &[]) }
+    pub fn send(self, channel: i64) -> Self { self.op(OpCode::Send, &[channel]) }
+    pub fn recv(self, channel: i64) -> Self { self.op(OpCode::Recv, &[channel]) }
+    pub fn assert(self) -> Self { self.op(OpCode::Assert, &[]) }
+    pub fn assert_msg(self, message: i64) -> Self { self.op(OpCode::Assert, &[message]) }
+    pub fn get_pc(self) -> Self { self.op(OpCode::GetPC, &[]) }
+    pub fn depth(self) -> Self { self.op(OpCode::Depth, &[]) }
+    pub fn get_call_depth(self) -> Self { self.op(OpCode::GetCallDepth, &[]) }
+    pub fn rand(self) -> Self { self.op(OpCode::Rand, &[]) }
+    pub fn rand_range(self) -> Self { self.op(OpCode::RandRange, &[]) }
+    pub fn steps(self) -> Self { self.op(OpCode::Steps, &[]) }
+    pub fn time_ms(self) -> Self { self.op(OpCode::TimeMs, &[]) }
+
+    pub fn nop(self) -> Self { self.op(OpCode::Nop, &[]) }
+    pub fn halt(self, code: i64) -> Self { self.op(OpCode::Halt, &[code]) }
+    pub fn exit(self) -> Self { self.op(OpCode::Exit, &[]) } // result from the stack
+    pub fn exit_reg(self, reg: i64) -> Self { self.op(OpCode::Exit, &[reg]) }
+    pub fn fexit(self) -> Self { self.op(OpCode::FExit, &[]) } // float result from the stack
+}
@@ -0,0 +1,410 @@
+// binary program files. Everything is little endian:
+//
+//   magic "BEEF", u16 format version, u16 opcode set version
+//   u32 instruction count, then per instruction: u8 opcode, u16 operand count, i64 operands
+//   u32 data block count, then per block: u64 start address, u64 word count, i64 words
+//   u32 symbol count, then per symbol: u16 name length, utf-8 name, u64 entry pc
+//   since 1.1: u8 debug info flag, when 1: u16 file name length, utf-8 file name,
+//              u32 line count, u32 source line per instruction
+//   since 1.2: u32 string count, then per string: u32 byte length, utf-8 text
+//   since 1.3: u8 name flag, when 1: u16 name length, utf-8 name; then u64 entry pc
+//
+// the format version is major << 8 | minor. A reader takes any file with its own major version:
+// minor versions only ever append sections, so a newer minor's extra bytes after the symbols are
+// skipped. Anything that changes how existing bytes read bumps the major version
+//
+// opcode bytes come from opcode_byte below, never from the enum's order. Opcode sets only grow, so a
+// file from a newer set loads fine unless it actually uses an opcode this build doesn't know
+
+use crate::error::{DecodeError, EncodeError};
+use crate::instruction::{DebugInfo, Instruction, OpCode, Program};
+use crate::prelude::*;
+
+const MAGIC: &[u8; 4] = b"BEEF";
+pub const FORMAT_VERSION: u16 = 0x0103; // 1.3
+// 2 added the float opcodes, 3 Select, 4 the unsigned ops, 5 MulHi, 6 PrintStr, 7 Assert, 8 GetPC, Depth and GetCallDepth,
+// 9 Trap, 10 TryPush, TryPop and Throw, 11 Rand and RandRange, 12 Steps and TimeMs, 13 AtomicAdd and AtomicCas,
+// 14 Send and Recv
+pub const OPCODE_SET_VERSION: u16 = 14;
+
+// the most operands a decoded instruction may claim, anything above is a corrupt file
+// rather than a real jump table
+pub const MAX_OPERANDS: usize = 4096;
+
+impl Program {
+    // fails if the program doesn't fit the format: more than MAX_OPERANDS operands on an
+    // instruction, a symbol, file or program name over 64k, or more than u32::MAX instructions, blocks, symbols or strings
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodeError> {
+        let mut out = Vec::with_capacity(MAGIC.len() + 2 + 12 + self.instructions.len() * 11);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&OPCODE_SET_VERSION.to_le_bytes());
+
+        write_count(&mut out, self.instructions.len(), "instructions")?;
+        for (pc, instruction) in self.instructions.iter().enumerate() {
+            if instruction.operands.len() > MAX_OPERANDS {
+                return Err(EncodeError::TooManyOperands { pc, opcode: instruction.opcode, count: instruction.operands.len() });
+            }
+            out.push(opcode_byte(instruction.opcode));
+            out.extend_from_slice(&(instruction.operands.len() as u16).to_le_bytes());
+            for operand in &instruction.operands {
+                out.extend_from_slice(&operand.to_le_bytes());
+            }
+        }
+
+        write_count(&mut out, self.data.len(), "data blocks")?;
+        for (start, words) in &self.data {
+            out.extend_from_slice(&(*start as u64).to_le_bytes());
+            out.extend_from_slice(&(words.len() as u64).to_le_bytes());
+            for word in words {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        write_count(&mut out, self.symbols.len(), "symbols")?;
+        for (name, entry) in &self.symbols {
+            let len = u16::try_from(name.len()).map_err(|_| EncodeError::SymbolTooLong { name: name.clone(), len: name.len() })?;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&(*entry as u64).to_le_bytes());
+        }
+
+        match &self.debug_info {
+            Some(debug_info) => {
+                out.push(1);
+                let len = u16::try_from(debug_info.file.len()).map_err(|_| EncodeError::FileNameTooLong { len: debug_info.file.len() })?;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(debug_info.file.as_bytes());
+                write_count(&mut out, debug_info.lines.len(), "source lines")?;
+                for (pc, &line) in debug_info.lines.iter().enumerate() {
+                    let line = u32::try_from(line).map_err(|_| EncodeError::LineTooLarge { pc, line })?;
+                    out.extend_from_slice(&line.to_le_bytes());
+                }
+            },
+            None => out.push(0),
+        }
+
+        write_count(&mut out, self.strings.len(), "strings")?;
+        for text in &self.strings {
+            write_count(&mut out, text.len(), "string bytes")?;
+            out.extend_from_slice(text.as_bytes());
+        }
+
+        match &self.name {
+            Some(name) => {
+                out.push(1);
+                let len = u16::try_from(name.len()).map_err(|_| EncodeError::NameTooLong { len: name.len() })?;
+                out.extend_from_slice(&len.to_le_bytes());
+                out.extend_from_slice(name.as_bytes());
+            },
+            None => out.push(0),
+        }
+        out.extend_from_slice(&(self.entry as u64).to_le_bytes());
+
+        Ok(out)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, DecodeError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(MAGIC.len(), "magic").map_err(|_| DecodeError::BadMagic)? != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = reader.u16("format version")?;
+        if version >> 8 != FORMAT_VERSION >> 8 {
+            return Err(DecodeError::UnsupportedVersion { found: version, supported: FORMAT_VERSION });
+        }
+        // newer sets are fine as long as every opcode used is one we know
+        reader.u16("opcode set version")?;
+
+        let opcodes = opcode_table();
+
+        // counts come from the file, so never reserve more than the remaining bytes could hold
+        let count = reader.u32("instruction count")? as usize;
+        let mut instructions = Vec::with_capacity(count.min(reader.remaining() / 3));
+        let mut string_uses = Vec::new(); // (offset, index) of every string operand, checked once the table is read
+        for _ in 0..count {
+            let offset = reader.pos;
+            let byte = reader.u8("opcode")?;
+            let opcode = opcodes[byte as usize].ok_or(DecodeError::UnknownOpcode { offset, byte })?;
+            let operand_count = reader.u16("operand count")? as usize;
+            if operand_count > MAX_OPERANDS {
+                return Err(DecodeError::TooManyOperands { offset, opcode, count: operand_count });
+            }
+            let operands: Vec<i64> = (0..operand_count).map(|_| reader.i64("operand")).collect::<Result<_, _>>()?;
+            if opcode.is_string_operand(0) {
+                string_uses.extend(operands.first().map(|&index| (offset, index)));
+            }
+            instructions.push(Instruction { opcode, operands });
+        }
+
+        let count = reader.u32("data block count")? as usize;
+        let mut data = Vec::with_capacity(count.min(reader.remaining() / 16));
+        for _ in 0..count {
+            let start = reader.address("data block address")?;
+            let len = reader.u64("data block length")?;
+            // a length this big can't be backed by the rest of the input anyway
+            let len = usize::try_from(len).ok().filter(|len| *len <= reader.remaining() / 8)
+                .ok_or(DecodeError::Truncated { offset: reader.bytes.len(), expected: "data words" })?;
+            let words = (0..len).map(|_| reader.i64("data word")).collect::<Result<_, _>>()?;
+            data.push((start, words));
+        }
+
+        let count = reader.u32("symbol count")? as usize;
+        let mut symbols = Vec::with_capacity(count.min(reader.remaining() / 10));
+        for _ in 0..count {
+            let len = reader.u16("symbol name length")? as usize;
+            let offset = reader.pos;
+            let name = core::str::from_utf8(reader.take(len, "symbol name")?).map_err(|_| DecodeError::InvalidSymbol { offset })?;
+            let entry = reader.address("symbol entry")?;
+            symbols.push((name.to_string(), entry));
+        }
+
+        let mut debug_info = None;
+        if version >= 0x0101 && reader.u8("debug info flag")? == 1 {
+            let len = reader.u16("file name length")? as usize;
+            let offset = reader.pos;
+            let file = core::str::from_utf8(reader.take(len, "file name")?).map_err(|_| DecodeError::InvalidUtf8 { offset, what: "file name" })?;
+            let count = reader.u32("line count")? as usize;
+            let lines = (0..count).map(|_| reader.u32("source line").map(|line| line as usize)).collect::<Result<_, _>>()?;
+            debug_info = Some(DebugInfo { file: file.to_string(), lines });
+        }
+
+        let mut strings = Vec::new();
+        if version >= 0x0102 {
+            let count = reader.u32("string count")? as usize;
+            strings.reserve(count.min(reader.remaining() / 4));
+            for _ in 0..count {
+                let len = reader.u32("string length")? as usize;
+                let offset = reader.pos;
+                let text = core::str::from_utf8(reader.take(len, "string")?).map_err(|_| DecodeError::InvalidUtf8 { offset, what: "string" })?;
+                strings.push(text.to_string());
+            }
+        }
+        if let Some(&(offset, index)) = string_uses.iter().find(|&&(_, index)| !usize::try_from(index).is_ok_and(|index| index < strings.len())) {
+            return Err(DecodeError::UnknownString { offset, index, count: strings.len() });
+        }
+
+        let (mut name, mut entry) = (None, 0);
+        if version >= 0x0103 {
+            if reader.u8("name flag")? == 1 {
+                let len = reader.u16("name length")? as usize;
+                let offset = reader.pos;
+                let text = core::str::from_utf8(reader.take(len, "name")?).map_err(|_| DecodeError::InvalidUtf8 { offset, what: "program name" })?;
+                name = Some(text.to_string());
+            }
+            entry = reader.address("entry pc")?;
+        }
+
+        // only a newer minor version may have sections we don't know about
+        if reader.remaining() > 0 && version <= FORMAT_VERSION {
+            return Err(DecodeError::TrailingBytes { offset: reader.pos });
+        }
+        Ok(Program { name, instructions, entry, data, symbols, strings, debug_info })
+    }
+}
+
+// the stable opcode numbering, new opcodes take the next free number and bump OPCODE_SET_VERSION.
+// Never renumber an existing opcode, that's a new major format version
+pub fn opcode_byte(opcode: OpCode) -> u8 {
+    match opcode {
+        OpCode::Push => 0,
+        OpCode::Pop => 1,
+        OpCode::Dup => 2,
+        OpCode::Swap => 3,
+        OpCode::Over => 4,
+        OpCode::Rot => 5,
+        OpCode::Pick => 6,
+        OpCode::Add => 7,
+        OpCode::Sub => 8,
+        OpCode::Mul => 9,
+        OpCode::Div => 10,
+        OpCode::Mod => 11,
+        OpCode::AddImm => 12,
+        OpCode::SubImm => 13,
+        OpCode::MulImm => 14,
+        OpCode::Neg => 15,
+        OpCode::Abs => 16,
+        OpCode::Min => 17,
+        OpCode::Max => 18,
+        OpCode::And => 19,
+        OpCode::Or => 20,
+        OpCode::Xor => 21,
+        OpCode::Not => 22,
+        OpCode::Shl => 23,
+        OpCode::Shr => 24,
+        OpCode::Sar => 25,
+        OpCode::Eq => 26,
+        OpCode::Ne => 27,
+        OpCode::Lt => 28,
+        OpCode::Le => 29,
+        OpCode::Gt => 30,
+        OpCode::Ge => 31,
+        OpCode::LoadReg => 32,
+        OpCode::StoreReg => 33,
+        OpCode::AddReg => 34,
+        OpCode::SubReg => 35,
+        OpCode::MulReg => 36,
+        OpCode::DivReg => 37,
+        OpCode::MovReg => 38,
+        OpCode::IncReg => 39,
+        OpCode::DecReg => 40,
+        OpCode::Load => 41,
+        OpCode::Store => 42,
+        OpCode::LoadInd => 43,
+        OpCode::StoreInd => 44,
+        OpCode::MemSet => 45,
+        OpCode::MemCpy => 46,
+        OpCode::Alloc => 47,
+        OpCode::Free => 48,
+        OpCode::PushM => 49,
+        OpCode::PopM => 50,
+        OpCode::Load8 => 51,
+        OpCode::Load16 => 52,
+        OpCode::Load32 => 53,
+        OpCode::Load64 => 54,
+        OpCode::Store8 => 55,
+        OpCode::Store16 => 56,
+        OpCode::Store32 => 57,
+        OpCode::Store64 => 58,
+        OpCode::Jump => 59,
+        OpCode::JumpEq => 60,
+        OpCode::JumpGt => 61,
+        OpCode::JumpLt => 62,
+        OpCode::JumpNe => 63,
+        OpCode::JumpGe => 64,
+        OpCode::JumpLe => 65,
+        OpCode::JumpZero => 66,
+        OpCode::JumpNotZero => 67,
+        OpCode::JumpRel => 68,
+        OpCode::JumpRelEq => 69,
+        OpCode::JumpRelNe => 70,
+        OpCode::JumpRelGt => 71,
+        OpCode::JumpRelLt => 72,
+        OpCode::JumpRelGe => 73,
+        OpCode::JumpRelLe => 74,
+        OpCode::Switch => 75,
+        OpCode::JumpDyn => 76,
+        OpCode::Call => 77,
+        OpCode::CallIndirect => 78,
+        OpCode::CallN => 79,
+        OpCode::TailCall => 80,
+        OpCode::Return => 81,
+        OpCode::Enter => 82,
+        OpCode::LoadLocal => 83,
+        OpCode::StoreLocal => 84,
+        OpCode::Syscall => 85,
+        OpCode::Print => 86,
+        OpCode::PrintChar => 87,
+        OpCode::Read => 88,
+        OpCode::Yield => 89,
+        OpCode::Nop => 90,
+        OpCode::Halt => 91,
+        OpCode::Exit => 92,
+        OpCode::FPush => 93,
+        OpCode::FAdd => 94,
+        OpCode::FSub => 95,
+        OpCode::FMul => 96,
+        OpCode::FDiv => 97,
+        OpCode::FEq => 98,
+        OpCode::FLt => 99,
+        OpCode::FGt => 100,
+        OpCode::IntToFloat => 101,
+        OpCode::FloatToInt => 102,
+        OpCode::FPrint => 103,
+        OpCode::FExit => 104,
+        OpCode::Select => 105,
+        OpCode::DivU => 106,
+        OpCode::ModU => 107,
+        OpCode::LtU => 108,
+        OpCode::LeU => 109,
+        OpCode::GtU => 110,
+        OpCode::GeU => 111,
+        OpCode::MulHi => 112,
+        OpCode::MulHiU => 113,
+        OpCode::PrintStr => 114,
+        OpCode::Assert => 115,
+        OpCode::GetPC => 116,
+        OpCode::Depth => 117,
+        OpCode::GetCallDepth => 118,
+        OpCode::Trap => 119,
+        OpCode::TryPush => 120,
+        OpCode::TryPop => 121,
+        OpCode::Throw => 122,
+        OpCode::Rand => 123,
+        OpCode::RandRange => 124,
+        OpCode::Steps => 125,
+        OpCode::TimeMs => 126,
+        OpCode::AtomicAdd => 127,
+        OpCode::AtomicCas => 128,
+        OpCode::Send => 129,
+        OpCode::Recv => 130,
+    }
+}
+
+// opcode_byte backwards, None for bytes no opcode uses
+pub(crate) fn opcode_table() -> [Option<OpCode>; 256] {
+    let mut opcodes = [None; 256];
+    for opcode in OpCode::ALL {
+        opcodes[opcode_byte(opcode) as usize] = Some(opcode);
+    }
+    opcodes
+}
+
+fn write_count(out: &mut Vec<u8>, count: usize, what: &'static str) -> Result<(), EncodeError> {
+    let count = u32::try_from(count).map_err(|_| EncodeError::TooMany { what, count })?;
+    out.extend_from_slice(&count.to_le_bytes());
+    Ok(())
+}
+
+// cursor over the input, every read names what it wanted for the truncation error
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize, expected: &'static str) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::Truncated { offset: self.bytes.len(), expected });
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self, expected: &'static str) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N, expected)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self, expected: &'static str) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>(expected)?[0])
+    }
+
+    fn u16(&mut self, expected: &'static str) -> Result<u16, DecodeError> {
+        self.array(expected).map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self, expected: &'static str) -> Result<u32, DecodeError> {
+        self.array(expected).map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self, expected: &'static str) -> Result<u64, DecodeError> {
+        self.array(expected).map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self, expected: &'static str) -> Result<i64, DecodeError> {
+        self.array(expected).map(i64::from_le_bytes)
+    }
+
+    fn address(&mut self, expected: &'static str) -> Result<usize, DecodeError> {
+        let offset = self.pos;
+        let value = self.u64(expected)?;
+        usize::try_from(value).map_err(|_| DecodeError::AddressOverflow { offset, value })
+    }
+}
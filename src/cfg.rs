@@ -0,0 +1,212 @@
+// control flow graph: basic blocks and the edges between them, with a Graphviz renderer for
+// looking at generated code. Blocks start at pc 0, at every jump/call/switch target, and right
+// after anything that transfers control (jumps, switch, calls, Return, Exit, Halt)
+
+use alloc::collections::BTreeSet;
+use core::fmt::{self, Write};
+
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+// a straight run of instructions, pcs start..end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize, // exclusive
+    pub instructions: Vec<Instruction>,
+    // whether any path of edges leads here from pc 0. JumpDyn and CallIndirect targets aren't known
+    // statically, so code only they reach shows up unreachable too
+    pub reachable: bool,
+}
+
+// why control goes from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    Unconditional, // Jump, JumpRel
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Zero,
+    NotZero,
+    Case(usize), // Switch case by index
+    Default, // Switch default
+    Call, // Call, CallN and TailCall into the callee
+    Handler, // TryPush to its handler, taken by a later Throw
+    Fallthrough, // the next block, after a not taken branch or a returning call
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdgeKind::Unconditional => write!(f, "unconditional"),
+            EdgeKind::Eq => write!(f, "eq"),
+            EdgeKind::Ne => write!(f, "ne"),
+            EdgeKind::Gt => write!(f, "gt"),
+            EdgeKind::Lt => write!(f, "lt"),
+            EdgeKind::Ge => write!(f, "ge"),
+            EdgeKind::Le => write!(f, "le"),
+            EdgeKind::Zero => write!(f, "zero"),
+            EdgeKind::NotZero => write!(f, "nonzero"),
+            EdgeKind::Case(index) => write!(f, "case {}", index),
+            EdgeKind::Default => write!(f, "default"),
+            EdgeKind::Call => write!(f, "call"),
+            EdgeKind::Handler => write!(f, "handler"),
+            EdgeKind::Fallthrough => write!(f, "fallthrough"),
+        }
+    }
+}
+
+// from and to are the start pcs of the two blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>, // in pc order
+    pub edges: Vec<Edge>, // grouped by from block, in the order the last instruction names them
+}
+
+pub fn cfg(program: &[Instruction]) -> Cfg {
+    let target = |pc: usize, opcode: OpCode, position: usize, operand: i64| -> Option<usize> {
+        let target = if opcode.is_relative_jump() && position == 0 { (pc as i64).checked_add(operand)? } else { operand };
+        usize::try_from(target).ok().filter(|&target| target < program.len())
+    };
+
+    let mut leaders = BTreeSet::from([0]);
+    for (pc, instruction) in program.iter().enumerate() {
+        for (position, &operand) in instruction.operands.iter().enumerate() {
+            if instruction.opcode.is_target_operand(position) || (instruction.opcode.is_relative_jump() && position == 0) {
+                leaders.extend(target(pc, instruction.opcode, position, operand));
+            }
+        }
+        if ends_block(instruction.opcode) {
+            leaders.insert(pc + 1);
+        }
+    }
+    let starts: Vec<usize> = leaders.into_iter().filter(|&pc| pc < program.len()).collect();
+
+    let mut edges = Vec::new();
+    for (index, &start) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).copied().unwrap_or(program.len());
+        let last = end - 1;
+        let instruction = &program[last];
+        let mut edge = |to: Option<usize>, kind| {
+            if let Some(to) = to {
+                edges.push(Edge { from: start, to, kind });
+            }
+        };
+        let next = Some(end).filter(|&next| next < program.len());
+        let first = |position| instruction.operands.get(position).and_then(|&operand| target(last, instruction.opcode, position, operand));
+
+        match instruction.opcode {
+            OpCode::Jump | OpCode::JumpRel => edge(first(0), EdgeKind::Unconditional),
+            OpCode::Switch => {
+                let cases = instruction.operands.len().saturating_sub(1);
+                for position in 0..cases {
+                    edge(first(position), EdgeKind::Case(position));
+                }
+                edge(first(cases), EdgeKind::Default);
+            },
+            OpCode::TailCall => edge(first(0), EdgeKind::Call),
+            OpCode::Call | OpCode::CallN => {
+                edge(first(0), EdgeKind::Call);
+                edge(next, EdgeKind::Fallthrough);
+            },
+            OpCode::TryPush => {
+                edge(first(0), EdgeKind::Handler);
+                edge(next, EdgeKind::Fallthrough);
+            },
+            OpCode::JumpDyn | OpCode::Return | OpCode::Throw | OpCode::Exit | OpCode::FExit | OpCode::Halt => {},
+            opcode => {
+                if let Some(kind) = branch_kind(opcode) {
+                    edge(first(0), kind);
+                }
+                edge(next, EdgeKind::Fallthrough);
+            },
+        }
+    }
+
+    let index: HashMap<usize, usize> = starts.iter().enumerate().map(|(index, &start)| (start, index)).collect();
+    let mut reachable = vec![false; starts.len()];
+    let mut pending = if starts.is_empty() { vec![] } else { vec![0] };
+    while let Some(block) = pending.pop() {
+        if core::mem::replace(&mut reachable[block], true) {
+            continue;
+        }
+        pending.extend(edges.iter().filter(|edge| edge.from == starts[block]).map(|edge| index[&edge.to]));
+    }
+
+    let blocks = starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(program.len());
+            Block { start, end, instructions: program[start..end].to_vec(), reachable: reachable[index] }
+        })
+        .collect();
+    Cfg { blocks, edges }
+}
+
+// whether control can leave the straight line after this opcode
+fn ends_block(opcode: OpCode) -> bool {
+    branch_kind(opcode).is_some()
+        || matches!(
+            opcode,
+            OpCode::Jump | OpCode::JumpRel | OpCode::Switch | OpCode::JumpDyn | OpCode::Call | OpCode::CallIndirect
+                | OpCode::CallN | OpCode::TailCall | OpCode::Return | OpCode::Exit | OpCode::FExit | OpCode::Halt
+                | OpCode::TryPush | OpCode::Throw
+        )
+}
+
+// the taken edge of a conditional jump
+fn branch_kind(opcode: OpCode) -> Option<EdgeKind> {
+    Some(match opcode {
+        OpCode::JumpEq | OpCode::JumpRelEq => EdgeKind::Eq,
+        OpCode::JumpNe | OpCode::JumpRelNe => EdgeKind::Ne,
+        OpCode::JumpGt | OpCode::JumpRelGt => EdgeKind::Gt,
+        OpCode::JumpLt | OpCode::JumpRelLt => EdgeKind::Lt,
+        OpCode::JumpGe | OpCode::JumpRelGe => EdgeKind::Ge,
+        OpCode::JumpLe | OpCode::JumpRelLe => EdgeKind::Le,
+        OpCode::JumpZero => EdgeKind::Zero,
+        OpCode::JumpNotZero => EdgeKind::NotZero,
+        _ => return None,
+    })
+}
+
+impl Cfg {
+    // the block containing pc
+    pub fn block_at(&self, pc: usize) -> Option<&Block> {
+        self.blocks.iter().find(|block| (block.start..block.end).contains(&pc))
+    }
+
+    // Graphviz source, one box per block listing its instructions, unreachable blocks dashed and grey.
+    // render with `dot -Tsvg`
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for block in &self.blocks {
+            let mut label = String::new();
+            for (pc, instruction) in (block.start..).zip(&block.instructions) {
+                let mut line = format!("{:?}", instruction.opcode).to_lowercase();
+                for operand in &instruction.operands {
+                    write!(line, " {}", operand).unwrap();
+                }
+                // \l left-justifies the line in graphviz
+                write!(label, "{}: {}\\l", pc, line).unwrap();
+            }
+            let style = if block.reachable { "" } else { ", style=dashed, color=grey, fontcolor=grey" };
+            writeln!(out, "    pc{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(out, "    pc{} -> pc{} [label=\"{}\"];", edge.from, edge.to, edge.kind).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
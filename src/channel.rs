@@ -0,0 +1,97 @@
+// bounded queues of values between Contexts. The host makes a Channel, attaches its send end to one
+// Context with attach_sender and its receive end to another with attach_receiver, each under an id
+// the guest names in Send and Recv. A Send on a full channel or a Recv on an empty one blocks: the
+// instruction doesn't run, pc stays on it and the run stops with RunOutcome::Blocked so a Scheduler
+// can let the other side catch up. Every blocked attempt still counts as a step.
+//
+// A Context's send ends close when it exits or is dropped. Once a channel has had senders and all of
+// them are closed, a Recv that finds it empty fails with ChannelClosed instead of blocking forever
+
+use alloc::collections::VecDeque;
+
+use crate::sync::Shared;
+
+// clones are handles to the same queue
+#[derive(Debug, Clone)]
+pub struct Channel {
+    state: Shared<ChannelState>,
+}
+
+#[derive(Debug)]
+struct ChannelState {
+    queue: VecDeque<i64>,
+    capacity: usize,
+    senders: usize, // open send ends
+    had_senders: bool,
+}
+
+impl Channel {
+    // holds at most `capacity` values, at least 1
+    pub fn new(capacity: usize) -> Self {
+        let state = ChannelState { queue: VecDeque::new(), capacity: capacity.max(1), senders: 0, had_senders: false };
+        Channel { state: Shared::new(state) }
+    }
+
+    // values waiting to be received
+    pub fn len(&self) -> usize {
+        self.state.with(|state| state.queue.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // every send end that was attached has closed
+    pub fn is_closed(&self) -> bool {
+        self.state.with(|state| state.had_senders && state.senders == 0)
+    }
+
+    // false when full
+    pub(crate) fn try_send(&self, value: i64) -> bool {
+        self.state.with(|state| {
+            if state.queue.len() >= state.capacity {
+                return false;
+            }
+            state.queue.push_back(value);
+            true
+        })
+    }
+
+    // Ok(None) when empty but a sender may still fill it, Err(()) when empty and closed
+    pub(crate) fn try_recv(&self) -> Result<Option<i64>, ()> {
+        self.state.with(|state| match state.queue.pop_front() {
+            Some(value) => Ok(Some(value)),
+            None if state.had_senders && state.senders == 0 => Err(()),
+            None => Ok(None),
+        })
+    }
+}
+
+// a Context's send end, counted as open until closed or dropped
+#[derive(Debug)]
+pub(crate) struct SendEnd {
+    pub(crate) channel: Channel,
+    open: bool,
+}
+
+impl SendEnd {
+    pub(crate) fn new(channel: Channel) -> Self {
+        channel.state.with(|state| {
+            state.senders += 1;
+            state.had_senders = true;
+        });
+        SendEnd { channel, open: true }
+    }
+
+    pub(crate) fn close(&mut self) {
+        if core::mem::replace(&mut self.open, false) {
+            self.channel.state.with(|state| state.senders -= 1);
+        }
+    }
+}
+
+impl Drop for SendEnd {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -0,0 +1,170 @@
+// the `beef` command line, kept in the library so the driver can be tested without spawning it
+//
+//   beef run <file> [--format bytecode|asm|json] [--debug] [--reg rN=VALUE]...
+//   beef build <file> [--format bytecode|asm|json] -o <out.beef>
+//
+// the format comes from the extension (.beef, .basm, .json) unless --format says otherwise
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::assemble_program;
+use crate::context::{Config, Context};
+use crate::error::CliError;
+use crate::instruction::Program;
+use crate::prelude::*;
+
+pub const USAGE: &str = "usage: beef run <file> [--format bytecode|asm|json] [--debug] [--reg rN=VALUE]...
+       beef build <file> [--format bytecode|asm|json] -o <out.beef>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramFormat {
+    Bytecode,
+    Assembly,
+    Json,
+}
+
+impl ProgramFormat {
+    fn from_name(name: &str) -> Option<ProgramFormat> {
+        match name {
+            "bytecode" | "bin" | "beef" => Some(ProgramFormat::Bytecode),
+            "asm" | "assembly" | "basm" => Some(ProgramFormat::Assembly),
+            "json" => Some(ProgramFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<ProgramFormat> {
+        path.extension().and_then(|extension| extension.to_str()).and_then(ProgramFormat::from_name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOptions {
+    pub path: PathBuf,
+    pub format: Option<ProgramFormat>, // None -> guess from the extension
+    pub debug: bool,
+    pub registers: Vec<(usize, i64)>,
+}
+
+// args without the program name
+pub fn parse_args<S: AsRef<str>>(args: &[S]) -> Result<RunOptions, CliError> {
+    let usage = |message: String| CliError::Usage(message);
+    let mut args = args.iter().map(AsRef::as_ref);
+    match args.next() {
+        Some("run") => {},
+        Some(command) => return Err(usage(format!("unknown command `{}`", command))),
+        None => return Err(usage("missing command".to_string())),
+    }
+
+    let mut options = RunOptions { path: PathBuf::new(), format: None, debug: false, registers: Vec::new() };
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "--debug" => options.debug = true,
+            "--format" => {
+                let name = args.next().ok_or_else(|| usage("--format needs a value".to_string()))?;
+                options.format = Some(ProgramFormat::from_name(name).ok_or_else(|| usage(format!("unknown format `{}`", name)))?);
+            },
+            "--reg" => {
+                let seed = args.next().ok_or_else(|| usage("--reg needs rN=VALUE".to_string()))?;
+                options.registers.push(parse_register(seed).ok_or_else(|| usage(format!("bad register seed `{}`, expected rN=VALUE", seed)))?);
+            },
+            flag if flag.starts_with("--") => return Err(usage(format!("unknown option `{}`", flag))),
+            file if path.is_none() => path = Some(PathBuf::from(file)),
+            extra => return Err(usage(format!("unexpected argument `{}`", extra))),
+        }
+    }
+    options.path = path.ok_or_else(|| usage("missing program file".to_string()))?;
+    Ok(options)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildOptions {
+    pub path: PathBuf,
+    pub format: Option<ProgramFormat>,
+    pub output: PathBuf,
+}
+
+// args without the program name, starting at `build`
+pub fn parse_build_args<S: AsRef<str>>(args: &[S]) -> Result<BuildOptions, CliError> {
+    let usage = |message: String| CliError::Usage(message);
+    let mut args = args.iter().map(AsRef::as_ref);
+    match args.next() {
+        Some("build") => {},
+        Some(command) => return Err(usage(format!("unknown command `{}`", command))),
+        None => return Err(usage("missing command".to_string())),
+    }
+
+    let (mut path, mut format, mut output) = (None, None, None);
+    while let Some(arg) = args.next() {
+        match arg {
+            "--format" => {
+                let name = args.next().ok_or_else(|| usage("--format needs a value".to_string()))?;
+                format = Some(ProgramFormat::from_name(name).ok_or_else(|| usage(format!("unknown format `{}`", name)))?);
+            },
+            "-o" | "--output" => output = Some(PathBuf::from(args.next().ok_or_else(|| usage("-o needs a file".to_string()))?)),
+            flag if flag.starts_with('-') => return Err(usage(format!("unknown option `{}`", flag))),
+            file if path.is_none() => path = Some(PathBuf::from(file)),
+            extra => return Err(usage(format!("unexpected argument `{}`", extra))),
+        }
+    }
+    Ok(BuildOptions {
+        path: path.ok_or_else(|| usage("missing program file".to_string()))?,
+        format,
+        output: output.ok_or_else(|| usage("missing -o <out.beef>".to_string()))?,
+    })
+}
+
+fn parse_register(seed: &str) -> Option<(usize, i64)> {
+    let (register, value) = seed.split_once('=')?;
+    let register = register.strip_prefix(['r', 'R']).unwrap_or(register);
+    Some((register.parse().ok()?, value.parse().ok()?))
+}
+
+pub fn load_program(path: &Path, format: Option<ProgramFormat>) -> Result<Program, CliError> {
+    let format = format.or_else(|| ProgramFormat::from_path(path)).ok_or_else(|| {
+        CliError::Usage(format!("can't tell the format of {} from its extension, pass --format", path.display()))
+    })?;
+    let io_error = |e: std::io::Error| CliError::Io { path: path.to_path_buf(), message: e.to_string() };
+    match format {
+        ProgramFormat::Bytecode => {
+            let bytes = fs::read(path).map_err(io_error)?;
+            Program::from_bytes(&bytes).map_err(|error| CliError::Decode { path: path.to_path_buf(), error })
+        },
+        ProgramFormat::Assembly => {
+            let src = fs::read_to_string(path).map_err(io_error)?;
+            let file = path.display().to_string();
+            assemble_program(&src, &file).map_err(|error| CliError::Asm { path: path.to_path_buf(), error })
+        },
+        #[cfg(feature = "json")]
+        ProgramFormat::Json => {
+            let src = fs::read_to_string(path).map_err(io_error)?;
+            Program::from_json(&src).map_err(|error| CliError::Json { path: path.to_path_buf(), error })
+        },
+        #[cfg(not(feature = "json"))]
+        ProgramFormat::Json => Err(CliError::Usage(format!("{}: json programs need the json feature", path.display()))),
+    }
+}
+
+// load, seed registers and run to the exit value
+pub fn run_file(options: &RunOptions) -> Result<i64, CliError> {
+    let program = load_program(&options.path, options.format)?;
+    let mut context = Context::load(program, Config::default())?;
+    for &(register, value) in &options.registers {
+        context.set_register(register, value)?;
+    }
+    if options.debug {
+        context.set_trace(Box::new(io::stdout()));
+    }
+    Ok(context.run(false)?)
+}
+
+// load any format and write it out as bytecode, programs the format can't hold are reported
+// against the output file and nothing is written
+pub fn build_file(options: &BuildOptions) -> Result<(), CliError> {
+    let program = load_program(&options.path, options.format)?;
+    let bytes = program.to_bytes().map_err(|error| CliError::Encode { path: options.output.clone(), error })?;
+    fs::write(&options.output, bytes).map_err(|e| CliError::Io { path: options.output.clone(), message: e.to_string() })
+}
@@ -0,0 +1,2715 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+
+use crate::host::{Clock, Discard, EventSink, ExecutionEvent, ExecutionHooks, HostFn, Input, MmioHandler, Output, TrapHandler, TrapOutcome};
+use crate::error::{ValidationError, VmError};
+use crate::fuse::{fuse, Fused};
+use crate::instruction::{DebugInfo, Instruction, Op, OpCode, Program};
+use crate::profile::{ProfileReport, Profiler, Stopwatch};
+use crate::channel::{Channel, SendEnd};
+#[cfg(feature = "std")]
+use crate::handle::{Control, Request, VmHandle};
+#[cfg(feature = "std")]
+use crate::replay::Recorder;
+use crate::shared::{SharedMemory, SharedRegion};
+use crate::snapshot::{program_hash, History, Snapshot};
+use crate::validate::validate;
+use crate::prelude::*;
+
+mod dispatch;
+
+use dispatch::{translate, Handler, Operands};
+
+// what Add/Sub/Mul do when the result doesn't fit in an i64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArithMode {
+    #[default]
+    Wrapping,
+    Checked, // overflow is a runtime error
+    Saturating,
+}
+
+// knobs for a Context, Config::default() matches Context::new
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub arith_mode: ArithMode,
+    pub memory_limit: Option<usize>, // max distinct word cells, None is unlimited
+    pub heap: Range<usize>, // word addresses Alloc hands out, keep host data outside it
+    pub memory_stack: Option<usize>, // initial SP for PushM/PopM, the stack grows down from here
+    pub max_call_depth: usize, // Call past this many frames is an error
+    pub stop_check_interval: u64, // run looks at the stop flag every this many instructions
+    pub superinstructions: bool, // run common sequences like Push k; Add in one dispatch, see fuse.rs
+    pub table_dispatch: bool, // run through handlers picked at construction instead of the match, see context/dispatch.rs
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            arith_mode: ArithMode::default(),
+            memory_limit: None,
+            heap: 0x10000..0x20000,
+            memory_stack: None,
+            max_call_depth: 1024,
+            stop_check_interval: 1024,
+            superinstructions: false,
+            // the table-dispatch feature flips the default so the whole test suite runs through the table
+            table_dispatch: cfg!(feature = "table-dispatch"),
+        }
+    }
+}
+
+// one active call, pushed by Call and popped by Return
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub(crate) return_addr: usize,
+    pub(crate) locals: Vec<i64>,
+    pub(crate) stack_base: usize, // operand stack depth when the frame was entered
+}
+
+// a TryPush still in effect
+#[derive(Debug, Clone)]
+pub(crate) struct TryHandler {
+    pub(crate) pc: usize,
+    pub(crate) stack_depth: usize,
+    pub(crate) call_depth: usize,
+}
+
+// Rand's seed until the host calls set_rng_seed, so runs repeat unless it opts into entropy
+pub const DEFAULT_RNG_SEED: u64 = 0x5eed_0000_beef;
+
+// r0..r10
+pub const REGISTER_COUNT: usize = 11;
+
+// most local slots one frame can have, Call, TailCall and Enter operands past this are invalid
+pub const MAX_LOCALS: usize = 1 << 16;
+
+// most cells one MemSet or MemCpy may touch, so a bad count fails instead of running for hours
+pub const MAX_BULK_CELLS: usize = 1 << 20;
+
+// register holding the memory stack pointer when Config::memory_stack is set
+pub const SP_REGISTER: usize = 10;
+
+// how many of the topmost stack values a runtime error reports
+const STACK_CONTEXT: usize = 4;
+
+// execution context
+pub struct Context {
+    pc: usize,
+    entry: usize, // where run starts and reset goes back to
+
+    stack: Vec<i64>, // LIFO stack here is just a logical concept not rust physical call stack
+
+    call_stack: Vec<Frame>,
+    handlers: Vec<TryHandler>, // newest last
+
+    registers: [i64; REGISTER_COUNT],
+
+    memory: HashMap<usize, i64>, // word cells for Load/Store
+    data: Vec<(usize, Vec<i64>)>, // the program's data segment, what reset puts back into memory
+
+    // byte addressed memory for the sized loads/stores, empty unless created with new_with_memory
+    // it's a separate address space from the word cells above
+    linear: Vec<u8>,
+
+    protected: Vec<Range<usize>>, // read-only word addresses, see protect
+
+    mmio: Vec<(Range<usize>, Box<dyn MmioHandler>)>, // host backed word addresses, see map_region
+    shared: Vec<(Range<usize>, SharedMemory)>, // the attach_shared regions, also in mmio
+    senders: HashMap<i64, SendEnd>, // Send's channel id -> send end
+    receivers: HashMap<i64, Channel>, // Recv's channel id -> channel
+
+    allocations: BTreeMap<usize, usize>, // live heap blocks, base -> size
+
+    program: Vec<Instruction>,
+    code: Vec<Op>, // program decoded for the interpreter loop, same indices
+    table: Vec<(Handler, Operands)>, // code translated for table dispatch, empty when it's off
+    verified: bool, // program passed validate, execute_verified may skip operand checks
+
+    symbols: Vec<(String, usize)>, // name -> entry pc, from Program::symbols
+    debug_info: Option<DebugInfo>, // source lines, from Program::debug_info
+    strings: Vec<String>, // PrintStr's table, from Program::strings
+
+    hooks: Option<Box<dyn ExecutionHooks>>,
+
+    host_fns: HashMap<i64, HostFn>, // Syscall number -> host function
+    trap_handlers: HashMap<i64, TrapHandler>, // Trap code -> handler
+
+    output: Box<dyn Output>, // where Print and PrintChar go
+    trace: Option<Box<dyn Output>>, // per-step trace, see set_trace
+
+    input: Input, // where Read comes from
+
+    steps: u64, // instructions executed so far
+    rng_seed: u64, // reset starts the Rand sequence over from here
+    rng: u64, // splitmix64 state behind Rand and RandRange
+    clock: Option<Box<dyn Clock>>, // TimeMs source, None reads the time since `started`
+    started: Option<Stopwatch>, // when the first run or step since new or reset began
+    finished: Option<i64>, // exit value once Exit or Halt has run
+    stop: Arc<AtomicBool>, // set from another thread to interrupt run
+    #[cfg(feature = "std")]
+    control: Option<Control>, // requests from VmHandles, None until handle is called
+
+    fuel: Option<u64>, // None means unmetered
+    fuel_costs: HashMap<OpCode, u64>, // overrides, anything missing costs 1
+
+    events: Option<EventSink>,
+    #[cfg(feature = "std")]
+    recorder: Option<Recorder>, // binary trace for replay, see record_trace
+
+    profile: Option<Box<Profiler>>, // None unless enable_profiling was called
+    coverage: Option<Vec<u64>>, // hits per pc, None unless enable_coverage was called
+
+    breakpoints: BTreeMap<usize, Option<BreakCondition>>, // pc -> condition, None always stops
+    watches: BTreeSet<Watch>,
+    watch_hit: Option<(Watch, i64, i64)>, // first watched write of the current instruction, old and new value
+    stepped_over: Option<usize>, // breakpoint just reported, the next step executes it instead of hitting it again
+    history: Option<History>, // checkpoints for step_back, None until enable_reverse_stepping
+
+    config: Config,
+}
+
+impl Frame {
+    // where Return goes back to
+    pub fn return_addr(&self) -> usize {
+        self.return_addr
+    }
+
+    pub fn locals(&self) -> &[i64] {
+        &self.locals
+    }
+}
+
+// what happened after executing one instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StepResult {
+    Continue,
+    Exited(i64), // Exit or Halt ran, carries the result
+    Yielded(i64),
+    Blocked, // a channel op couldn't go ahead, pc is still on it
+}
+
+// decides whether a conditional breakpoint stops, called with the context paused before the instruction
+pub type BreakCondition = Box<dyn Fn(&Context) -> bool + Send>;
+
+// a location watch_register / watch_memory pauses on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Watch {
+    Register(usize),
+    Memory(usize), // word address
+}
+
+// what Context::step did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Continued,
+    Exited(i64), // the program is finished, stepping again keeps reporting this
+    Yielded(i64),
+    Blocked { pc: usize }, // the channel op at pc couldn't go ahead, the next step tries it again
+    Breakpoint { pc: usize }, // nothing ran, the next step executes the instruction at pc
+    Watchpoint { pc: usize, watch: Watch, old: i64, new: i64 }, // the instruction at pc ran and wrote `watch`
+}
+
+// how a budgeted run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Completed(i64),
+    Paused, // step budget used up, run again to continue
+    Yielded(i64), // guest ran Yield, run again to continue after it
+    Blocked { pc: usize }, // Send on a full or Recv on an empty channel, run again to retry it
+    Hit { pc: usize }, // stopped before a breakpointed instruction, run again to execute it
+    Watchpoint { pc: usize, watch: Watch, old: i64, new: i64 }, // stopped after the instruction at pc wrote `watch`
+}
+
+// a finished program's result with its type, see Context::exit_value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitValue {
+    Int(i64),
+    Float(f64), // FExit ran, run reported this float's bit pattern
+}
+
+// everything execute reports about a finished run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionResult {
+    pub value: i64,                       // what Exit/Halt produced
+    pub registers: [i64; REGISTER_COUNT], // final register file
+    pub steps: u64,                       // instructions executed
+    pub elapsed: Option<Duration>,        // wall time, None without a clock: no std, or wasm32-unknown-unknown
+}
+
+impl Context {
+    pub fn new(program: Vec<Instruction>) -> Self {
+        Self::new_with_config(program, Config::default())
+    }
+
+    pub fn new_with_config(program: Vec<Instruction>, config: Config) -> Self {
+        let code = program.iter().map(Op::from).collect();
+        let mut context = Context {
+            pc: 0,
+            entry: 0,
+            stack: Vec::new(),
+            call_stack: Vec::new(),
+            handlers: Vec::new(),
+            registers: [0; REGISTER_COUNT],
+            memory: HashMap::new(),
+            data: Vec::new(),
+            linear: Vec::new(),
+            protected: Vec::new(),
+            mmio: Vec::new(),
+            shared: Vec::new(),
+            senders: HashMap::new(),
+            receivers: HashMap::new(),
+            allocations: BTreeMap::new(),
+            program,
+            code,
+            table: Vec::new(),
+            verified: false,
+            symbols: Vec::new(),
+            debug_info: None,
+            strings: Vec::new(),
+            hooks: None,
+            host_fns: HashMap::new(),
+            trap_handlers: HashMap::new(),
+            #[cfg(feature = "std")]
+            output: Box::new(io::stdout()),
+            #[cfg(not(feature = "std"))]
+            output: Box::new(Discard),
+            trace: None,
+            #[cfg(feature = "std")]
+            input: Input::Stdin,
+            #[cfg(not(feature = "std"))]
+            input: Input::Values(Box::new(core::iter::empty())),
+            steps: 0,
+            rng_seed: DEFAULT_RNG_SEED,
+            rng: DEFAULT_RNG_SEED,
+            clock: None,
+            started: None,
+            finished: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "std")]
+            control: None,
+            fuel: None,
+            fuel_costs: HashMap::new(),
+            events: None,
+            #[cfg(feature = "std")]
+            recorder: None,
+            profile: None,
+            coverage: None,
+            breakpoints: BTreeMap::new(),
+            watches: BTreeSet::new(),
+            watch_hit: None,
+            stepped_over: None,
+            history: None,
+            config,
+        };
+        if let Some(sp) = context.config.memory_stack {
+            context.registers[SP_REGISTER] = sp as i64;
+        }
+        if context.config.superinstructions {
+            fuse(&context.program, &mut context.code);
+        }
+        if context.config.table_dispatch {
+            context.table = translate(&context.code);
+        }
+        context
+    }
+
+    // Context::new after validate, so operand and target mistakes surface up front.
+    // the program counts as verified, run gets the unchecked fast path like run_verified
+    pub fn new_validated(program: Vec<Instruction>) -> Result<Self, Vec<ValidationError>> {
+        validate(&program)?;
+        let mut context = Self::new(program);
+        context.verified = true;
+        Ok(context)
+    }
+
+    // build a context for a Program, its data segment is copied into memory up front
+    pub fn load(program: Program, config: Config) -> Result<Self, VmError> {
+        program.check_data()?;
+        if let Some((name, entry)) = program.symbols.iter().find(|(_, entry)| *entry >= program.instructions.len()) {
+            return Err(VmError::InvalidProgram(format!("Symbol {} points outside the program: {}", name, entry)));
+        }
+        if program.entry != 0 && program.entry >= program.instructions.len() {
+            return Err(VmError::InvalidProgram(format!("Entry point {} is outside the program", program.entry)));
+        }
+        let mut context = Self::new_with_config(program.instructions, config);
+        context.entry = program.entry;
+        context.pc = program.entry;
+        context.symbols = program.symbols;
+        context.debug_info = program.debug_info;
+        context.strings = program.strings;
+        context.data = program.data;
+        context.load_data();
+
+        Ok(context)
+    }
+
+    fn load_data(&mut self) {
+        for (start, words) in &self.data {
+            for (i, &word) in words.iter().enumerate() {
+                self.memory.insert(start + i, word);
+            }
+        }
+    }
+
+    // back to how the program started: pc at the entry point, empty stacks, zeroed registers (SP set again with a memory
+    // stack), word memory holding just the data segment, zeroed linear memory, no heap blocks, no steps.
+    // Host setup stays as it is, fuel included. Profiling and coverage keep counting across runs,
+    // step_back history is dropped
+    pub fn reset(&mut self) {
+        self.pc = self.entry;
+        self.stack.clear();
+        self.call_stack.clear();
+        self.handlers.clear();
+        self.registers = [0; REGISTER_COUNT];
+        if let Some(sp) = self.config.memory_stack {
+            self.registers[SP_REGISTER] = sp as i64;
+        }
+        self.memory.clear();
+        self.load_data();
+        self.linear.fill(0);
+        self.allocations.clear();
+        self.steps = 0;
+        self.rng = self.rng_seed;
+        self.started = None;
+        self.finished = None;
+        self.watch_hit = None;
+        self.stepped_over = None;
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+    }
+
+    // same as new but with `size` bytes of zeroed linear memory for Load8..Store64
+    pub fn new_with_memory(program: Vec<Instruction>, size: usize) -> Self {
+        let mut context = Self::new(program);
+        context.linear = vec![0; size];
+        context
+    }
+
+    // make a range of word addresses read-only, stores into it become runtime errors
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.protected.push(range);
+    }
+
+    // route Load/Store on `range` to a host handler, ranges can't overlap
+    pub fn map_region(&mut self, range: Range<usize>, handler: Box<dyn MmioHandler>) -> Result<(), VmError> {
+        if let Some((existing, _)) = self.mmio.iter().find(|(r, _)| range.start < r.end && r.start < range.end) {
+            return Err(VmError::MmioOverlap { range, existing: existing.clone() });
+        }
+        self.mmio.push((range, handler));
+
+        Ok(())
+    }
+
+    // map `memory` at base..base + memory.len(). Other Contexts can attach the same memory, anywhere
+    // they like. Like map_region it can't overlap another mapped range. reset, snapshots and restore
+    // leave the shared cells alone
+    pub fn attach_shared(&mut self, base: usize, memory: &SharedMemory) -> Result<(), VmError> {
+        let range = base..base.saturating_add(memory.len());
+        self.map_region(range.clone(), Box::new(SharedRegion { base, memory: memory.clone() }))?;
+        self.shared.push((range, memory.clone()));
+
+        Ok(())
+    }
+
+    // let `Send id` queue on `channel`. The send end stays open until this Context exits or is dropped,
+    // attaching another end under the same id closes the old one
+    pub fn attach_sender(&mut self, id: i64, channel: &Channel) {
+        self.senders.insert(id, SendEnd::new(channel.clone()));
+    }
+
+    // let `Recv id` take from `channel`
+    pub fn attach_receiver(&mut self, id: i64, channel: &Channel) {
+        self.receivers.insert(id, channel.clone());
+    }
+
+    // make `Syscall n` call `f`, replaces any function already registered for n
+    pub fn register_host_fn(&mut self, n: i64, f: HostFn) {
+        self.host_fns.insert(n, f);
+    }
+
+    // make `Trap code` call `handler`, replaces any handler already registered for code
+    pub fn register_trap_handler(&mut self, code: i64, handler: TrapHandler) {
+        self.trap_handlers.insert(code, handler);
+    }
+
+    // redirect Print/PrintChar, e.g. into a Vec<u8> to capture guest output. The default is stdout, or
+    // nowhere without std
+    pub fn set_output(&mut self, output: Box<dyn Output>) {
+        self.output = output;
+    }
+
+    // write a trace of every executed instruction here: pc and instruction, the stack before and
+    // after, and the registers. Superinstructions are skipped while tracing so no step goes missing
+    pub fn set_trace(&mut self, trace: Box<dyn Output>) {
+        self.trace = Some(trace);
+    }
+
+    // stop tracing, handing the sink back
+    pub fn take_trace(&mut self) -> Option<Box<dyn Output>> {
+        self.trace.take()
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    // flag another thread can set to interrupt run. run stops before the next instruction and
+    // leaves pc there, clear the flag and call run again to carry on
+    pub fn stop_handle(&mut self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    // control half for another thread, see VmHandle. Every handle shares the stop flag with stop_handle
+    #[cfg(feature = "std")]
+    pub fn handle(&mut self) -> VmHandle {
+        let control = self.control.get_or_insert_with(Control::new);
+        VmHandle { stop: Arc::clone(&self.stop), requests: control.sender.clone(), program_len: self.program.len() }
+    }
+
+    // answer whatever the handles have sent so far. run does this by itself, call it while not running
+    #[cfg(feature = "std")]
+    pub fn poll_handle(&mut self) {
+        let Some(control) = &self.control else { return };
+        let requests: Vec<Request> = control.receiver.try_iter().collect();
+        for request in requests {
+            match request {
+                Request::AddBreakpoint(pc) => {
+                    let _ = self.insert_breakpoint(pc, None);
+                },
+                Request::RemoveBreakpoint(pc) => {
+                    self.breakpoints.remove(&pc);
+                },
+                Request::Snapshot(reply) => {
+                    let _ = reply.send(self.snapshot());
+                },
+            }
+        }
+    }
+
+    // seed Rand and RandRange, the sequence starts over. Pass something from the clock or the OS for
+    // runs that differ, the default is DEFAULT_RNG_SEED every time
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng_seed = seed;
+        self.rng = seed;
+    }
+
+    // where TimeMs gets the time, e.g. a fake clock in tests. Without one it's the milliseconds since the
+    // first run or step after new or reset, always 0 on targets with no clock (no std, or wasm32-unknown-unknown)
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    // meter execution, each instruction burns its cost and running out stops with an error
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    // make an opcode more (or less) expensive than the default of 1
+    pub fn set_fuel_cost(&mut self, opcode: OpCode, cost: u64) {
+        self.fuel_costs.insert(opcode, cost);
+    }
+
+    // stream ExecutionEvents to a callback, nothing is recorded while no sink is set
+    pub fn set_event_sink(&mut self, sink: EventSink) {
+        self.events = Some(sink);
+    }
+
+    // same, but into a channel. a dropped receiver just stops the events
+    #[cfg(feature = "std")]
+    pub fn set_event_channel(&mut self, sender: Sender<ExecutionEvent>) {
+        self.events = Some(Box::new(move |event| {
+            let _ = sender.send(event);
+        }));
+    }
+
+    // observe calls, returns and every executed instruction
+    pub fn set_hooks(&mut self, hooks: Box<dyn ExecutionHooks>) {
+        self.hooks = Some(hooks);
+    }
+
+    // log every step from here on to `out` for replay, starting from the current pc, registers and stack.
+    // Memory isn't part of the start state, so record from the beginning of a run
+    #[cfg(feature = "std")]
+    pub fn record_trace(&mut self, out: Box<dyn Write + Send>) {
+        self.recorder = Some(Recorder::new(out, self.pc, &self.registers, &self.stack, self.rng));
+    }
+
+    // stop recording and flush, reports the first write that failed while recording
+    #[cfg(feature = "std")]
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        self.recorder.take().map_or(Ok(()), Recorder::finish)
+    }
+
+    // count executions per opcode and per pc from here on, and time each opcode where there's a clock.
+    // Nothing is measured until this is called, so an unprofiled run pays only for checking it's off
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Box::new(Profiler::new(self.program.len())));
+    }
+
+    // everything counted since enable_profiling, None if profiling is off
+    pub fn profile_report(&self) -> Option<ProfileReport> {
+        self.profile.as_ref().map(|profile| profile.report(&self.program))
+    }
+
+    // count how often each pc executes from here on, see coverage
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(|| vec![0; self.program.len()]);
+    }
+
+    // hits per pc since enable_coverage, all zero if coverage is off. merge_coverage adds up several runs
+    pub fn coverage(&self) -> Vec<u64> {
+        self.coverage.clone().unwrap_or_else(|| vec![0; self.program.len()])
+    }
+
+    // stop run, run_for and step before the instruction at pc executes
+    pub fn add_breakpoint(&mut self, pc: usize) -> Result<(), VmError> {
+        self.insert_breakpoint(pc, None)
+    }
+
+    // like add_breakpoint, but only stops when `condition` holds as the instruction is reached
+    pub fn add_conditional_breakpoint(&mut self, pc: usize, condition: BreakCondition) -> Result<(), VmError> {
+        self.insert_breakpoint(pc, Some(condition))
+    }
+
+    fn insert_breakpoint(&mut self, pc: usize, condition: Option<BreakCondition>) -> Result<(), VmError> {
+        if pc >= self.program.len() {
+            return Err(VmError::InvalidBreakpoint { pc, len: self.program.len() });
+        }
+        self.breakpoints.insert(pc, condition);
+
+        Ok(())
+    }
+
+    // false if there was no breakpoint at pc
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc).is_some()
+    }
+
+    // in pc order, conditional ones included
+    pub fn list_breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.keys().copied().collect()
+    }
+
+    fn breakpoint_stops(&self, pc: usize) -> bool {
+        match self.breakpoints.get(&pc) {
+            Some(Some(condition)) => condition(self),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // pause after any instruction that writes register `index`, even when the value doesn't change
+    pub fn watch_register(&mut self, index: usize) -> Result<(), VmError> {
+        if index >= REGISTER_COUNT {
+            return Err(VmError::InvalidRegister { pc: self.pc, index: i64::try_from(index).unwrap_or(i64::MAX) });
+        }
+        self.watches.insert(Watch::Register(index));
+
+        Ok(())
+    }
+
+    // pause after any instruction that writes the word at `addr`, host pokes don't count
+    pub fn watch_memory(&mut self, addr: usize) {
+        self.watches.insert(Watch::Memory(addr));
+    }
+
+    // false if the location wasn't watched
+    pub fn unwatch(&mut self, watch: Watch) -> bool {
+        self.watches.remove(&watch)
+    }
+
+    // remember the first watched write of this instruction for step to report
+    fn note_write(&mut self, watch: Watch, old: i64, new: i64) {
+        if self.watch_hit.is_none() && self.watches.contains(&watch) {
+            self.watch_hit = Some((watch, old, new));
+        }
+    }
+
+    // same for a word about to be overwritten
+    fn note_memory_write(&mut self, addr: usize, new: i64) {
+        if !self.watches.is_empty() {
+            let old = self.memory.get(&addr).copied().unwrap_or(0);
+            self.note_write(Watch::Memory(addr), old, new);
+        }
+    }
+
+    // inspection and seeding, usable before run and after it returns (even with an error)
+
+    // word memory sorted by address for stable output
+    pub fn memory_snapshot(&self) -> BTreeMap<usize, i64> {
+        self.memory.iter().map(|(&addr, &value)| (addr, value)).collect()
+    }
+
+    // None if the cell was never written
+    pub fn peek(&self, addr: usize) -> Option<i64> {
+        self.memory.get(&addr).copied()
+    }
+
+    // host write, bypasses limits, protection and mmio
+    pub fn poke(&mut self, addr: usize, value: i64) {
+        self.memory.insert(addr, value);
+    }
+
+    pub fn registers(&self) -> &[i64] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, index: usize, value: i64) -> Result<(), VmError> {
+        let pc = self.pc;
+        let reg = self.registers.get_mut(index)
+            .ok_or(VmError::InvalidRegister { pc, index: i64::try_from(index).unwrap_or(i64::MAX) })?;
+        *reg = value;
+
+        Ok(())
+    }
+
+    pub fn stack(&self) -> &[i64] {
+        &self.stack
+    }
+
+    pub fn set_stack(&mut self, stack: Vec<i64>) {
+        self.stack = stack;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    // instructions executed so far, a fused sequence counts every instruction in it
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // the result once the program has finished, a Float when it ended with FExit, None before that
+    pub fn exit_value(&self) -> Option<ExitValue> {
+        let value = self.finished?;
+        // a finished program's pc stays on the instruction that ended it
+        Some(match self.program.get(self.pc).map(|instruction| instruction.opcode) {
+            Some(OpCode::FExit) => ExitValue::Float(f64::from_bits(value as u64)),
+            _ => ExitValue::Int(value),
+        })
+    }
+
+    // copy of the execution state: pc, both stacks, registers, memory, heap blocks, step count and fuel
+    pub fn snapshot(&self) -> Snapshot {
+        self.capture(program_hash(&self.program))
+    }
+
+    // go back to `snapshot`, which has to come from a context running the same program.
+    // reverse stepping starts over from here, older checkpoints are forgotten
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), VmError> {
+        let program = program_hash(&self.program);
+        if snapshot.program_hash != program {
+            return Err(VmError::SnapshotMismatch { snapshot: snapshot.program_hash, program });
+        }
+        self.apply(snapshot);
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+            history.push(snapshot.clone());
+        }
+        Ok(())
+    }
+
+    fn capture(&self, program_hash: u64) -> Snapshot {
+        Snapshot {
+            program_hash,
+            pc: self.pc,
+            stack: self.stack.clone(),
+            call_stack: self.call_stack.clone(),
+            handlers: self.handlers.clone(),
+            registers: self.registers,
+            memory: self.memory.clone(),
+            linear: self.linear.clone(),
+            allocations: self.allocations.clone(),
+            steps: self.steps,
+            rng: self.rng,
+            finished: self.finished,
+            fuel: self.fuel,
+        }
+    }
+
+    fn apply(&mut self, snapshot: &Snapshot) {
+        self.pc = snapshot.pc;
+        self.stack.clone_from(&snapshot.stack);
+        self.call_stack.clone_from(&snapshot.call_stack);
+        self.handlers.clone_from(&snapshot.handlers);
+        self.registers = snapshot.registers;
+        self.memory.clone_from(&snapshot.memory);
+        self.linear.clone_from(&snapshot.linear);
+        self.allocations.clone_from(&snapshot.allocations);
+        self.steps = snapshot.steps;
+        self.rng = snapshot.rng;
+        self.finished = snapshot.finished;
+        self.fuel = snapshot.fuel;
+        self.watch_hit = None;
+        self.stepped_over = None;
+    }
+
+    // checkpoint every `snapshot_interval` steps from here on so step_back works. Only the newest
+    // MAX_CHECKPOINTS are kept, stepping back further than those reach is an error
+    pub fn enable_reverse_stepping(&mut self, snapshot_interval: u64) {
+        let mut history = History::new(snapshot_interval, program_hash(&self.program));
+        history.push(self.capture(history.program_hash));
+        self.history = Some(history);
+    }
+
+    // enable_reverse_stepping, then resume
+    pub fn run_reversible(&mut self, snapshot_interval: u64) -> Result<RunOutcome, VmError> {
+        self.enable_reverse_stepping(snapshot_interval);
+        self.resume()
+    }
+
+    // undo the last instruction: restore the newest checkpoint before it and quietly execute forward to
+    // one step short of where we were. Output, hooks, events, traces, profiling, breakpoints and watches
+    // are off while catching up, but Read, Syscall and mmio run again and may not give the same answers
+    pub fn step_back(&mut self) -> Result<(), VmError> {
+        let steps = self.steps;
+        let target = steps.checked_sub(1);
+        let checkpoint = match (target, self.history.as_mut()) {
+            (Some(target), Some(history)) => history.rewind(target),
+            _ => None,
+        };
+        let (Some(target), Some(checkpoint)) = (target, checkpoint) else {
+            return Err(VmError::NoCheckpoint { steps });
+        };
+
+        self.apply(&checkpoint);
+        self.quietly(|context| {
+            while context.steps < target && context.finished.is_none() {
+                context.step_one()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn checkpoint(&mut self) {
+        let Some(hash) = self.history.as_ref().map(|history| history.program_hash) else { return };
+        let snapshot = self.capture(hash);
+        if let Some(history) = self.history.as_mut() {
+            history.push(snapshot);
+        }
+    }
+
+    // run `f` with every observer and stopping point detached
+    fn quietly<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let output = core::mem::replace(&mut self.output, Box::new(Discard));
+        let trace = self.trace.take();
+        let hooks = self.hooks.take();
+        let events = self.events.take();
+        #[cfg(feature = "std")]
+        let recorder = self.recorder.take();
+        let profile = self.profile.take();
+        let breakpoints = core::mem::take(&mut self.breakpoints);
+        let watches = core::mem::take(&mut self.watches);
+
+        let result = f(self);
+
+        self.output = output;
+        self.trace = trace;
+        self.hooks = hooks;
+        self.events = events;
+        #[cfg(feature = "std")]
+        {
+            self.recorder = recorder;
+        }
+        self.profile = profile;
+        self.breakpoints = breakpoints;
+        self.watches = watches;
+        result
+    }
+
+    // run to the exit value. debug=true is the old way of tracing to stdout, it still works but only as
+    // shorthand for set_trace(stdout) for this one run, new code should pass false and use set_trace.
+    // Once the program has finished, running again is AlreadyFinished until reset
+    pub fn run(&mut self, debug: bool) -> Result<i64, VmError> {
+        if let Some(value) = self.finished {
+            return Err(VmError::AlreadyFinished { value });
+        }
+        let installed = cfg!(feature = "std") && debug && self.trace.is_none();
+        #[cfg(feature = "std")]
+        if installed {
+            self.trace = Some(Box::new(io::stdout()));
+        }
+        let outcome = self.run_steps(None);
+        if installed {
+            self.trace = None;
+        }
+
+        match outcome? {
+            RunOutcome::Completed(value) => Ok(value),
+            RunOutcome::Yielded(value) => Err(VmError::Yielded { pc: self.pc, value }),
+            RunOutcome::Blocked { pc } => Err(VmError::Blocked { pc }),
+            RunOutcome::Hit { pc } => Err(VmError::Breakpoint { pc }),
+            RunOutcome::Watchpoint { pc, watch, old, new } => Err(VmError::Watchpoint { pc, watch, old, new }),
+            // only a step budget pauses and run has none, treat it as stopping early rather than panic
+            RunOutcome::Paused => Err(VmError::Interrupted { pc: self.pc }),
+        }
+    }
+
+    // run, after checking the whole program with validate. Once it passes, the hot opcodes skip their
+    // per-step operand, register and target checks. Stack and arithmetic checks depend on data so they stay
+    pub fn run_verified(&mut self) -> Result<i64, VmError> {
+        if !self.verified {
+            validate(&self.program).map_err(VmError::Unverified)?;
+            self.verified = true;
+        }
+        self.run(false)
+    }
+
+    // one-call embedding entry point: args go into r1..rN (r0 is left for the result by convention),
+    // then the program runs to completion with the default config
+    pub fn execute(program: Vec<Instruction>, args: &[i64]) -> Result<ExecutionResult, VmError> {
+        let mut context = Context::new(program);
+        if args.len() >= context.registers.len() {
+            return Err(VmError::TooManyArguments { count: args.len(), max: context.registers.len() - 1 });
+        }
+        context.registers[1..=args.len()].copy_from_slice(args);
+
+        let start = Stopwatch::start();
+        let value = context.run(false)?;
+
+        Ok(ExecutionResult { value, registers: context.registers, steps: context.steps, elapsed: start.elapsed() })
+    }
+
+    // like run, but a Yield comes back as an outcome instead of an error
+    pub fn resume(&mut self) -> Result<RunOutcome, VmError> {
+        self.run_steps(None)
+    }
+
+    // execute at most max_steps instructions. Paused keeps all state so the next run_for carries on
+    // where this one stopped, once the program has finished it keeps reporting Completed
+    pub fn run_for(&mut self, max_steps: usize) -> Result<RunOutcome, VmError> {
+        self.run_steps(Some(max_steps))
+    }
+
+    fn run_steps(&mut self, max_steps: Option<usize>) -> Result<RunOutcome, VmError> {
+        if let Some(value) = self.finished {
+            return Ok(RunOutcome::Completed(value));
+        }
+
+        self.started.get_or_insert_with(Stopwatch::start);
+        let mut executed = 0;
+        while self.pc < self.program.len() {
+            if max_steps.is_some_and(|max| executed >= max) {
+                return Ok(RunOutcome::Paused);
+            }
+            let budget = max_steps.map(|max| max - executed);
+            executed += 1;
+
+            // polling an atomic every step is measurable, so only look every few instructions
+            if self.steps.is_multiple_of(self.config.stop_check_interval.max(1)) {
+                #[cfg(feature = "std")]
+                if self.control.is_some() {
+                    self.poll_handle();
+                }
+                if self.stop.load(Ordering::Relaxed) {
+                    return Err(VmError::Interrupted { pc: self.pc });
+                }
+            }
+
+            if let Some(fused) = self.code[self.pc].fused {
+                if self.fusion_allowed(fused.width(), budget) && self.execute_fused(fused) {
+                    self.steps += fused.width() as u64;
+                    executed += fused.width() - 1;
+                    continue;
+                }
+            }
+
+            match self.step_one()? {
+                StepOutcome::Exited(value) => return Ok(RunOutcome::Completed(value)),
+                StepOutcome::Yielded(value) => return Ok(RunOutcome::Yielded(value)),
+                StepOutcome::Blocked { pc } => return Ok(RunOutcome::Blocked { pc }),
+                StepOutcome::Breakpoint { pc } => return Ok(RunOutcome::Hit { pc }),
+                StepOutcome::Watchpoint { pc, watch, old, new } => return Ok(RunOutcome::Watchpoint { pc, watch, old, new }),
+                StepOutcome::Continued => {},
+            }
+        }
+        
+        Err(VmError::NoExit)
+    }
+
+    // execute exactly one instruction. run is a loop over this, so stepping through a program
+    // behaves the same as running it
+    pub fn step(&mut self) -> Result<StepOutcome, VmError> {
+        self.step_one()
+    }
+
+    fn step_one(&mut self) -> Result<StepOutcome, VmError> {
+        if let Some(value) = self.finished {
+            return Ok(StepOutcome::Exited(value));
+        }
+        if self.pc >= self.program.len() {
+            return Err(VmError::NoExit);
+        }
+        if !self.breakpoints.is_empty() && self.stepped_over != Some(self.pc) && self.breakpoint_stops(self.pc) {
+            self.stepped_over = Some(self.pc);
+            return Ok(StepOutcome::Breakpoint { pc: self.pc });
+        }
+        self.stepped_over = None;
+        if self.history.as_ref().is_some_and(|history| history.due(self.steps)) {
+            self.checkpoint();
+        }
+
+        if self.trace.is_some() {
+            let before = format!(
+                "PC: {}, Executing: {:?}\nStack before: {:?}\n", self.describe_pc(self.pc), self.program[self.pc], self.stack
+            );
+            self.write_trace(&before)?;
+        }
+
+        // Execute instruction
+        let pc = self.pc;
+        let result = self.execute_located(self.code[pc]);
+        // watch_hit is only ever set while something is watched
+        let watch_hit = if self.watches.is_empty() { None } else { self.watch_hit.take() };
+        let result = result?;
+
+        if self.trace.is_some() {
+            let after = format!("Stack after: {:?}\nRegisters: {:?}\n-------------------\n", self.stack, self.registers);
+            self.write_trace(&after)?;
+        }
+
+        Ok(match result {
+            StepResult::Exited(value) => {
+                self.finished = Some(value);
+                self.senders.values_mut().for_each(SendEnd::close);
+                StepOutcome::Exited(value)
+            },
+            StepResult::Yielded(value) => StepOutcome::Yielded(value),
+            StepResult::Blocked => StepOutcome::Blocked { pc },
+            StepResult::Continue => match watch_hit {
+                Some((watch, old, new)) => StepOutcome::Watchpoint { pc, watch, old, new },
+                None => StepOutcome::Continued,
+            },
+        })
+    }
+
+    // a failing trace sink fails the run, same as a failing output
+    fn write_trace(&mut self, text: &str) -> Result<(), VmError> {
+        let (pc, opcode) = (self.pc, self.program[self.pc].opcode);
+        let trace = self.trace.as_mut().expect("only called while tracing");
+        trace.write_text(text).map_err(|message| VmError::Io { pc, opcode, message })
+    }
+
+    // run the function named `name` with `args` as its locals until it returns, result is the
+    // value it leaves on top of the stack. On success pc and the call stack are back where they
+    // were, on error they're left at the fault like run does
+    pub fn call_function(&mut self, name: &str, args: &[i64]) -> Result<i64, VmError> {
+        let entry = self.symbols.iter().find(|(symbol, _)| symbol == name).map(|(_, entry)| *entry)
+            .ok_or_else(|| VmError::UnknownFunction(name.to_string()))?;
+        let depth = self.call_stack.len();
+        let resume_pc = self.pc;
+
+        self.push_frame(Frame { return_addr: resume_pc, locals: args.to_vec(), stack_base: self.stack.len() }, entry)?;
+        self.pc = entry;
+        while self.call_stack.len() > depth {
+            if self.pc >= self.program.len() {
+                return Err(call_failed(name, "ran past the end of the program".to_string()));
+            }
+            match self.execute_located(self.code[self.pc])? {
+                StepResult::Exited(value) => return Err(call_failed(name, format!("exited the program with {}", value))),
+                StepResult::Yielded(value) => return Err(call_failed(name, format!("yielded {}", value))),
+                StepResult::Blocked => return Err(call_failed(name, "blocked on a channel".to_string())),
+                StepResult::Continue => {},
+            }
+        }
+
+        self.stack.pop().ok_or_else(|| call_failed(name, "returned without a value".to_string()))
+    }
+
+    // execute_ix with the location, instruction, top of stack and return addresses appended to any error
+    fn execute_located(&mut self, instruction: Op) -> Result<StepResult, VmError> {
+        if let Some(fuel) = self.fuel {
+            let cost = self.fuel_costs.get(&instruction.opcode).copied().unwrap_or(1);
+            // nothing runs on a partial budget, pc stays on the instruction we couldn't afford
+            if cost > fuel {
+                return Err(VmError::OutOfFuel { pc: self.pc, steps: self.steps });
+            }
+            self.fuel = Some(fuel - cost);
+        }
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_instruction(self.pc, &self.program[self.pc]);
+        }
+        self.steps += 1;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage[self.pc] += 1;
+        }
+        let (pc, opcode) = (self.pc, instruction.opcode);
+        // captured up front since the instruction may consume what it failed on
+        let mut stack_top = [0; STACK_CONTEXT];
+        let shown = self.stack.len().min(STACK_CONTEXT);
+        stack_top[..shown].copy_from_slice(&self.stack[self.stack.len() - shown..]);
+
+        // stack traffic is worked out by diffing, so only pay for the copy when someone listens
+        let stack_before = if self.observed() {
+            let operands = self.program[pc].operands.clone();
+            self.emit(|| ExecutionEvent::Instruction { pc, opcode, operands });
+            Some(self.stack.clone())
+        } else {
+            None
+        };
+
+        let started = self.profile.is_some().then(Stopwatch::start);
+        let result = if !self.table.is_empty() {
+            self.execute_table()
+        } else if self.verified {
+            self.execute_verified(instruction)
+        } else {
+            self.execute_ix(instruction)
+        };
+        if let (Some(profile), Some(started)) = (self.profile.as_mut(), started) {
+            profile.record(pc, opcode, started.elapsed());
+        }
+        let result = result.map_err(|error| VmError::Located {
+            location: self.describe_pc(pc),
+            instruction: self.program[pc].clone(),
+            stack_top: stack_top[..shown].to_vec(),
+            call_stack: self.backtrace(),
+            error: Box::new(error),
+        })?;
+
+        if let Some(before) = stack_before {
+            self.emit_stack_diff(&before);
+            if let StepResult::Exited(value) = result {
+                self.emit(|| ExecutionEvent::Exit { value });
+            }
+        }
+
+        Ok(result)
+    }
+
+    // pops of everything past the common prefix, then pushes of the new tail
+    fn emit_stack_diff(&mut self, before: &[i64]) {
+        let common = before.iter().zip(&self.stack).take_while(|(a, b)| a == b).count();
+        for &value in before[common..].iter().rev() {
+            self.emit(|| ExecutionEvent::StackPop(value));
+        }
+        for i in common..self.stack.len() {
+            let value = self.stack[i];
+            self.emit(|| ExecutionEvent::StackPush(value));
+        }
+    }
+
+    // an event sink or a replay recorder wants ExecutionEvents
+    fn observed(&self) -> bool {
+        #[cfg(feature = "std")]
+        if self.recorder.is_some() {
+            return true;
+        }
+        self.events.is_some()
+    }
+
+    // the event is only built when a sink is attached
+    fn emit(&mut self, event: impl FnOnce() -> ExecutionEvent) {
+        if !self.observed() {
+            return;
+        }
+        let event = event();
+        #[cfg(feature = "std")]
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&event);
+        }
+        if let Some(sink) = self.events.as_mut() {
+            sink(event);
+        }
+    }
+
+    fn write_register(&mut self, index: usize, value: i64) {
+        if !self.watches.is_empty() {
+            self.note_write(Watch::Register(index), self.registers[index], value);
+        }
+        self.registers[index] = value;
+        self.emit(|| ExecutionEvent::RegisterWrite { reg: index, value });
+    }
+
+    // pc as `name+offset (pc)` when a symbol covers it, plain number otherwise,
+    // followed by `(file:line)` when the program carries debug info
+    fn describe_pc(&self, pc: usize) -> String {
+        let symbol = self.symbols.iter()
+            .filter(|(_, entry)| *entry <= pc)
+            .max_by_key(|(_, entry)| *entry);
+        let location = match symbol {
+            Some((name, entry)) => format!("{}+{} ({})", name, pc - entry, pc),
+            None => pc.to_string(),
+        };
+        match self.debug_info.as_ref().and_then(|debug_info| debug_info.location(pc)) {
+            Some(source) => format!("{} ({})", location, source),
+            None => location,
+        }
+    }
+
+    // a superinstruction runs as one step only when nothing watches the instructions inside it and it
+    // doesn't straddle the step budget or a stop flag check
+    fn fusion_allowed(&self, width: usize, budget: Option<usize>) -> bool {
+        let interval = self.config.stop_check_interval.max(1);
+        self.hooks.is_none()
+            && !self.observed()
+            && self.fuel.is_none()
+            && self.profile.is_none()
+            && self.coverage.is_none()
+            && self.breakpoints.is_empty()
+            && self.trace.is_none()
+            && self.history.is_none()
+            && budget.is_none_or(|budget| width <= budget)
+            && interval - self.steps % interval >= width as u64
+    }
+
+    // the fast path for a fused sequence. false when it would fail part way (short stack, checked overflow),
+    // the caller then runs the plain Ops so the error comes out exactly as it would unfused
+    fn execute_fused(&mut self, fused: Fused) -> bool {
+        match fused {
+            Fused::PushAdd(value) | Fused::PushSub(value) => {
+                let Some(&a) = self.stack.last() else {
+                    return false;
+                };
+                let result = match fused {
+                    Fused::PushAdd(_) => self.arith(OpCode::Add, a, value, i64::wrapping_add, i64::checked_add, i64::saturating_add),
+                    _ => self.arith(OpCode::Sub, a, value, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub),
+                };
+                let Ok(result) = result else {
+                    return false;
+                };
+                *self.stack.last_mut().unwrap() = result;
+                self.pc += 2;
+            },
+            Fused::LoadRegPushJumpEq { reg, value, target } => {
+                self.pc = if self.registers[reg] == value { target } else { self.pc + 3 };
+            },
+        }
+
+        true
+    }
+
+    // execute_ix for a program that passed validate. Operand counts, register indices and jump targets are
+    // known good, so the hot opcodes go straight to work and everything else takes the checked path
+    fn execute_verified(&mut self, instruction: Op) -> Result<StepResult, VmError> {
+        let (opcode, operands) = (instruction.opcode, instruction.operands());
+        match opcode {
+            OpCode::Push => self.stack.push(operands[0]),
+            OpCode::LoadReg => self.stack.push(self.registers[operands[0] as usize]),
+            OpCode::StoreReg => {
+                let value = self.pop(opcode)?;
+                self.write_register(operands[0] as usize, value);
+            },
+            OpCode::IncReg | OpCode::DecReg => {
+                let reg_idx = operands[0] as usize;
+                let delta = if opcode == OpCode::IncReg { 1 } else { -1 };
+                self.write_register(reg_idx, self.registers[reg_idx].wrapping_add(delta));
+            },
+            OpCode::Jump => {
+                self.pc = operands[0] as usize;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::JumpEq | OpCode::JumpNe | OpCode::JumpLt | OpCode::JumpGt | OpCode::JumpLe | OpCode::JumpGe => {
+                let [a, b] = self.pop_args(opcode)?;
+                let taken = match opcode {
+                    OpCode::JumpEq => a == b,
+                    OpCode::JumpNe => a != b,
+                    OpCode::JumpLt => a < b,
+                    OpCode::JumpGt => a > b,
+                    OpCode::JumpLe => a <= b,
+                    _ => a >= b,
+                };
+                self.pc = if taken { operands[0] as usize } else { self.pc + 1 };
+                return Ok(StepResult::Continue);
+            },
+            OpCode::JumpZero | OpCode::JumpNotZero => {
+                let value = self.pop(opcode)?;
+                let taken = (value == 0) == (opcode == OpCode::JumpZero);
+                self.pc = if taken { operands[0] as usize } else { self.pc + 1 };
+                return Ok(StepResult::Continue);
+            },
+            _ => return self.execute_ix(instruction),
+        }
+
+        self.pc += 1;
+        Ok(StepResult::Continue)
+    }
+
+    fn execute_ix(&mut self, instruction: Op) -> Result<StepResult, VmError> {
+        match instruction.opcode {
+            OpCode::Push => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an operand" });
+                }
+                self.stack.push(instruction.operands()[0]);
+                self.pc += 1;
+            },
+            OpCode::Pop => {
+                self.pop(instruction.opcode)?;
+                self.pc += 1;
+            }
+            OpCode::Dup => {
+                let [top] = self.pop_args(instruction.opcode)?;
+                self.stack.extend([top, top]);
+                self.pc += 1;
+            },
+            OpCode::Swap => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: 2, found: len });
+                }
+                self.stack.swap(len - 1, len - 2);
+                self.pc += 1;
+            },
+            OpCode::Over => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: 2, found: len });
+                }
+                self.stack.push(self.stack[len - 2]);
+                self.pc += 1;
+            },
+            OpCode::Rot => {
+                let len = self.stack.len();
+                if len < 3 {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: 3, found: len });
+                }
+                // third from top moves to the top
+                self.stack[len - 3..].rotate_left(1);
+                self.pc += 1;
+            },
+            OpCode::Pick => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a depth operand" });
+                }
+                let n = instruction.operands()[0];
+                let len = self.stack.len();
+                if n < 0 {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: n });
+                }
+                if n as usize >= len {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: (n as usize).saturating_add(1), found: len });
+                }
+                self.stack.push(self.stack[len - 1 - n as usize]);
+                self.pc += 1;
+            },
+            OpCode::Add => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                let result = self.arith(OpCode::Add, a, b, i64::wrapping_add, i64::checked_add, i64::saturating_add)?;
+                self.stack.push(result);
+
+                self.pc += 1;
+            },
+            OpCode::Sub => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                let result = self.arith(OpCode::Sub, a, b, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub)?;
+                self.stack.push(result);
+                self.pc += 1;
+            },          
+            OpCode::Mul => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                let result = self.arith(OpCode::Mul, a, b, i64::wrapping_mul, i64::checked_mul, i64::saturating_mul)?;
+                self.stack.push(result);
+                self.pc += 1;
+            },            
+            OpCode::Div => {
+                let b = self.pop(instruction.opcode)?;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero { pc: self.pc });
+                }
+                let a = self.pop(instruction.opcode)?;
+                // i64::MIN / -1 doesn't fit, checked_div catches it instead of panicking
+                let result = a.checked_div(b).ok_or_else(|| self.overflow(instruction.opcode, &[a, b]))?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::Mod => {
+                let b = self.pop(instruction.opcode)?;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero { pc: self.pc });
+                }
+                let a = self.pop(instruction.opcode)?;
+                // same sign rules as rust's % -> result takes the sign of a
+                let result = a.checked_rem(b).ok_or_else(|| self.overflow(instruction.opcode, &[a, b]))?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::MulHi => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(((a as i128 * b as i128) >> 64) as i64);
+                self.pc += 1;
+            },
+            OpCode::MulHiU => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(((a as u64 as u128 * b as u64 as u128) >> 64) as i64);
+                self.pc += 1;
+            },
+            OpCode::AddImm => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an immediate operand" });
+                }
+                let a = self.pop(instruction.opcode)?;
+                let result = self.arith(OpCode::AddImm, a, instruction.operands()[0], i64::wrapping_add, i64::checked_add, i64::saturating_add)?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::SubImm => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an immediate operand" });
+                }
+                let a = self.pop(instruction.opcode)?;
+                let result = self.arith(OpCode::SubImm, a, instruction.operands()[0], i64::wrapping_sub, i64::checked_sub, i64::saturating_sub)?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::MulImm => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an immediate operand" });
+                }
+                let a = self.pop(instruction.opcode)?;
+                let result = self.arith(OpCode::MulImm, a, instruction.operands()[0], i64::wrapping_mul, i64::checked_mul, i64::saturating_mul)?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::Neg => {
+                let a = self.pop(instruction.opcode)?;
+                // i64::MIN has no positive counterpart
+                let result = a.checked_neg().ok_or_else(|| self.overflow(instruction.opcode, &[a]))?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::Abs => {
+                let a = self.pop(instruction.opcode)?;
+                let result = a.checked_abs().ok_or_else(|| self.overflow(instruction.opcode, &[a]))?;
+                self.stack.push(result);
+                self.pc += 1;
+            },
+            OpCode::Min => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(a.min(b));
+                self.pc += 1;
+            },
+            OpCode::Max => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(a.max(b));
+                self.pc += 1;
+            },
+
+            // bitwise operations
+            OpCode::And => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(a & b);
+                self.pc += 1;
+            },
+            OpCode::Or => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(a | b);
+                self.pc += 1;
+            },
+            OpCode::Xor => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push(a ^ b);
+                self.pc += 1;
+            },
+            OpCode::Not => {
+                // bitwise complement, the sign bit flips too
+                let a = self.pop(instruction.opcode)?;
+                self.stack.push(!a);
+                self.pc += 1;
+            },
+
+            // shift operations, amount is on top of the value
+            OpCode::Shl => {
+                let [value, amount] = self.pop_args(instruction.opcode)?;
+                if !(0..64).contains(&amount) {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: amount });
+                }
+                self.stack.push(value << amount);
+                self.pc += 1;
+            },
+            OpCode::Shr => {
+                let [value, amount] = self.pop_args(instruction.opcode)?;
+                if !(0..64).contains(&amount) {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: amount });
+                }
+                // shift as u64 so zeros come in from the left
+                self.stack.push(((value as u64) >> amount) as i64);
+                self.pc += 1;
+            },
+            OpCode::Sar => {
+                let [value, amount] = self.pop_args(instruction.opcode)?;
+                if !(0..64).contains(&amount) {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: amount });
+                }
+                self.stack.push(value >> amount);
+                self.pc += 1;
+            },
+
+            // comparison operations
+            OpCode::Eq => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a == b) as i64);
+                self.pc += 1;
+            },
+            OpCode::Ne => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a != b) as i64);
+                self.pc += 1;
+            },
+            OpCode::Lt => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a < b) as i64);
+                self.pc += 1;
+            },
+            OpCode::Le => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a <= b) as i64);
+                self.pc += 1;
+            },
+            OpCode::Gt => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a > b) as i64);
+                self.pc += 1;
+            },
+            OpCode::Ge => {
+                let [a, b] = self.pop_args(instruction.opcode)?;
+                self.stack.push((a >= b) as i64);
+                self.pc += 1;
+            },
+
+            OpCode::DivU | OpCode::ModU => {
+                let b = self.pop(instruction.opcode)?;
+                if b == 0 {
+                    return Err(VmError::DivisionByZero { pc: self.pc });
+                }
+                let a = self.pop(instruction.opcode)?;
+                // no overflow case unsigned, u64::MAX / 1 fits
+                let (a, b) = (a as u64, b as u64);
+                self.stack.push(if instruction.opcode == OpCode::DivU { a / b } else { a % b } as i64);
+                self.pc += 1;
+            },
+            OpCode::LtU | OpCode::LeU | OpCode::GtU | OpCode::GeU => {
+                let [a, b] = self.pop_args(instruction.opcode)?.map(|value| value as u64);
+                let result = match instruction.opcode {
+                    OpCode::LtU => a < b,
+                    OpCode::LeU => a <= b,
+                    OpCode::GtU => a > b,
+                    _ => a >= b,
+                };
+                self.stack.push(result as i64);
+                self.pc += 1;
+            },
+            OpCode::Select => {
+                let [a, b, condition] = self.pop_args(instruction.opcode)?;
+                self.stack.push(if condition != 0 { a } else { b });
+                self.pc += 1;
+            },
+
+            // floats, bit patterns in and out
+            OpCode::FPush => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a float operand" });
+                }
+                self.stack.push(instruction.operands()[0]);
+                self.pc += 1;
+            },
+            OpCode::FAdd | OpCode::FSub | OpCode::FMul | OpCode::FDiv => {
+                let [a, b] = self.pop_args(instruction.opcode)?.map(|value| f64::from_bits(value as u64));
+                let result = match instruction.opcode {
+                    OpCode::FAdd => a + b,
+                    OpCode::FSub => a - b,
+                    OpCode::FMul => a * b,
+                    _ => a / b,
+                };
+                self.stack.push(result.to_bits() as i64);
+                self.pc += 1;
+            },
+            OpCode::FEq | OpCode::FLt | OpCode::FGt => {
+                // IEEE comparisons, so NaN is unequal and unordered against everything
+                let [a, b] = self.pop_args(instruction.opcode)?.map(|value| f64::from_bits(value as u64));
+                let result = match instruction.opcode {
+                    OpCode::FEq => a == b,
+                    OpCode::FLt => a < b,
+                    _ => a > b,
+                };
+                self.stack.push(result as i64);
+                self.pc += 1;
+            },
+            OpCode::IntToFloat => {
+                let a = self.pop(instruction.opcode)?;
+                self.stack.push((a as f64).to_bits() as i64);
+                self.pc += 1;
+            },
+            OpCode::FloatToInt => {
+                let bits = self.pop(instruction.opcode)?;
+                let a = f64::from_bits(bits as u64);
+                // i64::MIN is exactly -2^63, and 2^63 is the first value past i64::MAX
+                let fits = (-9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0).contains(&a);
+                if !fits && self.config.arith_mode == ArithMode::Checked {
+                    return Err(self.overflow(instruction.opcode, &[bits]));
+                }
+                // `as` truncates and saturates, NaN becomes 0
+                self.stack.push(a as i64);
+                self.pc += 1;
+            },
+
+            //register operations
+            OpCode::LoadReg => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a register index operand" });
+                }
+                let reg_idx = self.operand_index(&instruction, 0)?;
+                if reg_idx >= self.registers.len() {
+                    return Err(VmError::InvalidRegister { pc: self.pc, index: reg_idx as i64 });
+                }
+                self.stack.push(self.registers[reg_idx]);
+                self.pc += 1;
+            },
+            OpCode::StoreReg => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a register index operand" });
+                }
+                let reg_idx = self.operand_index(&instruction, 0)?;
+                if reg_idx >= self.registers.len() {
+                    return Err(VmError::InvalidRegister { pc: self.pc, index: reg_idx as i64 });
+                }
+                // fixed unreacheable bug
+                let value = self.pop(instruction.opcode)?;
+                self.write_register(reg_idx, value);
+                self.pc += 1;
+            },
+            OpCode::AddReg | OpCode::SubReg | OpCode::MulReg | OpCode::DivReg => {
+                if instruction.operands().len() < 3 {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "dst, a and b register operands" });
+                }
+                let dst = self.register_operand(&instruction, 0)?;
+                let a = self.registers[self.register_operand(&instruction, 1)?];
+                let b = self.registers[self.register_operand(&instruction, 2)?];
+
+                let value = match instruction.opcode {
+                    OpCode::AddReg => self.arith(OpCode::AddReg, a, b, i64::wrapping_add, i64::checked_add, i64::saturating_add)?,
+                    OpCode::SubReg => self.arith(OpCode::SubReg, a, b, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub)?,
+                    OpCode::MulReg => self.arith(OpCode::MulReg, a, b, i64::wrapping_mul, i64::checked_mul, i64::saturating_mul)?,
+                    _ => {
+                        if b == 0 {
+                            return Err(VmError::DivisionByZero { pc: self.pc });
+                        }
+                        a.checked_div(b).ok_or_else(|| self.overflow(instruction.opcode, &[a, b]))?
+                    }
+                };
+                self.write_register(dst, value);
+                self.pc += 1;
+            },
+            OpCode::MovReg => {
+                if instruction.operands().len() < 2 {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "dst and src register operands" });
+                }
+                let dst = self.register_operand(&instruction, 0)?;
+                let src = self.register_operand(&instruction, 1)?;
+                self.write_register(dst, self.registers[src]);
+                self.pc += 1;
+            },
+            OpCode::IncReg | OpCode::DecReg => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a register index operand" });
+                }
+                let reg_idx = self.register_operand(&instruction, 0)?;
+                // counters wrap around instead of trapping
+                let delta = if matches!(instruction.opcode, OpCode::IncReg) { 1 } else { -1 };
+                self.write_register(reg_idx, self.registers[reg_idx].wrapping_add(delta));
+                self.pc += 1;
+            },
+            //control flow
+            OpCode::Jump => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                self.pc = target;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::JumpEq => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a == b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpGt => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a > b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpLt => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a < b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpNe => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a != b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpGe => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a >= b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpLe => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                if a <= b {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpZero => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let value = self.pop(instruction.opcode)?;
+
+                if value == 0 {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpNotZero => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a target address operand" });
+                }
+
+                let target = self.operand_index(&instruction, 0)?;
+                if target >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: target as i64 });
+                }
+
+                let value = self.pop(instruction.opcode)?;
+
+                if value != 0 {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::JumpRel => {
+                self.pc = self.relative_target(&instruction)?;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::JumpRelEq | OpCode::JumpRelNe | OpCode::JumpRelGt | OpCode::JumpRelLt | OpCode::JumpRelGe | OpCode::JumpRelLe => {
+                let target = self.relative_target(&instruction)?;
+
+                let [a, b] = self.pop_args(instruction.opcode)?;
+
+                let taken = match instruction.opcode {
+                    OpCode::JumpRelEq => a == b,
+                    OpCode::JumpRelNe => a != b,
+                    OpCode::JumpRelGt => a > b,
+                    OpCode::JumpRelLt => a < b,
+                    OpCode::JumpRelGe => a >= b,
+                    _ => a <= b,
+                };
+
+                if taken {
+                    self.pc = target;
+                    return Ok(StepResult::Continue);
+                }
+
+                self.pc += 1;
+            },
+            OpCode::Switch => {
+                // the case table can be any length so it's read from the program rather than the Op
+                let table = &self.program[self.pc].operands;
+                if table.is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "at least a default target operand" });
+                }
+                if let Some(&target) = table.iter().find(|&&target| target < 0 || target as usize >= self.program.len()) {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target });
+                }
+
+                let index = self.pop(instruction.opcode)?;
+                let (default, cases) = self.program[self.pc].operands.split_last().unwrap();
+
+                // anything outside the case table goes to the default
+                let target = if index >= 0 && (index as usize) < cases.len() {
+                    cases[index as usize]
+                } else {
+                    *default
+                };
+
+                self.pc = target as usize;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::JumpDyn => {
+                let target = self.pop(instruction.opcode)?;
+                if target < 0 || target as usize >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target });
+                }
+
+                self.pc = target as usize;
+                return Ok(StepResult::Continue);
+            },
+            // fn management
+            OpCode::Call => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a function address operand" });
+                }
+                let func_addr = self.operand_index(&instruction, 0)?;
+                if func_addr >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: func_addr as i64 });
+                }
+                let locals = match instruction.operands().get(1) {
+                    Some(_) => self.local_count(&instruction, 1)?,
+                    None => 0,
+                };
+                // save return address -> next ix after call
+                self.push_frame(Frame { return_addr: self.pc + 1, locals: vec![0; locals], stack_base: self.stack.len() }, func_addr)?;
+
+                //Jump to fn
+                self.pc = func_addr;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::CallIndirect => {
+                let func_addr = self.pop(instruction.opcode)?;
+                if func_addr < 0 || func_addr as usize >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: func_addr });
+                }
+                self.push_frame(Frame { return_addr: self.pc + 1, locals: Vec::new(), stack_base: self.stack.len() }, func_addr as usize)?;
+
+                self.pc = func_addr as usize;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::TailCall => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a function address operand" });
+                }
+                let func_addr = self.operand_index(&instruction, 0)?;
+                if func_addr >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: func_addr as i64 });
+                }
+                // fresh locals for the callee when asked for, the return address stays as is
+                if instruction.operands().len() > 1 {
+                    let locals = self.local_count(&instruction, 1)?;
+                    if let Some(frame) = self.call_stack.last_mut() {
+                        frame.locals.clear();
+                        frame.locals.resize(locals, 0);
+                    }
+                }
+                // to the hooks the replaced function returns and the target is called in its place
+                if let (Some(hooks), Some(frame)) = (self.hooks.as_mut(), self.call_stack.last()) {
+                    hooks.on_return(self.pc, frame.return_addr);
+                    hooks.on_call(self.pc, func_addr);
+                }
+
+                self.pc = func_addr;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::CallN => {
+                if instruction.operands().len() < 2 {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a function address and an argument count operand" });
+                }
+                let func_addr = self.operand_index(&instruction, 0)?;
+                if func_addr >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target: func_addr as i64 });
+                }
+                let argc = self.operand_index(&instruction, 1)?;
+                if argc > self.stack.len() {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: argc, found: self.stack.len() });
+                }
+                // first pushed argument ends up in local 0
+                let locals = self.stack.split_off(self.stack.len() - argc);
+                self.push_frame(Frame { return_addr: self.pc + 1, locals, stack_base: self.stack.len() }, func_addr)?;
+
+                self.pc = func_addr;
+                return Ok(StepResult::Continue);
+            },
+            OpCode::Return => {
+                // every check runs before the frame is popped, so a failed Return leaves the call stack intact
+                if self.call_stack.is_empty() {
+                    return Err(VmError::CallStackUnderflow { pc: self.pc });
+                }
+                let count = instruction.operands().first().copied();
+                if let Some(count) = count.filter(|count| !(0..=1).contains(count)) {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: count });
+                }
+                let value = if count == Some(1) {
+                    Some(self.pop(instruction.opcode)?)
+                } else {
+                    None
+                };
+                let frame = self.call_stack.pop().ok_or(VmError::CallStackUnderflow { pc: self.pc })?;
+                if count.is_some() {
+                    self.stack.truncate(frame.stack_base);
+                    self.stack.extend(value);
+                }
+                if let Some(hooks) = self.hooks.as_mut() {
+                    hooks.on_return(self.pc, frame.return_addr);
+                }
+                let (pc, return_addr) = (self.pc, frame.return_addr);
+                self.emit(|| ExecutionEvent::Return { pc, return_addr });
+                self.pc = frame.return_addr;
+
+                return Ok(StepResult::Continue);
+            },
+            OpCode::TryPush => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a handler operand" });
+                }
+                let target = instruction.operands()[0];
+                if target < 0 || target as usize >= self.program.len() {
+                    return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target });
+                }
+                self.handlers.push(TryHandler { pc: target as usize, stack_depth: self.stack.len(), call_depth: self.call_stack.len() });
+
+                self.pc += 1;
+            },
+            OpCode::TryPop => {
+                self.handlers.pop().ok_or(VmError::TryStackUnderflow { pc: self.pc })?;
+
+                self.pc += 1;
+            },
+            OpCode::Throw => {
+                let code = self.pop(instruction.opcode)?;
+                // handlers pushed by frames that have since returned no longer apply
+                let handler = loop {
+                    match self.handlers.pop() {
+                        Some(handler) if handler.call_depth > self.call_stack.len() => continue,
+                        Some(handler) => break handler,
+                        None => return Err(VmError::UncaughtGuestException { code, pc: self.pc }),
+                    }
+                };
+                // unwound frames return to the hooks and event sinks like any other
+                while self.call_stack.len() > handler.call_depth {
+                    let frame = self.call_stack.pop().expect("deeper than the handler");
+                    if let Some(hooks) = self.hooks.as_mut() {
+                        hooks.on_return(self.pc, frame.return_addr);
+                    }
+                    let (pc, return_addr) = (self.pc, frame.return_addr);
+                    self.emit(|| ExecutionEvent::Return { pc, return_addr });
+                }
+                self.stack.truncate(handler.stack_depth);
+                self.stack.push(code);
+                self.pc = handler.pc;
+
+                return Ok(StepResult::Continue);
+            },
+            OpCode::Enter => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a local count operand" });
+                }
+                let count = self.local_count(&instruction, 0)?;
+                let frame = self.call_stack.last_mut().ok_or(VmError::NoFrame { pc: self.pc, opcode: instruction.opcode })?;
+                frame.locals.resize(count, 0);
+
+                self.pc += 1;
+            },
+            OpCode::LoadLocal => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a local index operand" });
+                }
+                let index = self.operand_index(&instruction, 0)?;
+                let frame = self.call_stack.last().ok_or(VmError::NoFrame { pc: self.pc, opcode: instruction.opcode })?;
+                let value = *frame.locals.get(index)
+                    .ok_or(VmError::InvalidLocal { pc: self.pc, index, locals: frame.locals.len() })?;
+                self.stack.push(value);
+
+                self.pc += 1;
+            },
+            OpCode::StoreLocal => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a local index operand" });
+                }
+                let index = self.operand_index(&instruction, 0)?;
+                let value = self.pop(instruction.opcode)?;
+                let frame = self.call_stack.last_mut().ok_or(VmError::NoFrame { pc: self.pc, opcode: instruction.opcode })?;
+                let len = frame.locals.len();
+                let slot = frame.locals.get_mut(index)
+                    .ok_or(VmError::InvalidLocal { pc: self.pc, index, locals: len })?;
+                *slot = value;
+
+                self.pc += 1;
+            },
+            // mem ops
+            OpCode::Load => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an address operand" });
+                }
+                let addr = self.memory_operand(&instruction)?;
+                let value = self.read_memory(addr)?;
+                self.stack.push(value);
+
+                self.pc += 1;
+            },
+            OpCode::Store => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an address operand" });
+                }
+                let addr = self.memory_operand(&instruction)?;
+
+                let value = self.pop(instruction.opcode)?;
+                self.write_memory(addr, value)?;
+
+                self.pc += 1;
+            },
+            OpCode::LoadInd => {
+                let addr = self.pop(instruction.opcode)?;
+                if addr < 0 {
+                    return Err(VmError::InvalidAddress { pc: self.pc, opcode: instruction.opcode, addr });
+                }
+                let value = self.read_memory(addr as usize)?;
+                self.stack.push(value);
+
+                self.pc += 1;
+            },
+            OpCode::StoreInd => {
+                let addr = self.pop(instruction.opcode)?;
+                if addr < 0 {
+                    return Err(VmError::InvalidAddress { pc: self.pc, opcode: instruction.opcode, addr });
+                }
+                let value = self.pop(instruction.opcode)?;
+                self.write_memory(addr as usize, value)?;
+
+                self.pc += 1;
+            },
+            OpCode::MemSet => {
+                let [dst, value, count] = self.pop_args(instruction.opcode)?;
+                let dst = self.memory_region(instruction.opcode, dst, count)?;
+
+                if self.overlaps_mapped(&dst) {
+                    self.write_cells_mapped(dst, core::iter::repeat(Some(value)))?;
+                } else {
+                    self.check_writable(dst.clone())?;
+                    // at least this many cells are new, fail before counting them one by one if that's already too many
+                    self.check_memory_growth(dst.len().saturating_sub(self.memory.len()), dst.start)?;
+                    let new_cells = dst.clone().filter(|addr| !self.memory.contains_key(addr)).count();
+                    self.check_memory_growth(new_cells, dst.start)?;
+                    for addr in dst {
+                        self.note_memory_write(addr, value);
+                        self.memory.insert(addr, value);
+                        self.emit(|| ExecutionEvent::MemoryWrite { addr, value });
+                    }
+                }
+
+                self.pc += 1;
+            },
+            OpCode::MemCpy => {
+                let [dst, src, count] = self.pop_args(instruction.opcode)?;
+                let src = self.memory_region(instruction.opcode, src, count)?;
+                let dst = self.memory_region(instruction.opcode, dst, count)?;
+
+                // read the whole source first so overlapping copies act like memmove, mapped cells through read_memory
+                let values: Vec<Option<i64>> = if self.overlaps_mapped(&src) {
+                    let mut values = Vec::with_capacity(src.len());
+                    for addr in src {
+                        values.push(if self.is_mapped(addr) { Some(self.read_memory(addr)?) } else { self.memory.get(&addr).copied() });
+                    }
+                    values
+                } else {
+                    src.map(|addr| self.memory.get(&addr).copied()).collect()
+                };
+
+                if self.overlaps_mapped(&dst) {
+                    self.write_cells_mapped(dst, values.into_iter())?;
+                } else {
+                    self.check_writable(dst.clone())?;
+                    let new_cells = dst.clone().zip(&values)
+                        .filter(|(addr, value)| value.is_some() && !self.memory.contains_key(addr))
+                        .count();
+                    self.check_memory_growth(new_cells, dst.start)?;
+                    for (addr, value) in dst.zip(values) {
+                        // unwritten source cells read as 0, so the destination becomes unwritten too
+                        self.note_memory_write(addr, value.unwrap_or(0));
+                        match value {
+                            Some(value) => self.memory.insert(addr, value),
+                            None => self.memory.remove(&addr),
+                        };
+                        self.emit(|| ExecutionEvent::MemoryWrite { addr, value: value.unwrap_or(0) });
+                    }
+                }
+
+                self.pc += 1;
+            },
+            OpCode::Alloc => {
+                let size = self.pop(instruction.opcode)?;
+                if size <= 0 {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: size });
+                }
+                let base = self.allocate(size as usize)?;
+                self.stack.push(base as i64);
+
+                self.pc += 1;
+            },
+            OpCode::Free => {
+                let base = self.pop(instruction.opcode)?;
+                if base < 0 || self.allocations.remove(&(base as usize)).is_none() {
+                    return Err(VmError::InvalidFree { pc: self.pc, addr: base });
+                }
+
+                self.pc += 1;
+            },
+            OpCode::PushM => {
+                if self.config.memory_stack.is_none() {
+                    return Err(VmError::MemoryStackDisabled { pc: self.pc, opcode: instruction.opcode });
+                }
+                let value = self.pop(instruction.opcode)?;
+                let sp = self.registers[SP_REGISTER].checked_sub(1).filter(|sp| *sp >= 0)
+                    .ok_or(VmError::MemoryStackOverflow { pc: self.pc, sp: self.registers[SP_REGISTER] })?;
+                self.write_memory(sp as usize, value)?;
+                self.write_register(SP_REGISTER, sp);
+
+                self.pc += 1;
+            },
+            OpCode::PopM => {
+                if self.config.memory_stack.is_none() {
+                    return Err(VmError::MemoryStackDisabled { pc: self.pc, opcode: instruction.opcode });
+                }
+                let sp = self.registers[SP_REGISTER];
+                if sp < 0 {
+                    return Err(VmError::InvalidAddress { pc: self.pc, opcode: instruction.opcode, addr: sp });
+                }
+                let value = self.read_memory(sp as usize)?;
+                self.write_register(SP_REGISTER, sp.wrapping_add(1));
+                self.stack.push(value);
+
+                self.pc += 1;
+            },
+            OpCode::Load8 | OpCode::Load16 | OpCode::Load32 | OpCode::Load64 => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an address operand" });
+                }
+                let width = access_width(instruction.opcode);
+                let range = self.linear_range(&instruction, width)?;
+
+                let mut bytes = [0u8; 8];
+                bytes[..width].copy_from_slice(&self.linear[range]);
+                // shift up and back down to sign-extend the narrow value
+                let shift = 64 - 8 * width as u32;
+                self.stack.push((i64::from_le_bytes(bytes) << shift) >> shift);
+
+                self.pc += 1;
+            },
+            OpCode::Store8 | OpCode::Store16 | OpCode::Store32 | OpCode::Store64 => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an address operand" });
+                }
+                let width = access_width(instruction.opcode);
+                let range = self.linear_range(&instruction, width)?;
+
+                let value = self.pop(instruction.opcode)?;
+                // truncates to the low bytes
+                self.linear[range].copy_from_slice(&value.to_le_bytes()[..width]);
+
+                self.pc += 1;
+            },
+            OpCode::AtomicAdd | OpCode::AtomicCas => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an address operand" });
+                }
+                let addr = self.operand_index(&instruction, 0)?;
+                let (base, memory) = self.shared.iter().find(|(range, _)| range.contains(&addr))
+                    .map(|(range, memory)| (range.start, memory.clone()))
+                    .ok_or(VmError::NotShared { pc: self.pc, opcode: instruction.opcode, addr })?;
+                let old = if instruction.opcode == OpCode::AtomicAdd {
+                    let delta = self.pop(instruction.opcode)?;
+                    memory.fetch_add(addr - base, delta)
+                } else {
+                    let [expected, new] = self.pop_args(instruction.opcode)?;
+                    memory.compare_exchange(addr - base, expected, new)
+                };
+                self.stack.push(old);
+
+                self.pc += 1;
+            },
+            OpCode::Send => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a channel operand" });
+                }
+                let channel = instruction.operands()[0];
+                let end = self.senders.get(&channel)
+                    .ok_or(VmError::UnknownChannel { pc: self.pc, opcode: instruction.opcode, channel })?;
+                let &value = self.stack.last()
+                    .ok_or(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: 1, found: 0 })?;
+                if !end.channel.try_send(value) {
+                    return Ok(StepResult::Blocked);
+                }
+                self.stack.pop();
+
+                self.pc += 1;
+            },
+            OpCode::Recv => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a channel operand" });
+                }
+                let channel = instruction.operands()[0];
+                let queue = self.receivers.get(&channel)
+                    .ok_or(VmError::UnknownChannel { pc: self.pc, opcode: instruction.opcode, channel })?;
+                match queue.try_recv() {
+                    Ok(Some(value)) => self.stack.push(value),
+                    Ok(None) => return Ok(StepResult::Blocked),
+                    Err(()) => return Err(VmError::ChannelClosed { pc: self.pc, channel }),
+                }
+
+                self.pc += 1;
+            },
+            OpCode::Syscall => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a syscall number operand" });
+                }
+                let n = instruction.operands()[0];
+                let argc = match instruction.operands().get(1) {
+                    Some(_) => self.operand_index(&instruction, 1)?,
+                    None => 0,
+                };
+                if !self.host_fns.contains_key(&n) {
+                    return Err(VmError::UnknownSyscall { pc: self.pc, number: n });
+                }
+                if argc > self.stack.len() {
+                    return Err(VmError::StackUnderflow { pc: self.pc, opcode: instruction.opcode, needed: argc, found: self.stack.len() });
+                }
+                let mut args = self.stack.split_off(self.stack.len() - argc);
+                let host_fn = self.host_fns.get_mut(&n).unwrap();
+                let pc = self.pc;
+                let result = host_fn(&mut args).map_err(|message| VmError::Syscall { pc, number: n, message })?;
+                self.stack.push(result);
+
+                self.pc += 1;
+            },
+            OpCode::Trap => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a trap code operand" });
+                }
+                let (pc, code) = (self.pc, instruction.operands()[0]);
+                // out of the map while it runs, the handler borrows the whole Context
+                let mut handler = self.trap_handlers.remove(&code).ok_or(VmError::UnhandledTrap { pc, code })?;
+                let outcome = handler(self, code);
+                // unless it registered a replacement for itself
+                self.trap_handlers.entry(code).or_insert(handler);
+                // the outcome decides where to go, not a pc the handler set
+                self.pc = pc;
+                match outcome {
+                    TrapOutcome::Resume => self.pc = pc + 1,
+                    TrapOutcome::ResumeAt(target) if target < self.program.len() => self.pc = target,
+                    TrapOutcome::ResumeAt(target) => {
+                        return Err(VmError::JumpOutOfBounds { pc, opcode: instruction.opcode, target: target as i64 });
+                    },
+                    TrapOutcome::Abort(error) => return Err(error),
+                }
+                return Ok(StepResult::Continue);
+            },
+            OpCode::Print => {
+                let value = self.pop(instruction.opcode)?;
+                self.output.write_text(&format!("{}\n", value)).map_err(|e| self.io_error(instruction.opcode, e))?;
+
+                self.pc += 1;
+            },
+            OpCode::FPrint => {
+                let value = f64::from_bits(self.pop(instruction.opcode)? as u64);
+                self.output.write_text(&format!("{}\n", value)).map_err(|e| self.io_error(instruction.opcode, e))?;
+
+                self.pc += 1;
+            },
+            OpCode::PrintChar => {
+                let value = self.pop(instruction.opcode)?;
+                let c = u32::try_from(value).ok().and_then(char::from_u32)
+                    .ok_or(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value })?;
+                self.output.write_text(c.encode_utf8(&mut [0; 4])).map_err(|e| self.io_error(instruction.opcode, e))?;
+
+                self.pc += 1;
+            },
+            OpCode::PrintStr => {
+                if instruction.operands().is_empty() {
+                    return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "a string index operand" });
+                }
+                let index = instruction.operands()[0];
+                let text = usize::try_from(index).ok().and_then(|index| self.strings.get(index))
+                    .ok_or(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: index })?;
+                self.output.write_text(text).map_err(|message| VmError::Io { pc: self.pc, opcode: instruction.opcode, message })?;
+
+                self.pc += 1;
+            },
+            OpCode::Read => {
+                match self.input.next_value().map_err(|message| self.io_error(instruction.opcode, message))? {
+                    Some(value) => {
+                        self.stack.push(value);
+                        self.stack.push(1);
+                    },
+                    None => self.stack.push(0),
+                }
+
+                self.pc += 1;
+            },
+            OpCode::Yield => {
+                let value = match instruction.operands().first() {
+                    Some(1) => self.pop(instruction.opcode)?,
+                    Some(&n) if n != 0 => return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: n }),
+                    _ => 0,
+                };
+                // step past so resuming doesn't yield again
+                self.pc += 1;
+                return Ok(StepResult::Yielded(value));
+            },
+            OpCode::Assert => {
+                // a bad index fails even when the assertion holds, the message is only copied when it doesn't
+                let message = match instruction.operands().first() {
+                    Some(&index) => Some(usize::try_from(index).ok().filter(|&index| index < self.strings.len())
+                        .ok_or(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: index })?),
+                    None => None,
+                };
+                if self.pop(instruction.opcode)? == 0 {
+                    return Err(VmError::AssertionFailed { pc: self.pc, message: message.map(|index| self.strings[index].clone()) });
+                }
+
+                self.pc += 1;
+            },
+            OpCode::GetPC | OpCode::Depth | OpCode::GetCallDepth => {
+                let value = match instruction.opcode {
+                    OpCode::GetPC => self.pc,
+                    OpCode::Depth => self.stack.len(),
+                    _ => self.call_stack.len(),
+                };
+                self.stack.push(value as i64);
+
+                self.pc += 1;
+            },
+            OpCode::Rand => {
+                let value = self.next_random();
+                self.stack.push(value as i64);
+
+                self.pc += 1;
+            },
+            OpCode::RandRange => {
+                let bound = self.pop(instruction.opcode)?;
+                if bound <= 0 {
+                    return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: bound });
+                }
+                // redraw the top sliver that would favor small values
+                let bound = bound as u64;
+                let value = loop {
+                    let bits = self.next_random();
+                    if bits - bits % bound <= u64::MAX - (bound - 1) {
+                        break bits % bound;
+                    }
+                };
+                self.stack.push(value as i64);
+
+                self.pc += 1;
+            },
+            OpCode::Steps => {
+                self.stack.push(self.steps as i64);
+
+                self.pc += 1;
+            },
+            OpCode::TimeMs => {
+                let now = match self.clock.as_mut() {
+                    Some(clock) => clock.now_ms(),
+                    None => self.started.get_or_insert_with(Stopwatch::start).elapsed().unwrap_or_default().as_millis() as i64,
+                };
+                self.stack.push(now);
+
+                self.pc += 1;
+            },
+            OpCode::Nop => {
+                self.pc += 1;
+            },
+            OpCode::Halt => {
+                let code = match instruction.operands().first() {
+                    Some(&code) => code,
+                    None => self.pop(instruction.opcode)?,
+                };
+                return Ok(StepResult::Exited(code));
+            },
+            OpCode::FExit => {
+                // the result is the bit pattern, exit_value reads it back as a float
+                let value = self.pop(instruction.opcode)?;
+                return Ok(StepResult::Exited(value));
+            },
+            OpCode::Exit => {
+                // no operand -> result is the top of stack, operand n -> result is registers[n]
+                let value = match instruction.operands().first() {
+                    Some(_) => self.registers[self.register_operand(&instruction, 0)?],
+                    None => self.pop(instruction.opcode)?,
+                };
+                return Ok(StepResult::Exited(value));
+            }
+        }
+
+        Ok(StepResult::Continue)
+    }
+
+    // the active frames, outermost first
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    // return addresses of the active frames, outermost first
+    pub fn backtrace(&self) -> Vec<usize> {
+        self.call_stack.iter().map(|frame| frame.return_addr).collect()
+    }
+
+    // all the Call variants enter frames through here so the depth limit is enforced once
+    fn push_frame(&mut self, frame: Frame, target: usize) -> Result<(), VmError> {
+        if self.call_stack.len() >= self.config.max_call_depth {
+            // innermost return addresses are the useful ones
+            let return_addrs = self.call_stack.iter().rev().take(8).map(|f| f.return_addr).collect();
+            return Err(VmError::CallStackOverflow { pc: self.pc, depth: self.call_stack.len(), return_addrs });
+        }
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_call(self.pc, target);
+        }
+        let pc = self.pc;
+        self.emit(|| ExecutionEvent::Call { pc, target });
+        self.call_stack.push(frame);
+
+        Ok(())
+    }
+
+    // apply a binary op according to the configured ArithMode
+    fn arith(
+        &self,
+        opcode: OpCode,
+        a: i64,
+        b: i64,
+        wrapping: fn(i64, i64) -> i64,
+        checked: fn(i64, i64) -> Option<i64>,
+        saturating: fn(i64, i64) -> i64,
+    ) -> Result<i64, VmError> {
+        match self.config.arith_mode {
+            ArithMode::Wrapping => Ok(wrapping(a, b)),
+            ArithMode::Checked => checked(a, b).ok_or_else(|| self.overflow(opcode, &[a, b])),
+            ArithMode::Saturating => Ok(saturating(a, b)),
+        }
+    }
+
+    // validate a register index operand
+    fn register_operand(&self, instruction: &Op, position: usize) -> Result<usize, VmError> {
+        let index = self.operand_index(instruction, position)?;
+        if index >= self.registers.len() {
+            return Err(VmError::InvalidRegister { pc: self.pc, index: index as i64 });
+        }
+
+        Ok(index)
+    }
+
+    // address for Load/Store: one operand is absolute, two operands are base register + signed offset
+    fn memory_operand(&self, instruction: &Op) -> Result<usize, VmError> {
+        if instruction.operands().len() < 2 {
+            return self.operand_index(instruction, 0);
+        }
+        let base = self.registers[self.register_operand(instruction, 0)?];
+        // an overflowing sum is reported saturated, it's out of range either way
+        let addr = base.saturating_add(instruction.operands()[1]);
+        if addr < 0 || base.checked_add(instruction.operands()[1]).is_none() {
+            return Err(VmError::InvalidAddress { pc: self.pc, opcode: instruction.opcode, addr });
+        }
+
+        Ok(addr as usize)
+    }
+
+    // first fit over the gaps between live blocks, freed space gets reused
+    fn allocate(&mut self, size: usize) -> Result<usize, VmError> {
+        let heap = self.config.heap.clone();
+        let mut candidate = heap.start;
+        for (&base, &block) in &self.allocations {
+            if base - candidate >= size {
+                break;
+            }
+            candidate = base + block;
+        }
+        if heap.end.saturating_sub(candidate) < size {
+            return Err(VmError::OutOfHeap { pc: self.pc, size, heap });
+        }
+        self.allocations.insert(candidate, size);
+
+        Ok(candidate)
+    }
+
+    // every word load goes through here so mapped regions get a look first
+    fn read_memory(&mut self, addr: usize) -> Result<i64, VmError> {
+        let pc = self.pc;
+        if let Some((_, handler)) = self.mmio.iter_mut().find(|(r, _)| r.contains(&addr)) {
+            return handler.read(addr).map_err(|message| VmError::Mmio { pc, addr, write: false, message });
+        }
+
+        Ok(*self.memory.get(&addr).unwrap_or(&0))
+    }
+
+    // every word store goes through here so the memory limit applies everywhere
+    fn write_memory(&mut self, addr: usize, value: i64) -> Result<(), VmError> {
+        let pc = self.pc;
+        if let Some((_, handler)) = self.mmio.iter_mut().find(|(r, _)| r.contains(&addr)) {
+            return handler.write(addr, value).map_err(|message| VmError::Mmio { pc, addr, write: true, message });
+        }
+
+        self.check_writable(addr..addr.saturating_add(1))?;
+        // overwriting a cell that already exists doesn't grow memory
+        if !self.memory.contains_key(&addr) {
+            self.check_memory_growth(1, addr)?;
+        }
+        self.note_memory_write(addr, value);
+        self.memory.insert(addr, value);
+        self.emit(|| ExecutionEvent::MemoryWrite { addr, value });
+
+        Ok(())
+    }
+
+    fn is_mapped(&self, addr: usize) -> bool {
+        self.mmio.iter().any(|(r, _)| r.contains(&addr))
+    }
+
+    fn overlaps_mapped(&self, region: &Range<usize>) -> bool {
+        self.mmio.iter().any(|(r, _)| region.start < r.end && r.start < region.end)
+    }
+
+    // MemSet or MemCpy into a range that touches a mapped region. Every cell goes through write_memory
+    // so handlers and shared memory see each store, in address order. The plain cells are checked up
+    // front, so protection and the memory limit still fail before anything is written
+    fn write_cells_mapped(&mut self, dst: Range<usize>, values: impl Iterator<Item = Option<i64>> + Clone) -> Result<(), VmError> {
+        for addr in dst.clone().filter(|&addr| !self.is_mapped(addr)) {
+            self.check_writable(addr..addr + 1)?;
+        }
+        let new_cells = dst.clone().zip(values.clone())
+            .filter(|&(addr, value)| value.is_some() && !self.is_mapped(addr) && !self.memory.contains_key(&addr))
+            .count();
+        self.check_memory_growth(new_cells, dst.start)?;
+        for (addr, value) in dst.zip(values) {
+            match value {
+                Some(value) => self.write_memory(addr, value)?,
+                None if self.is_mapped(addr) => self.write_memory(addr, 0)?,
+                // an unwritten source cell leaves the destination unwritten, as on the plain path
+                None => {
+                    self.note_memory_write(addr, 0);
+                    self.memory.remove(&addr);
+                    self.emit(|| ExecutionEvent::MemoryWrite { addr, value: 0 });
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // fails if any address in `region` falls in a protected range
+    fn check_writable(&self, region: Range<usize>) -> Result<(), VmError> {
+        for range in &self.protected {
+            if region.start < range.end && range.start < region.end {
+                let addr = region.start.max(range.start);
+                return Err(VmError::ProtectedWrite { pc: self.pc, addr, range: range.clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    // fails if adding `new_cells` distinct cells would go past the memory limit
+    fn check_memory_growth(&self, new_cells: usize, addr: usize) -> Result<(), VmError> {
+        if let Some(limit) = self.config.memory_limit {
+            if self.memory.len() + new_cells > limit {
+                return Err(VmError::MemoryLimit { pc: self.pc, limit, addr });
+            }
+        }
+
+        Ok(())
+    }
+
+    // bytes touched by a sized access, bounds checked against the linear memory
+    fn linear_range(&self, instruction: &Op, width: usize) -> Result<Range<usize>, VmError> {
+        let addr = self.memory_operand(instruction)?;
+        match addr.checked_add(width) {
+            Some(end) if end <= self.linear.len() => Ok(addr..end),
+            _ => Err(VmError::OutOfBounds {
+                pc: self.pc,
+                opcode: instruction.opcode,
+                addr,
+                size: width,
+                memory: self.linear.len(),
+            }),
+        }
+    }
+
+    // resolve pc + offset for the relative jumps, checked so a negative offset can't wrap around
+    fn relative_target(&self, instruction: &Op) -> Result<usize, VmError> {
+        if instruction.operands().is_empty() {
+            return Err(VmError::MissingOperand { pc: self.pc, opcode: instruction.opcode, expected: "an offset operand" });
+        }
+        let target = (self.pc as i64).saturating_add(instruction.operands()[0]);
+        if target < 0 || target as usize >= self.program.len() {
+            return Err(VmError::JumpOutOfBounds { pc: self.pc, opcode: instruction.opcode, target });
+        }
+
+        Ok(target as usize)
+    }
+
+    // read an address/index operand, negative values are rejected instead of wrapping through `as usize`
+    fn operand_index(&self, instruction: &Op, position: usize) -> Result<usize, VmError> {
+        let raw = instruction.operands()[position];
+        usize::try_from(raw).map_err(|_| VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: raw })
+    }
+
+    // a frame size operand, capped so a bad one can't ask for a huge allocation
+    fn local_count(&self, instruction: &Op, position: usize) -> Result<usize, VmError> {
+        let count = self.operand_index(instruction, position)?;
+        if count > MAX_LOCALS {
+            return Err(VmError::InvalidOperand { pc: self.pc, opcode: instruction.opcode, value: count as i64 });
+        }
+        Ok(count)
+    }
+
+    // validate a (start, count) pair popped by the bulk memory ops
+    fn memory_region(&self, opcode: OpCode, start: i64, count: i64) -> Result<Range<usize>, VmError> {
+        if count < 0 || count as u64 > MAX_BULK_CELLS as u64 {
+            return Err(VmError::InvalidOperand { pc: self.pc, opcode, value: count });
+        }
+        let end = start.saturating_add(count);
+        if start < 0 || start.checked_add(count).is_none() {
+            return Err(VmError::InvalidAddress { pc: self.pc, opcode, addr: if start < 0 { start } else { end } });
+        }
+
+        Ok(start as usize..end as usize)
+    }
+
+    // pop the top N values, deepest first, or fail without touching the stack
+    fn pop_args<const N: usize>(&mut self, opcode: OpCode) -> Result<[i64; N], VmError> {
+        let len = self.stack.len();
+        if len < N {
+            return Err(VmError::StackUnderflow { pc: self.pc, opcode, needed: N, found: len });
+        }
+        let mut values = [0; N];
+        values.copy_from_slice(&self.stack[len - N..]);
+        self.stack.truncate(len - N);
+
+        Ok(values)
+    }
+
+    fn pop(&mut self, opcode: OpCode) -> Result<i64, VmError> {
+        self.pop_args(opcode).map(|[value]| value)
+    }
+
+    fn overflow(&self, opcode: OpCode, operands: &[i64]) -> VmError {
+        VmError::Overflow { pc: self.pc, opcode, operands: operands.to_vec() }
+    }
+
+    // splitmix64
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn io_error(&self, opcode: OpCode, message: String) -> VmError {
+        VmError::Io { pc: self.pc, opcode, message }
+    }
+}
+
+fn call_failed(name: &str, reason: String) -> VmError {
+    VmError::CallFailed { name: name.to_string(), reason }
+}
+
+// bytes moved by a sized load/store
+fn access_width(opcode: OpCode) -> usize {
+    match opcode {
+        OpCode::Load8 | OpCode::Store8 => 1,
+        OpCode::Load16 | OpCode::Store16 => 2,
+        OpCode::Load32 | OpCode::Store32 => 4,
+        _ => 8,
+    }
+}
@@ -0,0 +1,335 @@
+// table dispatch, the alternative to the match in execute_ix when Config::table_dispatch is on.
+// Context::new translates every instruction once into a handler and its operands, so a step is a call
+// through a function pointer. Operand counts, register indexes and jump targets are checked during
+// translation: an instruction that passes gets a handler that doesn't look at them again, one that
+// doesn't, and every opcode without a handler of its own, gets `fallback`, which runs it through
+// execute_ix and so fails the same way the match does
+
+use super::{Context, StepResult};
+use crate::error::VmError;
+use crate::host::ExecutionEvent;
+use crate::instruction::{Op, OpCode};
+use crate::prelude::*;
+
+pub(super) type Handler = fn(&mut Context, &Operands) -> Result<Flow, VmError>;
+
+// what a handler did with pc, Context::execute_table applies it
+pub(super) enum Flow {
+    Advance, // on to pc + 1
+    Jump(usize),
+    Exit(i64),
+    Settled(StepResult), // execute_ix already moved pc
+}
+
+// an instruction's operands after translation, only what its handler reads
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Operands {
+    value: i64, // an immediate, or Return's value count
+    index: usize, // a register or a jump target
+}
+
+// one handler per instruction of `code`
+pub(super) fn translate(code: &[Op]) -> Vec<(Handler, Operands)> {
+    code.iter().map(|op| translate_one(op, code.len())).collect()
+}
+
+fn translate_one(op: &Op, program_len: usize) -> (Handler, Operands) {
+    let operands = op.operands();
+    let value = operands.first().copied();
+    let register = value.and_then(|raw| usize::try_from(raw).ok()).filter(|&index| index < super::REGISTER_COUNT);
+    let target = value.and_then(|raw| usize::try_from(raw).ok()).filter(|&index| index < program_len);
+
+    let immediate = |handler: Handler| match value {
+        Some(value) => (handler, Operands { value, index: 0 }),
+        None => (fallback as Handler, Operands::default()),
+    };
+    let indexed = |handler: Handler, index: Option<usize>| match index {
+        Some(index) => (handler, Operands { value: 0, index }),
+        None => (fallback as Handler, Operands::default()),
+    };
+    let plain = |handler: Handler| (handler, Operands::default());
+
+    match op.opcode {
+        OpCode::Push => immediate(push),
+        OpCode::AddImm => immediate(add_imm),
+        OpCode::SubImm => immediate(sub_imm),
+        OpCode::MulImm => immediate(mul_imm),
+        OpCode::Pop => plain(pop),
+        OpCode::Dup => plain(dup),
+        OpCode::Swap => plain(swap),
+        OpCode::Over => plain(over),
+        OpCode::Nop => plain(nop),
+        OpCode::Add => plain(add),
+        OpCode::Sub => plain(sub),
+        OpCode::Mul => plain(mul),
+        OpCode::Eq => plain(eq),
+        OpCode::Ne => plain(ne),
+        OpCode::Lt => plain(lt),
+        OpCode::Le => plain(le),
+        OpCode::Gt => plain(gt),
+        OpCode::Ge => plain(ge),
+        OpCode::LoadReg => indexed(load_reg, register),
+        OpCode::StoreReg => indexed(store_reg, register),
+        OpCode::IncReg => indexed(inc_reg, register),
+        OpCode::DecReg => indexed(dec_reg, register),
+        OpCode::Jump => indexed(jump, target),
+        OpCode::JumpEq => indexed(jump_eq, target),
+        OpCode::JumpNe => indexed(jump_ne, target),
+        OpCode::JumpLt => indexed(jump_lt, target),
+        OpCode::JumpGt => indexed(jump_gt, target),
+        OpCode::JumpLe => indexed(jump_le, target),
+        OpCode::JumpGe => indexed(jump_ge, target),
+        OpCode::JumpZero => indexed(jump_zero, target),
+        OpCode::JumpNotZero => indexed(jump_not_zero, target),
+        // a locals operand goes through execute_ix, it needs its own limit check
+        OpCode::Call if operands.len() == 1 => indexed(call, target),
+        OpCode::Return => match value {
+            None => plain(return_bare),
+            Some(count @ (0 | 1)) => (return_values as Handler, Operands { value: count, index: 0 }),
+            Some(_) => plain(fallback),
+        },
+        OpCode::Exit => match value {
+            None => plain(exit_pop),
+            Some(_) => indexed(exit_reg, register),
+        },
+        _ => plain(fallback),
+    }
+}
+
+fn fallback(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let op = context.code[context.pc];
+    context.execute_ix(op).map(Flow::Settled)
+}
+
+fn push(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    context.stack.push(operands.value);
+    Ok(Flow::Advance)
+}
+
+fn pop(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    context.pop(OpCode::Pop)?;
+    Ok(Flow::Advance)
+}
+
+fn dup(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let [top] = context.pop_args(OpCode::Dup)?;
+    context.stack.extend([top, top]);
+    Ok(Flow::Advance)
+}
+
+fn swap(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let len = context.stack.len();
+    if len < 2 {
+        return Err(VmError::StackUnderflow { pc: context.pc, opcode: OpCode::Swap, needed: 2, found: len });
+    }
+    context.stack.swap(len - 1, len - 2);
+    Ok(Flow::Advance)
+}
+
+fn over(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let len = context.stack.len();
+    if len < 2 {
+        return Err(VmError::StackUnderflow { pc: context.pc, opcode: OpCode::Over, needed: 2, found: len });
+    }
+    context.stack.push(context.stack[len - 2]);
+    Ok(Flow::Advance)
+}
+
+fn nop(_: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    Ok(Flow::Advance)
+}
+
+fn add(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let [a, b] = context.pop_args(OpCode::Add)?;
+    let result = context.arith(OpCode::Add, a, b, i64::wrapping_add, i64::checked_add, i64::saturating_add)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+fn sub(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let [a, b] = context.pop_args(OpCode::Sub)?;
+    let result = context.arith(OpCode::Sub, a, b, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+fn mul(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let [a, b] = context.pop_args(OpCode::Mul)?;
+    let result = context.arith(OpCode::Mul, a, b, i64::wrapping_mul, i64::checked_mul, i64::saturating_mul)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+fn add_imm(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let a = context.pop(OpCode::AddImm)?;
+    let result = context.arith(OpCode::AddImm, a, operands.value, i64::wrapping_add, i64::checked_add, i64::saturating_add)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+fn sub_imm(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let a = context.pop(OpCode::SubImm)?;
+    let result = context.arith(OpCode::SubImm, a, operands.value, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+fn mul_imm(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let a = context.pop(OpCode::MulImm)?;
+    let result = context.arith(OpCode::MulImm, a, operands.value, i64::wrapping_mul, i64::checked_mul, i64::saturating_mul)?;
+    context.stack.push(result);
+    Ok(Flow::Advance)
+}
+
+// the compares push 1 or 0
+fn compare(context: &mut Context, opcode: OpCode, test: fn(i64, i64) -> bool) -> Result<Flow, VmError> {
+    let [a, b] = context.pop_args(opcode)?;
+    context.stack.push(test(a, b) as i64);
+    Ok(Flow::Advance)
+}
+
+fn eq(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Eq, |a, b| a == b)
+}
+
+fn ne(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Ne, |a, b| a != b)
+}
+
+fn lt(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Lt, |a, b| a < b)
+}
+
+fn le(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Le, |a, b| a <= b)
+}
+
+fn gt(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Gt, |a, b| a > b)
+}
+
+fn ge(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    compare(context, OpCode::Ge, |a, b| a >= b)
+}
+
+fn load_reg(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    context.stack.push(context.registers[operands.index]);
+    Ok(Flow::Advance)
+}
+
+fn store_reg(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let value = context.pop(OpCode::StoreReg)?;
+    context.write_register(operands.index, value);
+    Ok(Flow::Advance)
+}
+
+fn inc_reg(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    context.write_register(operands.index, context.registers[operands.index].wrapping_add(1));
+    Ok(Flow::Advance)
+}
+
+fn dec_reg(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    context.write_register(operands.index, context.registers[operands.index].wrapping_sub(1));
+    Ok(Flow::Advance)
+}
+
+fn jump(_: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    Ok(Flow::Jump(operands.index))
+}
+
+// the conditional jumps pop both sides even when they fall through
+fn jump_if(context: &mut Context, operands: &Operands, opcode: OpCode, test: fn(i64, i64) -> bool) -> Result<Flow, VmError> {
+    let [a, b] = context.pop_args(opcode)?;
+    Ok(if test(a, b) { Flow::Jump(operands.index) } else { Flow::Advance })
+}
+
+fn jump_eq(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpEq, |a, b| a == b)
+}
+
+fn jump_ne(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpNe, |a, b| a != b)
+}
+
+fn jump_lt(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpLt, |a, b| a < b)
+}
+
+fn jump_gt(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpGt, |a, b| a > b)
+}
+
+fn jump_le(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpLe, |a, b| a <= b)
+}
+
+fn jump_ge(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    jump_if(context, operands, OpCode::JumpGe, |a, b| a >= b)
+}
+
+fn jump_zero(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let value = context.pop(OpCode::JumpZero)?;
+    Ok(if value == 0 { Flow::Jump(operands.index) } else { Flow::Advance })
+}
+
+fn jump_not_zero(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let value = context.pop(OpCode::JumpNotZero)?;
+    Ok(if value != 0 { Flow::Jump(operands.index) } else { Flow::Advance })
+}
+
+fn call(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    let frame = super::Frame { return_addr: context.pc + 1, locals: Vec::new(), stack_base: context.stack.len() };
+    context.push_frame(frame, operands.index)?;
+    Ok(Flow::Jump(operands.index))
+}
+
+// Return without an operand leaves the stack alone
+fn return_bare(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    let frame = context.call_stack.pop().ok_or(VmError::CallStackUnderflow { pc: context.pc })?;
+    Ok(Flow::Jump(context.leave(frame)))
+}
+
+// Return 0 or Return 1, the frame's stack is cut back and the value, if any, carried over. As in
+// execute_ix the frame is only popped once the value is
+fn return_values(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    if context.call_stack.is_empty() {
+        return Err(VmError::CallStackUnderflow { pc: context.pc });
+    }
+    let value = if operands.value == 1 { Some(context.pop(OpCode::Return)?) } else { None };
+    let frame = context.call_stack.pop().ok_or(VmError::CallStackUnderflow { pc: context.pc })?;
+    context.stack.truncate(frame.stack_base);
+    context.stack.extend(value);
+    Ok(Flow::Jump(context.leave(frame)))
+}
+
+fn exit_pop(context: &mut Context, _: &Operands) -> Result<Flow, VmError> {
+    context.pop(OpCode::Exit).map(Flow::Exit)
+}
+
+fn exit_reg(context: &mut Context, operands: &Operands) -> Result<Flow, VmError> {
+    Ok(Flow::Exit(context.registers[operands.index]))
+}
+
+impl Context {
+    // one step through the table, the caller has done the bookkeeping execute_ix would have
+    pub(super) fn execute_table(&mut self) -> Result<StepResult, VmError> {
+        let (handler, operands) = self.table[self.pc];
+        match handler(self, &operands)? {
+            Flow::Advance => self.pc += 1,
+            Flow::Jump(target) => self.pc = target,
+            Flow::Exit(value) => return Ok(StepResult::Exited(value)),
+            Flow::Settled(result) => return Ok(result),
+        }
+        Ok(StepResult::Continue)
+    }
+
+    // tell the hooks and sinks about a frame Return just popped, its return address is where pc goes
+    fn leave(&mut self, frame: super::Frame) -> usize {
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_return(self.pc, frame.return_addr);
+        }
+        let (pc, return_addr) = (self.pc, frame.return_addr);
+        self.emit(|| ExecutionEvent::Return { pc, return_addr });
+        return_addr
+    }
+}
@@ -0,0 +1,108 @@
+// assembly text back out of instructions, in the syntax assemble reads. Jump, call and switch
+// targets inside the program get an `L<pc>:` label, anything else stays a plain number, so the
+// output re-assembles to the same instructions even when a target points nowhere
+
+use alloc::collections::BTreeSet;
+use core::fmt::Write;
+
+use crate::instruction::{Instruction, OpCode, Program};
+use crate::prelude::*;
+
+pub fn disassemble(program: &[Instruction]) -> String {
+    annotated(program, "", |_| String::new())
+}
+
+// disassemble with the string table up front as .string lines, so PrintStr indices mean the same
+// thing once it's assembled again
+pub fn disassemble_program(program: &Program) -> String {
+    let mut out = String::new();
+    for text in &program.strings {
+        writeln!(out, ".string {}", string_literal(text)).unwrap();
+    }
+    out + &disassemble(&program.instructions)
+}
+
+// the disassembly with each instruction's hit count in the left margin and never executed ones marked
+// #####, the way gcov does. `coverage` is Context::coverage's, pcs past its end count as never executed
+pub fn disassemble_with_coverage(program: &[Instruction], coverage: &[u64]) -> String {
+    annotated(program, "         |", |pc| match coverage.get(pc) {
+        Some(&hits) if hits > 0 => format!("{:>8} |", hits),
+        _ => format!("{:>8} |", "#####"),
+    })
+}
+
+// elementwise sum of two runs' coverage, for accumulating over a test suite. The shorter one counts as zeros
+pub fn merge_coverage(total: &mut Vec<u64>, run: &[u64]) {
+    if total.len() < run.len() {
+        total.resize(run.len(), 0);
+    }
+    total.iter_mut().zip(run).for_each(|(total, hits)| *total += hits);
+}
+
+// disassemble with `margin(pc)` in front of each instruction and `blank` in front of each label
+fn annotated(program: &[Instruction], blank: &str, margin: impl Fn(usize) -> String) -> String {
+    let label = |pc: usize, opcode: OpCode, position: usize, operand: i64| -> Option<usize> {
+        let target = if opcode.is_relative_jump() && position == 0 {
+            (pc as i64).checked_add(operand)?
+        } else if opcode.is_target_operand(position) {
+            operand
+        } else {
+            return None;
+        };
+        usize::try_from(target).ok().filter(|&target| target < program.len())
+    };
+
+    let labels: BTreeSet<usize> = program
+        .iter()
+        .enumerate()
+        .flat_map(|(pc, instruction)| {
+            instruction.operands.iter().enumerate().filter_map(move |(position, &operand)| label(pc, instruction.opcode, position, operand))
+        })
+        .collect();
+
+    let mut out = String::new();
+    for (pc, instruction) in program.iter().enumerate() {
+        if labels.contains(&pc) {
+            writeln!(out, "{}L{}:", blank, pc).unwrap();
+        }
+        let mut line = format!("    {:?}", instruction.opcode).to_lowercase();
+        for (position, &operand) in instruction.operands.iter().enumerate() {
+            match label(pc, instruction.opcode, position, operand) {
+                Some(target) => write!(line, " L{}", target).unwrap(),
+                None if instruction.opcode == OpCode::FPush => write!(line, " {}", float_literal(operand)).unwrap(),
+                None => write!(line, " {}", operand).unwrap(),
+            }
+        }
+        // the pc as a trailing comment, lined up when the instruction is short enough
+        writeln!(out, "{}{:<24} ; {}", margin(pc), line, pc).unwrap();
+    }
+    out
+}
+
+// FPush's operand as a float the assembler reads back to the same bits. NaNs other than the usual one
+// would lose their payload that way, so they stay hex
+fn float_literal(bits: i64) -> String {
+    let value = f64::from_bits(bits as u64);
+    if value.is_nan() && value.to_bits() != f64::NAN.to_bits() {
+        return format!("{:#x}", bits);
+    }
+    format!("{:?}", value)
+}
+
+// a quoted literal the assembler reads back as `text`
+fn string_literal(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,488 @@
+use core::error::Error;
+use core::fmt;
+use core::ops::{Range, RangeInclusive};
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+
+use crate::context::Watch;
+#[cfg(feature = "std")]
+use crate::replay::Divergence;
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+// everything that can go wrong loading or running a program. Errors raised by an instruction come
+// back from run wrapped in Located, use root() to match on what actually happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    StackUnderflow { pc: usize, opcode: OpCode, needed: usize, found: usize },
+    MissingOperand { pc: usize, opcode: OpCode, expected: &'static str },
+    InvalidOperand { pc: usize, opcode: OpCode, value: i64 }, // negative index, bad count, shift amount...
+    InvalidRegister { pc: usize, index: i64 },
+    InvalidLocal { pc: usize, index: usize, locals: usize },
+    NoFrame { pc: usize, opcode: OpCode }, // frame ops outside of any call
+    DivisionByZero { pc: usize },
+    Overflow { pc: usize, opcode: OpCode, operands: Vec<i64> },
+    JumpOutOfBounds { pc: usize, opcode: OpCode, target: i64 },
+    CallStackUnderflow { pc: usize },
+    TryStackUnderflow { pc: usize }, // TryPop with no handler in effect
+    UncaughtGuestException { code: i64, pc: usize }, // a Throw no handler caught
+    CallStackOverflow { pc: usize, depth: usize, return_addrs: Vec<usize> }, // innermost first
+    InvalidAddress { pc: usize, opcode: OpCode, addr: i64 }, // negative or overflowing word address
+    OutOfBounds { pc: usize, opcode: OpCode, addr: usize, size: usize, memory: usize },
+    ProtectedWrite { pc: usize, addr: usize, range: Range<usize> },
+    MemoryLimit { pc: usize, limit: usize, addr: usize },
+    OutOfHeap { pc: usize, size: usize, heap: Range<usize> },
+    InvalidFree { pc: usize, addr: i64 },
+    MemoryStackDisabled { pc: usize, opcode: OpCode },
+    MemoryStackOverflow { pc: usize, sp: i64 },
+    Mmio { pc: usize, addr: usize, write: bool, message: String },
+    NotShared { pc: usize, opcode: OpCode, addr: usize }, // an atomic op outside every shared region
+    UnknownChannel { pc: usize, opcode: OpCode, channel: i64 }, // no end of that kind attached under the id
+    ChannelClosed { pc: usize, channel: i64 }, // Recv on an empty channel whose senders have all closed
+    UnknownSyscall { pc: usize, number: i64 },
+    Syscall { pc: usize, number: i64, message: String },
+    UnhandledTrap { pc: usize, code: i64 }, // no handler registered for the code
+    Io { pc: usize, opcode: OpCode, message: String },
+    AssertionFailed { pc: usize, message: Option<String> }, // an Assert popped zero, message from its string operand
+
+    // where an instruction error happened, added by the run loop. stack_top is up to the top
+    // STACK_CONTEXT values (top last) as the failing instruction found them
+    Located {
+        location: String,
+        instruction: Instruction,
+        stack_top: Vec<i64>,
+        call_stack: Vec<usize>,
+        error: Box<VmError>,
+    },
+
+    OutOfFuel { pc: usize, steps: u64 },
+    Interrupted { pc: usize },
+    Yielded { pc: usize, value: i64 }, // run hit a Yield, resume or run_for handle those
+    Breakpoint { pc: usize }, // run stopped at a breakpoint, run again to continue
+    Blocked { pc: usize }, // run stopped on a full or empty channel, run again once the other side moved
+    Watchpoint { pc: usize, watch: Watch, old: i64, new: i64 }, // the instruction at pc wrote a watched location
+    InvalidBreakpoint { pc: usize, len: usize },
+    NoExit,
+    AlreadyFinished { value: i64 }, // run after the program exited with value, reset to run it again
+
+    InvalidProgram(String),
+    Unverified(Vec<ValidationError>), // run_verified refused the program, every problem validate found
+    MmioOverlap { range: Range<usize>, existing: Range<usize> },
+    TooManyArguments { count: usize, max: usize },
+    UnknownFunction(String),
+    SnapshotMismatch { snapshot: u64, program: u64 }, // restore got a snapshot of a different program, both hashes
+    NoCheckpoint { steps: u64 }, // step_back with reverse stepping off, at step 0, or past the oldest checkpoint
+    CallFailed { name: String, reason: String },
+}
+
+impl VmError {
+    // the underlying error with any Located wrapping peeled off
+    pub fn root(&self) -> &VmError {
+        match self {
+            VmError::Located { error, .. } => error.root(),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackUnderflow { opcode, needed, found, .. } => {
+                write!(f, "Stack underflow => {:?} Op needs {} values, found {}", opcode, needed, found)
+            },
+            VmError::MissingOperand { opcode, expected, .. } => write!(f, "{:?} requires {}", opcode, expected),
+            VmError::InvalidOperand { opcode, value, .. } => write!(f, "Invalid operand for {:?}: {}", opcode, value),
+            VmError::InvalidRegister { index, .. } => write!(f, "Invalid register index: {}", index),
+            VmError::InvalidLocal { index, locals, .. } => {
+                write!(f, "Invalid local index: {} (frame has {} locals)", index, locals)
+            },
+            VmError::NoFrame { opcode, .. } => write!(f, "{:?} outside of any call frame", opcode),
+            VmError::DivisionByZero { .. } => write!(f, "Division by zero"),
+            VmError::Overflow { opcode, operands, .. } => {
+                let operands: Vec<String> = operands.iter().map(|value| value.to_string()).collect();
+                write!(f, "Overflow in {:?} Op: {}", opcode, operands.join(" and "))
+            },
+            VmError::JumpOutOfBounds { opcode, target, .. } => write!(f, "{:?} target out of bounds: {}", opcode, target),
+            VmError::CallStackUnderflow { .. } => write!(f, "Call stack underflow (unmatched return)"),
+            VmError::TryStackUnderflow { .. } => write!(f, "TryPop with no handler in effect"),
+            VmError::UncaughtGuestException { code, .. } => write!(f, "Uncaught guest exception {}", code),
+            VmError::CallStackOverflow { depth, return_addrs, .. } => write!(
+                f, "Call stack overflow at depth {}, return addresses (innermost first): {:?}", depth, return_addrs
+            ),
+            VmError::InvalidAddress { opcode, addr, .. } => write!(f, "Invalid memory address in {:?} Op: {}", opcode, addr),
+            VmError::OutOfBounds { opcode, addr, size, memory, .. } => write!(
+                f, "Memory access out of bounds in {:?} Op: address {}, size {}, memory is {} bytes", opcode, addr, size, memory
+            ),
+            VmError::ProtectedWrite { addr, range, .. } => {
+                write!(f, "Write to protected address {} (protected range {:?})", addr, range)
+            },
+            VmError::MemoryLimit { limit, addr, .. } => {
+                write!(f, "Memory limit of {} cells exceeded storing to address {}", limit, addr)
+            },
+            VmError::OutOfHeap { size, heap, .. } => write!(f, "Out of heap memory allocating {} cells (heap {:?})", size, heap),
+            VmError::InvalidFree { addr, .. } => {
+                write!(f, "Free of address {} that isn't allocated (double free or unknown pointer)", addr)
+            },
+            VmError::MemoryStackDisabled { opcode, .. } => write!(f, "{:?} requires the memory stack to be enabled", opcode),
+            VmError::MemoryStackOverflow { sp, .. } => write!(f, "Memory stack overflow in PushM Op: SP is {}", sp),
+            VmError::Mmio { addr, write, message, .. } => {
+                let access = if *write { "write" } else { "read" };
+                write!(f, "{} (MMIO {} at address {})", message, access, addr)
+            },
+            VmError::NotShared { opcode, addr, .. } => write!(f, "{:?} on address {}, which isn't shared memory", opcode, addr),
+            VmError::UnknownChannel { opcode, channel, .. } => write!(f, "{:?} on unattached channel {}", opcode, channel),
+            VmError::ChannelClosed { channel, .. } => write!(f, "Channel {} is empty and all its senders have closed", channel),
+            VmError::UnknownSyscall { number, .. } => write!(f, "Unregistered syscall {}", number),
+            VmError::Syscall { number, message, .. } => write!(f, "Syscall {} failed: {}", number, message),
+            VmError::UnhandledTrap { code, .. } => write!(f, "Unhandled trap {}", code),
+            VmError::Io { opcode, message, .. } => write!(f, "I/O error in {:?} Op: {}", opcode, message),
+            VmError::AssertionFailed { message: Some(message), .. } => write!(f, "Assertion failed: {}", message),
+            VmError::AssertionFailed { message: None, .. } => write!(f, "Assertion failed"),
+            VmError::Located { location, instruction, stack_top, call_stack, error } => write!(
+                f, "{} at pc={} ({:?}), stack top: {:?}, call stack: {:?}", error, location, instruction, stack_top, call_stack
+            ),
+            VmError::OutOfFuel { pc, steps } => write!(f, "Out of fuel after {} instructions at pc={}", steps, pc),
+            VmError::Interrupted { pc } => write!(f, "Interrupted at pc={}", pc),
+            VmError::Yielded { pc, value } => {
+                write!(f, "Program yielded {} before pc={}, drive it with resume or run_for", value, pc)
+            },
+            VmError::Breakpoint { pc } => write!(f, "Stopped at breakpoint at pc={}", pc),
+            VmError::Blocked { pc } => write!(f, "Blocked on a channel at pc={}", pc),
+            VmError::Watchpoint { pc, watch, old, new } => {
+                write!(f, "Stopped after pc={} wrote {:?}: {} -> {}", pc, watch, old, new)
+            },
+            VmError::InvalidBreakpoint { pc, len } => {
+                write!(f, "Breakpoint at pc={} is outside the program ({} instructions)", pc, len)
+            },
+            VmError::NoExit => write!(f, "Program terminated without explicit exit"),
+            VmError::AlreadyFinished { value } => {
+                write!(f, "Program already finished with {}, reset the context to run it again", value)
+            },
+            VmError::InvalidProgram(message) => write!(f, "Invalid program: {}", message),
+            VmError::Unverified(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "Program failed verification: {}", errors.join("; "))
+            },
+            VmError::MmioOverlap { range, existing } => {
+                write!(f, "MMIO region {:?} overlaps already mapped region {:?}", range, existing)
+            },
+            VmError::TooManyArguments { count, max } => write!(f, "Too many arguments: {} (at most {})", count, max),
+            VmError::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            VmError::SnapshotMismatch { snapshot, program } => {
+                write!(f, "Snapshot is of a different program (hash {:#018x}, this one is {:#018x})", snapshot, program)
+            },
+            VmError::NoCheckpoint { steps } => write!(f, "Can't step back from step {}: no checkpoint that early", steps),
+            VmError::CallFailed { name, reason } => write!(f, "Function {} {}", name, reason),
+        }
+    }
+}
+
+impl Error for VmError {}
+
+// why a byte buffer isn't a valid bytecode file, offsets are into the buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion { found: u16, supported: u16 }, // major << 8 | minor
+    Truncated { offset: usize, expected: &'static str },
+    UnknownOpcode { offset: usize, byte: u8 },
+    TooManyOperands { offset: usize, opcode: OpCode, count: usize },
+    AddressOverflow { offset: usize, value: u64 }, // doesn't fit in usize on this target
+    InvalidSymbol { offset: usize }, // symbol name isn't utf-8
+    InvalidUtf8 { offset: usize, what: &'static str }, // debug info file name, string or program name
+    UnknownString { offset: usize, index: i64, count: usize }, // PrintStr at offset names a string past the table
+    TrailingBytes { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "Not a beef bytecode file (bad magic)"),
+            DecodeError::UnsupportedVersion { found, supported } => {
+                write!(f, "Unsupported bytecode version {}.{} (supported: {}.x)", found >> 8, found & 0xff, supported >> 8)
+            },
+            DecodeError::Truncated { offset, expected } => write!(f, "Truncated input at byte {}: expected {}", offset, expected),
+            DecodeError::UnknownOpcode { offset, byte } => write!(f, "Unknown opcode byte {:#04x} at byte {}", byte, offset),
+            DecodeError::TooManyOperands { offset, opcode, count } => {
+                write!(f, "{:?} at byte {} claims {} operands", opcode, offset, count)
+            },
+            DecodeError::AddressOverflow { offset, value } => write!(f, "Address {} at byte {} is too large", value, offset),
+            DecodeError::InvalidSymbol { offset } => write!(f, "Symbol name at byte {} isn't valid utf-8", offset),
+            DecodeError::InvalidUtf8 { offset, what } => write!(f, "The {} at byte {} isn't valid utf-8", what, offset),
+            DecodeError::UnknownString { offset, index, count } => {
+                write!(f, "PrintStr at byte {} uses string {}, the table has {}", offset, index, count)
+            },
+            DecodeError::TrailingBytes { offset } => write!(f, "Unexpected data after the end of the program at byte {}", offset),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+// why Program::to_bytes can't write a program, the format caps some lengths that memory doesn't
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    TooManyOperands { pc: usize, opcode: OpCode, count: usize }, // more than MAX_OPERANDS
+    SymbolTooLong { name: String, len: usize }, // names are capped at u16::MAX bytes
+    FileNameTooLong { len: usize },
+    NameTooLong { len: usize }, // the program's own name
+    LineTooLarge { pc: usize, line: usize },
+    TooMany { what: &'static str, count: usize }, // counts are u32
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::TooManyOperands { pc, opcode, count } => {
+                write!(f, "{:?} at pc={} has {} operands, the bytecode format allows {}", opcode, pc, count, crate::bytecode::MAX_OPERANDS)
+            },
+            EncodeError::SymbolTooLong { name, len } => {
+                let name: String = name.chars().take(32).collect();
+                write!(f, "Symbol {}... is {} bytes long, the bytecode format allows {}", name, len, u16::MAX)
+            },
+            EncodeError::FileNameTooLong { len } => {
+                write!(f, "Debug info file name is {} bytes long, the bytecode format allows {}", len, u16::MAX)
+            },
+            EncodeError::NameTooLong { len } => {
+                write!(f, "Program name is {} bytes long, the bytecode format allows {}", len, u16::MAX)
+            },
+            EncodeError::LineTooLarge { pc, line } => write!(f, "Source line {} of pc={} doesn't fit the bytecode format", line, pc),
+            EncodeError::TooMany { what, count } => {
+                write!(f, "{} {} don't fit the bytecode format, it allows {}", count, what, u32::MAX)
+            },
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+// an assembler error at a 1-based line and column, token is the offending source text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic,
+    InvalidOperand, // not a number or register
+    OperandCount { opcode: OpCode, expected: RangeInclusive<usize>, found: usize },
+    InvalidLabel, // label name that isn't an identifier, or looks like a register
+    DuplicateLabel { first_line: usize },
+    UndefinedLabel,
+    LabelNotAllowed { opcode: OpCode }, // label used where the opcode doesn't take a target
+    UnknownDirective,
+    InvalidMacro, // bad or missing macro name, or .endmacro without .macro
+    DuplicateMacro { first_line: usize },
+    UnterminatedMacro, // .macro without .endmacro, or nested inside another definition
+    RecursiveMacro,
+    MacroArguments { expected: usize, found: usize },
+    InvalidString, // unterminated literal, unknown escape, or not exactly one literal after .string
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic => write!(f, "unknown mnemonic `{}`", self.token),
+            AsmErrorKind::InvalidOperand => write!(f, "invalid operand `{}`", self.token),
+            AsmErrorKind::InvalidLabel => write!(f, "invalid label name `{}`", self.token),
+            AsmErrorKind::DuplicateLabel { first_line } => {
+                write!(f, "label `{}` is already defined on line {}", self.token, first_line)
+            },
+            AsmErrorKind::UndefinedLabel => write!(f, "undefined label `{}`", self.token),
+            AsmErrorKind::LabelNotAllowed { opcode } => {
+                write!(f, "{:?} doesn't take a jump target, can't use label `{}`", opcode, self.token)
+            },
+            AsmErrorKind::UnknownDirective => write!(f, "unknown directive `{}`", self.token),
+            AsmErrorKind::InvalidString => write!(f, "invalid string literal `{}`", self.token),
+            AsmErrorKind::InvalidMacro => write!(f, "invalid macro definition at `{}`", self.token),
+            AsmErrorKind::DuplicateMacro { first_line } => {
+                write!(f, "macro `{}` is already defined on line {}", self.token, first_line)
+            },
+            AsmErrorKind::UnterminatedMacro => write!(f, "macro `{}` is missing its .endmacro", self.token),
+            AsmErrorKind::RecursiveMacro => write!(f, "macro `{}` invokes itself", self.token),
+            AsmErrorKind::MacroArguments { expected, found } => {
+                write!(f, "macro `{}` takes {} arguments, found {}", self.token, expected, found)
+            },
+            AsmErrorKind::OperandCount { expected, found, .. } => {
+                let expected = match (expected.start(), expected.end()) {
+                    (min, &usize::MAX) => format!("at least {}", min),
+                    (min, max) if min == max => min.to_string(),
+                    (min, max) => format!("{} to {}", min, max),
+                };
+                write!(f, "`{}` takes {} operands, found {}", self.token, expected, found)
+            },
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+// why Program::from_json rejected its input: malformed JSON, or JSON that isn't a program.
+// path points at the offending field, like instructions[3].opcode
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonError {
+    Syntax { line: usize, column: usize, message: String },
+    Schema { path: String, message: String },
+}
+
+#[cfg(feature = "json")]
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::Syntax { line, column, message } => write!(f, "Invalid JSON at line {}, column {}: {}", line, column, message),
+            JsonError::Schema { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl Error for JsonError {}
+
+// everything the command line can fail with, file errors name the file
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    Usage(String),
+    Io { path: PathBuf, message: String },
+    Decode { path: PathBuf, error: DecodeError },
+    Encode { path: PathBuf, error: EncodeError },
+    Asm { path: PathBuf, error: AsmError },
+    #[cfg(feature = "json")]
+    Json { path: PathBuf, error: JsonError },
+    Vm(VmError),
+}
+
+#[cfg(feature = "std")]
+impl From<VmError> for CliError {
+    fn from(error: VmError) -> Self {
+        CliError::Vm(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}", message),
+            CliError::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+            CliError::Decode { path, error } => write!(f, "{}: {}", path.display(), error),
+            CliError::Encode { path, error } => write!(f, "{}: {}", path.display(), error),
+            CliError::Asm { path, error } => write!(f, "{}: {}", path.display(), error),
+            #[cfg(feature = "json")]
+            CliError::Json { path, error } => write!(f, "{}: {}", path.display(), error),
+            CliError::Vm(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CliError {}
+
+// ProgramBuilder::build failures, pc is the offending instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    DuplicateLabel { label: String, first: usize, second: usize },
+    UndefinedLabel { label: String, pc: usize },
+    OperandCount { pc: usize, opcode: OpCode, expected: RangeInclusive<usize>, found: usize },
+    InvalidRegister { pc: usize, opcode: OpCode, register: i64 },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::DuplicateLabel { label, first, second } => {
+                write!(f, "Label {} defined at {} and again at {}", label, first, second)
+            },
+            BuildError::UndefinedLabel { label, pc } => write!(f, "Undefined label {} used at pc={}", label, pc),
+            BuildError::OperandCount { pc, opcode, expected, found } => write!(
+                f, "{:?} at pc={} takes {}..={} operands, found {}", opcode, pc, expected.start(), expected.end(), found
+            ),
+            BuildError::InvalidRegister { pc, opcode, register } => {
+                write!(f, "Invalid register {} for {:?} at pc={}", register, opcode, pc)
+            },
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+// a problem validate found, pc is the offending instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    OperandCount { pc: usize, opcode: OpCode, expected: RangeInclusive<usize>, found: usize },
+    InvalidRegister { pc: usize, opcode: OpCode, register: i64 },
+    TargetOutOfBounds { pc: usize, opcode: OpCode, target: i64 }, // relative jumps report the absolute target
+    // flow problems from check_flow, warnings unless validate_strict
+    FallsOffEnd { pc: usize }, // a reachable path runs past the last instruction after this one
+    Unreachable { pcs: Range<usize> }, // nothing leads here from pc 0
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::OperandCount { pc, opcode, expected, found } => write!(
+                f, "pc={}: {:?} takes {}..={} operands, found {}", pc, opcode, expected.start(), expected.end(), found
+            ),
+            ValidationError::InvalidRegister { pc, opcode, register } => {
+                write!(f, "pc={}: Invalid register {} for {:?}", pc, register, opcode)
+            },
+            ValidationError::TargetOutOfBounds { pc, opcode, target } => {
+                write!(f, "pc={}: {:?} target out of bounds: {}", pc, opcode, target)
+            },
+            ValidationError::FallsOffEnd { pc } => write!(f, "pc={}: execution can run past the end of the program, no Exit", pc),
+            ValidationError::Unreachable { pcs } => write!(f, "pc={}..{}: unreachable from pc 0", pcs.start, pcs.end),
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+// why replay stopped short of the end of a trace
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    Io(String), // reading the trace failed
+    Corrupt { offset: usize, message: String },
+    Diverged(Box<Divergence>),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(message) => write!(f, "Failed to read trace: {}", message),
+            ReplayError::Corrupt { offset, message } => write!(f, "Corrupt trace at byte {}: {}", offset, message),
+            ReplayError::Diverged(divergence) => write!(
+                f, "Replay diverged at step {}: expected {:?}, found {:?}", divergence.step, divergence.expected, divergence.found
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ReplayError {}
+
+// an instruction analyze_stack found some path reaching with too few values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+    Underflow { pc: usize, opcode: OpCode, needed: usize, depth: usize }, // depth is the shallowest path's
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Underflow { pc, opcode, needed, depth } => {
+                write!(f, "pc={}: {:?} needs {} values, some path reaches it with {}", pc, opcode, needed, depth)
+            },
+        }
+    }
+}
+
+impl Error for StackError {}
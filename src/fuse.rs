@@ -0,0 +1,58 @@
+// superinstructions: common short sequences run as one dispatch when Config::superinstructions is on.
+// the original instructions stay where they are, a fused Op only records that a fast path for the whole
+// sequence starts at its pc. Anything that lands inside a sequence simply runs the plain Ops from there
+
+use crate::context::REGISTER_COUNT;
+use crate::instruction::{Instruction, Op, OpCode};
+use crate::prelude::*;
+use crate::validate::jump_targets;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fused {
+    PushAdd(i64), // Push k; Add
+    PushSub(i64), // Push k; Sub
+    LoadRegPushJumpEq { reg: usize, value: i64, target: usize }, // LoadReg r; Push k; JumpEq t
+}
+
+impl Fused {
+    // how many instructions the sequence covers
+    pub(crate) fn width(self) -> usize {
+        match self {
+            Fused::PushAdd(_) | Fused::PushSub(_) => 2,
+            Fused::LoadRegPushJumpEq { .. } => 3,
+        }
+    }
+}
+
+// mark the start of every fusable sequence in `code`, which is `program` decoded
+pub(crate) fn fuse(program: &[Instruction], code: &mut [Op]) {
+    let targets = jump_targets(program);
+    for pc in 0..program.len() {
+        let Some(fused) = recognize(&program[pc..], program.len()) else {
+            continue;
+        };
+        // a branch into the middle would make the sequence's head optional, only straight-line code is fused
+        if (pc + 1..pc + fused.width()).any(|inner| targets.contains(&inner)) {
+            continue;
+        }
+        code[pc].fused = Some(fused);
+    }
+}
+
+// the superinstruction `window` starts with, if any. Only well-formed sequences match, so the fast path
+// never has to re-check an operand
+fn recognize(window: &[Instruction], program_len: usize) -> Option<Fused> {
+    use OpCode::*;
+
+    let shape: Vec<(OpCode, &[i64])> = window.iter().take(3).map(|ix| (ix.opcode, &ix.operands[..])).collect();
+    match shape[..] {
+        [(LoadReg, &[reg]), (Push, &[value]), (JumpEq, &[target])]
+            if (0..REGISTER_COUNT as i64).contains(&reg) && (0..program_len as i64).contains(&target) =>
+        {
+            Some(Fused::LoadRegPushJumpEq { reg: reg as usize, value, target: target as usize })
+        },
+        [(Push, &[value]), (Add, &[]), ..] => Some(Fused::PushAdd(value)),
+        [(Push, &[value]), (Sub, &[]), ..] => Some(Fused::PushSub(value)),
+        _ => None,
+    }
+}
@@ -0,0 +1,78 @@
+// controlling a Context from another thread. Context::handle gives out a VmHandle and the Context
+// itself stays the execution half: move it to a worker thread and run it there, then stop it, toggle
+// breakpoints or ask for snapshots through the handle. Requests go over a channel the run loop polls
+// each time it checks the stop flag, every Config::stop_check_interval instructions, so they land
+// between instructions. A Context that isn't running answers once its owner calls poll_handle
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::VmError;
+use crate::snapshot::Snapshot;
+
+pub(crate) enum Request {
+    AddBreakpoint(usize),
+    RemoveBreakpoint(usize),
+    Snapshot(Sender<Snapshot>),
+}
+
+// the Context's end of the request channel, every handle shares it
+pub(crate) struct Control {
+    pub(crate) sender: Sender<Request>,
+    pub(crate) receiver: Receiver<Request>,
+}
+
+impl Control {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Control { sender, receiver }
+    }
+}
+
+// cloneable, requests from every clone are handled in the order they were sent
+#[derive(Debug, Clone)]
+pub struct VmHandle {
+    pub(crate) stop: Arc<AtomicBool>,
+    pub(crate) requests: Sender<Request>,
+    pub(crate) program_len: usize, // for checking breakpoints before they're sent
+}
+
+impl VmHandle {
+    // same as setting the stop_handle flag, run ends with Interrupted at the next check
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    // let the next run go ahead
+    pub fn clear_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // takes effect at the next poll. Only the pc is checked here, a dropped Context just ignores it
+    pub fn add_breakpoint(&self, pc: usize) -> Result<(), VmError> {
+        if pc >= self.program_len {
+            return Err(VmError::InvalidBreakpoint { pc, len: self.program_len });
+        }
+        let _ = self.requests.send(Request::AddBreakpoint(pc));
+
+        Ok(())
+    }
+
+    pub fn remove_breakpoint(&self, pc: usize) {
+        let _ = self.requests.send(Request::RemoveBreakpoint(pc));
+    }
+
+    // the state at the next poll, None if that doesn't come within `timeout` or the Context is gone
+    pub fn snapshot(&self, timeout: Duration) -> Option<Snapshot> {
+        let (sender, receiver) = mpsc::channel();
+        self.requests.send(Request::Snapshot(sender)).ok()?;
+        receiver.recv_timeout(timeout).ok()
+    }
+}
+
@@ -0,0 +1,209 @@
+#[cfg(feature = "std")]
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+use crate::context::Context;
+use crate::error::VmError;
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+use crate::sync::Shared;
+
+// host code behind a range of word addresses, Load/Store (the indirect forms too) and
+// MemSet/MemCpy call these for each mapped cell instead of touching memory.
+// Everything the host hands a Context has to be Send, so the Context can move to another thread
+pub trait MmioHandler: Send {
+    fn read(&mut self, addr: usize) -> Result<i64, String>;
+    fn write(&mut self, addr: usize, value: i64) -> Result<(), String>;
+}
+
+// host function behind a Syscall number, gets the popped arguments and returns the value to push
+pub type HostFn = Box<dyn FnMut(&mut [i64]) -> Result<i64, String> + Send>;
+
+// what happens after a trap handler returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrapOutcome {
+    Resume, // carry on with the instruction after the Trap
+    ResumeAt(usize), // carry on at this pc
+    Abort(VmError), // stop the run with this error
+}
+
+// host code behind a Trap code. It gets the whole Context, pc on the Trap, so it can fix up memory,
+// registers or the stack before the program carries on. Where it carries on is up to the TrapOutcome
+pub type TrapHandler = Box<dyn FnMut(&mut Context, i64) -> TrapOutcome + Send>;
+
+// where TimeMs reads the time, see Context::set_clock. Only differences between readings mean anything,
+// the start can be any fixed point
+pub trait Clock: Send {
+    fn now_ms(&mut self) -> i64;
+}
+
+// where Print, PrintChar, PrintStr and the trace go, see Context::set_output. With std every io::Write
+// is one, without it implement write_text
+pub trait Output: Send {
+    fn write_text(&mut self, text: &str) -> Result<(), String>;
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write + Send + ?Sized> Output for W {
+    fn write_text(&mut self, text: &str) -> Result<(), String> {
+        self.write_all(text.as_bytes()).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Output for Vec<u8> {
+    fn write_text(&mut self, text: &str) -> Result<(), String> {
+        self.extend_from_slice(text.as_bytes());
+        Ok(())
+    }
+}
+
+// swallows everything, the default output without std
+pub(crate) struct Discard;
+
+impl Output for Discard {
+    fn write_text(&mut self, _text: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// cloneable in-memory sink, hand one clone to set_output and read the other afterwards
+#[derive(Debug, Clone)]
+pub struct SharedBuffer(Shared<Vec<u8>>);
+
+impl Default for SharedBuffer {
+    fn default() -> Self {
+        SharedBuffer(Shared::new(Vec::new()))
+    }
+}
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.with(|buf| buf.clone())
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.with(|contents| contents.extend_from_slice(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Output for SharedBuffer {
+    fn write_text(&mut self, text: &str) -> Result<(), String> {
+        self.0.with(|contents| contents.extend_from_slice(text.as_bytes()));
+        Ok(())
+    }
+}
+
+// source of values for Read
+pub enum Input {
+    Values(Box<dyn Iterator<Item = i64> + Send>),
+    #[cfg(feature = "std")]
+    Lines(Box<dyn BufRead + Send>), // one integer per line, blank lines are skipped
+    // Lines from the process's stdin, the default. Reads go through std's shared stdin buffer one line
+    // at a time, so Contexts reading one after another (or other stdin readers) don't lose each other's input
+    #[cfg(feature = "std")]
+    Stdin,
+}
+
+impl Input {
+    // None at end of input
+    pub(crate) fn next_value(&mut self) -> Result<Option<i64>, String> {
+        match self {
+            Input::Values(values) => Ok(values.next()),
+            #[cfg(feature = "std")]
+            Input::Lines(reader) => next_line_value(|line| reader.read_line(line)),
+            #[cfg(feature = "std")]
+            Input::Stdin => next_line_value(|line| io::stdin().read_line(line)),
+        }
+    }
+}
+
+// the next non-blank line parsed as an integer, None at end of input
+#[cfg(feature = "std")]
+fn next_line_value(mut read_line: impl FnMut(&mut String) -> io::Result<usize>) -> Result<Option<i64>, String> {
+    loop {
+        let mut line = String::new();
+        let read = read_line(&mut line).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if !line.is_empty() {
+            return line.parse().map(Some)
+                .map_err(|_| format!("invalid integer {:?}", line));
+        }
+    }
+}
+
+// structured trace of what the VM did. For each instruction: Instruction first, then any
+// register/memory/call/return effects, then the stack pops and pushes, then Exit if it ended the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionEvent {
+    Instruction { pc: usize, opcode: OpCode, operands: Vec<i64> },
+    StackPush(i64),
+    StackPop(i64),
+    RegisterWrite { reg: usize, value: i64 },
+    MemoryWrite { addr: usize, value: i64 },
+    Call { pc: usize, target: usize },
+    Return { pc: usize, return_addr: usize },
+    Exit { value: i64 },
+}
+
+pub type EventSink = Box<dyn FnMut(ExecutionEvent) + Send>;
+
+// callbacks for profilers and tracers, everything defaults to doing nothing
+pub trait ExecutionHooks: Send {
+    // pc is the calling instruction, target the function entry. A TailCall inside a function
+    // reports on_return for the function it replaces, then on_call for its target
+    fn on_call(&mut self, _pc: usize, _target: usize) {}
+
+    // pc is the Return instruction
+    fn on_return(&mut self, _pc: usize, _return_addr: usize) {}
+
+    // runs before the instruction executes
+    fn on_instruction(&mut self, _pc: usize, _instruction: &Instruction) {}
+}
+
+// example hooks: instructions executed per function, keyed by entry pc (0 is top level code).
+// grab counts() before handing the counter to set_hooks to read the results afterwards
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct FunctionCounter {
+    counts: Arc<Mutex<BTreeMap<usize, u64>>>,
+    entries: Vec<usize>,
+}
+
+#[cfg(feature = "std")]
+impl FunctionCounter {
+    pub fn counts(&self) -> Arc<Mutex<BTreeMap<usize, u64>>> {
+        Arc::clone(&self.counts)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ExecutionHooks for FunctionCounter {
+    fn on_call(&mut self, _pc: usize, target: usize) {
+        self.entries.push(target);
+    }
+
+    fn on_return(&mut self, _pc: usize, _return_addr: usize) {
+        self.entries.pop();
+    }
+
+    fn on_instruction(&mut self, _pc: usize, _instruction: &Instruction) {
+        let entry = self.entries.last().copied().unwrap_or(0);
+        *self.counts.lock().unwrap().entry(entry).or_insert(0) += 1;
+    }
+}
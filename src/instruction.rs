@@ -0,0 +1,420 @@
+use core::ops::{Range, RangeInclusive};
+
+use crate::error::VmError;
+use crate::fuse::Fused;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OpCode {
+    Push,
+    Pop,
+    Dup,
+    Swap,
+    Over, // copy second element to top
+    Rot,  // a b c -> b c a
+    Pick, // copy n-th element from top, Pick 0 == Dup
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+
+    // upper 64 bits of the full 128-bit product, never overflows
+    MulHi,
+    MulHiU, // operands and result read as u64
+
+    // immediate forms, constant in operands[0] applied to top of stack
+    AddImm,
+    SubImm,
+    MulImm,
+
+    Neg,
+    Abs,
+    Min,
+    Max,
+
+    // bitwise ops
+    And,
+    Or,
+    Xor,
+    Not,
+
+    // shifts
+    Shl,
+    Shr, // logical, zero fill
+    Sar, // arithmetic, sign fill
+
+    // comparisons, push 1 or 0
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    // unsigned forms, operands read as u64 so values with the sign bit set count as large.
+    // Shr is already the unsigned shift
+    DivU,
+    ModU,
+    LtU,
+    LeU,
+    GtU,
+    GeU,
+
+    // branchless choice: pops the condition, then b, then a, so the stack reads `a b cond`.
+    // Pushes a when cond is nonzero, otherwise b
+    Select,
+
+    // floats, stored as their f64 bit pattern in the same i64 slots as everything else.
+    // IEEE 754 throughout: no overflow or division errors whatever the ArithMode, x/0 is inf or NaN
+    FPush, // f -- operand is the bit pattern, the assembler reads a float literal
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FEq, // push 1 or 0, anything compared with NaN is 0, even NaN itself
+    FLt,
+    FGt,
+    IntToFloat, // nearest f64, large values lose their low bits
+    FloatToInt, // truncates toward zero, NaN and out of range values follow the ArithMode
+    FPrint,     // pops a float, writes it plus a newline: 2.5, -0, inf, NaN
+
+    LoadReg, // Load from register to stack
+    StoreReg, // Store from stack to register
+
+    // register to register arithmetic: dst, a, b
+    AddReg,
+    SubReg,
+    MulReg,
+    DivReg,
+    MovReg, // dst, src
+    IncReg, // wraps at i64::MAX
+    DecReg, // wraps at i64::MIN
+
+    //Mem Ops, either [addr] or [reg, offset] meaning registers[reg] + offset
+    Load,
+    Store,
+    LoadInd,  // address popped from the stack
+    StoreInd, // pops address, then the value to store
+
+    // bulk word memory ops, all operands popped with count on top
+    MemSet, // dst, value, count
+    MemCpy, // dst, src, count -- overlapping regions behave like memmove
+
+    // heap, carved out of Config::heap
+    Alloc, // pops a size in cells, pushes the base address
+    Free,  // pops a base address returned by Alloc
+
+    // memory stack through the SP register, needs Config::memory_stack
+    PushM, // pops a value, SP -= 1, memory[SP] = value
+    PopM,  // pushes memory[SP], SP += 1
+
+    // sized access to the linear byte memory, little endian, loads sign-extend
+    // same operand forms as Load/Store, unaligned addresses are fine
+    Load8,
+    Load16,
+    Load32,
+    Load64,
+    Store8,
+    Store16,
+    Store32,
+    Store64,
+
+    // read-modify-write on memory attached with Context::attach_shared, under the region's lock.
+    // Both push the value the cell held before, an address outside every shared region is an error
+    AtomicAdd, // addr -- pops a delta and adds it, wrapping
+    AtomicCas, // addr -- pops new then expected, stores new only if the cell holds expected
+
+    // channels attached with Context::attach_sender and attach_receiver, blocking when full or empty
+    Send, // chan -- pops a value and queues it
+    Recv, // chan -- pushes the oldest queued value
+
+    // control flow
+    Jump,
+    JumpEq,
+    JumpGt,
+    JumpLt,
+    JumpNe,
+    JumpGe,
+    JumpLe,
+    JumpZero,    // pops one value, no compare operand needed
+    JumpNotZero,
+
+    // relative jumps, operand is a signed offset from the current pc
+    JumpRel,
+    JumpRelEq,
+    JumpRelNe,
+    JumpRelGt,
+    JumpRelLt,
+    JumpRelGe,
+    JumpRelLe,
+
+    // jump table: operands are the case targets followed by a default target
+    Switch,
+    JumpDyn, // computed jump, target popped from the stack
+
+    // function management
+    Call, // addr [, n] -- second operand reserves n local slots in the new frame
+    CallIndirect, // function address popped from the stack
+    CallN, // addr, argc -- moves the top argc values into the new frame's locals
+    TailCall, // addr [, n] -- like Call but reuses the current frame, so Return goes to our caller
+    Return, // [n] -- with an operand, keeps the top n (0 or 1) values and drops the rest of the callee's stack
+
+    // frame locals
+    Enter,      // n -- resize the current frame to n local slots
+    LoadLocal,  // i
+    StoreLocal, // i
+
+    // exceptions. A handler remembers the operand and call stack depths at its TryPush, Throw drops
+    // frames and values back to those, pushes the code and jumps to the handler
+    TryPush, // handler -- newest handler first, until TryPop or a Throw it catches removes it
+    TryPop,
+    Throw, // pops the code, with no handler left the run stops with UncaughtGuestException
+
+    Syscall, // n [, argc] -- pops argc args (first pushed is args[0]), calls host fn n, pushes its result
+    Trap,    // code -- hands the whole Context to the trap handler for code, its TrapOutcome says what's next
+
+    // output, written to the sink set with Context::set_output (stdout by default)
+    Print,     // pops a value, writes it in decimal plus a newline
+    PrintChar, // pops a unicode code point and writes it as utf-8
+    PrintStr,  // i -- writes string i of the program's string table as is, no newline added
+
+    // input, from the source set with Context::set_input (stdin by default)
+    // pushes the value then 1, or just 0 once input is exhausted, so `Read; JumpZero done` loops work
+    Read,
+
+    Yield, // [n] -- hand control back to the host, n=1 pops the value to yield, no operand yields 0
+
+    // [i] -- pops a value, zero stops the run with AssertionFailed carrying string i of the table as its
+    // message. Anything else carries on, so guest programs can check themselves
+    Assert,
+
+    // introspection, each pushes one value
+    GetPC,        // the pc of the GetPC itself, not of the instruction after it
+    Depth,        // operand stack depth before the push
+    GetCallDepth, // frames on the call stack, 0 outside any call
+
+    // pseudo-random numbers from the Context's generator, the same seed gives the same sequence
+    Rand,      // pushes the next 64 random bits
+    RandRange, // pops bound, pushes a value in [0, bound), bound has to be positive
+
+    // progress
+    Steps,  // pushes Context::steps, this instruction included
+    TimeMs, // pushes milliseconds from the Context's Clock, by default since the run started. Not deterministic
+
+    Nop,  // does nothing, handy as a patch target
+    Halt, // stop with an exit code from the operand, or popped from the stack
+    FExit, // stop with the float popped from the stack, Context::exit_value tells it from an Exit
+
+    Exit 
+}
+
+impl OpCode {
+    // every opcode in declaration order, so ALL[op as usize] == op
+    pub const ALL: [OpCode; 131] = [
+        OpCode::Push, OpCode::Pop, OpCode::Dup, OpCode::Swap, OpCode::Over, OpCode::Rot, OpCode::Pick,
+        OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div, OpCode::Mod, OpCode::MulHi, OpCode::MulHiU,
+        OpCode::AddImm, OpCode::SubImm, OpCode::MulImm, OpCode::Neg, OpCode::Abs, OpCode::Min, OpCode::Max,
+        OpCode::And, OpCode::Or, OpCode::Xor, OpCode::Not, OpCode::Shl, OpCode::Shr, OpCode::Sar, OpCode::Eq,
+        OpCode::Ne, OpCode::Lt, OpCode::Le, OpCode::Gt, OpCode::Ge, OpCode::DivU, OpCode::ModU, OpCode::LtU,
+        OpCode::LeU, OpCode::GtU, OpCode::GeU, OpCode::Select, OpCode::FPush, OpCode::FAdd, OpCode::FSub,
+        OpCode::FMul, OpCode::FDiv, OpCode::FEq, OpCode::FLt, OpCode::FGt, OpCode::IntToFloat,
+        OpCode::FloatToInt, OpCode::FPrint, OpCode::LoadReg, OpCode::StoreReg, OpCode::AddReg, OpCode::SubReg,
+        OpCode::MulReg, OpCode::DivReg, OpCode::MovReg, OpCode::IncReg, OpCode::DecReg, OpCode::Load,
+        OpCode::Store, OpCode::LoadInd, OpCode::StoreInd, OpCode::MemSet, OpCode::MemCpy, OpCode::Alloc,
+        OpCode::Free, OpCode::PushM, OpCode::PopM, OpCode::Load8, OpCode::Load16, OpCode::Load32,
+        OpCode::Load64, OpCode::Store8, OpCode::Store16, OpCode::Store32, OpCode::Store64, OpCode::AtomicAdd,
+        OpCode::AtomicCas, OpCode::Send, OpCode::Recv, OpCode::Jump, OpCode::JumpEq, OpCode::JumpGt,
+        OpCode::JumpLt, OpCode::JumpNe, OpCode::JumpGe, OpCode::JumpLe, OpCode::JumpZero, OpCode::JumpNotZero,
+        OpCode::JumpRel, OpCode::JumpRelEq, OpCode::JumpRelNe, OpCode::JumpRelGt, OpCode::JumpRelLt,
+        OpCode::JumpRelGe, OpCode::JumpRelLe, OpCode::Switch, OpCode::JumpDyn, OpCode::Call,
+        OpCode::CallIndirect, OpCode::CallN, OpCode::TailCall, OpCode::Return, OpCode::Enter, OpCode::LoadLocal,
+        OpCode::StoreLocal, OpCode::TryPush, OpCode::TryPop, OpCode::Throw, OpCode::Syscall, OpCode::Trap,
+        OpCode::Print, OpCode::PrintChar, OpCode::PrintStr, OpCode::Read, OpCode::Yield, OpCode::Assert,
+        OpCode::GetPC, OpCode::Depth, OpCode::GetCallDepth, OpCode::Rand, OpCode::RandRange, OpCode::Steps,
+        OpCode::TimeMs, OpCode::Nop, OpCode::Halt, OpCode::FExit, OpCode::Exit,
+    ];
+
+    // how many operands the opcode takes, Switch is open ended
+    pub fn operand_count(self) -> RangeInclusive<usize> {
+        match self {
+            OpCode::Push | OpCode::FPush | OpCode::Pick | OpCode::AddImm | OpCode::SubImm | OpCode::MulImm => 1..=1,
+            OpCode::LoadReg | OpCode::StoreReg | OpCode::IncReg | OpCode::DecReg => 1..=1,
+            OpCode::AddReg | OpCode::SubReg | OpCode::MulReg | OpCode::DivReg => 3..=3,
+            OpCode::MovReg => 2..=2,
+            OpCode::Load | OpCode::Store => 1..=2,
+            OpCode::Load8 | OpCode::Load16 | OpCode::Load32 | OpCode::Load64 => 1..=2,
+            OpCode::Store8 | OpCode::Store16 | OpCode::Store32 | OpCode::Store64 => 1..=2,
+            OpCode::Jump | OpCode::JumpEq | OpCode::JumpGt | OpCode::JumpLt | OpCode::JumpNe => 1..=1,
+            OpCode::JumpGe | OpCode::JumpLe | OpCode::JumpZero | OpCode::JumpNotZero => 1..=1,
+            OpCode::JumpRel | OpCode::JumpRelEq | OpCode::JumpRelNe | OpCode::JumpRelGt => 1..=1,
+            OpCode::JumpRelLt | OpCode::JumpRelGe | OpCode::JumpRelLe => 1..=1,
+            OpCode::Switch => 1..=usize::MAX,
+            OpCode::Call | OpCode::TailCall | OpCode::Syscall => 1..=2,
+            OpCode::CallN => 2..=2,
+            OpCode::Return | OpCode::Yield | OpCode::Halt | OpCode::Exit | OpCode::Assert => 0..=1,
+            OpCode::Enter | OpCode::LoadLocal | OpCode::StoreLocal | OpCode::PrintStr | OpCode::Trap => 1..=1,
+            OpCode::TryPush | OpCode::AtomicAdd | OpCode::AtomicCas | OpCode::Send | OpCode::Recv => 1..=1,
+            _ => 0..=0,
+        }
+    }
+
+    // whether the operand at `position` is an absolute pc: jump and call targets, every Switch case
+    pub fn is_target_operand(self, position: usize) -> bool {
+        match self {
+            OpCode::Switch => true,
+            OpCode::Jump | OpCode::JumpEq | OpCode::JumpGt | OpCode::JumpLt | OpCode::JumpNe | OpCode::JumpGe
+            | OpCode::JumpLe | OpCode::JumpZero | OpCode::JumpNotZero | OpCode::Call | OpCode::TailCall
+            | OpCode::CallN | OpCode::TryPush => position == 0,
+            _ => false,
+        }
+    }
+
+    // whether the operand at `position` indexes the program's string table
+    pub fn is_string_operand(self, position: usize) -> bool {
+        matches!(self, OpCode::PrintStr | OpCode::Assert) && position == 0
+    }
+
+    // positions holding register numbers, given how many operands the instruction has
+    pub fn register_operands(self, operand_count: usize) -> Range<usize> {
+        match self {
+            OpCode::LoadReg | OpCode::StoreReg | OpCode::IncReg | OpCode::DecReg | OpCode::Exit => 0..operand_count.min(1),
+            OpCode::AddReg | OpCode::SubReg | OpCode::MulReg | OpCode::DivReg => 0..operand_count.min(3),
+            OpCode::MovReg => 0..operand_count.min(2),
+            // [reg, offset] form
+            OpCode::Load | OpCode::Store | OpCode::Load8 | OpCode::Load16 | OpCode::Load32 | OpCode::Load64
+            | OpCode::Store8 | OpCode::Store16 | OpCode::Store32 | OpCode::Store64 if operand_count >= 2 => 0..1,
+            _ => 0..0,
+        }
+    }
+
+    // JumpRel and friends, operand 0 is an offset from the instruction's own pc
+    pub fn is_relative_jump(self) -> bool {
+        matches!(
+            self,
+            OpCode::JumpRel | OpCode::JumpRelEq | OpCode::JumpRelNe | OpCode::JumpRelGt | OpCode::JumpRelLt
+                | OpCode::JumpRelGe | OpCode::JumpRelLe
+        )
+    }
+}
+
+// Instruction structure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: OpCode,
+    pub operands: Vec<i64>,
+}
+
+// operands an Op holds inline, enough for every opcode except Switch's case table
+const INLINE_OPERANDS: usize = 3;
+
+// fixed width copy of an Instruction that the interpreter loop runs from, built once when a Context is created
+// so executing a step never clones or follows a heap pointer. Switch reads its full table from the Instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Op {
+    pub(crate) opcode: OpCode,
+    len: u8,
+    inline: [i64; INLINE_OPERANDS],
+    pub(crate) fused: Option<Fused>, // set by fuse when a superinstruction starts here
+}
+
+impl Op {
+    pub(crate) fn operands(&self) -> &[i64] {
+        &self.inline[..self.len as usize]
+    }
+}
+
+impl From<&Instruction> for Op {
+    fn from(instruction: &Instruction) -> Self {
+        // extra operands are ignored by everything but Switch, so only the first few are kept
+        let len = instruction.operands.len().min(INLINE_OPERANDS);
+        let mut inline = [0; INLINE_OPERANDS];
+        inline[..len].copy_from_slice(&instruction.operands[..len]);
+        Op { opcode: instruction.opcode, len: len as u8, inline, fused: None }
+    }
+}
+
+// a program plus the memory it expects to find initialized
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub name: Option<String>,
+    pub instructions: Vec<Instruction>,
+    pub entry: usize, // pc run starts from, and reset goes back to
+    pub data: Vec<(usize, Vec<i64>)>, // (start address, words) blocks loaded before run
+    pub symbols: Vec<(String, usize)>, // function name -> entry pc
+    pub strings: Vec<String>, // PrintStr's operand indexes this
+    pub debug_info: Option<DebugInfo>,
+}
+
+// where each instruction came from, filled in by assemble_program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub file: String,
+    pub lines: Vec<usize>, // 1-based source line per instruction, 0 when unknown
+}
+
+impl DebugInfo {
+    // `file:line` for the instruction at pc, if its line is known
+    pub fn location(&self, pc: usize) -> Option<String> {
+        self.lines.get(pc).filter(|&&line| line > 0).map(|line| format!("{}:{}", self.file, line))
+    }
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        Program { name: None, instructions, entry: 0, data: Vec::new(), symbols: Vec::new(), strings: Vec::new(), debug_info: None }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    // start at `entry` instead of pc 0
+    pub fn with_entry(mut self, entry: usize) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    // name the function starting at `entry`
+    pub fn with_symbol(mut self, name: &str, entry: usize) -> Self {
+        self.symbols.push((name.to_string(), entry));
+        self
+    }
+
+    // add a block of words starting at `address`
+    pub fn with_data(mut self, address: usize, words: Vec<i64>) -> Self {
+        self.data.push((address, words));
+        self
+    }
+
+    // index of `text` in the string table, added at the end unless an equal string is already there
+    pub fn intern(&mut self, text: &str) -> usize {
+        match self.strings.iter().position(|existing| existing == text) {
+            Some(index) => index,
+            None => {
+                self.strings.push(text.to_string());
+                self.strings.len() - 1
+            },
+        }
+    }
+
+    // data blocks must not overlap or run past the end of the address space
+    pub(crate) fn check_data(&self) -> Result<(), VmError> {
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        for (start, words) in &self.data {
+            let end = start.checked_add(words.len())
+                .ok_or_else(|| VmError::InvalidProgram(format!("Data block at {} with {} words overflows the address space", start, words.len())))?;
+            blocks.push((*start, end));
+        }
+        blocks.sort();
+        for pair in blocks.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                return Err(VmError::InvalidProgram(format!("Data block at {} overlaps data block {}..{}", pair[1].0, pair[0].0, pair[0].1)));
+            }
+        }
+
+        Ok(())
+    }
+}
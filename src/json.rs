@@ -0,0 +1,536 @@
+// programs as JSON, for toolchains that would rather not write bytecode:
+//
+//   {
+//     "instructions": [{"opcode": "Push", "operands": [5]}, {"opcode": "Exit"}],
+//     "metadata": {"name": "...", "entry": 0, "data": [{"address": 8, "words": [1, 2]}], "symbols": {"double": 1},
+//                  "strings": ["hello\n"]}
+//   }
+//
+// opcode names are the OpCode variants, operands default to none and metadata is optional.
+// Unknown metadata keys (a compiler version, say) are accepted and dropped, anywhere else they're an error.
+// Snapshots have a JSON form too, see Snapshot::to_json. The reader is hand rolled so the crate stays
+// dependency free
+
+use alloc::collections::BTreeMap;
+use core::fmt::Write;
+
+use crate::context::{Frame, TryHandler, REGISTER_COUNT};
+use crate::error::JsonError;
+use crate::instruction::{Instruction, OpCode, Program};
+use crate::prelude::*;
+use crate::snapshot::Snapshot;
+
+// nesting deeper than any real program needs, stops hostile input from blowing the stack
+const MAX_DEPTH: usize = 64;
+
+impl Program {
+    pub fn from_json(json: &str) -> Result<Program, JsonError> {
+        let value = parse(json, "program")?;
+        let fields = object(&value, "")?;
+        check_keys(fields, "", &["instructions", "metadata"])?;
+        let instructions = array(field(fields, "", "instructions")?, "instructions")?
+            .iter()
+            .enumerate()
+            .map(|(index, value)| instruction(value, &format!("instructions[{}]", index)))
+            .collect::<Result<_, _>>()?;
+        let mut program = Program::new(instructions);
+
+        let Some(metadata) = optional_field(fields, "metadata") else {
+            return Ok(program);
+        };
+        let metadata = object(metadata, "metadata")?;
+        if let Some(name) = optional_field(metadata, "name") {
+            match name {
+                Value::String(name) => program.name = Some(name.clone()),
+                other => return Err(schema("metadata.name", format!("expected a string, found {}", other.kind()))),
+            }
+        }
+        if let Some(entry) = optional_field(metadata, "entry") {
+            program.entry = address(entry, "metadata.entry")?;
+        }
+        if let Some(data) = optional_field(metadata, "data") {
+            for (index, block) in array(data, "metadata.data")?.iter().enumerate() {
+                let path = format!("metadata.data[{}]", index);
+                let block = object(block, &path)?;
+                check_keys(block, &path, &["address", "words"])?;
+                let address = address(field(block, &path, "address")?, &format!("{}.address", path))?;
+                let words = integers(field(block, &path, "words")?, &format!("{}.words", path))?;
+                program.data.push((address, words));
+            }
+        }
+        if let Some(symbols) = optional_field(metadata, "symbols") {
+            for (name, entry) in object(symbols, "metadata.symbols")? {
+                let entry = address(entry, &format!("metadata.symbols.{}", name))?;
+                program.symbols.push((name.clone(), entry));
+            }
+        }
+        if let Some(strings) = optional_field(metadata, "strings") {
+            for (index, text) in array(strings, "metadata.strings")?.iter().enumerate() {
+                match text {
+                    Value::String(text) => program.strings.push(text.clone()),
+                    other => return Err(schema(&format!("metadata.strings[{}]", index), format!("expected a string, found {}", other.kind()))),
+                }
+            }
+        }
+        Ok(program)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n  \"instructions\": [");
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(out, "{}\n    {{\"opcode\": \"{:?}\"", separator, instruction.opcode).unwrap();
+            if !instruction.operands.is_empty() {
+                write!(out, ", \"operands\": {:?}", instruction.operands).unwrap();
+            }
+            out.push('}');
+        }
+        out.push_str(if self.instructions.is_empty() { "]" } else { "\n  ]" });
+
+        if self.name.is_some() || self.entry != 0 || !self.data.is_empty() || !self.symbols.is_empty() || !self.strings.is_empty() {
+            out.push_str(",\n  \"metadata\": {");
+            if let Some(name) = &self.name {
+                write!(out, "\"name\": {}, ", quote(name)).unwrap();
+            }
+            if self.entry != 0 {
+                write!(out, "\"entry\": {}, ", self.entry).unwrap();
+            }
+            let data: Vec<String> =
+                self.data.iter().map(|(address, words)| format!("{{\"address\": {}, \"words\": {:?}}}", address, words)).collect();
+            let symbols: Vec<String> =
+                self.symbols.iter().map(|(name, entry)| format!("{}: {}", quote(name), entry)).collect();
+            write!(out, "\"data\": [{}], \"symbols\": {{{}}}", data.join(", "), symbols.join(", ")).unwrap();
+            if !self.strings.is_empty() {
+                let strings: Vec<String> = self.strings.iter().map(|text| quote(text)).collect();
+                write!(out, ", \"strings\": [{}]", strings.join(", ")).unwrap();
+            }
+            out.push('}');
+        }
+        out.push_str("\n}\n");
+        out
+    }
+}
+
+// a snapshot on one line per field:
+//
+//   {
+//     "program_hash": 1234567890,
+//     "pc": 4,
+//     "stack": [1, 2],
+//     "call_stack": [{"return_addr": 2, "locals": [5], "stack_base": 0}],
+//     "handlers": [{"pc": 9, "stack_depth": 1, "call_depth": 1}],
+//     "registers": [0, 0, ...],
+//     "memory": [[8, 1], [9, 2]],
+//     "linear": [0, 255],
+//     "allocations": [[4096, 2]],
+//     "steps": 12,
+//     "rng": 99,
+//     "finished": null,
+//     "fuel": null
+//   }
+//
+// memory and allocations are [address, value] and [base, size] pairs sorted by address. Every field
+// is required, restore still checks program_hash against the program it's given
+impl Snapshot {
+    pub fn to_json(&self) -> String {
+        let memory: BTreeMap<_, _> = self.memory.iter().collect();
+        let memory: Vec<String> = memory.iter().map(|(addr, value)| format!("[{}, {}]", addr, value)).collect();
+        let allocations: Vec<String> = self.allocations.iter().map(|(base, size)| format!("[{}, {}]", base, size)).collect();
+        let call_stack: Vec<String> = self.call_stack.iter()
+            .map(|frame| {
+                format!("{{\"return_addr\": {}, \"locals\": {:?}, \"stack_base\": {}}}", frame.return_addr, frame.locals, frame.stack_base)
+            })
+            .collect();
+        let handlers: Vec<String> = self.handlers.iter()
+            .map(|handler| {
+                format!("{{\"pc\": {}, \"stack_depth\": {}, \"call_depth\": {}}}", handler.pc, handler.stack_depth, handler.call_depth)
+            })
+            .collect();
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+
+        let mut out = String::from("{\n");
+        writeln!(out, "  \"program_hash\": {},", self.program_hash).unwrap();
+        writeln!(out, "  \"pc\": {},", self.pc).unwrap();
+        writeln!(out, "  \"stack\": {:?},", self.stack).unwrap();
+        writeln!(out, "  \"call_stack\": [{}],", call_stack.join(", ")).unwrap();
+        writeln!(out, "  \"handlers\": [{}],", handlers.join(", ")).unwrap();
+        writeln!(out, "  \"registers\": {:?},", self.registers).unwrap();
+        writeln!(out, "  \"memory\": [{}],", memory.join(", ")).unwrap();
+        writeln!(out, "  \"linear\": {:?},", self.linear).unwrap();
+        writeln!(out, "  \"allocations\": [{}],", allocations.join(", ")).unwrap();
+        writeln!(out, "  \"steps\": {},", self.steps).unwrap();
+        writeln!(out, "  \"rng\": {},", self.rng).unwrap();
+        writeln!(out, "  \"finished\": {},", optional(self.finished.map(|value| value.to_string()))).unwrap();
+        writeln!(out, "  \"fuel\": {}", optional(self.fuel.map(|fuel| fuel.to_string()))).unwrap();
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn from_json(json: &str) -> Result<Snapshot, JsonError> {
+        let value = parse(json, "snapshot")?;
+        let fields = object(&value, "")?;
+        check_keys(fields, "", &[
+            "program_hash", "pc", "stack", "call_stack", "handlers", "registers", "memory", "linear", "allocations", "steps",
+            "rng", "finished", "fuel",
+        ])?;
+
+        let mut call_stack = Vec::new();
+        for (index, frame) in array(field(fields, "", "call_stack")?, "call_stack")?.iter().enumerate() {
+            let path = format!("call_stack[{}]", index);
+            let frame = object(frame, &path)?;
+            check_keys(frame, &path, &["return_addr", "locals", "stack_base"])?;
+            call_stack.push(Frame {
+                return_addr: address(field(frame, &path, "return_addr")?, &format!("{}.return_addr", path))?,
+                locals: integers(field(frame, &path, "locals")?, &format!("{}.locals", path))?,
+                stack_base: address(field(frame, &path, "stack_base")?, &format!("{}.stack_base", path))?,
+            });
+        }
+        let mut handlers = Vec::new();
+        for (index, handler) in array(field(fields, "", "handlers")?, "handlers")?.iter().enumerate() {
+            let path = format!("handlers[{}]", index);
+            let handler = object(handler, &path)?;
+            check_keys(handler, &path, &["pc", "stack_depth", "call_depth"])?;
+            handlers.push(TryHandler {
+                pc: address(field(handler, &path, "pc")?, &format!("{}.pc", path))?,
+                stack_depth: address(field(handler, &path, "stack_depth")?, &format!("{}.stack_depth", path))?,
+                call_depth: address(field(handler, &path, "call_depth")?, &format!("{}.call_depth", path))?,
+            });
+        }
+        let registers = integers(field(fields, "", "registers")?, "registers")?;
+        let registers: [i64; REGISTER_COUNT] = registers.as_slice().try_into()
+            .map_err(|_| schema("registers", format!("expected {} registers, found {}", REGISTER_COUNT, registers.len())))?;
+        let mut linear = Vec::new();
+        for (index, byte) in integers(field(fields, "", "linear")?, "linear")?.into_iter().enumerate() {
+            linear.push(u8::try_from(byte).map_err(|_| schema(&format!("linear[{}]", index), format!("expected a byte, found {}", byte)))?);
+        }
+        let mut allocations = BTreeMap::new();
+        for (base, size) in pairs(field(fields, "", "allocations")?, "allocations")? {
+            let size = usize::try_from(size).map_err(|_| schema("allocations", format!("expected a block size, found {}", size)))?;
+            allocations.insert(base, size);
+        }
+
+        Ok(Snapshot {
+            program_hash: unsigned(field(fields, "", "program_hash")?, "program_hash")?,
+            pc: address(field(fields, "", "pc")?, "pc")?,
+            stack: integers(field(fields, "", "stack")?, "stack")?,
+            call_stack,
+            handlers,
+            registers,
+            memory: pairs(field(fields, "", "memory")?, "memory")?.into_iter().collect(),
+            linear,
+            allocations,
+            steps: unsigned(field(fields, "", "steps")?, "steps")?,
+            rng: unsigned(field(fields, "", "rng")?, "rng")?,
+            finished: match field(fields, "", "finished")? {
+                Value::Null => None,
+                value => Some(integer(value, "finished")?),
+            },
+            fuel: match field(fields, "", "fuel")? {
+                Value::Null => None,
+                value => Some(unsigned(value, "fuel")?),
+            },
+        })
+    }
+}
+
+// the whole of `json` as one value, `what` names it in the trailing characters error
+fn parse(json: &str, what: &str) -> Result<Value, JsonError> {
+    let mut parser = Parser { src: json, pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos < json.len() {
+        return Err(parser.syntax(&format!("trailing characters after the {}", what)));
+    }
+    Ok(value)
+}
+
+fn instruction(value: &Value, path: &str) -> Result<Instruction, JsonError> {
+    let fields = object(value, path)?;
+    check_keys(fields, path, &["opcode", "operands"])?;
+    let opcode_path = format!("{}.opcode", path);
+    let name = match field(fields, path, "opcode")? {
+        Value::String(name) => name,
+        other => return Err(schema(&opcode_path, format!("expected an opcode name, found {}", other.kind()))),
+    };
+    let opcode = *OpCode::ALL
+        .iter()
+        .find(|opcode| format!("{:?}", opcode) == *name)
+        .ok_or_else(|| schema(&opcode_path, format!("unknown opcode {:?}", name)))?;
+    let operands = match optional_field(fields, "operands") {
+        Some(operands) => integers(operands, &format!("{}.operands", path))?,
+        None => Vec::new(),
+    };
+    Ok(Instruction { opcode, operands })
+}
+
+fn schema(path: &str, message: String) -> JsonError {
+    JsonError::Schema { path: path.to_string(), message }
+}
+
+fn object<'a>(value: &'a Value, path: &str) -> Result<&'a [(String, Value)], JsonError> {
+    match value {
+        Value::Object(fields) => Ok(fields),
+        other => Err(schema(path, format!("expected an object, found {}", other.kind()))),
+    }
+}
+
+fn array<'a>(value: &'a Value, path: &str) -> Result<&'a [Value], JsonError> {
+    match value {
+        Value::Array(items) => Ok(items),
+        other => Err(schema(path, format!("expected an array, found {}", other.kind()))),
+    }
+}
+
+fn integer(value: &Value, path: &str) -> Result<i64, JsonError> {
+    match value {
+        Value::Number(number) => {
+            number.parse().map_err(|_| schema(path, format!("expected a 64-bit integer, found {}", number)))
+        },
+        other => Err(schema(path, format!("expected an integer, found {}", other.kind()))),
+    }
+}
+
+fn integers(value: &Value, path: &str) -> Result<Vec<i64>, JsonError> {
+    array(value, path)?.iter().enumerate().map(|(index, value)| integer(value, &format!("{}[{}]", path, index))).collect()
+}
+
+// hashes, step counts and the rng state use the full u64 range
+fn unsigned(value: &Value, path: &str) -> Result<u64, JsonError> {
+    match value {
+        Value::Number(number) => {
+            number.parse().map_err(|_| schema(path, format!("expected an unsigned 64-bit integer, found {}", number)))
+        },
+        other => Err(schema(path, format!("expected an integer, found {}", other.kind()))),
+    }
+}
+
+// [[address, value], ...]
+fn pairs(value: &Value, path: &str) -> Result<Vec<(usize, i64)>, JsonError> {
+    array(value, path)?
+        .iter()
+        .enumerate()
+        .map(|(index, pair)| {
+            let path = format!("{}[{}]", path, index);
+            match integers(pair, &path)?.as_slice() {
+                &[addr, value] => {
+                    let addr = usize::try_from(addr).map_err(|_| schema(&path, format!("expected an address, found {}", addr)))?;
+                    Ok((addr, value))
+                },
+                other => Err(schema(&path, format!("expected an [address, value] pair, found {} numbers", other.len()))),
+            }
+        })
+        .collect()
+}
+
+fn address(value: &Value, path: &str) -> Result<usize, JsonError> {
+    let value = integer(value, path)?;
+    usize::try_from(value).map_err(|_| schema(path, format!("expected an address, found {}", value)))
+}
+
+fn optional_field<'a>(fields: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    fields.iter().find(|(name, _)| name == key).map(|(_, value)| value)
+}
+
+fn field<'a>(fields: &'a [(String, Value)], path: &str, key: &str) -> Result<&'a Value, JsonError> {
+    let path = if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    optional_field(fields, key).ok_or_else(|| schema(&path, "missing field".to_string()))
+}
+
+fn check_keys(fields: &[(String, Value)], path: &str, allowed: &[&str]) -> Result<(), JsonError> {
+    match fields.iter().find(|(name, _)| !allowed.contains(&name.as_str())) {
+        Some((name, _)) => {
+            let path = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+            Err(schema(&path, format!("unknown field, expected one of {:?}", allowed)))
+        },
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// parsed JSON, numbers keep their text so integers come out exact
+enum Value {
+    Null,
+    Bool, // no field takes a boolean, only the kind matters
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+            Value::Array(_) => "an array",
+            Value::Object(_) => "an object",
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn syntax(&self, message: &str) -> JsonError {
+        let before = &self.src[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        JsonError::Syntax { line, column, message: message.to_string() }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| matches!(c, ' ' | '\t' | '\n' | '\r')) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.syntax(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.syntax("nested too deeply"));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        },
+                        _ => return Err(self.syntax("expected ',' or '}'")),
+                    }
+                }
+            },
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        },
+                        _ => return Err(self.syntax("expected ',' or ']'")),
+                    }
+                }
+            },
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => {
+                let start = self.pos;
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                    self.pos += c.len_utf8();
+                }
+                Ok(Value::Number(self.src[start..self.pos].to_string()))
+            },
+            _ => {
+                for (word, value) in [("true", Value::Bool), ("false", Value::Bool), ("null", Value::Null)] {
+                    if self.src[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.syntax("expected a value"))
+            },
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        if self.peek() != Some('"') {
+            return Err(self.syntax("expected a string"));
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.syntax("unterminated string"))?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self.peek().ok_or_else(|| self.syntax("unterminated string"))?;
+                    self.pos += escape.len_utf8();
+                    match escape {
+                        '"' | '\\' | '/' => out.push(escape),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // a surrogate pair spells one code point as two escapes
+                            if (0xd800..0xdc00).contains(&code) && self.src[self.pos..].starts_with("\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            out.push(char::from_u32(code).ok_or_else(|| self.syntax("invalid unicode escape"))?);
+                        },
+                        _ => return Err(self.syntax("invalid escape")),
+                    }
+                },
+                c if (c as u32) < 0x20 => return Err(self.syntax("control character in string")),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.src.get(self.pos..self.pos + 4).filter(|digits| digits.chars().all(|c| c.is_ascii_hexdigit()));
+        let code = digits.and_then(|digits| u32::from_str_radix(digits, 16).ok()).ok_or_else(|| self.syntax("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+}
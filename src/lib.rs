@@ -0,0 +1,73 @@
+// beef: a small stack + register bytecode VM. The crate is no_std + alloc underneath, the default std
+// feature adds the CLI, replay, VmHandle, stdin/stdout defaults and io::Write sinks on top
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod analysis;
+mod assembler;
+mod builder;
+mod bytecode;
+mod cfg;
+mod channel;
+#[cfg(feature = "std")]
+mod cli;
+mod context;
+mod disassembler;
+mod error;
+mod fuse;
+#[cfg(feature = "std")]
+mod handle;
+mod host;
+mod instruction;
+#[cfg(feature = "json")]
+mod json;
+mod prelude;
+mod profile;
+#[cfg(feature = "std")]
+mod replay;
+mod scheduler;
+mod shared;
+mod snapshot;
+mod sync;
+mod validate;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use analysis::{analyze_stack, analyze_stack_with, BlockDepth, Depth, StackConfig, StackReport, StackWarning};
+pub use assembler::{assemble, assemble_program};
+pub use builder::ProgramBuilder;
+pub use bytecode::{opcode_byte, FORMAT_VERSION, MAX_OPERANDS, OPCODE_SET_VERSION};
+pub use cfg::{cfg, Block, Cfg, Edge, EdgeKind};
+pub use channel::Channel;
+#[cfg(feature = "std")]
+pub use cli::{build_file, load_program, parse_args, parse_build_args, run_file, BuildOptions, ProgramFormat, RunOptions, USAGE};
+pub use context::{
+    ArithMode, BreakCondition, Config, Context, ExecutionResult, ExitValue, Frame, RunOutcome, StepOutcome, Watch,
+    DEFAULT_RNG_SEED, MAX_BULK_CELLS, MAX_LOCALS, REGISTER_COUNT, SP_REGISTER,
+};
+pub use disassembler::{disassemble, disassemble_program, disassemble_with_coverage, merge_coverage};
+pub use error::{AsmError, AsmErrorKind, BuildError, DecodeError, EncodeError, StackError, ValidationError, VmError};
+#[cfg(feature = "json")]
+pub use error::JsonError;
+#[cfg(feature = "std")]
+pub use error::{CliError, ReplayError};
+#[cfg(feature = "std")]
+pub use handle::VmHandle;
+pub use host::{
+    Clock, EventSink, ExecutionEvent, ExecutionHooks, HostFn, Input, MmioHandler, Output, SharedBuffer, TrapHandler, TrapOutcome,
+};
+#[cfg(feature = "std")]
+pub use host::FunctionCounter;
+pub use instruction::{DebugInfo, Instruction, OpCode, Program};
+pub use profile::{OpcodeStats, PcStats, ProfileReport};
+#[cfg(feature = "std")]
+pub use replay::{replay, Divergence};
+pub use scheduler::{Scheduler, TaskStatus};
+pub use shared::SharedMemory;
+pub use snapshot::Snapshot;
+pub use validate::{check_flow, validate, validate_strict};
+#[cfg(feature = "wasm")]
+pub use wasm::{VmState, VmStatus, WasmVm, RECENT_EVENTS};
@@ -0,0 +1,13 @@
+// what the std prelude would have brought in. The crate is always no_std, so every module takes these
+// from here instead
+
+pub(crate) use alloc::boxed::Box;
+pub(crate) use alloc::string::{String, ToString};
+pub(crate) use alloc::vec::Vec;
+pub(crate) use alloc::{format, vec};
+
+// there's no hasher without std, the ordered collections stand in (every key type here is Ord)
+#[cfg(not(feature = "std"))]
+pub(crate) use alloc::collections::{BTreeMap as HashMap, BTreeSet as HashSet};
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{HashMap, HashSet};
@@ -0,0 +1,147 @@
+// execution statistics per opcode and per pc, collected while Context::enable_profiling is on. Counts of
+// executed instructions are the measure, they come out the same on every run and every target. Wall time
+// per opcode is extra, only there when the build has a clock
+
+use core::fmt;
+use core::time::Duration;
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+use std::time::Instant;
+
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+// how many of the hottest pcs the report prints
+const HOTTEST_PCS: usize = 10;
+
+// wall time since start. There's no clock without std, and none on wasm32-unknown-unknown either where
+// Instant::now panics, so there every reading is None
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+    pub(crate) fn elapsed(&self) -> Option<Duration> {
+        Some(self.start.elapsed())
+    }
+
+    #[cfg(not(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown")))))]
+    pub(crate) fn elapsed(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// what the run loop accumulates, indexed by opcode and by pc
+pub(crate) struct Profiler {
+    opcodes: Vec<(u64, Option<Duration>)>,
+    pcs: Vec<u64>,
+}
+
+impl Profiler {
+    pub(crate) fn new(program_len: usize) -> Self {
+        Profiler { opcodes: vec![(0, None); OpCode::ALL.len()], pcs: vec![0; program_len] }
+    }
+
+    pub(crate) fn record(&mut self, pc: usize, opcode: OpCode, elapsed: Option<Duration>) {
+        let (count, time) = &mut self.opcodes[opcode as usize];
+        *count += 1;
+        if let Some(elapsed) = elapsed {
+            *time = Some(time.unwrap_or_default() + elapsed);
+        }
+        self.pcs[pc] += 1;
+    }
+
+    pub(crate) fn report(&self, program: &[Instruction]) -> ProfileReport {
+        let mut opcodes: Vec<_> = OpCode::ALL.iter()
+            .zip(&self.opcodes)
+            .filter(|(_, (count, _))| *count > 0)
+            .map(|(&opcode, &(count, time))| OpcodeStats { opcode, count, time })
+            .collect();
+        // stable and by count alone, so the order doesn't depend on timing noise
+        opcodes.sort_by_key(|stats| core::cmp::Reverse(stats.count));
+
+        let mut pcs: Vec<_> = self.pcs.iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(pc, &count)| PcStats { pc, count, instruction: program[pc].clone() })
+            .collect();
+        // stable, so equally hot pcs stay in program order
+        pcs.sort_by_key(|stats| core::cmp::Reverse(stats.count));
+
+        ProfileReport { total: self.pcs.iter().sum(), opcodes, pcs }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeStats {
+    pub opcode: OpCode,
+    pub count: u64,
+    pub time: Option<Duration>, // summed wall time of every execution, None without a clock
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcStats {
+    pub pc: usize,
+    pub count: u64,
+    pub instruction: Instruction,
+}
+
+// Context::profile_report's result. opcodes and pcs are hottest first and leave out anything that never ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub total: u64, // instructions executed while profiling
+    pub opcodes: Vec<OpcodeStats>,
+    pub pcs: Vec<PcStats>,
+}
+
+impl ProfileReport {
+    // executions of one opcode, 0 if it never ran
+    pub fn count(&self, opcode: OpCode) -> u64 {
+        self.opcodes.iter().find(|stats| stats.opcode == opcode).map_or(0, |stats| stats.count)
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.total.max(1) as f64
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the time column only when there's something to put in it
+        let timed = self.opcodes.iter().any(|stats| stats.time.is_some());
+        if timed {
+            writeln!(f, "{:>10} {:>7}  {:<12} opcode", "count", "%", "time")?;
+        } else {
+            writeln!(f, "{:>10} {:>7}  opcode", "count", "%")?;
+        }
+        for stats in &self.opcodes {
+            write!(f, "{:>10} {:>6.2}%  ", stats.count, self.percent(stats.count))?;
+            if timed {
+                write!(f, "{:<12} ", stats.time.map_or_else(|| "-".to_string(), |time| format!("{:?}", time)))?;
+            }
+            writeln!(f, "{:?}", stats.opcode)?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "hottest pcs:")?;
+        writeln!(f, "{:>10} {:>7}  {:>6}  instruction", "count", "%", "pc")?;
+        for stats in self.pcs.iter().take(HOTTEST_PCS) {
+            let operands: Vec<String> = stats.instruction.operands.iter().map(ToString::to_string).collect();
+            writeln!(
+                f, "{:>10} {:>6.2}%  {:>6}  {:?} {}",
+                stats.count, self.percent(stats.count), stats.pc, stats.instruction.opcode, operands.join(" ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+// execution traces: Context::record_trace logs every step to a compact binary stream, replay runs
+// the program again and checks each step against the log. The stream is
+//
+//   magic "BTRC", u8 version
+//   start state: pc, register count and registers, stack length and values, then (since version 2)
+//   the Rand generator's state
+//   then one record per ExecutionEvent, a u8 tag followed by its fields
+//
+// every number after the version is a LEB128 varint, signed values zigzag encoded first.
+// A step is an Instruction record plus whatever follows it up to the next Instruction record.
+// TimeMs replays the value its StackPush recorded, so the clock doesn't have to repeat itself.
+// Input, syscalls and mmio aren't recorded, programs using them only replay if they behave the same
+
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::bytecode::{opcode_byte, opcode_table};
+use crate::context::Context;
+use crate::error::{ReplayError, VmError};
+use crate::host::{Clock, ExecutionEvent, Input};
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+const MAGIC: &[u8; 4] = b"BTRC";
+const VERSION: u8 = 2;
+
+// the first step whose events differ between the recording and the replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize, // 0-based, counted from where recording started
+    pub expected: Vec<ExecutionEvent>, // what the recording saw
+    pub found: Vec<ExecutionEvent>, // what the replay did instead
+    pub error: Option<VmError>, // the replayed step failed
+    // replay state right after the step
+    pub pc: usize,
+    pub stack: Vec<i64>,
+    pub registers: Vec<i64>,
+}
+
+// writes the trace as events arrive. The first write error stops recording and is kept for finish
+pub(crate) struct Recorder {
+    out: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+    buf: Vec<u8>,
+}
+
+impl Recorder {
+    pub(crate) fn new(out: Box<dyn Write + Send>, pc: usize, registers: &[i64], stack: &[i64], rng: u64) -> Self {
+        let mut recorder = Recorder { out, error: None, buf: Vec::new() };
+        recorder.buf.extend_from_slice(MAGIC);
+        recorder.buf.push(VERSION);
+        put_u(&mut recorder.buf, pc as u64);
+        for values in [registers, stack] {
+            put_u(&mut recorder.buf, values.len() as u64);
+            values.iter().for_each(|&value| put_i(&mut recorder.buf, value));
+        }
+        put_u(&mut recorder.buf, rng);
+        recorder.flush_buf();
+        recorder
+    }
+
+    pub(crate) fn record(&mut self, event: &ExecutionEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let buf = &mut self.buf;
+        match event {
+            ExecutionEvent::Instruction { pc, opcode, operands } => {
+                buf.push(0);
+                put_u(buf, *pc as u64);
+                buf.push(opcode_byte(*opcode));
+                put_u(buf, operands.len() as u64);
+                operands.iter().for_each(|&operand| put_i(buf, operand));
+            },
+            ExecutionEvent::StackPush(value) => {
+                buf.push(1);
+                put_i(buf, *value);
+            },
+            ExecutionEvent::StackPop(value) => {
+                buf.push(2);
+                put_i(buf, *value);
+            },
+            ExecutionEvent::RegisterWrite { reg, value } => {
+                buf.push(3);
+                put_u(buf, *reg as u64);
+                put_i(buf, *value);
+            },
+            ExecutionEvent::MemoryWrite { addr, value } => {
+                buf.push(4);
+                put_u(buf, *addr as u64);
+                put_i(buf, *value);
+            },
+            ExecutionEvent::Call { pc, target } => {
+                buf.push(5);
+                put_u(buf, *pc as u64);
+                put_u(buf, *target as u64);
+            },
+            ExecutionEvent::Return { pc, return_addr } => {
+                buf.push(6);
+                put_u(buf, *pc as u64);
+                put_u(buf, *return_addr as u64);
+            },
+            ExecutionEvent::Exit { value } => {
+                buf.push(7);
+                put_i(buf, *value);
+            },
+        }
+        self.flush_buf();
+    }
+
+    fn flush_buf(&mut self) {
+        if let Err(error) = self.out.write_all(&self.buf) {
+            self.error = Some(error);
+        }
+        self.buf.clear();
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+// run `program` from the recorded start state and compare every step with the trace. Ok is the number
+// of steps that matched, which is all of them. Output is discarded and input reads as exhausted
+pub fn replay(program: Vec<Instruction>, mut trace: impl Read) -> Result<usize, ReplayError> {
+    let mut bytes = Vec::new();
+    trace.read_to_end(&mut bytes).map_err(|error| ReplayError::Io(error.to_string()))?;
+    let mut reader = TraceReader { bytes: &bytes, pos: 0 };
+    if reader.bytes.get(..MAGIC.len()) != Some(MAGIC) {
+        return Err(reader.corrupt("not a beef trace (bad magic)"));
+    }
+    reader.pos = MAGIC.len();
+    let version = reader.byte("version")?;
+    if !(1..=VERSION).contains(&version) {
+        return Err(reader.corrupt(&format!("unsupported trace version {}", version)));
+    }
+
+    let mut context = Context::new(program);
+    context.set_pc(reader.usize("start pc")?);
+    let count = reader.usize("register count")?;
+    for index in 0..count {
+        let value = reader.i64("register")?;
+        context.set_register(index, value).map_err(|_| reader.corrupt("too many registers"))?;
+    }
+    let count = reader.usize("stack length")?;
+    let stack = (0..count).map(|_| reader.i64("stack value")).collect::<Result<_, _>>()?;
+    context.set_stack(stack);
+    if version >= 2 {
+        context.set_rng_seed(reader.u64("rng state")?);
+    }
+    context.set_output(Box::new(io::sink()));
+    context.set_input(Input::Values(Box::new(std::iter::empty())));
+
+    let time = Arc::new(AtomicI64::new(0));
+    context.set_clock(Box::new(ReplayClock(Arc::clone(&time))));
+
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&found);
+    context.set_event_sink(Box::new(move |event| sink.lock().unwrap().push(event)));
+
+    let mut steps = 0;
+    let mut expected = reader.event()?;
+    while let Some(first) = expected.take() {
+        let mut step = vec![first];
+        loop {
+            match reader.event()? {
+                Some(event @ ExecutionEvent::Instruction { .. }) => {
+                    expected = Some(event);
+                    break;
+                },
+                Some(event) => step.push(event),
+                None => break,
+            }
+        }
+
+        if matches!(step[0], ExecutionEvent::Instruction { opcode: OpCode::TimeMs, .. }) {
+            let pushed = step.iter().find_map(|event| match event {
+                ExecutionEvent::StackPush(value) => Some(*value),
+                _ => None,
+            });
+            time.store(pushed.unwrap_or(0), Ordering::Relaxed);
+        }
+        let result = context.step();
+        let events = std::mem::take(&mut *found.lock().unwrap());
+        if events != step {
+            return Err(ReplayError::Diverged(Box::new(Divergence {
+                step: steps,
+                expected: step,
+                found: events,
+                error: result.err(),
+                pc: context.pc(),
+                stack: context.stack().to_vec(),
+                registers: context.registers().to_vec(),
+            })));
+        }
+        steps += 1;
+    }
+
+    Ok(steps)
+}
+
+// hands TimeMs whatever the trace says it read
+struct ReplayClock(Arc<AtomicI64>);
+
+impl Clock for ReplayClock {
+    fn now_ms(&mut self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+fn put_u(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn put_i(buf: &mut Vec<u8>, value: i64) {
+    put_u(buf, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+struct TraceReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl TraceReader<'_> {
+    fn corrupt(&self, message: &str) -> ReplayError {
+        ReplayError::Corrupt { offset: self.pos, message: message.to_string() }
+    }
+
+    fn byte(&mut self, expected: &str) -> Result<u8, ReplayError> {
+        let byte = *self.bytes.get(self.pos).ok_or_else(|| self.corrupt(&format!("truncated, expected {}", expected)))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn u64(&mut self, expected: &str) -> Result<u64, ReplayError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte(expected)?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(self.corrupt(&format!("{} is too long", expected)))
+    }
+
+    fn usize(&mut self, expected: &str) -> Result<usize, ReplayError> {
+        let value = self.u64(expected)?;
+        usize::try_from(value).map_err(|_| self.corrupt(&format!("{} {} is too large", expected, value)))
+    }
+
+    fn i64(&mut self, expected: &str) -> Result<i64, ReplayError> {
+        let value = self.u64(expected)?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    // the next record, None at a clean end of the trace
+    fn event(&mut self) -> Result<Option<ExecutionEvent>, ReplayError> {
+        if self.pos == self.bytes.len() {
+            return Ok(None);
+        }
+        let event = match self.byte("event tag")? {
+            0 => {
+                let pc = self.usize("pc")?;
+                let byte = self.byte("opcode")?;
+                let opcode = opcode_table()[byte as usize].ok_or_else(|| self.corrupt(&format!("unknown opcode byte {:#04x}", byte)))?;
+                let count = self.usize("operand count")?;
+                // each operand takes at least a byte, so a bad count can't reserve much
+                let mut operands = Vec::with_capacity(count.min(self.bytes.len() - self.pos));
+                for _ in 0..count {
+                    operands.push(self.i64("operand")?);
+                }
+                ExecutionEvent::Instruction { pc, opcode, operands }
+            },
+            1 => ExecutionEvent::StackPush(self.i64("value")?),
+            2 => ExecutionEvent::StackPop(self.i64("value")?),
+            3 => ExecutionEvent::RegisterWrite { reg: self.usize("register")?, value: self.i64("value")? },
+            4 => ExecutionEvent::MemoryWrite { addr: self.usize("address")?, value: self.i64("value")? },
+            5 => ExecutionEvent::Call { pc: self.usize("pc")?, target: self.usize("target")? },
+            6 => ExecutionEvent::Return { pc: self.usize("pc")?, return_addr: self.usize("return address")? },
+            7 => ExecutionEvent::Exit { value: self.i64("value")? },
+            tag => {
+                self.pos -= 1;
+                return Err(self.corrupt(&format!("unknown event tag {}", tag)));
+            },
+        };
+        Ok(Some(event))
+    }
+}
@@ -0,0 +1,117 @@
+// cooperative multitasking: a Scheduler owns several Contexts and gives each a quantum of steps per
+// round with run_for, in the order they were spawned. A Yield ends a context's turn early, and so does
+// blocking on a channel. Contexts that exit or fail are reported once and dropped, the rest carry on
+// next round
+
+use crate::context::{Context, RunOutcome};
+use crate::error::VmError;
+use crate::prelude::*;
+
+// what a context did with its turn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    Running, // used its whole quantum
+    Yielded(i64), // the next round picks up after the Yield
+    Blocked { pc: usize }, // waiting on a channel, the next round tries the Send or Recv at pc again
+    Stopped(RunOutcome), // at a breakpoint or watchpoint, the next round carries on
+    Exited(i64),
+    Failed(VmError),
+}
+
+impl TaskStatus {
+    // exited or failed, the context is gone from the scheduler
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskStatus::Exited(_) | TaskStatus::Failed(_))
+    }
+}
+
+pub struct Scheduler {
+    quantum: usize,
+    tasks: Vec<(usize, Context)>, // id -> context, in spawn order
+    next_id: usize,
+}
+
+impl Scheduler {
+    // `quantum` steps per context per round, at least 1
+    pub fn new(quantum: usize) -> Self {
+        Scheduler { quantum: quantum.max(1), tasks: Vec::new(), next_id: 0 }
+    }
+
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = quantum.max(1);
+    }
+
+    // add a context to the end of the round, ids aren't reused
+    pub fn spawn(&mut self, context: Context) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tasks.push((id, context));
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // the live contexts with their ids, in round order
+    pub fn contexts(&self) -> impl Iterator<Item = (usize, &Context)> {
+        self.tasks.iter().map(|(id, context)| (*id, context))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Context> {
+        self.tasks.iter().find(|(task, _)| *task == id).map(|(_, context)| context)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Context> {
+        self.tasks.iter_mut().find(|(task, _)| *task == id).map(|(_, context)| context)
+    }
+
+    // take a context out of the scheduler, finished or not
+    pub fn remove(&mut self, id: usize) -> Option<Context> {
+        let index = self.tasks.iter().position(|(task, _)| *task == id)?;
+        Some(self.tasks.remove(index).1)
+    }
+
+    // one turn for every context, returns what each did in round order
+    pub fn round(&mut self) -> Vec<(usize, TaskStatus)> {
+        let quantum = self.quantum;
+        let statuses: Vec<(usize, TaskStatus)> = self
+            .tasks
+            .iter_mut()
+            .map(|(id, context)| {
+                let status = match context.run_for(quantum) {
+                    Ok(RunOutcome::Paused) => TaskStatus::Running,
+                    Ok(RunOutcome::Yielded(value)) => TaskStatus::Yielded(value),
+                    Ok(RunOutcome::Blocked { pc }) => TaskStatus::Blocked { pc },
+                    Ok(RunOutcome::Completed(value)) => TaskStatus::Exited(value),
+                    Ok(outcome) => TaskStatus::Stopped(outcome),
+                    Err(error) => TaskStatus::Failed(error),
+                };
+                (*id, status)
+            })
+            .collect();
+        let finished: Vec<usize> = statuses.iter().filter(|(_, status)| status.is_finished()).map(|(id, _)| *id).collect();
+        self.tasks.retain(|(id, _)| !finished.contains(id));
+        statuses
+    }
+
+    // rounds until every context has finished, the final status of each in the order they finished.
+    // Contexts that never exit keep this running, use round for those. A round where every context
+    // is blocked is a deadlock, run_all stops there and leaves them in the scheduler
+    pub fn run_all(&mut self) -> Vec<(usize, TaskStatus)> {
+        let mut finished = Vec::new();
+        while !self.is_empty() {
+            let statuses = self.round();
+            let deadlocked = statuses.iter().all(|(_, status)| matches!(status, TaskStatus::Blocked { .. }));
+            finished.extend(statuses.into_iter().filter(|(_, status)| status.is_finished()));
+            if deadlocked {
+                break;
+            }
+        }
+        finished
+    }
+}
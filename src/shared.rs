@@ -0,0 +1,95 @@
+// word memory shared between Contexts. Each Context attaches the same SharedMemory at some base
+// address, Load and Store (and the indirect forms) in that range reach the shared cells, every other
+// address stays private to the Context. The region is mapped like an mmio range, MemSet and MemCpy
+// reach the shared cells one at a time as well.
+//
+// Every Load or Store takes the lock for just that one cell. A Load, Add, Store sequence is three
+// separate accesses and another Context can write in between, once Contexts run on different threads
+// or a Scheduler switches between them mid-sequence. AtomicAdd and AtomicCas hold the lock across
+// their read and write, use those for anything read-modify-write
+
+use crate::host::MmioHandler;
+use crate::prelude::*;
+use crate::sync::Shared;
+
+// clones are handles to the same cells
+#[derive(Debug, Clone)]
+pub struct SharedMemory {
+    cells: Shared<Vec<i64>>,
+}
+
+impl SharedMemory {
+    // `size` zeroed cells
+    pub fn new(size: usize) -> Self {
+        SharedMemory { cells: Shared::new(vec![0; size]) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.with(|cells| cells.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // cell at `offset` from the start of the region
+    pub fn get(&self, offset: usize) -> Option<i64> {
+        self.cells.with(|cells| cells.get(offset).copied())
+    }
+
+    // false if offset is past the end
+    pub fn set(&self, offset: usize, value: i64) -> bool {
+        self.cells.with(|cells| match cells.get_mut(offset) {
+            Some(cell) => {
+                *cell = value;
+                true
+            },
+            None => false,
+        })
+    }
+
+    // every cell, copied under the lock
+    pub fn contents(&self) -> Vec<i64> {
+        self.cells.with(|cells| cells.clone())
+    }
+
+    // adds delta with wrapping, returns the old value
+    pub(crate) fn fetch_add(&self, offset: usize, delta: i64) -> i64 {
+        self.cells.with(|cells| {
+            let old = cells[offset];
+            cells[offset] = old.wrapping_add(delta);
+            old
+        })
+    }
+
+    // stores new only if the cell holds expected, returns what it held
+    pub(crate) fn compare_exchange(&self, offset: usize, expected: i64, new: i64) -> i64 {
+        self.cells.with(|cells| {
+            let old = cells[offset];
+            if old == expected {
+                cells[offset] = new;
+            }
+            old
+        })
+    }
+}
+
+// Load and Store on an attached region, addresses come in absolute
+pub(crate) struct SharedRegion {
+    pub(crate) base: usize,
+    pub(crate) memory: SharedMemory,
+}
+
+impl MmioHandler for SharedRegion {
+    fn read(&mut self, addr: usize) -> Result<i64, String> {
+        self.memory.get(addr - self.base).ok_or_else(|| format!("shared cell {} is past the end", addr - self.base))
+    }
+
+    fn write(&mut self, addr: usize, value: i64) -> Result<(), String> {
+        if self.memory.set(addr - self.base, value) {
+            Ok(())
+        } else {
+            Err(format!("shared cell {} is past the end", addr - self.base))
+        }
+    }
+}
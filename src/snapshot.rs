@@ -0,0 +1,112 @@
+// save-states: Context::snapshot copies everything a running program can change, Context::restore
+// puts it back. Host setup (output, input, hooks, breakpoints, mmio handlers, config) isn't part of
+// a snapshot and stays as it is on restore
+
+use alloc::collections::{BTreeMap, VecDeque};
+
+use crate::bytecode::opcode_byte;
+use crate::context::{Frame, TryHandler, REGISTER_COUNT};
+use crate::instruction::Instruction;
+use crate::prelude::*;
+
+// a Context's execution state at one point. Restoring checks the program hash, so a snapshot
+// only goes back into a context running the program it was taken from. With the json feature
+// to_json and from_json carry one across processes, hash included
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) program_hash: u64,
+    pub(crate) pc: usize,
+    pub(crate) stack: Vec<i64>,
+    pub(crate) call_stack: Vec<Frame>,
+    pub(crate) handlers: Vec<TryHandler>,
+    pub(crate) registers: [i64; REGISTER_COUNT],
+    pub(crate) memory: HashMap<usize, i64>,
+    pub(crate) linear: Vec<u8>,
+    pub(crate) allocations: BTreeMap<usize, usize>,
+    pub(crate) steps: u64,
+    pub(crate) rng: u64,
+    pub(crate) finished: Option<i64>,
+    pub(crate) fuel: Option<u64>,
+}
+
+impl Snapshot {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn stack(&self) -> &[i64] {
+        &self.stack
+    }
+
+    pub fn registers(&self) -> &[i64] {
+        &self.registers
+    }
+
+    // instructions executed when the snapshot was taken
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // fingerprint of the program the snapshot belongs to
+    pub fn program_hash(&self) -> u64 {
+        self.program_hash
+    }
+}
+
+// most checkpoints reverse stepping keeps, the oldest go first
+pub(crate) const MAX_CHECKPOINTS: usize = 64;
+
+// checkpoints for Context::step_back, taken every `interval` steps, oldest first
+pub(crate) struct History {
+    pub(crate) interval: u64,
+    pub(crate) program_hash: u64,
+    checkpoints: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub(crate) fn new(interval: u64, program_hash: u64) -> Self {
+        History { interval: interval.max(1), program_hash, checkpoints: VecDeque::new() }
+    }
+
+    pub(crate) fn due(&self, steps: u64) -> bool {
+        steps.is_multiple_of(self.interval) && self.checkpoints.back().is_none_or(|newest| newest.steps != steps)
+    }
+
+    pub(crate) fn push(&mut self, snapshot: Snapshot) {
+        if self.checkpoints.len() == MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(snapshot);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+
+    // the newest checkpoint at or before `steps`. Later ones are dropped, they belong to a future
+    // that gets executed again
+    pub(crate) fn rewind(&mut self, steps: u64) -> Option<Snapshot> {
+        while self.checkpoints.back().is_some_and(|newest| newest.steps > steps) {
+            self.checkpoints.pop_back();
+        }
+        self.checkpoints.back().cloned()
+    }
+}
+
+// FNV-1a over the opcode bytes and operands, stable across builds and platforms
+pub(crate) fn program_hash(program: &[Instruction]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    for instruction in program {
+        feed(&[opcode_byte(instruction.opcode)]);
+        feed(&(instruction.operands.len() as u64).to_le_bytes());
+        for operand in &instruction.operands {
+            feed(&operand.to_le_bytes());
+        }
+    }
+    hash
+}
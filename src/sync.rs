@@ -0,0 +1,75 @@
+// state a Channel, SharedMemory or SharedBuffer shares between its clones. With std that's a Mutex.
+// Without std there's no Mutex, a spin lock stands in so the clones stay Send like the rest of what a
+// Context holds. Single-threaded targets never find it taken
+
+use alloc::sync::Arc;
+#[cfg(not(feature = "std"))]
+use core::cell::UnsafeCell;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub(crate) struct Shared<T> {
+    #[cfg(feature = "std")]
+    inner: Arc<Mutex<T>>,
+    #[cfg(not(feature = "std"))]
+    inner: Arc<SpinLock<T>>,
+}
+
+impl<T> Shared<T> {
+    #[cfg(feature = "std")]
+    pub(crate) fn new(value: T) -> Self {
+        Shared { inner: Arc::new(Mutex::new(value)) }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn new(value: T) -> Self {
+        Shared { inner: Arc::new(SpinLock { locked: AtomicBool::new(false), value: UnsafeCell::new(value) }) }
+    }
+
+    // `f` holds the lock for its whole run
+    #[cfg(feature = "std")]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.inner.lock().unwrap())
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let lock = &self.inner;
+        while lock.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        // SAFETY: `locked` was false and is now ours, so nothing else has a reference into `value` until
+        // it's cleared below
+        let result = f(unsafe { &mut *lock.value.get() });
+        lock.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared { inner: Arc::clone(&self.inner) }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) struct SpinLock<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: `value` is only reached through `with`, which holds `locked` for as long as the reference lives
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Send for SpinLock<T> {}
+#[cfg(not(feature = "std"))]
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+#[cfg(not(feature = "std"))]
+impl<T> core::fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("SpinLock { .. }")
+    }
+}
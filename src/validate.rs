@@ -0,0 +1,105 @@
+// static checks over a whole program, so a bad operand in a branch that rarely runs is found
+// before the first instruction executes instead of when the branch is finally taken
+
+use crate::cfg::cfg;
+use crate::context::REGISTER_COUNT;
+use crate::error::ValidationError;
+use crate::instruction::{Instruction, OpCode};
+use crate::prelude::*;
+
+// every problem in the program in pc order, not just the first
+pub fn validate(program: &[Instruction]) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+    for (pc, instruction) in program.iter().enumerate() {
+        let (opcode, operands) = (instruction.opcode, &instruction.operands);
+        let expected = opcode.operand_count();
+        if !expected.contains(&operands.len()) {
+            errors.push(ValidationError::OperandCount { pc, opcode, expected, found: operands.len() });
+            continue;
+        }
+
+        for position in opcode.register_operands(operands.len()) {
+            let register = operands[position];
+            if usize::try_from(register).map_or(true, |register| register >= REGISTER_COUNT) {
+                errors.push(ValidationError::InvalidRegister { pc, opcode, register });
+            }
+        }
+
+        for target in targets(pc, instruction) {
+            if usize::try_from(target).map_or(true, |target| target >= program.len()) {
+                errors.push(ValidationError::TargetOutOfBounds { pc, opcode, target });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// validate plus check_flow, with the flow warnings counted as errors
+pub fn validate_strict(program: &[Instruction]) -> Result<(), Vec<ValidationError>> {
+    let mut errors = validate(program).err().unwrap_or_default();
+    errors.extend(check_flow(program));
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// warnings about the program's shape: reachable code that can run off the end without an Exit, and
+// runs of instructions nothing reaches from pc 0, in pc order. Both sides of every branch count as
+// possible, so a path that can never actually run isn't noticed (an Exit behind a comparison that's
+// always false still counts as an exit). Code only reached through JumpDyn or CallIndirect shows up
+// as unreachable since those targets aren't known before running
+pub fn check_flow(program: &[Instruction]) -> Vec<ValidationError> {
+    let mut warnings = Vec::new();
+    for block in cfg(program).blocks {
+        if !block.reachable {
+            match warnings.last_mut() {
+                Some(ValidationError::Unreachable { pcs }) if pcs.end == block.start => pcs.end = block.end,
+                _ => warnings.push(ValidationError::Unreachable { pcs: block.start..block.end }),
+            }
+        } else if block.end == program.len() && falls_through(program[block.end - 1].opcode) {
+            warnings.push(ValidationError::FallsOffEnd { pc: block.end - 1 });
+        }
+    }
+    warnings
+}
+
+// whether execution can carry on to pc + 1, directly or once a call returns
+fn falls_through(opcode: OpCode) -> bool {
+    !matches!(
+        opcode,
+        OpCode::Jump | OpCode::JumpRel | OpCode::Switch | OpCode::JumpDyn | OpCode::TailCall | OpCode::Return
+            | OpCode::Throw | OpCode::Exit | OpCode::FExit | OpCode::Halt
+    )
+}
+
+// every in-bounds pc a jump, call or switch operand names, the superinstruction pass won't fuse across these
+pub(crate) fn jump_targets(program: &[Instruction]) -> HashSet<usize> {
+    program
+        .iter()
+        .enumerate()
+        .flat_map(|(pc, instruction)| targets(pc, instruction))
+        .filter_map(|target| usize::try_from(target).ok())
+        .filter(|&target| target < program.len())
+        .collect()
+}
+
+// the pcs named by an instruction's target operands, relative jumps already resolved against pc
+fn targets(pc: usize, instruction: &Instruction) -> impl Iterator<Item = i64> + '_ {
+    let opcode = instruction.opcode;
+    instruction.operands.iter().enumerate().filter_map(move |(position, &operand)| {
+        if opcode.is_relative_jump() && position == 0 {
+            Some((pc as i64).saturating_add(operand))
+        } else if opcode.is_target_operand(position) {
+            Some(operand)
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,164 @@
+// a small surface for running beef in a browser, e.g. an interactive teaching page. Programs go in as
+// bytecode or JSON, WasmVm::step runs a few instructions at a time and every call hands back a VmState
+// that serializes to JSON for the page to draw. Wiring it to JavaScript (wasm-bindgen or a hand written
+// extern "C" layer) is up to the embedding crate, nothing here depends on it.
+//
+// A panic aborts the whole wasm instance, so nothing here panics: bad programs are Err, VM errors end up
+// in the state's status, and a failed VM keeps reporting that failure until reset. JSON numbers are
+// written as exact i64 values, past 2^53 JavaScript's JSON.parse rounds them
+
+use alloc::collections::VecDeque;
+use core::fmt::Write;
+
+use crate::context::{Config, Context, RunOutcome};
+use crate::host::{ExecutionEvent, SharedBuffer};
+use crate::instruction::Program;
+use crate::json::quote;
+use crate::prelude::*;
+use crate::sync::Shared;
+
+// how many ExecutionEvents a VmState carries, the oldest are dropped first
+pub const RECENT_EVENTS: usize = 64;
+
+pub struct WasmVm {
+    context: Context,
+    output: SharedBuffer,
+    events: Shared<VecDeque<ExecutionEvent>>,
+    status: VmStatus,
+}
+
+// where the VM stands after the last step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmStatus {
+    Ready, // loaded or reset, nothing ran yet
+    Paused, // used up its steps, step again to carry on
+    Yielded(i64),
+    Blocked, // waiting on a channel
+    Breakpoint, // stopped at a breakpoint or watchpoint
+    Exited(i64),
+    Failed(String), // the VmError's message
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmState {
+    pub pc: usize,
+    pub steps: u64,
+    pub stack: Vec<i64>, // bottom first
+    pub registers: Vec<i64>,
+    pub status: VmStatus,
+    pub output: String, // everything printed since load or reset, invalid UTF-8 replaced
+    pub events: Vec<ExecutionEvent>, // the last RECENT_EVENTS, oldest first
+}
+
+impl WasmVm {
+    // a bytecode file as produced by Program::to_bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<WasmVm, String> {
+        let program = Program::from_bytes(bytes).map_err(|error| error.to_string())?;
+        WasmVm::load(program)
+    }
+
+    // a program in the Program::from_json format
+    pub fn from_json(json: &str) -> Result<WasmVm, String> {
+        let program = Program::from_json(json).map_err(|error| error.to_string())?;
+        WasmVm::load(program)
+    }
+
+    fn load(program: Program) -> Result<WasmVm, String> {
+        let mut context = Context::load(program, Config::default()).map_err(|error| error.to_string())?;
+        let output = SharedBuffer::default();
+        context.set_output(Box::new(output.clone()));
+        let events = Shared::new(VecDeque::with_capacity(RECENT_EVENTS));
+        let sink = events.clone();
+        context.set_event_sink(Box::new(move |event| {
+            sink.with(|events: &mut VecDeque<ExecutionEvent>| {
+                if events.len() == RECENT_EVENTS {
+                    events.pop_front();
+                }
+                events.push_back(event);
+            })
+        }));
+        Ok(WasmVm { context, output, events, status: VmStatus::Ready })
+    }
+
+    // run at most `steps` instructions. Once the program has exited or failed this only reports that
+    pub fn step(&mut self, steps: u32) -> VmState {
+        if !matches!(self.status, VmStatus::Exited(_) | VmStatus::Failed(_)) {
+            self.status = match self.context.run_for(steps as usize) {
+                Ok(RunOutcome::Paused) => VmStatus::Paused,
+                Ok(RunOutcome::Completed(value)) => VmStatus::Exited(value),
+                Ok(RunOutcome::Yielded(value)) => VmStatus::Yielded(value),
+                Ok(RunOutcome::Blocked { .. }) => VmStatus::Blocked,
+                Ok(RunOutcome::Hit { .. } | RunOutcome::Watchpoint { .. }) => VmStatus::Breakpoint,
+                Err(error) => VmStatus::Failed(error.to_string()),
+            };
+        }
+        self.state()
+    }
+
+    pub fn state(&self) -> VmState {
+        VmState {
+            pc: self.context.pc(),
+            steps: self.context.steps(),
+            stack: self.context.stack().to_vec(),
+            registers: self.context.registers().to_vec(),
+            status: self.status.clone(),
+            output: String::from_utf8_lossy(&self.output.contents()).into_owned(),
+            events: self.events.with(|events| events.iter().cloned().collect()),
+        }
+    }
+
+    // back to the start of the program with the output and events cleared
+    pub fn reset(&mut self) {
+        self.context.reset();
+        self.output = SharedBuffer::default();
+        self.context.set_output(Box::new(self.output.clone()));
+        self.events.with(|events| events.clear());
+        self.status = VmStatus::Ready;
+    }
+
+    // the Context underneath, for breakpoints, input and the rest of the full API
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+}
+
+impl VmState {
+    //   {"pc": 3, "steps": 3, "stack": [5], "registers": [...], "status": {"kind": "paused"},
+    //    "output": "", "events": [{"kind": "instruction", "pc": 2, "opcode": "Push", "operands": [5]}, ...]}
+    //
+    // status carries "value" for yielded and exited and "message" for failed
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        // writing into a String can't fail, so the results are dropped rather than unwrapped
+        let _ = write!(out, "{{\"pc\": {}, \"steps\": {}, \"stack\": {:?}, \"registers\": {:?}, ", self.pc, self.steps, self.stack, self.registers);
+        let status = match &self.status {
+            VmStatus::Ready => String::from("{\"kind\": \"ready\"}"),
+            VmStatus::Paused => String::from("{\"kind\": \"paused\"}"),
+            VmStatus::Yielded(value) => format!("{{\"kind\": \"yielded\", \"value\": {}}}", value),
+            VmStatus::Blocked => String::from("{\"kind\": \"blocked\"}"),
+            VmStatus::Breakpoint => String::from("{\"kind\": \"breakpoint\"}"),
+            VmStatus::Exited(value) => format!("{{\"kind\": \"exited\", \"value\": {}}}", value),
+            VmStatus::Failed(message) => format!("{{\"kind\": \"failed\", \"message\": {}}}", quote(message)),
+        };
+        let events: Vec<String> = self.events.iter().map(event_json).collect();
+        let _ = write!(out, "\"status\": {}, \"output\": {}, \"events\": [{}]}}", status, quote(&self.output), events.join(", "));
+        out
+    }
+}
+
+fn event_json(event: &ExecutionEvent) -> String {
+    match event {
+        ExecutionEvent::Instruction { pc, opcode, operands } => {
+            format!("{{\"kind\": \"instruction\", \"pc\": {}, \"opcode\": \"{:?}\", \"operands\": {:?}}}", pc, opcode, operands)
+        },
+        ExecutionEvent::StackPush(value) => format!("{{\"kind\": \"push\", \"value\": {}}}", value),
+        ExecutionEvent::StackPop(value) => format!("{{\"kind\": \"pop\", \"value\": {}}}", value),
+        ExecutionEvent::RegisterWrite { reg, value } => format!("{{\"kind\": \"register\", \"reg\": {}, \"value\": {}}}", reg, value),
+        ExecutionEvent::MemoryWrite { addr, value } => format!("{{\"kind\": \"memory\", \"addr\": {}, \"value\": {}}}", addr, value),
+        ExecutionEvent::Call { pc, target } => format!("{{\"kind\": \"call\", \"pc\": {}, \"target\": {}}}", pc, target),
+        ExecutionEvent::Return { pc, return_addr } => {
+            format!("{{\"kind\": \"return\", \"pc\": {}, \"return_addr\": {}}}", pc, return_addr)
+        },
+        ExecutionEvent::Exit { value } => format!("{{\"kind\": \"exit\", \"value\": {}}}", value),
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use beef::{analyze_stack, analyze_stack_with, Depth, OpCode::*, StackConfig, StackError, StackWarning};
+use common::{factorial, ix};
+
+#[test]
+fn factorial_is_clean() {
+    let report = analyze_stack(&factorial(5)).unwrap();
+    assert_eq!(report.warnings, []);
+    assert_eq!(report.max_depth, Some(2));
+    let entries: Vec<(usize, Depth)> = report.blocks.iter().map(|block| (block.start, block.entry)).collect();
+    assert_eq!(entries, [0, 4, 7, 13].map(|pc| (pc, Depth { min: 0, max: Some(0) })));
+}
+
+#[test]
+fn an_underflow_on_the_branch_not_taken_is_still_found() {
+    let program = vec![
+        ix(Push, &[7]),
+        ix(Push, &[1]),
+        ix(JumpNotZero, &[5]), // always taken when run
+        ix(Add, &[]), // only one value here
+        ix(Exit, &[]),
+        ix(Exit, &[]),
+    ];
+    // the Exit at 4 still finds the Add's result, so only the Add is reported
+    let errors = analyze_stack(&program).unwrap_err();
+    assert_eq!(errors, [StackError::Underflow { pc: 3, opcode: Add, needed: 2, depth: 1 }]);
+    assert_eq!(errors[0].to_string(), "pc=3: Add needs 2 values, some path reaches it with 1");
+
+    let errors = analyze_stack(&[ix(Pop, &[]), ix(Exit, &[0])]).unwrap_err();
+    assert_eq!(errors, [StackError::Underflow { pc: 0, opcode: Pop, needed: 1, depth: 0 }]);
+}
+
+#[test]
+fn joins_with_different_depths_warn() {
+    // one path pushes an extra value before the join at 4
+    let program = vec![
+        ix(Push, &[0]),
+        ix(JumpZero, &[3]),
+        ix(Push, &[1]),
+        ix(Nop, &[]),
+        ix(Exit, &[0]),
+    ];
+    let report = analyze_stack(&program).unwrap();
+    assert_eq!(report.warnings, [StackWarning::JoinMismatch { pc: 3, depth: Depth { min: 0, max: Some(1) } }]);
+
+    // a loop that pushes every time around is unbounded, and still terminates
+    let program = vec![ix(Push, &[1]), ix(Dup, &[]), ix(Dup, &[]), ix(JumpNotZero, &[1]), ix(Exit, &[])];
+    let report = analyze_stack(&program).unwrap();
+    assert_eq!(report.max_depth, None);
+    assert_eq!(report.warnings[0].to_string(), "pc=1: paths meet here with different stack depths (1..)");
+
+    // and one that pops every time around from a huge Pick depth bottoms out instead of counting down
+    let program = vec![ix(Pick, &[i64::MAX / 2]), ix(Pop, &[]), ix(Pop, &[]), ix(JumpNotZero, &[0]), ix(Exit, &[0])];
+    assert!(analyze_stack(&program).is_err());
+}
+
+#[test]
+fn calls_use_the_configured_effect() {
+    let program = vec![ix(Call, &[3]), ix(Print, &[]), ix(Exit, &[0]), ix(Push, &[42]), ix(Return, &[])];
+    assert!(matches!(analyze_stack(&program).unwrap_err()[..], [StackError::Underflow { pc: 1, .. }]));
+    let report = analyze_stack_with(&program, &StackConfig { call_effect: 1 }).unwrap();
+    assert_eq!(report.blocks.iter().map(|block| block.start).collect::<Vec<_>>(), [0, 1]);
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use beef::{ArithMode, Config, Context, Instruction, OpCode::*, VmError};
+use common::{factorial, ix, run, run_err};
+
+fn binary(opcode: beef::OpCode, a: i64, b: i64) -> Result<i64, VmError> {
+    run(vec![ix(Push, &[a]), ix(Push, &[b]), ix(opcode, &[]), ix(Exit, &[])])
+}
+
+#[test]
+fn mod_follows_rust_sign_rules() {
+    assert_eq!(binary(Mod, -7, 3), Ok(-1));
+    assert_eq!(binary(Mod, 7, -3), Ok(1));
+    assert_eq!(binary(Mod, 7, 3), Ok(1));
+}
+
+#[test]
+fn div_and_mod_by_zero_error() {
+    assert_eq!(binary(Div, 7, 0).unwrap_err().root(), &VmError::DivisionByZero { pc: 2 });
+    assert_eq!(binary(Mod, 7, 0).unwrap_err().root(), &VmError::DivisionByZero { pc: 2 });
+}
+
+#[test]
+fn min_divided_by_minus_one_errors_instead_of_panicking() {
+    let err = binary(Div, i64::MIN, -1).unwrap_err();
+    assert_eq!(err.root(), &VmError::Overflow { pc: 2, opcode: Div, operands: vec![i64::MIN, -1] });
+    let err = binary(Mod, i64::MIN, -1).unwrap_err();
+    assert!(err.to_string().starts_with("Overflow in Mod Op"), "{}", err);
+}
+
+#[test]
+fn bitwise_ops_with_the_sign_bit_set() {
+    let high = i64::MIN; // only the sign bit
+    assert_eq!(binary(And, -1, high), Ok(high));
+    assert_eq!(binary(Or, high, 1), Ok(i64::MIN + 1));
+    assert_eq!(binary(Xor, -1, high), Ok(i64::MAX));
+    assert_eq!(run(vec![ix(Push, &[0]), ix(Not, &[]), ix(Exit, &[])]), Ok(-1));
+    assert_eq!(run(vec![ix(Push, &[i64::MAX]), ix(Not, &[]), ix(Exit, &[])]), Ok(i64::MIN));
+}
+
+#[test]
+fn sar_keeps_the_sign_and_shr_does_not() {
+    assert_eq!(binary(Sar, -16, 2), Ok(-4));
+    assert_eq!(binary(Shr, -16, 2), Ok(((-16i64 as u64) >> 2) as i64));
+    assert_eq!(binary(Shl, 3, 4), Ok(48));
+}
+
+#[test]
+fn out_of_range_shift_amounts_error() {
+    for opcode in [Shl, Shr, Sar] {
+        assert_eq!(binary(opcode, 1, 64).unwrap_err().root(), &VmError::InvalidOperand { pc: 2, opcode, value: 64 });
+        assert_eq!(binary(opcode, 1, -1).unwrap_err().root(), &VmError::InvalidOperand { pc: 2, opcode, value: -1 });
+    }
+}
+
+#[test]
+fn comparisons_push_one_or_zero() {
+    assert_eq!(binary(Eq, 3, 3), Ok(1));
+    assert_eq!(binary(Ne, 3, 3), Ok(0));
+    assert_eq!(binary(Lt, 2, 3), Ok(1));
+    assert_eq!(binary(Le, 3, 3), Ok(1));
+    assert_eq!(binary(Gt, 2, 3), Ok(0));
+    assert_eq!(binary(Ge, 2, 3), Ok(0));
+}
+
+#[test]
+fn unsigned_ops_treat_the_sign_bit_as_magnitude() {
+    // -1 is u64::MAX and i64::MIN is 2^63 when read unsigned
+    assert_eq!(binary(Div, -1, 2), Ok(0));
+    assert_eq!(binary(DivU, -1, 2), Ok(i64::MAX));
+    assert_eq!(binary(DivU, i64::MIN, -1), Ok(0));
+    assert_eq!(binary(DivU, -1, -1), Ok(1));
+    assert_eq!(binary(Mod, -7, 3), Ok(-1));
+    assert_eq!(binary(ModU, -7, 3), Ok(((-7i64 as u64) % 3) as i64));
+    assert_eq!(binary(ModU, i64::MIN, -1), Ok(i64::MIN));
+
+    assert_eq!(binary(Lt, -1, 1), Ok(1));
+    assert_eq!(binary(LtU, -1, 1), Ok(0));
+    assert_eq!(binary(LeU, 1, i64::MIN), Ok(1));
+    assert_eq!(binary(GtU, i64::MIN, i64::MAX), Ok(1));
+    assert_eq!(binary(GeU, -1, -1), Ok(1));
+    assert_eq!(binary(GeU, 0, -1), Ok(0));
+
+    // Shr is the unsigned shift, zeros come in from the left
+    assert_eq!(binary(Shr, -1, 60), Ok(0xf));
+}
+
+#[test]
+fn mul_hi_is_the_top_of_the_128_bit_product() {
+    // u64::MAX * u64::MAX = 2^128 - 2^65 + 1, high half u64::MAX - 1
+    assert_eq!(binary(MulHiU, -1, -1), Ok(-2));
+    // signed that's -1 * -1 = 1, nothing in the high half
+    assert_eq!(binary(MulHi, -1, -1), Ok(0));
+    // a negative product sign-extends into the high half, the unsigned one sees 2^64 - 1 instead
+    assert_eq!(binary(MulHi, -1, 1), Ok(-1));
+    assert_eq!(binary(MulHiU, -1, 1), Ok(0));
+    assert_eq!(binary(MulHi, i64::MIN, i64::MIN), Ok(1 << 62));
+    assert_eq!(binary(MulHi, i64::MAX, 2), Ok(0));
+    assert_eq!(binary(MulHiU, 1 << 32, 1 << 32), Ok(1));
+    assert_eq!(binary(MulHi, 1 << 40, -(1 << 40)), Ok(-(1 << 16)));
+}
+
+#[test]
+fn unsigned_division_by_zero_errors_like_div() {
+    for opcode in [DivU, ModU] {
+        assert_eq!(binary(opcode, -1, 0).unwrap_err().root(), &VmError::DivisionByZero { pc: 2 });
+    }
+}
+
+#[test]
+fn comparison_result_feeds_a_jump_eq() {
+    // (4 < 9) == 1 -> jump to the Halt 1
+    let program = vec![
+        ix(Push, &[4]),
+        ix(Push, &[9]),
+        ix(Lt, &[]),
+        ix(Push, &[1]),
+        ix(JumpEq, &[6]),
+        ix(Halt, &[0]),
+        ix(Halt, &[1]),
+    ];
+    assert_eq!(run(program), Ok(1));
+}
+
+#[test]
+fn neg_and_abs_of_min_error() {
+    let err = run_err(vec![ix(Push, &[i64::MIN]), ix(Neg, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::Overflow { pc: 1, opcode: Neg, operands: vec![i64::MIN] });
+    let err = run_err(vec![ix(Push, &[i64::MIN]), ix(Abs, &[]), ix(Exit, &[])]);
+    assert!(matches!(err, VmError::Overflow { opcode: Abs, .. }));
+    assert_eq!(run(vec![ix(Push, &[5]), ix(Neg, &[]), ix(Exit, &[])]), Ok(-5));
+    assert_eq!(run(vec![ix(Push, &[-5]), ix(Abs, &[]), ix(Exit, &[])]), Ok(5));
+}
+
+#[test]
+fn min_and_max() {
+    assert_eq!(binary(Min, -3, 2), Ok(-3));
+    assert_eq!(binary(Max, -3, 2), Ok(2));
+}
+
+#[test]
+fn immediate_forms_match_push_then_op() {
+    assert_eq!(run(vec![ix(Push, &[10]), ix(AddImm, &[5]), ix(Exit, &[])]), Ok(15));
+    assert_eq!(run(vec![ix(Push, &[10]), ix(SubImm, &[5]), ix(Exit, &[])]), Ok(5));
+    assert_eq!(run(vec![ix(Push, &[10]), ix(MulImm, &[5]), ix(Exit, &[])]), Ok(50));
+}
+
+#[test]
+fn immediates_shrink_the_factorial_loop() {
+    // factorial(5) before the immediate forms, r1 counted down with LoadReg 1; Push 1; Sub; StoreReg 1
+    let mut longhand = factorial(5);
+    longhand.splice(11..12, [ix(LoadReg, &[1]), ix(Push, &[1]), ix(Sub, &[]), ix(StoreReg, &[1])]);
+    longhand[6] = ix(JumpEq, &[16]);
+    let count = |program: Vec<Instruction>| {
+        let mut context = Context::new(program);
+        assert_eq!(context.run(false), Ok(120));
+        context.steps()
+    };
+    assert_eq!((factorial(5).len(), longhand.len()), (14, 17));
+    assert_eq!((count(factorial(5)), count(longhand.clone())), (44, 56));
+
+    // SubImm alone saves one instruction per iteration
+    longhand.splice(12..14, [ix(SubImm, &[1])]);
+    longhand[6] = ix(JumpEq, &[15]);
+    assert_eq!(count(longhand), 52);
+}
+
+#[test]
+fn overflow_policy_changes_the_outcome() {
+    let program = vec![ix(Push, &[i64::MAX]), ix(Push, &[1]), ix(Add, &[]), ix(Exit, &[])];
+    let with = |arith_mode| Context::new_with_config(program.clone(), Config { arith_mode, ..Config::default() }).run(false);
+
+    assert_eq!(with(ArithMode::Wrapping), Ok(i64::MIN));
+    assert_eq!(with(ArithMode::Saturating), Ok(i64::MAX));
+    let err = with(ArithMode::Checked).unwrap_err();
+    assert_eq!(err.root(), &VmError::Overflow { pc: 2, opcode: Add, operands: vec![i64::MAX, 1] });
+    assert!(err.to_string().starts_with(&format!("Overflow in Add Op: {} and 1 at pc=2", i64::MAX)), "{}", err);
+}
+
+#[test]
+fn register_arithmetic_factorial() {
+    // r0 = 1, r1 = 5, r2 = 1; loop: r0 = r0 * r1, r1 = r1 - r2 until r1 == 1
+    let program = vec![
+        ix(Push, &[1]),
+        ix(StoreReg, &[0]),
+        ix(Push, &[5]),
+        ix(StoreReg, &[1]),
+        ix(Push, &[1]),
+        ix(StoreReg, &[2]),
+        ix(MulReg, &[0, 0, 1]),
+        ix(SubReg, &[1, 1, 2]),
+        ix(LoadReg, &[1]),
+        ix(Push, &[1]),
+        ix(JumpNe, &[6]),
+        ix(Exit, &[0]),
+    ];
+    assert_eq!(run(program), Ok(120));
+}
+
+#[test]
+fn register_ops_validate_each_index() {
+    let err = run_err(vec![ix(AddReg, &[0, 1, 11]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidRegister { pc: 0, index: 11 });
+    let err = run_err(vec![ix(DivReg, &[0, 1, 2]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::DivisionByZero { pc: 0 });
+}
+
+#[test]
+fn inc_and_dec_wrap_at_the_boundaries() {
+    let program = |start: i64, opcode| {
+        vec![ix(Push, &[start]), ix(StoreReg, &[3]), ix(opcode, &[3]), ix(Exit, &[3])]
+    };
+    assert_eq!(run(program(i64::MAX, IncReg)), Ok(i64::MIN));
+    assert_eq!(run(program(i64::MIN, DecReg)), Ok(i64::MAX));
+    assert_eq!(run(vec![ix(Push, &[7]), ix(StoreReg, &[2]), ix(MovReg, &[4, 2]), ix(Exit, &[4])]), Ok(7));
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use beef::{assemble, assemble_program, AsmError, AsmErrorKind, Config, Context, Instruction, OpCode::*, Program, VmError};
+use common::{factorial, ix};
+
+const FACTORIAL: &str = "
+; factorial of 5, the same program as main
+    push 5
+    storereg r1
+    push 1
+    storereg r0
+    ; loop start at 4
+    loadreg r1
+    push 1
+    jumpeq 13       ; done
+    loadreg r0
+    loadreg r1
+    mul
+    storereg r0
+    decreg r1
+    jump 4
+    exit r0
+";
+
+#[test]
+fn assembles_and_runs_factorial() {
+    let program = assemble(FACTORIAL).unwrap();
+    assert_eq!(program, factorial(5));
+    assert_eq!(Context::new(program).run(false), Ok(120));
+}
+
+#[test]
+fn mnemonics_ignore_case_and_literals_can_be_hex() {
+    let src = "PUSH 0x1F\nPush -0x10 ; comment\nswitch 1,2 , 3\npush 0xffffffffffffffff\npush -9223372036854775808\nExit";
+    let program = assemble(src).unwrap();
+    assert_eq!(program, vec![
+        ix(Push, &[31]),
+        ix(Push, &[-16]),
+        ix(Switch, &[1, 2, 3]),
+        ix(Push, &[-1]),
+        ix(Push, &[i64::MIN]),
+        ix(Exit, &[]),
+    ]);
+}
+
+#[test]
+fn every_opcode_has_a_mnemonic() {
+    for opcode in beef::OpCode::ALL {
+        let operands = vec!["0"; *opcode.operand_count().start()].join(" ");
+        let src = format!("{:?} {}", opcode, operands);
+        assert_eq!(assemble(&src).unwrap()[0].opcode, opcode, "{}", src);
+    }
+}
+
+#[test]
+fn unknown_mnemonics_are_located() {
+    let err = assemble("push 1\n  pusj 2\nexit").unwrap_err();
+    assert_eq!(err, AsmError { line: 2, column: 3, token: "pusj".to_string(), kind: AsmErrorKind::UnknownMnemonic });
+    assert_eq!(err.to_string(), "line 2, column 3: unknown mnemonic `pusj`");
+}
+
+#[test]
+fn bad_operands_are_located() {
+    let err = assemble("push 1\npush\texit").unwrap_err();
+    assert_eq!((err.line, err.column, err.token.as_str(), err.kind), (2, 6, "exit", AsmErrorKind::LabelNotAllowed { opcode: Push }));
+    for operand in ["0xg", "1.5", "r-1", "--1", "a-b", "0x-1", "99999999999999999999"] {
+        let err = assemble(&format!("push {}", operand)).unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::InvalidOperand, "{}", operand);
+        assert_eq!(err.token, operand);
+    }
+}
+
+#[test]
+fn wrong_operand_counts() {
+    let err = assemble("addreg r0 r1").unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::OperandCount { opcode: AddReg, expected: 3..=3, found: 2 });
+    assert_eq!(err.to_string(), "line 1, column 1: `addreg` takes 3 operands, found 2");
+
+    let err = assemble("nop\nadd 1").unwrap_err();
+    assert_eq!((err.line, err.to_string()), (2, "line 2, column 1: `add` takes 0 operands, found 1".to_string()));
+    assert!(assemble("call 4 1 2").unwrap_err().to_string().ends_with("takes 1 to 2 operands, found 3"));
+    assert!(assemble("switch").unwrap_err().to_string().ends_with("takes at least 1 operands, found 0"));
+}
+
+const FACTORIAL_WITH_LABELS: &str = "
+    push 5
+    storereg r1
+    push 1
+    storereg r0
+loop_start:
+    loadreg r1
+    push 1
+    jumpeq done
+    loadreg r0
+    loadreg r1
+    mul
+    storereg r0
+    decreg r1
+    jump loop_start
+done: exit r0
+";
+
+fn targets(program: &[Instruction]) -> Vec<(usize, i64)> {
+    program.iter().enumerate().filter(|(_, ix)| ix.opcode.is_target_operand(0)).map(|(pc, ix)| (pc, ix.operands[0])).collect()
+}
+
+#[test]
+fn labels_resolve_forward_and_backward() {
+    let program = assemble(FACTORIAL_WITH_LABELS).unwrap();
+    assert_eq!(program, factorial(5));
+    assert_eq!(targets(&program), vec![(6, 13), (12, 4)]);
+
+    // one more instruction before the loop moves every target after it
+    let patched = FACTORIAL_WITH_LABELS.replacen("    push 1\n", "    nop\n    push 1\n", 1);
+    let program = assemble(&patched).unwrap();
+    assert_eq!(targets(&program), vec![(7, 14), (13, 5)]);
+    assert_eq!(Context::new(program).run(false), Ok(120));
+}
+
+#[test]
+fn labels_on_calls_switches_and_relative_jumps() {
+    let src = "
+        push 1
+        switch zero one one
+    zero: push 10
+        jumprel end
+    one: push 1
+        calln double 1
+    end:
+        exit
+    double:
+    .twice: loadlocal 0
+        mulimm 2
+        return 1
+    ";
+    let program = assemble(src).unwrap();
+    assert_eq!(program[1], ix(Switch, &[2, 4, 4]));
+    assert_eq!(program[3], ix(JumpRel, &[3]));
+    assert_eq!(program[5], ix(CallN, &[7, 1]));
+    assert_eq!(Context::new(program).run(false), Ok(2));
+}
+
+#[test]
+fn label_errors() {
+    let err = assemble("start: nop\nnop\n  start:\nexit").unwrap_err();
+    assert_eq!(err, AsmError { line: 3, column: 3, token: "start".to_string(), kind: AsmErrorKind::DuplicateLabel { first_line: 1 } });
+    assert_eq!(err.to_string(), "line 3, column 3: label `start` is already defined on line 1");
+
+    let err = assemble("jump nowhere\n\ncall missing").unwrap_err();
+    assert_eq!(err, AsmError { line: 1, column: 6, token: "nowhere".to_string(), kind: AsmErrorKind::UndefinedLabel });
+
+    let err = assemble("x: push x").unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::LabelNotAllowed { opcode: Push });
+    assert_eq!(assemble("r1: nop").unwrap_err().kind, AsmErrorKind::InvalidLabel);
+    assert_eq!(assemble("1st: nop").unwrap_err().kind, AsmErrorKind::InvalidLabel);
+}
+
+const ADD_REGS: &str = "
+.macro add_regs dst a b
+    loadreg a
+    loadreg b
+    add
+    storereg dst
+.endmacro
+";
+
+#[test]
+fn macros_expand_with_their_arguments() {
+    let src = format!("{}
+    push 3
+    storereg r1
+    push 4
+    storereg r2
+    add_regs r3 r1 r2   ; 7
+    add_regs r4, r3, r3 ; 14
+    add_regs r0 r4 r1   ; 17
+    exit r0
+", ADD_REGS);
+    let program = assemble(&src).unwrap();
+    assert_eq!(program.len(), 4 + 3 * 4 + 1);
+    assert_eq!(&program[4..8], &[ix(LoadReg, &[1]), ix(LoadReg, &[2]), ix(Add, &[]), ix(StoreReg, &[3])]);
+    let mut context = Context::new(program);
+    assert_eq!(context.run(false), Ok(17));
+    assert_eq!(&context.registers()[3..5], &[7, 14]);
+}
+
+#[test]
+fn macro_labels_are_local_to_each_expansion() {
+    let src = "
+    .macro countdown reg
+        loadreg reg
+    again:
+        jumpzero done
+        decreg reg
+        loadreg reg
+        jump again
+    done:
+    .endmacro
+    start: countdown r1
+        countdown r2
+        exit r0
+        jump start ; labels before an invocation name its first instruction
+    ";
+    let program = assemble(src).unwrap();
+    assert_eq!(program[0], ix(LoadReg, &[1]));
+    assert_eq!(program[1], ix(JumpZero, &[5]));
+    assert_eq!(program[4], ix(Jump, &[1]));
+    assert_eq!(program[6], ix(JumpZero, &[10]));
+    assert_eq!(program[9], ix(Jump, &[6]));
+    assert_eq!(program[11], ix(Jump, &[0]));
+
+    let mut context = Context::new(program);
+    context.set_register(1, 3).unwrap();
+    context.set_register(2, 5).unwrap();
+    assert_eq!(context.run(false), Ok(0));
+    assert_eq!(&context.registers()[1..3], &[0, 0]);
+}
+
+#[test]
+fn macros_can_use_other_macros() {
+    let src = format!("{}
+    .macro triple dst src
+        add_regs dst src src
+        add_regs dst dst src
+    .endmacro
+        push 5
+        storereg r1
+        triple r0 r1
+        exit r0
+    ", ADD_REGS);
+    assert_eq!(Context::new(assemble(&src).unwrap()).run(false), Ok(15));
+}
+
+#[test]
+fn macro_errors() {
+    let err = assemble(".macro a\n  b\n.endmacro\n.macro b\n  a\n.endmacro\nnop\na").unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::RecursiveMacro);
+    assert_eq!((err.line, err.token.as_str()), (5, "a"));
+    assert_eq!(err.to_string(), "line 5, column 1: macro `a` invokes itself");
+    assert_eq!(assemble(".macro loop\n  loop\n.endmacro\nloop").unwrap_err().kind, AsmErrorKind::RecursiveMacro);
+
+    let err = assemble(&format!("{}\nadd_regs r0 r1", ADD_REGS)).unwrap_err();
+    assert_eq!(err.kind, AsmErrorKind::MacroArguments { expected: 3, found: 2 });
+    assert_eq!(err.line, 9);
+
+    assert_eq!(assemble(".macro m\n  nop\n").unwrap_err().kind, AsmErrorKind::UnterminatedMacro);
+    assert_eq!(assemble(".macro m\n.macro n\n.endmacro").unwrap_err().kind, AsmErrorKind::UnterminatedMacro);
+    assert_eq!(assemble(".endmacro").unwrap_err().kind, AsmErrorKind::InvalidMacro);
+    assert_eq!(assemble(".macro push x\n.endmacro").unwrap_err().kind, AsmErrorKind::InvalidMacro);
+    assert_eq!(assemble(".macro m\n.endmacro\n.macro m\n.endmacro").unwrap_err().kind, AsmErrorKind::DuplicateMacro { first_line: 1 });
+    assert_eq!(assemble(".org 5").unwrap_err().kind, AsmErrorKind::UnknownDirective);
+
+    // errors inside an expansion point into the macro body
+    let err = assemble(".macro bad\n  nop\n  pusj 1\n.endmacro\nbad").unwrap_err();
+    assert_eq!((err.line, err.kind), (3, AsmErrorKind::UnknownMnemonic));
+}
+
+#[test]
+fn runtime_errors_point_at_the_source_line() {
+    let src = "
+; divide r1 by r2
+    loadreg r1
+    loadreg r2
+    div
+    exit
+";
+    let program = assemble_program(src, "divide.basm").unwrap();
+    assert_eq!(program.debug_info.as_ref().unwrap().lines, vec![3, 4, 5, 6]);
+    let mut context = Context::load(program, Config::default()).unwrap();
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::DivisionByZero { pc: 2 });
+    assert!(err.to_string().contains("at pc=2 (divide.basm:5) (Instruction { opcode: Div"), "{}", err);
+
+    // the line survives a trip through bytecode, symbols come first
+    let program = Program::from_bytes(&assemble_program(src, "divide.basm").unwrap().with_symbol("divide", 0).to_bytes().unwrap()).unwrap();
+    let err = Context::load(program, Config::default()).unwrap().run(false).unwrap_err();
+    assert!(err.to_string().contains("at pc=divide+2 (2) (divide.basm:5)"), "{}", err);
+}
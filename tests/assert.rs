@@ -0,0 +1,104 @@
+mod common;
+
+use beef::{assemble_program, Config, Context, OpCode::*, Program, ProgramBuilder, VmError};
+use common::{ix, run, run_err};
+
+// factorial(5) twice, recursively and with a loop, checked against each other and against 120
+const SELF_TEST: &str = r#"
+    push 5
+    calln fact 1
+    storereg r0
+
+    push 1
+    storereg r1
+    push 5
+    storereg r2
+loop:
+    loadreg r2
+    jumpzero done
+    loadreg r1
+    loadreg r2
+    mul
+    storereg r1
+    decreg r2
+    jump loop
+
+done:
+    loadreg r0
+    loadreg r1
+    eq
+    assert "recursive and iterative factorial disagree"
+    loadreg r0
+    push 120
+    eq
+    assert "factorial(5) is not 120"
+    push 0
+    exit
+
+fact:
+    loadlocal 0
+    push 1
+    jumple base
+    loadlocal 0
+    loadlocal 0
+    subimm 1
+    calln fact 1
+    mul
+    return 1
+base:
+    push 1
+    return 1
+"#;
+
+fn run_program(program: Program) -> Result<i64, VmError> {
+    Context::load(program, Config::default())?.run(false)
+}
+
+#[test]
+fn a_guest_program_checks_itself() {
+    let program = assemble_program(SELF_TEST, "self_test.asm").unwrap();
+    assert_eq!(run_program(program), Ok(0));
+
+    // a harness can run a batch of them and count the failed assertions
+    let cases = [
+        SELF_TEST.to_string(),
+        SELF_TEST.replace("push 120", "push 121"),
+        SELF_TEST.replace("mul\n    return", "add\n    return"),
+    ];
+    let failures: Vec<_> = cases
+        .iter()
+        .filter_map(|src| match run_program(assemble_program(src, "case.asm").unwrap()) {
+            Err(err) => match err.root() {
+                VmError::AssertionFailed { message, .. } => Some(message.clone().unwrap()),
+                other => panic!("unexpected error {}", other),
+            },
+            Ok(_) => None,
+        })
+        .collect();
+    assert_eq!(failures, ["factorial(5) is not 120", "recursive and iterative factorial disagree"]);
+}
+
+#[test]
+fn failures_name_the_pc_and_message() {
+    let mut program = Program::new(vec![ix(Push, &[1]), ix(Assert, &[0]), ix(Push, &[0]), ix(Assert, &[0]), ix(Exit, &[0])]);
+    program.strings = vec!["second check".to_string()];
+    let err = run_program(program).unwrap_err();
+    assert_eq!(err.root(), &VmError::AssertionFailed { pc: 3, message: Some("second check".to_string()) });
+    assert_eq!(err.root().to_string(), "Assertion failed: second check");
+
+    let program = ProgramBuilder::new().push(0).assert().push(0).exit().build().unwrap();
+    let err = run_err(program);
+    assert_eq!(err, VmError::AssertionFailed { pc: 1, message: None });
+    assert_eq!(err.to_string(), "Assertion failed");
+}
+
+#[test]
+fn any_nonzero_value_passes() {
+    for value in [1, -1, i64::MIN, i64::MAX] {
+        assert_eq!(run(vec![ix(Push, &[value]), ix(Assert, &[]), ix(Push, &[7]), ix(Exit, &[])]), Ok(7));
+    }
+    assert!(matches!(run_err(vec![ix(Assert, &[]), ix(Exit, &[0])]), VmError::StackUnderflow { pc: 0, .. }));
+    // the message index is checked even when the assertion holds
+    let err = run_err(vec![ix(Push, &[1]), ix(Assert, &[2]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 1, opcode: Assert, value: 2 });
+}
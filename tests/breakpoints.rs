@@ -0,0 +1,143 @@
+mod common;
+
+use beef::{Config, Context, OpCode::*, RunOutcome, StepOutcome, VmError, Watch};
+use common::{factorial, ix};
+
+#[test]
+fn breakpoint_in_the_loop_body() {
+    let mut context = Context::new(factorial(5));
+    context.add_breakpoint(9).unwrap();
+    let mut hits = Vec::new();
+    let value = loop {
+        match context.resume().unwrap() {
+            RunOutcome::Hit { pc } => hits.push((pc, context.stack().to_vec())),
+            RunOutcome::Completed(value) => break value,
+            outcome => panic!("{:?}", outcome),
+        }
+    };
+    assert_eq!(value, 120);
+    // Mul about to run on r0 and r1 each time round
+    assert_eq!(hits, vec![(9, vec![1, 5]), (9, vec![5, 4]), (9, vec![20, 3]), (9, vec![60, 2])]);
+    assert_eq!(context.steps(), 44);
+}
+
+#[test]
+fn breakpoint_on_the_first_instruction() {
+    let mut context = Context::new(factorial(3));
+    context.add_breakpoint(0).unwrap();
+    assert_eq!(context.run(false), Err(VmError::Breakpoint { pc: 0 }));
+    assert_eq!((context.pc(), context.steps()), (0, 0));
+    // resuming runs the breakpointed instruction once and carries on
+    assert_eq!(context.run(false), Ok(6));
+
+    let mut context = Context::new(factorial(3));
+    context.add_breakpoint(0).unwrap();
+    assert_eq!(context.step(), Ok(StepOutcome::Breakpoint { pc: 0 }));
+    assert_eq!(context.step(), Ok(StepOutcome::Continued));
+    assert_eq!(context.stack(), [3]);
+}
+
+#[test]
+fn run_for_counts_only_executed_instructions() {
+    let mut context = Context::new(factorial(5));
+    context.add_breakpoint(4).unwrap();
+    assert_eq!(context.run_for(100), Ok(RunOutcome::Hit { pc: 4 }));
+    assert_eq!(context.run_for(1), Ok(RunOutcome::Paused));
+    assert_eq!(context.pc(), 5);
+    assert_eq!(context.run_for(100), Ok(RunOutcome::Hit { pc: 4 }));
+}
+
+#[test]
+fn managing_breakpoints() {
+    let mut context = Context::new(factorial(5));
+    assert_eq!(context.add_breakpoint(14), Err(VmError::InvalidBreakpoint { pc: 14, len: 14 }));
+    assert_eq!(context.add_breakpoint(14).unwrap_err().to_string(), "Breakpoint at pc=14 is outside the program (14 instructions)");
+    context.add_breakpoint(13).unwrap();
+    context.add_breakpoint(4).unwrap();
+    context.add_breakpoint(4).unwrap();
+    assert_eq!(context.list_breakpoints(), vec![4, 13]);
+    assert!(context.remove_breakpoint(4));
+    assert!(!context.remove_breakpoint(4));
+    assert_eq!(context.list_breakpoints(), vec![13]);
+    assert_eq!(context.resume(), Ok(RunOutcome::Hit { pc: 13 }));
+    assert_eq!(context.resume(), Ok(RunOutcome::Completed(120)));
+}
+
+#[test]
+fn breakpoints_stop_superinstructions() {
+    // 5 is the Push in the middle of the fused LoadReg; Push; JumpEq at 4
+    let mut context = Context::new_with_config(factorial(5), Config { superinstructions: true, ..Config::default() });
+    context.add_breakpoint(5).unwrap();
+    assert_eq!(context.resume(), Ok(RunOutcome::Hit { pc: 5 }));
+    assert_eq!(context.stack(), [5]);
+
+    let mut context = Context::new(vec![ix(Push, &[1]), ix(Exit, &[])]);
+    context.add_breakpoint(1).unwrap();
+    assert_eq!(context.resume(), Ok(RunOutcome::Hit { pc: 1 }));
+    assert_eq!(context.resume(), Ok(RunOutcome::Completed(1)));
+}
+
+#[test]
+fn conditional_breakpoint_on_a_late_iteration() {
+    // count r1 down from 10,000, stopping only when it's about to reach 1
+    let mut context = Context::new(vec![ix(DecReg, &[1]), ix(LoadReg, &[1]), ix(JumpNotZero, &[0]), ix(Exit, &[1])]);
+    context.set_register(1, 10_000).unwrap();
+    context.add_conditional_breakpoint(0, Box::new(|context| context.registers()[1] == 2)).unwrap();
+    assert_eq!(context.list_breakpoints(), vec![0]);
+    assert_eq!(context.resume(), Ok(RunOutcome::Hit { pc: 0 }));
+    assert_eq!(context.steps(), 9_998 * 3);
+    assert_eq!(context.resume(), Ok(RunOutcome::Completed(0)));
+
+    let mut context = Context::new(factorial(3));
+    assert!(context.add_conditional_breakpoint(40, Box::new(|_| true)).is_err());
+}
+
+#[test]
+fn watching_the_factorial_counter() {
+    let mut context = Context::new(factorial(5));
+    context.watch_register(1).unwrap();
+    let mut writes = Vec::new();
+    let value = loop {
+        match context.resume().unwrap() {
+            RunOutcome::Watchpoint { pc, watch, old, new } => {
+                assert_eq!(watch, Watch::Register(1));
+                assert_eq!(context.registers()[1], new);
+                writes.push((pc, old, new));
+            },
+            RunOutcome::Completed(value) => break value,
+            outcome => panic!("{:?}", outcome),
+        }
+    };
+    assert_eq!(value, 120);
+    assert_eq!(writes, vec![(1, 0, 5), (11, 5, 4), (11, 4, 3), (11, 3, 2), (11, 2, 1)]);
+
+    assert!(context.watch_register(11).is_err());
+    assert!(context.unwatch(Watch::Register(1)));
+    assert!(!context.unwatch(Watch::Register(1)));
+}
+
+#[test]
+fn watching_memory() {
+    let program = vec![
+        ix(Push, &[7]),
+        ix(Store, &[100]),
+        ix(Push, &[99]),
+        ix(Push, &[100]),
+        ix(Push, &[3]),
+        ix(MemSet, &[]),
+        ix(Load, &[101]),
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new(program.clone());
+    context.watch_memory(101);
+    assert_eq!(context.step(), Ok(StepOutcome::Continued));
+    assert_eq!(context.step(), Ok(StepOutcome::Continued));
+    assert_eq!(context.resume(), Ok(RunOutcome::Watchpoint { pc: 5, watch: Watch::Memory(101), old: 0, new: 100 }));
+    assert_eq!(context.resume(), Ok(RunOutcome::Completed(100)));
+
+    let mut context = Context::new(program);
+    context.watch_memory(100);
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err, VmError::Watchpoint { pc: 1, watch: Watch::Memory(100), old: 0, new: 7 });
+    assert_eq!(err.to_string(), "Stopped after pc=1 wrote Memory(100): 0 -> 7");
+}
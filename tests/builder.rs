@@ -0,0 +1,90 @@
+mod common;
+
+use beef::{BuildError, Context, OpCode::*, ProgramBuilder};
+use common::{factorial, ix};
+
+fn factorial_builder(n: i64) -> ProgramBuilder {
+    ProgramBuilder::new()
+        .push(n)
+        .store_reg(1)
+        .push(1)
+        .store_reg(0)
+        .label("loop_start")
+        .load_reg(1)
+        .push(1)
+        .jump_eq("done")
+        .load_reg(0)
+        .load_reg(1)
+        .mul()
+        .store_reg(0)
+        .dec_reg(1)
+        .jump("loop_start")
+        .label("done")
+        .exit_reg(0)
+}
+
+#[test]
+fn builds_factorial() {
+    let program = factorial_builder(5).build().unwrap();
+    assert_eq!(program, factorial(5));
+    assert_eq!(Context::new(program).run(false), Ok(120));
+}
+
+#[test]
+fn forward_references_and_calls() {
+    let program = ProgramBuilder::new()
+        .push(20)
+        .call_n("double", 1)
+        .jump("end")
+        .label("double")
+        .load_local(0)
+        .mul_imm(2)
+        .ret_values(1)
+        .label("end")
+        .exit()
+        .build()
+        .unwrap();
+    assert_eq!(program[1], ix(CallN, &[3, 1]));
+    assert_eq!(program[2], ix(Jump, &[6]));
+    assert_eq!(Context::new(program).run(false), Ok(40));
+
+    // labels may also point one past the end, and several may share an instruction
+    let program = ProgramBuilder::new().label("a").label("b").jump("b").jump("end").label("end").build().unwrap();
+    assert_eq!(program, vec![ix(Jump, &[0]), ix(Jump, &[2])]);
+}
+
+#[test]
+fn raw_instructions_pass_through() {
+    let program = ProgramBuilder::new().raw(ix(Push, &[1])).op(Switch, &[2, 2]).raw(ix(Exit, &[])).build().unwrap();
+    assert_eq!(program, vec![ix(Push, &[1]), ix(Switch, &[2, 2]), ix(Exit, &[])]);
+}
+
+#[test]
+fn undefined_labels() {
+    let err = factorial_builder(5).jump("nowhere").build().unwrap_err();
+    assert_eq!(err, BuildError::UndefinedLabel { label: "nowhere".to_string(), pc: 14 });
+    assert_eq!(err.to_string(), "Undefined label nowhere used at pc=14");
+}
+
+#[test]
+fn duplicate_labels() {
+    let err = factorial_builder(5).label("loop_start").nop().build().unwrap_err();
+    assert_eq!(err, BuildError::DuplicateLabel { label: "loop_start".to_string(), first: 4, second: 14 });
+}
+
+#[test]
+fn operands_are_checked_at_build_time() {
+    let err = ProgramBuilder::new().push(1).op(Push, &[]).build().unwrap_err();
+    assert_eq!(err, BuildError::OperandCount { pc: 1, opcode: Push, expected: 1..=1, found: 0 });
+    let err = ProgramBuilder::new().raw(ix(AddReg, &[0, 1])).build().unwrap_err();
+    assert!(matches!(err, BuildError::OperandCount { opcode: AddReg, found: 2, .. }));
+
+    let err = ProgramBuilder::new().push(1).store_reg(11).build().unwrap_err();
+    assert_eq!(err, BuildError::InvalidRegister { pc: 1, opcode: StoreReg, register: 11 });
+    let err = ProgramBuilder::new().add_reg(0, 1, -1).build().unwrap_err();
+    assert_eq!(err, BuildError::InvalidRegister { pc: 0, opcode: AddReg, register: -1 });
+    let err = ProgramBuilder::new().load_at(12, 4).build().unwrap_err();
+    assert_eq!(err, BuildError::InvalidRegister { pc: 0, opcode: Load, register: 12 });
+    // a one operand Load is an address, not a register
+    assert!(ProgramBuilder::new().load(12).exit().build().is_ok());
+}
@@ -0,0 +1,268 @@
+mod common;
+
+use beef::{
+    opcode_byte, Config, Context, DebugInfo, DecodeError, EncodeError, Instruction, OpCode, OpCode::*, Program, FORMAT_VERSION, MAX_OPERANDS,
+    OPCODE_SET_VERSION,
+};
+use common::{factorial, ix};
+
+// xorshift64, enough randomness for round trips without pulling in a crate
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // mostly small values, with the edges showing up often
+    fn value(&mut self) -> i64 {
+        match self.below(4) {
+            0 => [0, 1, -1, i64::MIN, i64::MAX][self.below(5)],
+            1 => self.next() as i64,
+            _ => self.below(64) as i64 - 32,
+        }
+    }
+}
+
+fn random_program(rng: &mut Rng) -> Program {
+    let instructions = (0..rng.below(40))
+        .map(|_| {
+            let opcode = OpCode::ALL[rng.below(OpCode::ALL.len())];
+            let operands = (0..rng.below(5)).map(|_| rng.value()).collect();
+            Instruction { opcode, operands }
+        })
+        .collect();
+    let mut program = Program::new(instructions);
+    for _ in 0..rng.below(3) {
+        let words = (0..rng.below(6)).map(|_| rng.value()).collect();
+        program = program.with_data(rng.below(1 << 20), words);
+    }
+    for i in 0..rng.below(3) {
+        program = program.with_symbol(&format!("f{}é", i), rng.below(100));
+    }
+    program.strings = (0..rng.below(3)).map(|i| format!("s{}\n→", i)).collect();
+    // the decoder checks string indices against the table
+    for instruction in program.instructions.iter_mut().filter(|instruction| instruction.opcode.is_string_operand(0)) {
+        if let Some(index) = instruction.operands.first_mut() {
+            *index = program.strings.len() as i64;
+            program.strings.push(String::new());
+        }
+    }
+    if rng.below(2) == 0 {
+        let lines = (0..program.instructions.len()).map(|_| rng.below(1000)).collect();
+        program.debug_info = Some(DebugInfo { file: format!("src/{}.basm", rng.below(10)), lines });
+    }
+    program
+}
+
+#[test]
+fn random_programs_round_trip() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..2000 {
+        let program = random_program(&mut rng);
+        assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()), Ok(program));
+    }
+}
+
+#[test]
+fn every_opcode_round_trips() {
+    for (byte, &opcode) in OpCode::ALL.iter().enumerate() {
+        assert_eq!(opcode as usize, byte);
+        let mut program = Program::new(vec![ix(opcode, &[byte as i64, -1])]);
+        if opcode.is_string_operand(0) {
+            program.strings = vec![String::new(); byte + 1];
+        }
+        assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()), Ok(program));
+    }
+}
+
+#[test]
+fn decoded_program_runs() {
+    let bytes = Program::new(factorial(5)).to_bytes().unwrap();
+    let program = Program::from_bytes(&bytes).unwrap();
+    assert_eq!(Context::load(program, Config::default()).unwrap().run(false), Ok(120));
+}
+
+#[test]
+fn layout() {
+    let bytes = Program::new(vec![ix(Push, &[-2]), ix(Exit, &[])]).to_bytes().unwrap();
+    let mut expected = b"BEEF".to_vec();
+    expected.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    expected.extend_from_slice(&OPCODE_SET_VERSION.to_le_bytes());
+    expected.extend_from_slice(&2u32.to_le_bytes());
+    expected.extend_from_slice(&[opcode_byte(Push), 1, 0]);
+    expected.extend_from_slice(&(-2i64).to_le_bytes());
+    expected.extend_from_slice(&[opcode_byte(Exit), 0, 0]);
+    expected.extend_from_slice(&[0; 22]); // no data, no symbols, no debug info, no strings, no name, entry 0
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn rejects_bad_magic() {
+    assert_eq!(Program::from_bytes(b""), Err(DecodeError::BadMagic));
+    assert_eq!(Program::from_bytes(b"BEFF\x01\x00"), Err(DecodeError::BadMagic));
+}
+
+#[test]
+fn versions() {
+    let program = Program::new(factorial(3)).with_symbol("main", 0);
+    let with_version = |version: u16, opcode_set: u16| {
+        let mut bytes = program.to_bytes().unwrap();
+        bytes[4..6].copy_from_slice(&version.to_le_bytes());
+        bytes[6..8].copy_from_slice(&opcode_set.to_le_bytes());
+        bytes
+    };
+
+    // other major versions fail loudly, including the unversioned-opcode-set 0.1 files
+    let err = Program::from_bytes(&with_version(0x0200, 1)).unwrap_err();
+    assert_eq!(err, DecodeError::UnsupportedVersion { found: 0x0200, supported: FORMAT_VERSION });
+    assert_eq!(err.to_string(), "Unsupported bytecode version 2.0 (supported: 1.x)");
+    assert!(matches!(Program::from_bytes(&with_version(0x0001, 1)), Err(DecodeError::UnsupportedVersion { found: 1, .. })));
+
+    // a newer minor may append sections, which are skipped
+    let mut bytes = with_version(FORMAT_VERSION + 1, OPCODE_SET_VERSION + 3);
+    bytes.extend_from_slice(b"debug info from the future");
+    assert_eq!(Program::from_bytes(&bytes).as_ref(), Ok(&program));
+    // 1.2 files have no name or entry point, 1.1 files no string table, 1.0 files no debug info either
+    let mut bytes = program.to_bytes().unwrap();
+    bytes[4..6].copy_from_slice(&0x0102u16.to_le_bytes());
+    assert_eq!(bytes.split_off(bytes.len() - 9), [0; 9]);
+    assert_eq!(Program::from_bytes(&bytes).as_ref(), Ok(&program));
+    bytes[4..6].copy_from_slice(&0x0101u16.to_le_bytes());
+    assert_eq!(bytes.split_off(bytes.len() - 4), [0; 4]);
+    assert_eq!(Program::from_bytes(&bytes).as_ref(), Ok(&program));
+    bytes[4..6].copy_from_slice(&0x0100u16.to_le_bytes());
+    assert_eq!(bytes.pop(), Some(0));
+    assert_eq!(Program::from_bytes(&bytes).as_ref(), Ok(&program));
+
+    // but not by files claiming our own version
+    let mut bytes = program.to_bytes().unwrap();
+    bytes.push(0);
+    assert!(matches!(Program::from_bytes(&bytes), Err(DecodeError::TrailingBytes { .. })));
+}
+
+// changing any of these numbers breaks every bytecode file out there
+#[test]
+fn opcode_numbers_are_pinned() {
+    let pinned = [
+        (Push, 0), (Pop, 1), (Dup, 2), (Swap, 3), (Over, 4), (Rot, 5), (Pick, 6), (Add, 7), (Sub, 8), (Mul, 9),
+        (Div, 10), (Mod, 11), (AddImm, 12), (SubImm, 13), (MulImm, 14), (Neg, 15), (Abs, 16), (Min, 17), (Max, 18),
+        (And, 19), (Or, 20), (Xor, 21), (Not, 22), (Shl, 23), (Shr, 24), (Sar, 25), (Eq, 26), (Ne, 27), (Lt, 28),
+        (Le, 29), (Gt, 30), (Ge, 31), (LoadReg, 32), (StoreReg, 33), (AddReg, 34), (SubReg, 35), (MulReg, 36),
+        (DivReg, 37), (MovReg, 38), (IncReg, 39), (DecReg, 40), (Load, 41), (Store, 42), (LoadInd, 43),
+        (StoreInd, 44), (MemSet, 45), (MemCpy, 46), (Alloc, 47), (Free, 48), (PushM, 49), (PopM, 50), (Load8, 51),
+        (Load16, 52), (Load32, 53), (Load64, 54), (Store8, 55), (Store16, 56), (Store32, 57), (Store64, 58),
+        (Jump, 59), (JumpEq, 60), (JumpGt, 61), (JumpLt, 62), (JumpNe, 63), (JumpGe, 64), (JumpLe, 65),
+        (JumpZero, 66), (JumpNotZero, 67), (JumpRel, 68), (JumpRelEq, 69), (JumpRelNe, 70), (JumpRelGt, 71),
+        (JumpRelLt, 72), (JumpRelGe, 73), (JumpRelLe, 74), (Switch, 75), (JumpDyn, 76), (Call, 77),
+        (CallIndirect, 78), (CallN, 79), (TailCall, 80), (Return, 81), (Enter, 82), (LoadLocal, 83),
+        (StoreLocal, 84), (Syscall, 85), (Print, 86), (PrintChar, 87), (Read, 88), (Yield, 89), (Nop, 90),
+        (Halt, 91), (Exit, 92), (FPush, 93), (FAdd, 94), (FSub, 95), (FMul, 96), (FDiv, 97), (FEq, 98), (FLt, 99),
+        (FGt, 100), (IntToFloat, 101), (FloatToInt, 102), (FPrint, 103), (FExit, 104),
+        (Select, 105), (DivU, 106), (ModU, 107), (LtU, 108), (LeU, 109), (GtU, 110), (GeU, 111),
+        (MulHi, 112), (MulHiU, 113), (PrintStr, 114), (Assert, 115),
+        (GetPC, 116), (Depth, 117), (GetCallDepth, 118), (Trap, 119),
+        (TryPush, 120), (TryPop, 121), (Throw, 122), (Rand, 123), (RandRange, 124),
+        (Steps, 125), (TimeMs, 126), (AtomicAdd, 127), (AtomicCas, 128),
+        (Send, 129), (Recv, 130),
+    ];
+    assert_eq!(pinned.len(), OpCode::ALL.len());
+    for (opcode, byte) in pinned {
+        assert_eq!(opcode_byte(opcode), byte, "{:?}", opcode);
+    }
+}
+
+#[test]
+fn rejects_unknown_opcodes() {
+    let mut bytes = Program::new(vec![ix(Nop, &[]), ix(Exit, &[])]).to_bytes().unwrap();
+    bytes[15] = 0xff;
+    let err = Program::from_bytes(&bytes).unwrap_err();
+    assert_eq!(err, DecodeError::UnknownOpcode { offset: 15, byte: 0xff });
+    assert_eq!(err.to_string(), "Unknown opcode byte 0xff at byte 15");
+}
+
+#[test]
+fn rejects_absurd_operand_counts() {
+    let mut bytes = Program::new(vec![ix(Switch, &[])]).to_bytes().unwrap();
+    bytes[13..15].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(Program::from_bytes(&bytes), Err(DecodeError::TooManyOperands { offset: 12, opcode: Switch, count: 65535 }));
+
+    // an instruction or data count bigger than the file must not be trusted for allocation
+    let mut bytes = Program::default().to_bytes().unwrap();
+    bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(Program::from_bytes(&bytes), Err(DecodeError::Truncated { .. })));
+    let mut bytes = Program::default().with_data(0, vec![1]).to_bytes().unwrap();
+    bytes[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(Program::from_bytes(&bytes), Err(DecodeError::Truncated { expected: "data words", .. })));
+}
+
+#[test]
+fn rejects_truncated_input() {
+    let program = Program::new(factorial(3)).with_data(8, vec![1, 2]).with_symbol("main", 0);
+    let bytes = program.to_bytes().unwrap();
+    for len in 0..bytes.len() {
+        assert!(Program::from_bytes(&bytes[..len]).is_err(), "decoded a prefix of {} bytes", len);
+    }
+    let err = Program::from_bytes(&bytes[..22]).unwrap_err();
+    assert_eq!(err, DecodeError::Truncated { offset: 22, expected: "operand" });
+
+    let mut bytes = bytes;
+    bytes.push(0);
+    assert_eq!(Program::from_bytes(&bytes), Err(DecodeError::TrailingBytes { offset: bytes.len() - 1 }));
+}
+
+#[test]
+fn rejects_invalid_symbol_names() {
+    let mut bytes = Program::default().with_symbol("ab", 0).to_bytes().unwrap();
+    bytes[22] = 0xff;
+    assert_eq!(Program::from_bytes(&bytes), Err(DecodeError::InvalidSymbol { offset: 22 }));
+}
+
+#[test]
+fn rejects_invalid_utf8_outside_the_symbols() {
+    // each program holds "zz" once, in the section under test
+    let with_file = Program { debug_info: Some(DebugInfo { file: "zz".to_string(), lines: vec![] }), ..Program::default() };
+    let with_string = Program { strings: vec!["zz".to_string()], ..Program::default() };
+    let with_name = Program::default().with_name("zz");
+
+    for (program, what) in [(with_file, "file name"), (with_string, "string"), (with_name, "program name")] {
+        let mut bytes = program.to_bytes().unwrap();
+        let offset = bytes.windows(2).position(|pair| pair == b"zz").unwrap();
+        bytes[offset] = 0xff;
+        assert_eq!(Program::from_bytes(&bytes), Err(DecodeError::InvalidUtf8 { offset, what }));
+    }
+}
+
+#[test]
+fn programs_the_format_cant_hold_fail_to_encode() {
+    let program = Program::new(vec![ix(Nop, &[]), ix(Switch, &vec![0; MAX_OPERANDS + 1])]);
+    assert_eq!(program.to_bytes(), Err(EncodeError::TooManyOperands { pc: 1, opcode: Switch, count: MAX_OPERANDS + 1 }));
+
+    let name = "f".repeat(70_000);
+    let err = Program::default().with_symbol(&name, 0).to_bytes().unwrap_err();
+    assert_eq!(err, EncodeError::SymbolTooLong { name, len: 70_000 });
+    assert!(err.to_string().starts_with("Symbol ffff"), "{}", err);
+
+    let mut program = Program::new(vec![ix(Exit, &[])]);
+    program.debug_info = Some(DebugInfo { file: "x".repeat(65_536), lines: vec![1] });
+    assert_eq!(program.to_bytes(), Err(EncodeError::FileNameTooLong { len: 65_536 }));
+}
+
+#[test]
+fn strings_round_trip_and_indices_are_checked() {
+    let mut program = Program::new(vec![ix(PrintStr, &[1]), ix(Exit, &[0])]);
+    program.strings = vec!["first\n".to_string(), "zweite ü 😀".to_string()];
+    assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()).as_ref(), Ok(&program));
+
+    program.strings.pop();
+    let err = Program::from_bytes(&program.to_bytes().unwrap()).unwrap_err();
+    assert_eq!(err, DecodeError::UnknownString { offset: 12, index: 1, count: 1 });
+    assert_eq!(err.to_string(), "PrintStr at byte 12 uses string 1, the table has 1");
+}
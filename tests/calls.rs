@@ -0,0 +1,231 @@
+mod common;
+
+#[cfg(feature = "std")]
+use beef::FunctionCounter;
+use beef::{Config, Context, OpCode::*, Program, RunOutcome, VmError, MAX_LOCALS};
+use common::{ix, run, run_err};
+
+#[test]
+fn recursive_fibonacci_with_locals() {
+    let program = vec![
+        ix(Push, &[10]),
+        ix(Call, &[3, 1]),
+        ix(Exit, &[]),
+        // fib at 3, n in local 0
+        ix(StoreLocal, &[0]),
+        ix(LoadLocal, &[0]),
+        ix(Push, &[2]),
+        ix(JumpLt, &[15]),
+        ix(LoadLocal, &[0]),
+        ix(SubImm, &[1]),
+        ix(Call, &[3, 1]),
+        ix(LoadLocal, &[0]),
+        ix(SubImm, &[2]),
+        ix(Call, &[3, 1]),
+        ix(Add, &[]),
+        ix(Return, &[1]),
+        // n < 2
+        ix(LoadLocal, &[0]),
+        ix(Return, &[1]),
+    ];
+    assert_eq!(run(program), Ok(55));
+}
+
+#[test]
+fn locals_need_a_frame() {
+    let err = run_err(vec![ix(LoadLocal, &[0]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::NoFrame { pc: 0, opcode: LoadLocal });
+    let err = run_err(vec![ix(Call, &[2, 1]), ix(Exit, &[]), ix(LoadLocal, &[1]), ix(Return, &[])]);
+    assert_eq!(err, VmError::InvalidLocal { pc: 2, index: 1, locals: 1 });
+}
+
+#[test]
+fn call_n_moves_arguments_into_locals() {
+    let max = |a: i64, b: i64| {
+        run(vec![
+            ix(Push, &[a]),
+            ix(Push, &[b]),
+            ix(CallN, &[4, 2]),
+            ix(Exit, &[]),
+            // max at 4
+            ix(LoadLocal, &[0]),
+            ix(LoadLocal, &[1]),
+            ix(Max, &[]),
+            ix(Return, &[1]),
+        ])
+    };
+    assert_eq!(max(3, 8), Ok(8));
+    assert_eq!(max(-1, -6), Ok(-1));
+    let err = run_err(vec![ix(Push, &[1]), ix(CallN, &[0, 2]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::StackUnderflow { pc: 1, opcode: CallN, needed: 2, found: 1 });
+}
+
+#[test]
+fn return_truncates_to_the_callers_stack() {
+    let program = vec![
+        ix(Push, &[7]),
+        ix(Call, &[3]),
+        ix(Exit, &[]),
+        // leaves junk behind, only the top value survives
+        ix(Push, &[1]),
+        ix(Push, &[2]),
+        ix(Push, &[3]),
+        ix(Return, &[1]),
+    ];
+    let mut context = Context::new(program);
+    assert_eq!(context.run(false), Ok(3));
+    assert_eq!(context.stack(), &[7]);
+}
+
+// r1 levels of recursion, then unwind
+fn recurse(levels: i64, max_call_depth: usize) -> Result<i64, VmError> {
+    let program = vec![
+        ix(Call, &[2]),
+        ix(Exit, &[0]),
+        ix(DecReg, &[1]),
+        ix(LoadReg, &[1]),
+        ix(JumpZero, &[6]),
+        ix(Call, &[2]),
+        ix(Return, &[]),
+    ];
+    let mut context = Context::new_with_config(program, Config { max_call_depth, ..Config::default() });
+    context.set_register(1, levels).unwrap();
+    context.run(false)
+}
+
+#[test]
+fn call_depth_limit() {
+    assert_eq!(recurse(8, 8), Ok(0));
+    let err = recurse(9, 8).unwrap_err();
+    assert!(matches!(err.root(), VmError::CallStackOverflow { pc: 5, depth: 8, .. }), "{}", err);
+
+    let err = run_err(vec![ix(Call, &[0])]);
+    assert_eq!(err, VmError::CallStackOverflow { pc: 0, depth: 1024, return_addrs: vec![1; 8] });
+}
+
+#[test]
+fn errors_show_the_call_stack() {
+    let program = vec![
+        ix(Call, &[2]),
+        ix(Exit, &[0]),
+        ix(Call, &[4]),
+        ix(Return, &[]),
+        ix(Call, &[6]),
+        ix(Return, &[]),
+        ix(Pop, &[]),
+        ix(Return, &[]),
+    ];
+    let mut context = Context::new(program);
+    let err = context.run(false).unwrap_err();
+    assert!(err.to_string().ends_with("stack top: [], call stack: [1, 3, 5]"), "{}", err);
+    assert!(matches!(err, VmError::Located { ref instruction, ref call_stack, .. }
+        if instruction.opcode == Pop && *call_stack == [1, 3, 5]));
+    assert_eq!(context.backtrace(), vec![1, 3, 5]);
+}
+
+#[test]
+fn call_function_by_name() {
+    let program = Program::new(vec![
+        ix(Exit, &[0]),
+        // double at 1
+        ix(LoadLocal, &[0]),
+        ix(MulImm, &[2]),
+        ix(Return, &[]),
+        // fail at 4
+        ix(Nop, &[]),
+        ix(Pop, &[]),
+        ix(Return, &[]),
+    ])
+    .with_symbol("double", 1)
+    .with_symbol("fail", 4);
+    let mut context = Context::load(program, Config::default()).unwrap();
+
+    assert_eq!(context.call_function("double", &[21]), Ok(42));
+    assert_eq!(context.call_function("double", &[-4]), Ok(-8));
+    assert_eq!(context.pc(), 0);
+    assert!(context.backtrace().is_empty());
+
+    let err = context.call_function("fail", &[]).unwrap_err();
+    assert!(err.to_string().contains("at pc=fail+1 (5) (Instruction { opcode: Pop"), "{}", err);
+    assert_eq!(context.call_function("missing", &[]), Err(VmError::UnknownFunction("missing".to_string())));
+}
+
+#[test]
+fn symbols_must_point_into_the_program() {
+    let program = Program::new(vec![ix(Exit, &[0])]).with_symbol("f", 1);
+    assert!(Context::load(program, Config::default()).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn function_counter_attributes_instructions_to_callees() {
+    let counter = FunctionCounter::default();
+    let counts = counter.counts();
+    let mut context = Context::new(vec![
+        ix(Call, &[3]),
+        ix(Call, &[3]),
+        ix(Exit, &[0]),
+        ix(Nop, &[]),
+        ix(Return, &[]),
+    ]);
+    context.set_hooks(Box::new(counter));
+    context.run(false).unwrap();
+    assert_eq!(counts.lock().unwrap().iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(), vec![(0, 3), (3, 4)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn function_counter_charges_tail_called_functions_to_themselves() {
+    let counter = FunctionCounter::default();
+    let counts = counter.counts();
+    let mut context = Context::new(vec![
+        ix(Call, &[2]),
+        ix(Exit, &[0]),
+        // f at 2 hands over to g
+        ix(Nop, &[]),
+        ix(TailCall, &[4]),
+        // g at 4
+        ix(Nop, &[]),
+        ix(Nop, &[]),
+        ix(Return, &[]),
+    ]);
+    context.set_hooks(Box::new(counter));
+    context.run(false).unwrap();
+    assert_eq!(counts.lock().unwrap().iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(), vec![(0, 2), (2, 2), (4, 3)]);
+}
+
+#[test]
+fn tail_calls_run_in_constant_call_stack_space() {
+    // ping and pong bounce r1 down to zero through tail calls
+    let program = vec![
+        ix(Call, &[2]),
+        ix(Exit, &[1]),
+        // ping at 2
+        ix(DecReg, &[1]),
+        ix(LoadReg, &[1]),
+        ix(JumpZero, &[7]),
+        ix(TailCall, &[8]),
+        ix(Nop, &[]),
+        ix(Return, &[]),
+        // pong at 8
+        ix(TailCall, &[2]),
+    ];
+    let mut context = Context::new(program);
+    context.set_register(1, 10_000).unwrap();
+    loop {
+        let outcome = context.run_for(1).unwrap();
+        assert!(context.backtrace().len() <= 1);
+        if outcome == RunOutcome::Completed(0) {
+            break;
+        }
+    }
+}
+
+#[test]
+fn frames_past_the_local_cap_are_rejected() {
+    let locals = MAX_LOCALS as i64 + 1;
+    let err = run_err(vec![ix(Call, &[2, locals]), ix(Exit, &[0]), ix(Return, &[])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 0, opcode: Call, value: locals });
+    let err = run_err(vec![ix(Call, &[2]), ix(Exit, &[0]), ix(Enter, &[i64::MAX]), ix(Return, &[])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 2, opcode: Enter, value: i64::MAX });
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use beef::{cfg, Edge, EdgeKind, OpCode::*};
+use common::{factorial, ix};
+
+fn bounds(graph: &beef::Cfg) -> Vec<(usize, usize, bool)> {
+    graph.blocks.iter().map(|block| (block.start, block.end, block.reachable)).collect()
+}
+
+fn edge(from: usize, to: usize, kind: EdgeKind) -> Edge {
+    Edge { from, to, kind }
+}
+
+#[test]
+fn factorial_splits_at_the_loop() {
+    let graph = cfg(&factorial(5));
+    assert_eq!(bounds(&graph), [(0, 4, true), (4, 7, true), (7, 13, true), (13, 14, true)]);
+    assert_eq!(graph.edges, [
+        edge(0, 4, EdgeKind::Fallthrough),
+        edge(4, 13, EdgeKind::Eq),
+        edge(4, 7, EdgeKind::Fallthrough),
+        edge(7, 4, EdgeKind::Unconditional),
+    ]);
+    assert_eq!(graph.block_at(9).map(|block| block.start), Some(7));
+}
+
+#[test]
+fn a_jump_target_splits_a_straight_run() {
+    let program = vec![
+        ix(Push, &[10]),
+        // the loop jumps back here, into what would otherwise be one block with the Push
+        ix(Push, &[1]),
+        ix(Sub, &[]),
+        ix(Dup, &[]),
+        ix(JumpNotZero, &[1]),
+        ix(Exit, &[0]),
+        // dead code after the exit
+        ix(Push, &[9]),
+        ix(Exit, &[]),
+    ];
+    let graph = cfg(&program);
+    assert_eq!(bounds(&graph), [(0, 1, true), (1, 5, true), (5, 6, true), (6, 8, false)]);
+    assert_eq!(graph.edges, [
+        edge(0, 1, EdgeKind::Fallthrough),
+        edge(1, 1, EdgeKind::NotZero),
+        edge(1, 5, EdgeKind::Fallthrough),
+    ]);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph cfg {\n"), "{}", dot);
+    assert!(dot.contains("    pc1 [label=\"1: push 1\\l2: sub\\l3: dup\\l4: jumpnotzero 1\\l\"];\n"), "{}", dot);
+    assert!(dot.contains("    pc6 [label=\"6: push 9\\l7: exit\\l\", style=dashed, color=grey, fontcolor=grey];\n"), "{}", dot);
+    assert!(dot.contains("    pc1 -> pc1 [label=\"nonzero\"];\n"), "{}", dot);
+}
+
+#[test]
+fn calls_and_switches_get_labeled_edges() {
+    let program = vec![
+        ix(Call, &[4]),
+        ix(Switch, &[3, 5]),
+        ix(Nop, &[]),
+        ix(Exit, &[0]),
+        // the function
+        ix(Return, &[]),
+        ix(Halt, &[1]),
+    ];
+    let graph = cfg(&program);
+    assert_eq!(bounds(&graph), [(0, 1, true), (1, 2, true), (2, 3, false), (3, 4, true), (4, 5, true), (5, 6, true)]);
+    assert_eq!(graph.edges, [
+        edge(0, 4, EdgeKind::Call),
+        edge(0, 1, EdgeKind::Fallthrough),
+        edge(1, 3, EdgeKind::Case(0)),
+        edge(1, 5, EdgeKind::Default),
+        edge(2, 3, EdgeKind::Fallthrough),
+    ]);
+    assert!(cfg(&[]).blocks.is_empty());
+}
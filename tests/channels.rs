@@ -0,0 +1,179 @@
+mod common;
+
+use beef::{assemble, Channel, Context, OpCode::*, RunOutcome, Scheduler, TaskStatus, VmError};
+use common::{ix, run_err};
+
+// sends 1..=n on channel 0, then exits with 0
+fn producer(n: i64) -> Context {
+    let program = assemble(&format!(
+        "
+        push 1
+        storereg r0
+    loop:
+        loadreg r0
+        send 0
+        increg r0
+        loadreg r0
+        push {limit}
+        jumplt loop
+        push 0
+        exit
+        ",
+        limit = n + 1,
+    ))
+    .unwrap();
+    Context::new(program)
+}
+
+// receives n values on channel 0, exits with their sum if each was one more than the last and 0 if not
+fn consumer(n: i64) -> Context {
+    let program = assemble(&format!(
+        "
+        push {n}
+        storereg r2
+        push 1
+        storereg r1
+    loop:
+        recv 0
+        dup
+        loadreg r3
+        push 1
+        add
+        eq
+        loadreg r1
+        and
+        storereg r1
+        dup
+        storereg r3
+        loadreg r0
+        add
+        storereg r0
+        decreg r2
+        loadreg r2
+        jumpnotzero loop
+        loadreg r0
+        loadreg r1
+        mul
+        exit
+        ",
+        n = n,
+    ))
+    .unwrap();
+    Context::new(program)
+}
+
+#[test]
+fn a_thousand_values_through_a_small_channel() {
+    let channel = Channel::new(4);
+    let mut producer = producer(1000);
+    let mut consumer = consumer(1000);
+    producer.attach_sender(0, &channel);
+    consumer.attach_receiver(0, &channel);
+
+    let mut scheduler = Scheduler::new(50);
+    let sender = scheduler.spawn(producer);
+    let receiver = scheduler.spawn(consumer);
+    let mut blocked = 0;
+    let mut finished = Vec::new();
+    while !scheduler.is_empty() {
+        for (id, status) in scheduler.round() {
+            assert!(channel.len() <= 4);
+            match status {
+                TaskStatus::Blocked { .. } => blocked += 1,
+                status if status.is_finished() => finished.push((id, status)),
+                _ => {},
+            }
+        }
+    }
+    assert_eq!(finished, [(sender, TaskStatus::Exited(0)), (receiver, TaskStatus::Exited(500_500))]);
+    // the producer outruns a capacity of 4 every turn
+    assert!(blocked > 0);
+    assert!(channel.is_empty() && channel.is_closed());
+}
+
+#[test]
+fn values_arrive_in_order() {
+    let channel = Channel::new(4);
+    let mut producer = producer(100);
+    let mut consumer = consumer(100);
+    producer.attach_sender(0, &channel);
+    consumer.attach_receiver(0, &channel);
+
+    let mut scheduler = Scheduler::new(7);
+    scheduler.spawn(consumer);
+    scheduler.spawn(producer);
+    let finished = scheduler.run_all();
+    assert_eq!(finished.len(), 2);
+    let consumer = finished.iter().find(|(id, _)| *id == 0).unwrap();
+    assert_eq!(consumer.1, TaskStatus::Exited(5050));
+}
+
+#[test]
+fn blocking_stops_a_plain_run() {
+    let channel = Channel::new(2);
+    let mut sender = Context::new(vec![ix(Push, &[1]), ix(Push, &[2]), ix(Push, &[3]), ix(Send, &[5]), ix(Send, &[5]), ix(Send, &[5]), ix(Exit, &[0])]);
+    sender.attach_sender(5, &channel);
+    assert_eq!(sender.run_for(100), Ok(RunOutcome::Blocked { pc: 5 }));
+    assert_eq!((sender.pc(), sender.stack()), (5, &[1][..]));
+    assert_eq!(sender.run(false).unwrap_err().root(), &VmError::Blocked { pc: 5 });
+
+    let mut receiver = Context::new(vec![ix(Recv, &[5]), ix(Exit, &[])]);
+    receiver.attach_receiver(5, &channel);
+    assert_eq!(receiver.run(false), Ok(3));
+    assert_eq!(sender.run(false), Ok(0));
+    assert_eq!(channel.len(), 2);
+}
+
+#[test]
+fn a_deadlock_ends_run_all() {
+    let (a, b) = (Channel::new(1), Channel::new(1));
+    let mut first = Context::new(vec![ix(Recv, &[0]), ix(Exit, &[])]);
+    let mut second = Context::new(vec![ix(Recv, &[0]), ix(Exit, &[])]);
+    first.attach_receiver(0, &a);
+    first.attach_sender(1, &b);
+    second.attach_receiver(0, &b);
+    second.attach_sender(1, &a);
+
+    let mut scheduler = Scheduler::new(10);
+    scheduler.spawn(first);
+    scheduler.spawn(second);
+    assert_eq!(scheduler.run_all(), []);
+    assert_eq!(scheduler.len(), 2);
+}
+
+#[test]
+fn receiving_after_every_sender_exits_fails() {
+    let channel = Channel::new(4);
+    let mut sender = Context::new(vec![ix(Push, &[7]), ix(Send, &[0]), ix(Exit, &[0])]);
+    sender.attach_sender(0, &channel);
+    let mut receiver = Context::new(vec![ix(Recv, &[0]), ix(Recv, &[0]), ix(Add, &[]), ix(Exit, &[])]);
+    receiver.attach_receiver(0, &channel);
+
+    // still open, so an empty channel only blocks
+    assert_eq!(receiver.run_for(10), Ok(RunOutcome::Blocked { pc: 0 }));
+    assert_eq!(sender.run(false), Ok(0));
+    assert!(channel.is_closed());
+    // what was sent before the close is still delivered
+    assert_eq!(receiver.run(false).unwrap_err().root(), &VmError::ChannelClosed { pc: 1, channel: 0 });
+    assert_eq!(receiver.stack(), [7]);
+
+    // dropping a context closes its send ends too
+    let channel = Channel::new(4);
+    let mut sender = Context::new(vec![ix(Exit, &[0])]);
+    sender.attach_sender(0, &channel);
+    assert!(!channel.is_closed());
+    drop(sender);
+    assert!(channel.is_closed());
+}
+
+#[test]
+fn unknown_channels_are_errors() {
+    assert_eq!(run_err(vec![ix(Push, &[1]), ix(Send, &[3]), ix(Exit, &[0])]), VmError::UnknownChannel { pc: 1, opcode: Send, channel: 3 });
+    assert_eq!(run_err(vec![ix(Recv, &[3]), ix(Exit, &[])]), VmError::UnknownChannel { pc: 0, opcode: Recv, channel: 3 });
+
+    // each end only works in its own direction
+    let channel = Channel::new(1);
+    let mut context = Context::new(vec![ix(Recv, &[0]), ix(Exit, &[])]);
+    context.attach_sender(0, &channel);
+    assert_eq!(context.run(false).unwrap_err().root(), &VmError::UnknownChannel { pc: 0, opcode: Recv, channel: 0 });
+}
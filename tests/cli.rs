@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use beef::{
+    assemble, build_file, load_program, parse_args, parse_build_args, run_file, BuildOptions, CliError, DecodeError, EncodeError,
+    OpCode, ProgramFormat, RunOptions, VmError,
+};
+
+fn example(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples").join(name)
+}
+
+fn run(args: &[&str]) -> Result<i64, CliError> {
+    parse_args(args).and_then(|options| run_file(&options))
+}
+
+#[test]
+fn parses_run_options() {
+    let options = parse_args(&["run", "prog.bin", "--debug", "--reg", "r1=5", "--format", "bytecode", "--reg", "2=-7"]).unwrap();
+    assert_eq!(options, RunOptions {
+        path: PathBuf::from("prog.bin"),
+        format: Some(ProgramFormat::Bytecode),
+        debug: true,
+        registers: vec![(1, 5), (2, -7)],
+    });
+    assert_eq!(parse_args(&["run", "a.basm"]).unwrap().format, None);
+}
+
+#[test]
+fn bad_arguments_are_usage_errors() {
+    for args in [
+        &[][..],
+        &["walk", "a.basm"],
+        &["run"],
+        &["run", "a.basm", "b.basm"],
+        &["run", "a.basm", "--reg"],
+        &["run", "a.basm", "--reg", "r1"],
+        &["run", "a.basm", "--reg", "rx=1"],
+        &["run", "a.basm", "--format", "exe"],
+        &["run", "a.basm", "--verbose"],
+    ] {
+        assert!(matches!(parse_args(args), Err(CliError::Usage(_))), "{:?}", args);
+    }
+}
+
+#[test]
+fn runs_the_example_files() {
+    let basm = example("factorial.basm");
+    let beef = example("factorial.beef");
+    assert_eq!(run(&["run", basm.to_str().unwrap(), "--reg", "r1=5"]), Ok(120));
+    assert_eq!(run(&["run", beef.to_str().unwrap(), "--reg", "r1=10"]), Ok(3_628_800));
+    #[cfg(feature = "json")]
+    {
+        let sum = example("sum.json");
+        assert_eq!(run(&["run", sum.to_str().unwrap(), "--reg", "r1=10"]), Ok(55));
+        // without the seed r1 is 0, the loop guard has to stop it straight away
+        assert_eq!(run(&["run", sum.to_str().unwrap()]), Ok(0));
+    }
+
+    // the shipped bytecode is the assembled source
+    let src = std::fs::read_to_string(&basm).unwrap();
+    assert_eq!(load_program(&beef, None).unwrap().instructions, assemble(&src).unwrap());
+}
+
+#[test]
+fn format_comes_from_the_extension_or_the_flag() {
+    assert_eq!(ProgramFormat::from_path(Path::new("x/y.beef")), Some(ProgramFormat::Bytecode));
+    assert_eq!(ProgramFormat::from_path(Path::new("y.basm")), Some(ProgramFormat::Assembly));
+    assert_eq!(ProgramFormat::from_path(Path::new("y.txt")), None);
+    assert!(matches!(load_program(Path::new("y.txt"), None), Err(CliError::Usage(_))));
+
+    // forcing bytecode on a text file fails in the decoder, not with a panic
+    let err = load_program(&example("factorial.basm"), Some(ProgramFormat::Bytecode)).unwrap_err();
+    assert!(matches!(err, CliError::Decode { error: DecodeError::BadMagic, .. }));
+}
+
+#[test]
+fn errors_name_the_file() {
+    let err = run(&["run", "does/not/exist.basm"]).unwrap_err();
+    assert!(matches!(err, CliError::Io { ref path, .. } if path == Path::new("does/not/exist.basm")));
+    assert!(err.to_string().starts_with("does/not/exist.basm: "), "{}", err);
+
+    let dir = std::env::temp_dir().join(format!("beef-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bad.basm");
+    std::fs::write(&path, "push 1\nfrob 2\n").unwrap();
+    let err = load_program(&path, None).unwrap_err();
+    assert_eq!(err.to_string(), format!("{}: line 2, column 1: unknown mnemonic `frob`", path.display()));
+
+    // runtime errors in assembled files carry the source line
+    std::fs::write(&path, "push 1\npush 0\ndiv\nexit\n").unwrap();
+    let err = run_file(&parse_args(&["run", path.to_str().unwrap()]).unwrap()).unwrap_err();
+    assert!(err.to_string().contains(&format!("at pc=2 ({}:3)", path.display())), "{}", err);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let err = run(&["run", example("factorial.basm").to_str().unwrap(), "--reg", "r12=1"]).unwrap_err();
+    assert_eq!(err, CliError::Vm(VmError::InvalidRegister { pc: 0, index: 12 }));
+}
+
+#[test]
+fn build_writes_bytecode_or_reports_why_it_cant() {
+    let dir = std::env::temp_dir().join(format!("beef-build-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (basm, output) = (example("factorial.basm"), dir.join("factorial.beef"));
+    let args = ["build", basm.to_str().unwrap(), "-o", output.to_str().unwrap()];
+    let options = parse_build_args(&args).unwrap();
+    assert_eq!(options, BuildOptions { path: basm.clone(), format: None, output: output.clone() });
+    build_file(&options).unwrap();
+    assert_eq!(run(&["run", output.to_str().unwrap(), "--reg", "r1=5"]), Ok(120));
+    assert!(matches!(parse_build_args(&["build", "a.basm"]), Err(CliError::Usage(_))));
+
+    // a jump table too wide for the format is an error naming the output, not a panic
+    let wide = dir.join("wide.basm");
+    let targets = vec!["0"; 4097].join(" ");
+    std::fs::write(&wide, format!("push 0\nswitch {}\n", targets)).unwrap();
+    let options = BuildOptions { path: wide, format: None, output: dir.join("wide.beef") };
+    let err = build_file(&options).unwrap_err();
+    assert!(
+        matches!(err, CliError::Encode { ref path, error: EncodeError::TooManyOperands { pc: 1, opcode: OpCode::Switch, count: 4097 } }
+            if path == &dir.join("wide.beef")),
+        "{}", err
+    );
+    assert!(!dir.join("wide.beef").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,92 @@
+// random programs for the fuzz and property tests. No external crates, so this is a small
+// splitmix64 generator and a program builder biased towards operands that break things
+
+use beef::{Instruction, OpCode};
+
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // 0..n, n > 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+// values that sit on the edges of casts, shifts, indexing and overflow
+const NASTY: [i64; 14] = [0, 1, -1, 2, 63, 64, -64, i64::MAX, i64::MIN, i64::MAX - 1, i64::MIN + 1, 1 << 32, -(1 << 32), 0x10000];
+
+pub fn operand(rng: &mut Rng, len: usize) -> i64 {
+    match rng.below(4) {
+        0 => rng.pick(&NASTY),
+        1 => rng.below(len.max(1) + 2) as i64, // likely a pc or a register
+        2 => rng.below(16) as i64 - 4,
+        _ => rng.next() as i64,
+    }
+}
+
+// an operand for arithmetic, edge values a quarter of the time
+pub fn value(rng: &mut Rng) -> i64 {
+    if rng.chance(25) {
+        rng.pick(&NASTY)
+    } else {
+        rng.next() as i64
+    }
+}
+
+// run `property` on `cases` generated inputs, seeded so a failure names a case that reruns the same way.
+// Failing inputs aren't shrunk, they're small enough to read as they are
+pub fn check<T: std::fmt::Debug>(
+    name: &str,
+    cases: usize,
+    mut generate: impl FnMut(&mut Rng) -> T,
+    mut property: impl FnMut(&T) -> Result<(), String>,
+) {
+    let mut rng = Rng::new(name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)));
+    for case in 0..cases {
+        let input = generate(&mut rng);
+        if let Err(message) = property(&input) {
+            panic!("{}: case {} failed on {:?}: {}", name, case, input, message);
+        }
+    }
+}
+
+// up to max_len instructions, usually with a legal operand count but not always
+pub fn program(rng: &mut Rng, max_len: usize) -> Vec<Instruction> {
+    let len = rng.below(max_len + 1);
+    (0..len)
+        .map(|_| {
+            // plenty of pushes, or most programs die on their first stack underflow
+            if rng.chance(30) {
+                return Instruction { opcode: OpCode::Push, operands: vec![operand(rng, len)] };
+            }
+            let opcode = rng.pick(&OpCode::ALL);
+            let counts = opcode.operand_count();
+            let count = if rng.chance(90) {
+                *counts.start() + rng.below((*counts.end()).min(*counts.start() + 4) - *counts.start() + 1)
+            } else {
+                rng.below(5)
+            };
+            Instruction { opcode, operands: (0..count).map(|_| operand(rng, len)).collect() }
+        })
+        .collect()
+}
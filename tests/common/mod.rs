@@ -0,0 +1,60 @@
+#![allow(dead_code)]
+
+pub mod arbitrary;
+
+use beef::{Context, Instruction, OpCode, VmError};
+
+pub fn ix(opcode: OpCode, operands: &[i64]) -> Instruction {
+    Instruction { opcode, operands: operands.to_vec() }
+}
+
+pub fn run(program: Vec<Instruction>) -> Result<i64, VmError> {
+    Context::new(program).run(false)
+}
+
+// the factorial program from main, n baked into the first Push
+pub fn factorial(n: i64) -> Vec<Instruction> {
+    use OpCode::*;
+    vec![
+        ix(Push, &[n]),
+        ix(StoreReg, &[1]),
+        ix(Push, &[1]),
+        ix(StoreReg, &[0]),
+        // loop start at 4
+        ix(LoadReg, &[1]),
+        ix(Push, &[1]),
+        ix(JumpEq, &[13]),
+        ix(LoadReg, &[0]),
+        ix(LoadReg, &[1]),
+        ix(Mul, &[]),
+        ix(StoreReg, &[0]),
+        ix(DecReg, &[1]),
+        ix(Jump, &[4]),
+        ix(Exit, &[0]),
+    ]
+}
+
+// factorial of whatever is in r1, exits through JumpLe so 0 and 1 both work
+pub fn factorial_of_r1() -> Vec<Instruction> {
+    use OpCode::*;
+    vec![
+        ix(Push, &[1]),
+        ix(StoreReg, &[0]),
+        // loop start at 2
+        ix(LoadReg, &[1]),
+        ix(Push, &[1]),
+        ix(JumpLe, &[11]),
+        ix(LoadReg, &[0]),
+        ix(LoadReg, &[1]),
+        ix(Mul, &[]),
+        ix(StoreReg, &[0]),
+        ix(DecReg, &[1]),
+        ix(Jump, &[2]),
+        ix(Exit, &[0]),
+    ]
+}
+
+// the error run produced with the location wrapping peeled off
+pub fn run_err(program: Vec<Instruction>) -> VmError {
+    run(program).unwrap_err().root().clone()
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use beef::{Context, OpCode, OpCode::*, VmError};
+use common::{factorial, ix, run, run_err};
+
+#[test]
+fn factorial_runs_to_120() {
+    assert_eq!(run(factorial(5)), Ok(120));
+}
+
+fn branch(opcode: OpCode, a: i64, b: i64) -> Result<i64, VmError> {
+    run(vec![ix(Push, &[a]), ix(Push, &[b]), ix(opcode, &[4]), ix(Halt, &[0]), ix(Halt, &[1])])
+}
+
+#[test]
+fn inverse_conditional_jumps() {
+    assert_eq!(branch(JumpNe, 1, 2), Ok(1));
+    assert_eq!(branch(JumpNe, 2, 2), Ok(0));
+    assert_eq!(branch(JumpGe, 2, 2), Ok(1));
+    assert_eq!(branch(JumpGe, 1, 2), Ok(0));
+    assert_eq!(branch(JumpLe, 2, 2), Ok(1));
+    assert_eq!(branch(JumpLe, 3, 2), Ok(0));
+}
+
+#[test]
+fn jump_errors_name_the_right_opcode() {
+    for opcode in [JumpEq, JumpGt, JumpLt, JumpNe, JumpGe, JumpLe, JumpZero, JumpNotZero] {
+        let err = run(vec![ix(opcode, &[]), ix(Exit, &[])]).unwrap_err();
+        assert_eq!(err.root(), &VmError::MissingOperand { pc: 0, opcode, expected: "a target address operand" });
+        assert!(err.to_string().starts_with(&format!("{:?} requires a target address operand", opcode)), "{}", err);
+    }
+    let err = run_err(vec![ix(Push, &[1]), ix(JumpLt, &[0]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::StackUnderflow { pc: 1, opcode: JumpLt, needed: 2, found: 1 });
+}
+
+#[test]
+fn jump_zero_and_not_zero() {
+    let program = |opcode, value| vec![ix(Push, &[value]), ix(opcode, &[3]), ix(Halt, &[0]), ix(Halt, &[1])];
+    assert_eq!(run(program(JumpZero, 0)), Ok(1));
+    assert_eq!(run(program(JumpZero, 5)), Ok(0));
+    assert_eq!(run(program(JumpNotZero, -5)), Ok(1));
+    assert_eq!(run(program(JumpNotZero, 0)), Ok(0));
+}
+
+#[test]
+fn backwards_relative_loop() {
+    // count r1 from 0 to 5 with a loop closed by JumpRelLt -3
+    let program = vec![
+        ix(IncReg, &[1]),
+        ix(LoadReg, &[1]),
+        ix(Push, &[5]),
+        ix(JumpRelLt, &[-3]),
+        ix(Exit, &[1]),
+    ];
+    assert_eq!(run(program), Ok(5));
+}
+
+#[test]
+fn relative_jump_out_of_range() {
+    let err = run_err(vec![ix(Nop, &[]), ix(JumpRel, &[-2]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::JumpOutOfBounds { pc: 1, opcode: JumpRel, target: -1 });
+    assert!(run(vec![ix(JumpRel, &[i64::MIN]), ix(Exit, &[0])]).is_err());
+    assert!(run(vec![ix(JumpRel, &[2]), ix(Exit, &[0])]).is_err());
+}
+
+#[test]
+fn switch_dispatches_with_a_default() {
+    let dispatch = |index: i64| {
+        run(vec![
+            ix(Push, &[index]),
+            ix(Switch, &[3, 4, 5, 6, 7, 8]),
+            ix(Halt, &[-1]),
+            ix(Halt, &[10]),
+            ix(Halt, &[11]),
+            ix(Halt, &[12]),
+            ix(Halt, &[13]),
+            ix(Halt, &[14]),
+            ix(Halt, &[99]),
+        ])
+    };
+    for i in 0..5 {
+        assert_eq!(dispatch(i), Ok(10 + i));
+    }
+    assert_eq!(dispatch(5), Ok(99));
+    assert_eq!(dispatch(-1), Ok(99));
+    let err = run_err(vec![ix(Push, &[0]), ix(Switch, &[0, 9]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::JumpOutOfBounds { pc: 1, opcode: Switch, target: 9 });
+    // targets deep in a long table are checked too
+    let err = run_err(vec![ix(Push, &[0]), ix(Switch, &[2, 2, 2, 2, 9]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::JumpOutOfBounds { pc: 1, opcode: Switch, target: 9 });
+}
+
+#[test]
+fn jump_dyn_hand_rolled_call() {
+    // push the return address, jump to the function, which jumps back through it
+    let program = vec![
+        ix(Push, &[3]),    // return address
+        ix(Push, &[5]),
+        ix(JumpDyn, &[]),
+        ix(Exit, &[]),     // 3: result on top of the stack
+        ix(Halt, &[-1]),
+        // function at 5: ret -> 42 ret -> ret 42 -> jump ret
+        ix(Push, &[42]),
+        ix(Swap, &[]),
+        ix(JumpDyn, &[]),
+    ];
+    assert_eq!(run(program), Ok(42));
+    let err = run(vec![ix(Push, &[-3]), ix(JumpDyn, &[]), ix(Exit, &[0])]).unwrap_err();
+    assert!(err.to_string().starts_with("JumpDyn target out of bounds: -3"), "{}", err);
+}
+
+#[test]
+fn call_indirect_through_a_memory_table() {
+    // memory[100] = entry of the doubling function, call it with 21
+    let program = vec![
+        ix(Push, &[6]),
+        ix(Store, &[100]),
+        ix(Push, &[21]),
+        ix(Load, &[100]),
+        ix(CallIndirect, &[]),
+        ix(Exit, &[]),
+        ix(MulImm, &[2]),
+        ix(Return, &[]),
+    ];
+    assert_eq!(run(program), Ok(42));
+}
+
+#[test]
+fn call_to_program_len_is_out_of_bounds() {
+    let err = run_err(vec![ix(Call, &[2]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::JumpOutOfBounds { pc: 0, opcode: Call, target: 2 });
+}
+
+#[test]
+fn halt_stops_before_later_instructions() {
+    let program = vec![ix(Nop, &[]), ix(Halt, &[7]), ix(Push, &[1]), ix(Store, &[0]), ix(Exit, &[0])];
+    let mut context = Context::new(program);
+    assert_eq!(context.run(false), Ok(7));
+    assert_eq!(context.peek(0), None);
+    assert_eq!(run(vec![ix(Push, &[9]), ix(Halt, &[]), ix(Exit, &[0])]), Ok(9));
+}
+
+#[test]
+fn exit_result_sources() {
+    assert_eq!(run(vec![ix(Push, &[3]), ix(Exit, &[])]), Ok(3));
+    assert_eq!(run(vec![ix(Push, &[3]), ix(StoreReg, &[4]), ix(Exit, &[4])]), Ok(3));
+    let err = run_err(vec![ix(Exit, &[])]);
+    assert_eq!(err, VmError::StackUnderflow { pc: 0, opcode: Exit, needed: 1, found: 0 });
+}
+
+#[test]
+fn termination_edge_cases() {
+    // Exit as instruction 0 with more code after it
+    assert_eq!(run(vec![ix(Exit, &[0]), ix(Halt, &[1])]), Ok(0));
+    // Exit reached via Jump
+    assert_eq!(run(vec![ix(Jump, &[2]), ix(Halt, &[1]), ix(Exit, &[0])]), Ok(0));
+    // Exit as the only instruction
+    assert_eq!(run(vec![ix(Exit, &[0])]), Ok(0));
+    // last instruction is a backwards jump, the program still exits properly
+    assert_eq!(run(vec![ix(Jump, &[2]), ix(Exit, &[0]), ix(Jump, &[1])]), Ok(0));
+    // falling off the end is the only "no exit" case
+    assert_eq!(run(vec![ix(Nop, &[])]), Err(VmError::NoExit));
+}
+
+#[test]
+fn negative_operands_are_rejected() {
+    for opcode in [Jump, JumpEq, Call, Load, Store, LoadReg, StoreReg] {
+        for raw in [-1, i64::MIN] {
+            let err = run_err(vec![ix(Push, &[0]), ix(Push, &[0]), ix(opcode, &[raw]), ix(Exit, &[0])]);
+            assert_eq!(err, VmError::InvalidOperand { pc: 2, opcode, value: raw });
+        }
+    }
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use beef::{disassemble_with_coverage, merge_coverage, Context};
+use common::factorial;
+
+fn coverage(n: i64) -> Vec<u64> {
+    let mut context = Context::new(factorial(n));
+    context.enable_coverage();
+    context.run(false).unwrap();
+    context.coverage()
+}
+
+#[test]
+fn factorial_of_one_skips_the_loop_body() {
+    let hits = coverage(1);
+    assert_eq!(hits[..7], [1; 7]);
+    assert_eq!(hits[7..13], [0; 6]);
+    assert_eq!(hits[13], 1);
+
+    let listing = disassemble_with_coverage(&factorial(1), &hits);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines[4], "         |L4:");
+    assert_eq!(lines[7], "       1 |    jumpeq L13           ; 6");
+    assert_eq!(lines[8], "   ##### |    loadreg 0            ; 7");
+    assert_eq!(lines[15], "       1 |    exit 0               ; 13");
+}
+
+#[test]
+fn runs_merge_into_one_total() {
+    let mut total = coverage(1);
+    merge_coverage(&mut total, &coverage(3));
+    assert_eq!(total[9], 2); // Mul, only factorial(3) gets there
+    assert_eq!(total[4], 1 + 3);
+    assert_eq!(total[13], 2);
+
+    merge_coverage(&mut total, &[1; 20]);
+    assert_eq!((total.len(), total[17]), (20, 1));
+
+    // coverage off reads as nothing executed
+    assert_eq!(Context::new(factorial(1)).coverage(), vec![0; 14]);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use beef::{assemble, disassemble, OpCode::*};
+use common::{factorial, ix};
+
+#[test]
+fn output_reassembles_to_the_same_program() {
+    let src = "
+        push 1
+        switch zero one one
+    zero: push 10
+        jumprel end
+    one: push 0x20
+        calln double 1
+    end:
+        exit
+    double: loadlocal 0
+        mulimm 2
+        return 1
+    ";
+    let program = assemble(src).unwrap();
+    let text = disassemble(&program);
+    assert_eq!(assemble(&text).unwrap(), program);
+    assert_eq!(assemble(&disassemble(&factorial(5))).unwrap(), factorial(5));
+}
+
+#[test]
+fn lines_show_pc_mnemonic_operands_and_labels() {
+    let text = disassemble(&factorial(5));
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "    push 5               ; 0");
+    assert_eq!(lines[4], "L4:");
+    assert_eq!(lines[5], "    loadreg 1            ; 4");
+    assert_eq!(lines[7], "    jumpeq L13           ; 6");
+    assert_eq!(lines[12], "    decreg 1             ; 11");
+    assert_eq!(lines[13], "    jump L4              ; 12");
+    assert_eq!(lines[14], "L13:");
+    assert_eq!(lines[15], "    exit 0               ; 13");
+}
+
+#[test]
+fn targets_outside_the_program_stay_numeric() {
+    let program = vec![
+        ix(Jump, &[99]),
+        ix(JumpEq, &[-3]),
+        ix(JumpRel, &[-50]),
+        ix(JumpRel, &[i64::MAX]),
+        ix(Switch, &[0, 6, i64::MIN]),
+        ix(Call, &[5, 2]),
+        ix(Exit, &[]),
+    ];
+    let text = disassemble(&program);
+    assert!(text.contains("jump 99 "), "{}", text);
+    assert!(text.contains("switch L0 L6 -9223372036854775808 "), "{}", text);
+    assert!(text.contains("call L5 2 "), "{}", text);
+    assert_eq!(assemble(&text).unwrap(), program);
+    assert_eq!(disassemble(&[]), "");
+}
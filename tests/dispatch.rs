@@ -0,0 +1,124 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use beef::{ArithMode, Config, Context, ExecutionEvent, Instruction, OpCode::*, RunOutcome, VmError};
+use common::{factorial, factorial_of_r1, ix};
+
+fn pair(program: Vec<Instruction>, config: Config) -> (Context, Context) {
+    let matched = Context::new_with_config(program.clone(), Config { table_dispatch: false, ..config.clone() });
+    (matched, Context::new_with_config(program, Config { table_dispatch: true, ..config }))
+}
+
+// run through the match and through the table, everything observable has to match
+fn same_either_way(program: Vec<Instruction>, config: Config) -> Result<i64, VmError> {
+    let (mut matched, mut table) = pair(program, config);
+    let result = matched.run(false);
+    assert_eq!(table.run(false), result);
+    assert_eq!(
+        (table.pc(), table.steps(), table.stack(), table.registers(), table.backtrace()),
+        (matched.pc(), matched.steps(), matched.stack(), matched.registers(), matched.backtrace())
+    );
+    result
+}
+
+// sum of i * i for i in 1..=n, each square through a Call
+fn sum_of_squares(n: i64) -> Vec<Instruction> {
+    vec![
+        ix(Push, &[n]),
+        ix(StoreReg, &[1]),
+        // loop at 2
+        ix(LoadReg, &[1]),
+        ix(JumpZero, &[9]),
+        ix(LoadReg, &[1]),
+        ix(Call, &[10]),
+        ix(AddImm, &[0]),
+        ix(DecReg, &[1]),
+        ix(Jump, &[2]),
+        ix(Exit, &[0]),
+        // square at 10, adds n * n to r0
+        ix(Dup, &[]),
+        ix(Mul, &[]),
+        ix(LoadReg, &[0]),
+        ix(Add, &[]),
+        ix(StoreReg, &[0]),
+        ix(Push, &[0]),
+        ix(Return, &[0]),
+    ]
+}
+
+#[test]
+fn tables_run_programs_like_the_match() {
+    assert_eq!(same_either_way(factorial(20), Config::default()), Ok(2_432_902_008_176_640_000));
+    assert_eq!(same_either_way(sum_of_squares(10), Config::default()), Ok(385));
+    let program = vec![ix(Push, &[7]), ix(Push, &[3]), ix(Over, &[]), ix(Swap, &[]), ix(Sub, &[]), ix(Ge, &[]), ix(Exit, &[])];
+    assert_eq!(same_either_way(program, Config::default()), Ok(1));
+
+    let saturating = Config { arith_mode: ArithMode::Saturating, ..Config::default() };
+    let program = vec![ix(Push, &[i64::MAX]), ix(MulImm, &[2]), ix(Exit, &[])];
+    assert_eq!(same_either_way(program, saturating), Ok(i64::MAX));
+
+    // the fused fast paths sit in front of either engine
+    let fused = Config { superinstructions: true, ..Config::default() };
+    assert_eq!(same_either_way(factorial(10), fused), Ok(3_628_800));
+}
+
+#[test]
+fn operands_rejected_at_translation_fail_at_run_time() {
+    let cases = [
+        (vec![ix(Push, &[])], VmError::MissingOperand { pc: 0, opcode: Push, expected: "an operand" }),
+        (vec![ix(LoadReg, &[99])], VmError::InvalidRegister { pc: 0, index: 99 }),
+        (vec![ix(StoreReg, &[-1])], VmError::InvalidOperand { pc: 0, opcode: StoreReg, value: -1 }),
+        (vec![ix(Jump, &[5])], VmError::JumpOutOfBounds { pc: 0, opcode: Jump, target: 5 }),
+        (vec![ix(Push, &[1]), ix(JumpZero, &[-3])], VmError::InvalidOperand { pc: 1, opcode: JumpZero, value: -3 }),
+        (vec![ix(Call, &[1, 2]), ix(Return, &[2])], VmError::InvalidOperand { pc: 1, opcode: Return, value: 2 }),
+        (vec![ix(Return, &[])], VmError::CallStackUnderflow { pc: 0 }),
+        (vec![ix(Exit, &[16])], VmError::InvalidRegister { pc: 0, index: 16 }),
+    ];
+    for (program, expected) in cases {
+        let err = same_either_way(program.clone(), Config::default()).unwrap_err();
+        assert_eq!(err.root(), &expected, "{:?}", program);
+    }
+
+    let checked = Config { arith_mode: ArithMode::Checked, ..Config::default() };
+    let err = same_either_way(vec![ix(Push, &[i64::MAX]), ix(AddImm, &[1]), ix(Exit, &[])], checked).unwrap_err();
+    assert!(matches!(err.root(), VmError::Overflow { pc: 1, opcode: AddImm, .. }), "{}", err);
+}
+
+#[test]
+fn observers_see_the_same_events() {
+    let record = |table_dispatch| {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut context = Context::new_with_config(sum_of_squares(3), Config { table_dispatch, ..Config::default() });
+        let sink = Arc::clone(&events);
+        context.set_event_sink(Box::new(move |event| sink.lock().unwrap().push(event)));
+        context.run(false).unwrap();
+        let events = events.lock().unwrap().clone();
+        events
+    };
+    let events = record(true);
+    assert_eq!(events, record(false));
+    assert!(events.contains(&ExecutionEvent::Call { pc: 5, target: 10 }));
+    assert!(events.contains(&ExecutionEvent::Return { pc: 16, return_addr: 6 }));
+    assert!(events.contains(&ExecutionEvent::RegisterWrite { reg: 0, value: 14 }));
+}
+
+#[test]
+fn budgets_and_depth_limits_hold() {
+    let (mut matched, mut table) = pair(factorial_of_r1(), Config::default());
+    for context in [&mut matched, &mut table] {
+        context.set_register(1, 6).unwrap();
+    }
+    loop {
+        let outcome = matched.run_for(4);
+        assert_eq!(table.run_for(4), outcome);
+        assert_eq!((table.pc(), table.steps()), (matched.pc(), matched.steps()));
+        if outcome == Ok(RunOutcome::Completed(720)) {
+            break;
+        }
+    }
+
+    let shallow = Config { max_call_depth: 3, ..Config::default() };
+    let err = same_either_way(vec![ix(Call, &[0])], shallow).unwrap_err();
+    assert!(matches!(err.root(), VmError::CallStackOverflow { pc: 0, depth: 3, .. }), "{}", err);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::error::Error;
+
+use beef::{Config, Context, OpCode::*, Program, VmError};
+use common::{ix, run, run_err};
+
+#[test]
+fn errors_work_as_std_errors() {
+    let err: Box<dyn Error> = Box::new(run(vec![ix(Pop, &[]), ix(Exit, &[0])]).unwrap_err());
+    assert_eq!(
+        err.to_string(),
+        "Stack underflow => Pop Op needs 1 values, found 0 at pc=0 (Instruction { opcode: Pop, operands: [] }), \
+         stack top: [], call stack: []"
+    );
+}
+
+#[test]
+fn errors_show_the_pc_instruction_and_stack_top() {
+    let err = run(vec![ix(Push, &[7]), ix(Push, &[1]), ix(Push, &[2]), ix(Push, &[3]), ix(Push, &[0]), ix(Div, &[])])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Division by zero at pc=5 (Instruction { opcode: Div, operands: [] }), stack top: [1, 2, 3, 0], call stack: []"
+    );
+
+    let err = run(vec![ix(Push, &[4]), ix(Sub, &[])]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Stack underflow => Sub Op needs 2 values, found 1 at pc=1 (Instruction { opcode: Sub, operands: [] }), \
+         stack top: [4], call stack: []"
+    );
+
+    let err = run(vec![ix(Push, &[1]), ix(Push, &[2]), ix(JumpLt, &[40])]).unwrap_err();
+    assert!(matches!(err, VmError::Located { ref instruction, ref stack_top, .. }
+        if *instruction == ix(JumpLt, &[40]) && *stack_top == [1, 2]));
+    assert!(err.to_string().starts_with("JumpLt target out of bounds: 40 at pc=2 (Instruction { opcode: JumpLt, operands: [40] })"), "{}", err);
+
+    let err = run(vec![ix(Push, &[5]), ix(StoreReg, &[12])]).unwrap_err();
+    assert!(err.to_string().contains("at pc=1 (Instruction { opcode: StoreReg, operands: [12] }), stack top: [5]"), "{}", err);
+}
+
+#[test]
+fn root_sees_through_the_location() {
+    let err = run(vec![ix(Push, &[1]), ix(Push, &[0]), ix(Div, &[]), ix(Exit, &[])]).unwrap_err();
+    assert!(matches!(err, VmError::Located { ref instruction, .. } if instruction.opcode == Div));
+    assert_eq!(err.root(), &VmError::DivisionByZero { pc: 2 });
+    // errors that don't come from an instruction are their own root
+    assert_eq!(VmError::NoExit.root(), &VmError::NoExit);
+}
+
+#[test]
+fn missing_operands() {
+    assert_eq!(run_err(vec![ix(Push, &[])]), VmError::MissingOperand { pc: 0, opcode: Push, expected: "an operand" });
+    let err = run_err(vec![ix(AddReg, &[0, 1]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::MissingOperand { pc: 0, opcode: AddReg, expected: "dst, a and b register operands" });
+    assert_eq!(err.to_string(), "AddReg requires dst, a and b register operands");
+    assert!(matches!(run_err(vec![ix(JumpRel, &[])]), VmError::MissingOperand { opcode: JumpRel, .. }));
+}
+
+#[test]
+fn register_errors() {
+    assert_eq!(run_err(vec![ix(LoadReg, &[11])]), VmError::InvalidRegister { pc: 0, index: 11 });
+    assert_eq!(run_err(vec![ix(Exit, &[20])]), VmError::InvalidRegister { pc: 0, index: 20 });
+    let mut context = Context::new(vec![ix(Exit, &[0])]);
+    assert_eq!(context.set_register(11, 1), Err(VmError::InvalidRegister { pc: 0, index: 11 }));
+}
+
+#[test]
+fn call_stack_errors() {
+    assert_eq!(run_err(vec![ix(Return, &[])]), VmError::CallStackUnderflow { pc: 0 });
+    assert_eq!(run_err(vec![ix(Enter, &[2])]), VmError::NoFrame { pc: 0, opcode: Enter });
+    let err = run_err(vec![ix(Call, &[2]), ix(Exit, &[]), ix(Return, &[2])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 2, opcode: Return, value: 2 });
+
+    // a Return that fails keeps its frame, so the backtrace still shows where it was called from
+    for (count, expected) in [
+        (2, VmError::InvalidOperand { pc: 2, opcode: Return, value: 2 }),
+        (1, VmError::StackUnderflow { pc: 2, opcode: Return, needed: 1, found: 0 }),
+    ] {
+        let mut context = Context::new(vec![ix(Call, &[2]), ix(Exit, &[]), ix(Return, &[count])]);
+        assert_eq!(context.run(false).unwrap_err().root(), &expected);
+        assert_eq!(context.backtrace(), vec![1]);
+    }
+}
+
+#[test]
+fn memory_stack_errors() {
+    assert_eq!(
+        run_err(vec![ix(Push, &[1]), ix(PushM, &[])]),
+        VmError::MemoryStackDisabled { pc: 1, opcode: PushM }
+    );
+    let config = Config { memory_stack: Some(0), ..Config::default() };
+    let err = Context::new_with_config(vec![ix(Push, &[1]), ix(PushM, &[]), ix(Exit, &[0])], config).run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::MemoryStackOverflow { pc: 1, sp: 0 });
+}
+
+#[test]
+fn bulk_memory_errors() {
+    let err = run_err(vec![ix(Push, &[0]), ix(Push, &[0]), ix(Push, &[-1]), ix(MemSet, &[]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 3, opcode: MemSet, value: -1 });
+    let err = run_err(vec![ix(Push, &[-5]), ix(Push, &[0]), ix(Push, &[1]), ix(MemCpy, &[]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidAddress { pc: 3, opcode: MemCpy, addr: -5 });
+    let err = run_err(vec![ix(Push, &[0]), ix(Alloc, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 1, opcode: Alloc, value: 0 });
+}
+
+#[test]
+fn host_api_errors() {
+    let program = Program::new(vec![ix(Exit, &[0])]).with_symbol("f", 1);
+    assert!(matches!(Context::load(program, Config::default()), Err(VmError::InvalidProgram(_))));
+    let err = Context::execute(vec![ix(Exit, &[0])], &[0; 11]).unwrap_err();
+    assert_eq!(err, VmError::TooManyArguments { count: 11, max: 10 });
+
+    let program = Program::new(vec![ix(Exit, &[0]), ix(Halt, &[3])]).with_symbol("quit", 1);
+    let mut context = Context::load(program, Config::default()).unwrap();
+    let err = context.call_function("quit", &[]).unwrap_err();
+    assert!(matches!(err, VmError::CallFailed { ref name, .. } if name == "quit"));
+    assert_eq!(err.to_string(), "Function quit exited the program with 3");
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use beef::{analyze_stack, assemble, cfg, validate_strict, Context, EdgeKind, ExecutionEvent, OpCode::*, VmError};
+use common::{ix, run_err};
+
+#[test]
+fn throw_unwinds_two_frames_into_the_outer_handler() {
+    let program = assemble(
+        "
+        push 100
+        trypush caught
+        push 1
+        push 2
+        call outer
+        push -1
+        exit
+    caught:
+        getcalldepth
+        exit
+    outer:
+        push 3
+        call inner
+        return
+    inner:
+        push 4
+        push 7
+        throw
+        ",
+    )
+    .unwrap();
+    let mut context = Context::new(program);
+    let returns = Arc::new(Mutex::new(0));
+    let sink = Arc::clone(&returns);
+    context.set_event_sink(Box::new(move |event| {
+        if matches!(event, ExecutionEvent::Return { pc: 14, .. }) {
+            *sink.lock().unwrap() += 1;
+        }
+    }));
+
+    // back at the TryPush depths: one value on the operand stack plus the code, no frames
+    assert_eq!(context.run(false), Ok(0));
+    assert_eq!(context.stack(), [100, 7]);
+    assert!(context.call_stack().is_empty());
+    assert_eq!(*returns.lock().unwrap(), 2);
+}
+
+#[test]
+fn the_newest_handler_catches_first() {
+    let program = assemble(
+        "
+        trypush outer
+        call f
+        push 0
+        exit
+    outer:
+        addimm 1000
+        exit
+    f:
+        trypush inner
+        push 5
+        throw
+    inner:
+        addimm 10
+        throw           ; rethrow, the inner handler is gone
+        ",
+    )
+    .unwrap();
+    assert_eq!(Context::new(program).run(false), Ok(1015));
+}
+
+#[test]
+fn popped_and_returned_handlers_no_longer_catch() {
+    let err = run_err(vec![ix(TryPush, &[4]), ix(TryPop, &[]), ix(Push, &[9]), ix(Throw, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::UncaughtGuestException { code: 9, pc: 3 });
+    assert_eq!(err.to_string(), "Uncaught guest exception 9");
+
+    // f returns with its handler still pushed, the throw skips it for the one at top level
+    let program = assemble(
+        "
+        trypush top
+        call f
+        push 3
+        throw
+    top:
+        exit
+    f:
+        trypush stale
+        return
+    stale:
+        push -1
+        exit
+        ",
+    )
+    .unwrap();
+    assert_eq!(Context::new(program).run(false), Ok(3));
+
+    assert_eq!(run_err(vec![ix(TryPop, &[]), ix(Exit, &[0])]), VmError::TryStackUnderflow { pc: 0 });
+}
+
+#[test]
+fn handlers_are_part_of_the_flow_graph() {
+    let program = assemble(
+        "
+        push 1
+        trypush caught
+        push 2
+        push 5
+        throw
+    caught:
+        add
+        exit
+        ",
+    )
+    .unwrap();
+    assert_eq!(Context::new(program.clone()).run(false), Ok(6));
+
+    let graph = cfg(&program);
+    assert!(graph.edges.iter().any(|edge| edge.kind == EdgeKind::Handler && edge.to == 5));
+    assert_eq!(validate_strict(&program), Ok(()));
+    // the handler starts one deeper than the TryPush left it, so the Add has its two values
+    let report = analyze_stack(&program).unwrap();
+    assert_eq!(report.blocks.iter().find(|block| block.start == 5).unwrap().entry.to_string(), "2");
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use beef::{
+    assemble, disassemble, AsmErrorKind, ArithMode, Config, Context, ExitValue, Instruction, OpCode::*, ProgramBuilder, SharedBuffer,
+    VmError,
+};
+use common::{ix, run, run_err};
+
+fn f(value: f64) -> i64 {
+    value.to_bits() as i64
+}
+
+// push both, apply op, exit with the integer on top
+fn compare(a: f64, b: f64, op: beef::OpCode) -> i64 {
+    run(vec![ix(FPush, &[f(a)]), ix(FPush, &[f(b)]), ix(op, &[]), ix(Exit, &[])]).unwrap()
+}
+
+fn float_result(program: Vec<Instruction>) -> f64 {
+    let mut context = Context::new(program);
+    context.run(false).unwrap();
+    match context.exit_value() {
+        Some(ExitValue::Float(value)) => value,
+        other => panic!("expected a float result, got {:?}", other),
+    }
+}
+
+#[test]
+fn average_of_a_few_samples() {
+    let program = assemble(
+        "
+        fpush 0.0
+        fpush 1.5
+        fadd
+        fpush 2.25
+        fadd
+        fpush -0.75
+        fadd
+        push 3
+        inttofloat
+        fdiv
+        fexit
+        ",
+    )
+    .unwrap();
+    assert_eq!(float_result(program), 1.0);
+
+    let program = ProgramBuilder::new().fpush(0.1).fpush(0.2).fadd().fexit().build().unwrap();
+    assert_eq!(float_result(program), 0.1 + 0.2);
+}
+
+#[test]
+fn exit_value_tells_floats_from_ints() {
+    let mut context = Context::new(vec![ix(FPush, &[f(2.5)]), ix(FExit, &[])]);
+    assert_eq!(context.exit_value(), None);
+    // run itself still reports the bit pattern
+    assert_eq!(context.run(false), Ok(f(2.5)));
+    assert_eq!(context.exit_value(), Some(ExitValue::Float(2.5)));
+
+    let mut context = Context::new(vec![ix(Push, &[f(2.5)]), ix(Exit, &[])]);
+    context.run(false).unwrap();
+    assert_eq!(context.exit_value(), Some(ExitValue::Int(f(2.5))));
+}
+
+#[test]
+fn nan_compares_false_against_everything() {
+    for other in [f64::NAN, 0.0, 1.0, f64::INFINITY, f64::NEG_INFINITY] {
+        for op in [FEq, FLt, FGt] {
+            assert_eq!(compare(f64::NAN, other, op), 0, "NaN {:?} {}", op, other);
+            assert_eq!(compare(other, f64::NAN, op), 0, "{} {:?} NaN", other, op);
+        }
+    }
+    assert_eq!(compare(1.0, 2.0, FLt), 1);
+    assert_eq!(compare(2.0, 1.0, FGt), 1);
+    assert_eq!(compare(0.0, -0.0, FEq), 1);
+}
+
+#[test]
+fn infinities_and_division_by_zero_follow_ieee() {
+    let div = |a: f64, b: f64| float_result(vec![ix(FPush, &[f(a)]), ix(FPush, &[f(b)]), ix(FDiv, &[]), ix(FExit, &[])]);
+    assert_eq!(div(1.0, 0.0), f64::INFINITY);
+    assert_eq!(div(-1.0, 0.0), f64::NEG_INFINITY);
+    assert!(div(0.0, 0.0).is_nan());
+    assert_eq!(div(1.0, f64::INFINITY), 0.0);
+
+    let program = vec![ix(FPush, &[f(f64::INFINITY)]), ix(FPush, &[f(f64::NEG_INFINITY)]), ix(FAdd, &[]), ix(FExit, &[])];
+    assert!(float_result(program).is_nan());
+    assert_eq!(compare(f64::INFINITY, f64::MAX, FGt), 1);
+
+    // the arith mode doesn't turn any of this into an error
+    let checked = Config { arith_mode: ArithMode::Checked, ..Config::default() };
+    let mut context = Context::new_with_config(vec![ix(FPush, &[f(f64::MAX)]), ix(FPush, &[f(2.0)]), ix(FMul, &[]), ix(FExit, &[])], checked);
+    assert_eq!(context.run(false), Ok(f(f64::INFINITY)));
+}
+
+#[test]
+fn float_to_int_truncates_and_handles_the_edges_per_mode() {
+    let convert = |value: f64, arith_mode| {
+        let program = vec![ix(FPush, &[f(value)]), ix(FloatToInt, &[]), ix(Exit, &[])];
+        Context::new_with_config(program, Config { arith_mode, ..Config::default() }).run(false)
+    };
+    assert_eq!(convert(2.9, ArithMode::Wrapping), Ok(2));
+    assert_eq!(convert(-2.9, ArithMode::Wrapping), Ok(-2));
+    assert_eq!(convert(-9_223_372_036_854_775_808.0, ArithMode::Checked), Ok(i64::MIN));
+
+    for mode in [ArithMode::Wrapping, ArithMode::Saturating] {
+        assert_eq!(convert(1e30, mode), Ok(i64::MAX));
+        assert_eq!(convert(f64::NEG_INFINITY, mode), Ok(i64::MIN));
+        assert_eq!(convert(f64::NAN, mode), Ok(0));
+    }
+    for value in [1e30, 9_223_372_036_854_775_808.0, f64::INFINITY, f64::NAN] {
+        let err = convert(value, ArithMode::Checked).unwrap_err();
+        assert_eq!(err.root(), &VmError::Overflow { pc: 1, opcode: FloatToInt, operands: vec![f(value)] });
+    }
+
+    // and back, large ints round to the nearest float
+    let program = vec![ix(Push, &[i64::MAX]), ix(IntToFloat, &[]), ix(FExit, &[])];
+    assert_eq!(float_result(program), 9_223_372_036_854_775_808.0);
+}
+
+#[test]
+fn fprint_writes_decimal_floats() {
+    let buffer = SharedBuffer::default();
+    let mut program: Vec<_> = [2.5, -0.0, f64::INFINITY, f64::NAN].iter().flat_map(|&value| [ix(FPush, &[f(value)]), ix(FPrint, &[])]).collect();
+    program.push(ix(Exit, &[0]));
+    let mut context = Context::new(program);
+    context.set_output(Box::new(buffer.clone()));
+    context.run(false).unwrap();
+    assert_eq!(String::from_utf8(buffer.contents()).unwrap(), "2.5\n-0\ninf\nNaN\n");
+}
+
+#[test]
+fn float_literals_survive_the_disassembler() {
+    let odd_nan = f64::NAN.to_bits() as i64 | 1;
+    let values = [f(1.5), f(-0.0), f(1e300), f(5e-324), f(f64::NAN), f(f64::NEG_INFINITY), odd_nan, 3];
+    let program: Vec<_> = values.iter().map(|&bits| ix(FPush, &[bits])).collect();
+    let text = disassemble(&program);
+    assert!(text.contains("fpush 1.5 ") && text.contains("fpush -inf ") && text.contains("fpush 0x7ff8000000000001"), "{}", text);
+    assert_eq!(assemble(&text), Ok(program));
+
+    // fpush reads a plain integer as a float and hex as raw bits, other opcodes still take no floats
+    assert_eq!(assemble("fpush 3\nfpush 0x3"), Ok(vec![ix(FPush, &[f(3.0)]), ix(FPush, &[3])]));
+    assert_eq!(assemble("fpush -INF"), Ok(vec![ix(FPush, &[f(f64::NEG_INFINITY)])]));
+    assert_eq!(assemble("push 3.0").unwrap_err().kind, AsmErrorKind::InvalidOperand);
+}
+
+#[test]
+fn float_ops_need_their_operands() {
+    assert!(matches!(run_err(vec![ix(FPush, &[f(1.0)]), ix(FAdd, &[]), ix(FExit, &[])]), VmError::StackUnderflow { pc: 1, .. }));
+    assert!(matches!(run_err(vec![ix(FExit, &[])]), VmError::StackUnderflow { pc: 0, .. }));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use beef::{ArithMode, Config, Context, ExecutionEvent, Instruction, OpCode::*, RunOutcome, VmError};
+use common::{factorial, ix};
+
+fn pair(program: Vec<Instruction>, config: Config) -> (Context, Context) {
+    let fused = Context::new_with_config(program.clone(), Config { superinstructions: true, ..config.clone() });
+    (Context::new_with_config(program, config), fused)
+}
+
+// run with and without fusion, everything observable has to match
+fn same_either_way(program: Vec<Instruction>, config: Config) -> Result<i64, VmError> {
+    let (mut plain, mut fused) = pair(program, config);
+    let result = plain.run(false);
+    assert_eq!(fused.run(false), result);
+    assert_eq!(
+        (fused.pc(), fused.steps(), fused.stack(), fused.registers()),
+        (plain.pc(), plain.steps(), plain.stack(), plain.registers())
+    );
+    result
+}
+
+#[test]
+fn fused_programs_behave_like_the_originals() {
+    assert_eq!(same_either_way(factorial(5), Config::default()), Ok(120));
+    assert_eq!(same_either_way(factorial(20), Config::default()), Ok(2_432_902_008_176_640_000));
+    let program = vec![ix(Push, &[10]), ix(Push, &[3]), ix(Sub, &[]), ix(Push, &[-4]), ix(Add, &[]), ix(Exit, &[])];
+    assert_eq!(same_either_way(program, Config::default()), Ok(3));
+
+    let saturating = Config { arith_mode: ArithMode::Saturating, ..Config::default() };
+    let program = vec![ix(Push, &[i64::MIN]), ix(Push, &[1]), ix(Sub, &[]), ix(Exit, &[])];
+    assert_eq!(same_either_way(program, saturating), Ok(i64::MIN));
+}
+
+#[test]
+fn errors_keep_their_original_pcs() {
+    let err = same_either_way(vec![ix(Push, &[1]), ix(Add, &[]), ix(Exit, &[])], Config::default()).unwrap_err();
+    assert_eq!(err.root(), &VmError::StackUnderflow { pc: 1, opcode: Add, needed: 2, found: 1 });
+    assert!(err.to_string().contains("at pc=1 (Instruction { opcode: Add, operands: [] })"), "{}", err);
+
+    let checked = Config { arith_mode: ArithMode::Checked, ..Config::default() };
+    let program = vec![ix(Push, &[i64::MAX]), ix(Push, &[1]), ix(Add, &[]), ix(Exit, &[])];
+    let err = same_either_way(program, checked).unwrap_err();
+    assert!(matches!(err.root(), VmError::Overflow { pc: 2, opcode: Add, .. }), "{}", err);
+}
+
+#[test]
+fn jumps_into_a_sequence_still_run_it() {
+    // the Add at 4 is a jump target, so Push 5; Add isn't fused and the jump skips the Push
+    let program = vec![
+        ix(Push, &[1]),
+        ix(Push, &[2]),
+        ix(Jump, &[4]),
+        ix(Push, &[5]),
+        ix(Add, &[]),
+        ix(Exit, &[]),
+    ];
+    assert_eq!(same_either_way(program, Config::default()), Ok(3));
+
+    // pc set by the host into the middle of a fused sequence
+    let (mut plain, mut fused) = pair(vec![ix(Push, &[7]), ix(Push, &[1]), ix(Add, &[]), ix(Exit, &[])], Config::default());
+    for context in [&mut plain, &mut fused] {
+        context.set_stack(vec![4, 2]);
+        context.set_pc(2);
+    }
+    let result = plain.run(false);
+    assert_eq!(fused.run(false), result);
+    assert_eq!(result, Ok(6));
+}
+
+#[test]
+fn step_budgets_split_sequences() {
+    let (mut plain, mut fused) = pair(factorial(5), Config::default());
+    loop {
+        let outcome = plain.run_for(5);
+        assert_eq!(fused.run_for(5), outcome);
+        assert_eq!((fused.pc(), fused.steps()), (plain.pc(), plain.steps()));
+        if outcome == Ok(RunOutcome::Completed(120)) {
+            break;
+        }
+    }
+}
+
+#[test]
+fn observers_see_every_instruction() {
+    let record = |superinstructions| {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut context = Context::new_with_config(factorial(3), Config { superinstructions, ..Config::default() });
+        let sink = Arc::clone(&events);
+        context.set_event_sink(Box::new(move |event| sink.lock().unwrap().push(event)));
+        context.run(false).unwrap();
+        let events = events.lock().unwrap().clone();
+        events
+    };
+    let events = record(true);
+    assert_eq!(events, record(false));
+    assert!(events.contains(&ExecutionEvent::Instruction { pc: 9, opcode: Mul, operands: vec![] }));
+
+    let fuel = |superinstructions| {
+        let mut context = Context::new_with_config(factorial(5), Config { superinstructions, ..Config::default() });
+        context.set_fuel(1000);
+        context.run(false).unwrap();
+        context.remaining_fuel()
+    };
+    assert_eq!(fuel(true), fuel(false));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+
+use beef::{
+    analyze_stack, assemble, cfg, check_flow, disassemble, ArithMode, Config, Context, Input, Instruction, OpCode::*,
+    Program,
+};
+use common::arbitrary::{program, Rng};
+
+fn configs() -> [Config; 3] {
+    [
+        Config::default(),
+        Config { arith_mode: ArithMode::Checked, memory_limit: Some(64), ..Config::default() },
+        Config { arith_mode: ArithMode::Saturating, memory_stack: Some(100), superinstructions: true, ..Config::default() },
+    ]
+}
+
+// no output, canned input, one syscall, and fuel so a loop over a big MemSet can't take minutes
+fn quiet(context: &mut Context) {
+    context.set_fuel(1000);
+    context.set_fuel_cost(MemSet, 50);
+    context.set_fuel_cost(MemCpy, 50);
+    context.set_output(Box::new(io::sink()));
+    context.set_input(Input::Values(Box::new([7, -1].into_iter())));
+    context.register_host_fn(0, Box::new(|args| args.first().copied().ok_or_else(|| "no args".to_string())));
+}
+
+// every way of driving and inspecting the program, none of them may panic
+fn exercise(program: &[Instruction], config: &Config) {
+    let mut context = Context::new_with_config(program.to_vec(), config.clone());
+    quiet(&mut context);
+    let _ = context.run_for(500);
+    let _ = context.step();
+
+    let mut context = Context::new_with_memory(program.to_vec(), 64);
+    quiet(&mut context);
+    let _ = context.run_for(500);
+
+    if let Ok(mut context) = Context::new_validated(program.to_vec()) {
+        quiet(&mut context);
+        let _ = context.run_for(500);
+    }
+
+    let _ = (cfg(program).to_dot(), analyze_stack(program), check_flow(program));
+    let _ = assemble(&disassemble(program));
+    let bytes = Program::new(program.to_vec()).to_bytes().unwrap();
+    let _ = Program::from_bytes(&bytes);
+    let _ = Program::from_bytes(&bytes[..bytes.len() / 2]);
+    let _ = Program::from_json(&Program::new(program.to_vec()).to_json());
+}
+
+#[test]
+fn random_programs_never_panic() {
+    let mut rng = Rng::new(0xbeef);
+    for case in 0..3000 {
+        let program = program(&mut rng, 24);
+        for config in configs() {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| exercise(&program, &config)));
+            assert!(result.is_ok(), "case {} panicked with {:?} on {:?}", case, config, program);
+        }
+    }
+}
+
+#[test]
+fn random_bytes_never_panic_the_decoder() {
+    let mut rng = Rng::new(7);
+    let valid = Program::new(program(&mut rng, 16)).to_bytes().unwrap();
+    for _ in 0..20000 {
+        let mut bytes = valid.clone();
+        for _ in 0..1 + rng.below(4) {
+            let at = rng.below(bytes.len());
+            bytes[at] = rng.next() as u8;
+        }
+        bytes.truncate(rng.below(bytes.len() + 1));
+        let _ = Program::from_bytes(&bytes);
+    }
+}
+
+#[test]
+fn mangled_sources_never_panic_the_parsers() {
+    let mut rng = Rng::new(9);
+    for _ in 0..2000 {
+        let program = program(&mut rng, 12);
+        for text in [disassemble(&program), Program::new(program).to_json()] {
+            let mut bytes = text.into_bytes();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(bytes.len().max(1));
+                if at < bytes.len() {
+                    bytes[at] = rng.pick(b"-:;.,\"{}[]0123456789 \nLlrx#$%");
+                }
+            }
+            let text = String::from_utf8_lossy(&bytes);
+            let _ = (assemble(&text), Program::from_json(&text));
+        }
+    }
+}
@@ -0,0 +1,286 @@
+mod common;
+
+use std::env;
+use std::io::{self, Cursor, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use beef::{Context, ExecutionEvent, ExecutionHooks, Input, Instruction, OpCode::*, RunOutcome, SharedBuffer, VmError};
+use common::{factorial, factorial_of_r1, ix};
+
+#[test]
+fn syscalls_see_their_arguments_and_captured_state() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut context = Context::new(vec![
+        ix(Push, &[3]),
+        ix(Push, &[4]),
+        ix(Syscall, &[1, 2]),
+        ix(Syscall, &[1, 1]),
+        ix(Exit, &[]),
+    ]);
+    let seen = Arc::clone(&log);
+    context.register_host_fn(1, Box::new(move |args| {
+        seen.lock().unwrap().push(args.to_vec());
+        Ok(args.iter().sum::<i64>() * 10)
+    }));
+    assert_eq!(context.run(false), Ok(700));
+    assert_eq!(*log.lock().unwrap(), vec![vec![3, 4], vec![70]]);
+}
+
+#[test]
+fn syscall_errors_propagate() {
+    let mut context = Context::new(vec![ix(Syscall, &[2]), ix(Exit, &[])]);
+    context.register_host_fn(2, Box::new(|_| Err("no such file".to_string())));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::Syscall { pc: 0, number: 2, message: "no such file".to_string() });
+    assert!(err.to_string().starts_with("Syscall 2 failed: no such file at pc=0"), "{}", err);
+
+    let err = Context::new(vec![ix(Syscall, &[3]), ix(Exit, &[])]).run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::UnknownSyscall { pc: 0, number: 3 });
+}
+
+#[test]
+fn print_writes_to_the_configured_output() {
+    let buffer = SharedBuffer::default();
+    let mut context = Context::new(vec![
+        ix(Push, &[-12]),
+        ix(Print, &[]),
+        ix(Push, &['h' as i64]),
+        ix(PrintChar, &[]),
+        ix(Push, &['é' as i64]),
+        ix(PrintChar, &[]),
+        ix(Exit, &[0]),
+    ]);
+    context.set_output(Box::new(buffer.clone()));
+    context.run(false).unwrap();
+    assert_eq!(String::from_utf8(buffer.contents()).unwrap(), "-12\nhé");
+
+    let err = Context::new(vec![ix(Push, &[0xd800]), ix(PrintChar, &[]), ix(Exit, &[0])]).run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::InvalidOperand { pc: 1, opcode: PrintChar, value: 0xd800 });
+}
+
+struct BrokenPipe;
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_errors_fail_the_run() {
+    let mut context = Context::new(vec![ix(Push, &[1]), ix(Print, &[]), ix(Exit, &[0])]);
+    context.set_output(Box::new(BrokenPipe));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::Io { pc: 1, opcode: Print, message: "closed".to_string() });
+}
+
+// sum everything Read produces until EOF
+fn sum_input(input: Input) -> Result<i64, VmError> {
+    let mut context = Context::new(vec![
+        ix(Read, &[]),
+        ix(JumpZero, &[6]),
+        ix(LoadReg, &[0]),
+        ix(Add, &[]),
+        ix(StoreReg, &[0]),
+        ix(Jump, &[0]),
+        ix(Exit, &[0]),
+    ]);
+    context.set_input(input);
+    context.run(false)
+}
+
+#[test]
+fn read_until_end_of_input() {
+    assert_eq!(sum_input(Input::Values(Box::new(1..=10))), Ok(55));
+    assert_eq!(sum_input(Input::Values(Box::new(std::iter::empty()))), Ok(0));
+    assert_eq!(sum_input(Input::Lines(Box::new(Cursor::new("4\n\n -1 \n10\n")))), Ok(13));
+    let err = sum_input(Input::Lines(Box::new(Cursor::new("4\nfive\n")))).unwrap_err();
+    assert_eq!(err.root(), &VmError::Io { pc: 0, opcode: Read, message: "invalid integer \"five\"".to_string() });
+}
+
+// Contexts reading stdin one after another each get the next line. The test binary runs itself with
+// piped stdin, the child copy does the reading
+#[test]
+fn contexts_take_turns_reading_stdin() {
+    if env::var_os("BEEF_STDIN_CHILD").is_some() {
+        let read_one = || Context::new(vec![ix(Read, &[]), ix(Pop, &[]), ix(Exit, &[])]).run(false);
+        assert_eq!(read_one(), Ok(4));
+        assert_eq!(read_one(), Ok(5));
+        return;
+    }
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["contexts_take_turns_reading_stdin", "--exact", "--nocapture"])
+        .env("BEEF_STDIN_CHILD", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"4\n5\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+}
+
+#[test]
+fn stop_flag_interrupts_from_another_thread() {
+    let (sender, receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        let mut context = Context::new(vec![ix(Jump, &[0])]);
+        sender.send(context.stop_handle()).unwrap();
+        context.run(false)
+    });
+    receiver.recv().unwrap().store(true, Ordering::Relaxed);
+    assert_eq!(worker.join().unwrap(), Err(VmError::Interrupted { pc: 0 }));
+}
+
+#[test]
+fn fuel_is_deterministic() {
+    let remaining = || {
+        let mut context = Context::new(factorial(5));
+        context.set_fuel(1000);
+        context.set_fuel_cost(Mul, 10);
+        assert_eq!(context.run(false), Ok(120));
+        context.remaining_fuel()
+    };
+    // 44 instructions, 4 of them multiplies
+    assert_eq!(remaining(), Some(1000 - 40 - 40));
+    assert_eq!(remaining(), remaining());
+}
+
+#[test]
+fn infinite_loop_stops_at_the_fuel_budget() {
+    let mut context = Context::new(vec![ix(Nop, &[]), ix(Jump, &[0])]);
+    context.set_fuel(101);
+    assert_eq!(context.run(false), Err(VmError::OutOfFuel { pc: 1, steps: 101 }));
+    assert_eq!(context.remaining_fuel(), Some(0));
+}
+
+#[test]
+fn run_for_resumes_where_it_paused() {
+    let mut context = Context::new(factorial(5));
+    assert_eq!(context.run_for(20), Ok(RunOutcome::Paused));
+    assert_eq!(context.run_for(20), Ok(RunOutcome::Paused));
+    assert_eq!(context.run_for(100), Ok(RunOutcome::Completed(120)));
+    assert_eq!(context.registers()[0], 120);
+}
+
+#[test]
+fn yield_hands_values_to_the_host() {
+    let program = vec![
+        ix(Push, &[1]),
+        ix(Yield, &[1]),
+        ix(Push, &[2]),
+        ix(Yield, &[1]),
+        ix(Yield, &[]),
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new(program.clone());
+    assert_eq!(context.resume(), Ok(RunOutcome::Yielded(1)));
+    assert_eq!(context.resume(), Ok(RunOutcome::Yielded(2)));
+    assert_eq!(context.resume(), Ok(RunOutcome::Yielded(0)));
+    context.set_stack(vec![9]);
+    assert_eq!(context.resume(), Ok(RunOutcome::Completed(9)));
+
+    assert_eq!(Context::new(program).run(false), Err(VmError::Yielded { pc: 2, value: 1 }));
+}
+
+#[test]
+fn events_record_every_accumulator_write() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut context = Context::new(factorial(5));
+    let sink = Arc::clone(&events);
+    context.set_event_sink(Box::new(move |event| sink.lock().unwrap().push(event)));
+    context.run(false).unwrap();
+
+    let events = events.lock().unwrap();
+    let writes = events.iter().filter(|event| matches!(event, ExecutionEvent::RegisterWrite { reg: 0, .. })).count();
+    assert_eq!(writes, 5);
+    assert_eq!(events.last(), Some(&ExecutionEvent::Exit { value: 120 }));
+}
+
+#[test]
+fn events_over_a_channel() {
+    let (sender, receiver) = mpsc::channel();
+    let mut context = Context::new(vec![ix(Push, &[5]), ix(Exit, &[])]);
+    context.set_event_channel(sender);
+    context.run(false).unwrap();
+    drop(context);
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events, vec![
+        ExecutionEvent::Instruction { pc: 0, opcode: Push, operands: vec![5] },
+        ExecutionEvent::StackPush(5),
+        ExecutionEvent::Instruction { pc: 1, opcode: Exit, operands: vec![] },
+        ExecutionEvent::StackPop(5),
+        ExecutionEvent::Exit { value: 5 },
+    ]);
+}
+
+#[test]
+fn execute_seeds_arguments_into_registers() {
+    let factorial = |n| Context::execute(factorial_of_r1(), &[n]).unwrap().value;
+    assert_eq!(factorial(0), 1);
+    assert_eq!(factorial(1), 1);
+    assert_eq!(factorial(5), 120);
+    assert_eq!(factorial(20), 2_432_902_008_176_640_000);
+
+    let result = Context::execute(factorial_of_r1(), &[3]).unwrap();
+    assert_eq!(result.registers[0], 6);
+    assert!(result.steps > 0);
+}
+
+struct Recorder(Arc<Mutex<Vec<Instruction>>>);
+
+impl ExecutionHooks for Recorder {
+    fn on_instruction(&mut self, _pc: usize, instruction: &Instruction) {
+        self.0.lock().unwrap().push(instruction.clone());
+    }
+}
+
+#[test]
+fn hooks_see_the_instruction_as_written() {
+    let program = vec![ix(Push, &[5]), ix(Switch, &[3, 3, 3, 3, 3, 2]), ix(Nop, &[1, 2, 3, 4]), ix(Exit, &[0])];
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut context = Context::new(program.clone());
+    context.set_hooks(Box::new(Recorder(Arc::clone(&seen))));
+    assert_eq!(context.run(false), Ok(0));
+    assert_eq!(*seen.lock().unwrap(), program);
+}
+
+#[test]
+fn trace_goes_to_the_sink() {
+    let trace = SharedBuffer::default();
+    let mut context = Context::new(vec![ix(Push, &[2]), ix(AddImm, &[3]), ix(Exit, &[])]);
+    context.set_trace(Box::new(trace.clone()));
+    assert_eq!(context.run(false), Ok(5));
+    assert_eq!(String::from_utf8(trace.contents()).unwrap(), "\
+PC: 0, Executing: Instruction { opcode: Push, operands: [2] }
+Stack before: []
+Stack after: [2]
+Registers: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+-------------------
+PC: 1, Executing: Instruction { opcode: AddImm, operands: [3] }
+Stack before: [2]
+Stack after: [5]
+Registers: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+-------------------
+PC: 2, Executing: Instruction { opcode: Exit, operands: [] }
+Stack before: [5]
+Stack after: []
+Registers: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+-------------------
+");
+    assert!(context.take_trace().is_some());
+
+    let mut context = Context::new(vec![ix(Nop, &[]), ix(Exit, &[0])]);
+    context.set_trace(Box::new(BrokenPipe));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err, VmError::Io { pc: 0, opcode: Nop, message: "closed".to_string() });
+}
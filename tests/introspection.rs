@@ -0,0 +1,64 @@
+mod common;
+
+use beef::{assemble, Config, Context, OpCode::*};
+use common::{ix, run};
+
+#[test]
+fn get_pc_reports_its_own_address() {
+    assert_eq!(run(vec![ix(Nop, &[]), ix(Nop, &[]), ix(GetPC, &[]), ix(Exit, &[])]), Ok(2));
+
+    // a fused Push; Add right before it doesn't shift what it sees
+    let program = vec![ix(Push, &[1]), ix(Push, &[2]), ix(Add, &[]), ix(GetPC, &[]), ix(Exit, &[])];
+    for superinstructions in [false, true] {
+        let mut context = Context::new_with_config(program.clone(), Config { superinstructions, ..Config::default() });
+        assert_eq!(context.run(false), Ok(3));
+    }
+
+    // position independent: jump 3 past the GetPC, wherever the code ends up
+    let program = assemble(
+        "
+        nop
+        getpc
+        addimm 3
+        jumpdyn
+        push 1
+        push 2
+        exit
+        ",
+    )
+    .unwrap();
+    assert_eq!(run(program), Ok(2));
+}
+
+#[test]
+fn depth_counts_values_before_its_own_push() {
+    assert_eq!(run(vec![ix(Depth, &[]), ix(Exit, &[])]), Ok(0));
+    let program = vec![ix(Push, &[5]), ix(Push, &[6]), ix(Depth, &[]), ix(Depth, &[]), ix(Exit, &[])];
+    let mut context = Context::new(program);
+    assert_eq!(context.run(false), Ok(3));
+    assert_eq!(context.stack(), [5, 6, 2]);
+}
+
+#[test]
+fn call_depth_guards_recursion() {
+    assert_eq!(run(vec![ix(GetCallDepth, &[]), ix(Exit, &[])]), Ok(0));
+
+    // recurse until three frames deep, then report the depth back up
+    let program = assemble(
+        "
+        call down
+        exit
+    down:
+        getcalldepth
+        push 3
+        jumpge bottom
+        call down
+        return 1
+    bottom:
+        getcalldepth
+        return 1
+        ",
+    )
+    .unwrap();
+    assert_eq!(run(program), Ok(3));
+}
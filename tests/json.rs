@@ -0,0 +1,93 @@
+mod common;
+
+use beef::{Config, Context, JsonError, Program};
+use common::factorial;
+
+const FACTORIAL: &str = r#"{
+  "instructions": [
+    {"opcode": "Push", "operands": [5]},
+    {"opcode": "StoreReg", "operands": [1]},
+    {"opcode": "Push", "operands": [1]},
+    {"opcode": "StoreReg", "operands": [0]},
+    {"opcode": "LoadReg", "operands": [1]},
+    {"opcode": "Push", "operands": [1]},
+    {"opcode": "JumpEq", "operands": [13]},
+    {"opcode": "LoadReg", "operands": [0]},
+    {"opcode": "LoadReg", "operands": [1]},
+    {"opcode": "Mul"},
+    {"opcode": "StoreReg", "operands": [0]},
+    {"opcode": "DecReg", "operands": [1]},
+    {"opcode": "Jump", "operands": [4]},
+    {"opcode": "Exit", "operands": [0]}
+  ],
+  "metadata": {"name": "factorial", "compiler": {"version": "0.3"}}
+}"#;
+
+#[test]
+fn loads_and_runs_a_json_program() {
+    let program = Program::from_json(FACTORIAL).unwrap();
+    assert_eq!(program, Program::new(factorial(5)).with_name("factorial"));
+    assert_eq!(Context::load(program, Config::default()).unwrap().run(false), Ok(120));
+}
+
+#[test]
+fn round_trips_with_metadata() {
+    let program = Program::new(factorial(3)).with_data(8, vec![1, -2, i64::MIN]).with_symbol("main", 0).with_symbol("a \"b\"\n", 4);
+    let json = program.to_json();
+    assert_eq!(Program::from_json(&json), Ok(program));
+    assert_eq!(Program::from_json(&Program::default().to_json()), Ok(Program::default()));
+
+    // name and entry point come back too, and the entry point is where run starts
+    let program = Program::new(factorial(3)).with_name("fact \"3\"").with_entry(4);
+    let json = program.to_json();
+    assert!(json.contains(r#""metadata": {"name": "fact \"3\"", "entry": 4, "data": []"#), "{}", json);
+    assert_eq!(Program::from_json(&json).as_ref(), Ok(&program));
+    assert_eq!(Program::from_bytes(&program.to_bytes().unwrap()).as_ref(), Ok(&program));
+    let mut context = Context::load(program, Config::default()).unwrap();
+    context.set_register(1, 4).unwrap();
+    assert_eq!(context.run(false), Ok(0)); // skipped r0 = 1, so the product stays 0
+
+    let program = Program::from_json(r#"{"instructions": [], "metadata": {"data": [{"address": 2, "words": [7]}], "symbols": {"fé😀": 0}}}"#).unwrap();
+    assert_eq!(program.data, vec![(2, vec![7])]);
+    assert_eq!(program.symbols, vec![("fé😀".to_string(), 0)]);
+}
+
+fn schema_error(json: &str) -> (String, String) {
+    match Program::from_json(json) {
+        Err(JsonError::Schema { path, message }) => (path, message),
+        other => panic!("expected a schema error, got {:?}", other),
+    }
+}
+
+#[test]
+fn unknown_opcodes_name_the_field() {
+    let json = r#"{"instructions": [{"opcode": "Push", "operands": [1]}, {"opcode": "Pusj"}]}"#;
+    let err = Program::from_json(json).unwrap_err();
+    assert_eq!(err.to_string(), "instructions[1].opcode: unknown opcode \"Pusj\"");
+    assert_eq!(schema_error(r#"{"instructions": [{"opcode": 3}]}"#).0, "instructions[0].opcode");
+}
+
+#[test]
+fn operands_must_be_integers() {
+    let (path, message) = schema_error(r#"{"instructions": [{"opcode": "Push", "operands": [1.5]}]}"#);
+    assert_eq!((path.as_str(), message.as_str()), ("instructions[0].operands[0]", "expected a 64-bit integer, found 1.5"));
+    let (path, message) = schema_error(r#"{"instructions": [{"opcode": "Switch", "operands": [1, "2"]}]}"#);
+    assert_eq!((path.as_str(), message.as_str()), ("instructions[0].operands[1]", "expected an integer, found a string"));
+    assert_eq!(schema_error(r#"{"instructions": [{"opcode": "Push", "operands": [9223372036854775808]}]}"#).0, "instructions[0].operands[0]");
+    assert_eq!(schema_error(r#"{"instructions": [], "metadata": {"data": [{"address": -1, "words": []}]}}"#).0, "metadata.data[0].address");
+    assert_eq!(schema_error(r#"{"instructions": [], "metadata": {"name": 7}}"#).0, "metadata.name");
+    assert_eq!(schema_error(r#"{"instructions": [], "metadata": {"entry": "main"}}"#).0, "metadata.entry");
+}
+
+#[test]
+fn structural_errors() {
+    assert_eq!(schema_error("{}"), ("instructions".to_string(), "missing field".to_string()));
+    assert_eq!(schema_error("[]").1, "expected an object, found an array");
+    assert_eq!(schema_error(r#"{"instructions": [{"opcode": "Nop", "operand": [1]}]}"#).0, "instructions[0].operand");
+
+    let err = Program::from_json("{\n  \"instructions\": [\n    {\"opcode\": \"Nop\"}\n    {\"opcode\": \"Exit\"}\n  ]\n}").unwrap_err();
+    assert_eq!(err, JsonError::Syntax { line: 4, column: 5, message: "expected ',' or ']'".to_string() });
+    assert!(matches!(Program::from_json(r#"{"instructions": []} x"#), Err(JsonError::Syntax { .. })));
+    assert!(matches!(Program::from_json(r#"{"instructions": ["#), Err(JsonError::Syntax { .. })));
+    assert!(matches!(Program::from_json(&"[".repeat(10_000)), Err(JsonError::Syntax { .. })));
+}
@@ -0,0 +1,336 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use beef::{Config, Context, MmioHandler, MAX_BULK_CELLS, OpCode::*, Program, VmError};
+use common::{ix, run, run_err};
+
+#[test]
+fn indirect_loads_and_stores_fill_and_sum_an_array() {
+    // memory[100 + i] = i for i in 0..10, then sum them back
+    let program = vec![
+        // fill, r1 = i
+        ix(LoadReg, &[1]),
+        ix(LoadReg, &[1]),
+        ix(AddImm, &[100]),
+        ix(StoreInd, &[]),
+        ix(IncReg, &[1]),
+        ix(LoadReg, &[1]),
+        ix(Push, &[10]),
+        ix(JumpLt, &[0]),
+        // sum, r2 = i, r0 = total
+        ix(LoadReg, &[2]),
+        ix(AddImm, &[100]),
+        ix(LoadInd, &[]),
+        ix(LoadReg, &[0]),
+        ix(Add, &[]),
+        ix(StoreReg, &[0]),
+        ix(IncReg, &[2]),
+        ix(LoadReg, &[2]),
+        ix(Push, &[10]),
+        ix(JumpLt, &[8]),
+        ix(Exit, &[0]),
+    ];
+    assert_eq!(run(program), Ok(45));
+}
+
+#[test]
+fn indirect_negative_addresses_error() {
+    let err = run_err(vec![ix(Push, &[-4]), ix(LoadInd, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::InvalidAddress { pc: 1, opcode: LoadInd, addr: -4 });
+    let err = run_err(vec![ix(Push, &[1]), ix(Push, &[-4]), ix(StoreInd, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::InvalidAddress { pc: 2, opcode: StoreInd, addr: -4 });
+}
+
+#[test]
+fn base_plus_offset_addressing() {
+    let program = vec![
+        // legacy absolute form still works
+        ix(Push, &[7]),
+        ix(Store, &[18]),
+        // r3 = 20, read 20 - 2
+        ix(Push, &[20]),
+        ix(StoreReg, &[3]),
+        ix(Load, &[3, -2]),
+        ix(Store, &[3, 5]),
+        ix(Load, &[25]),
+        ix(Exit, &[]),
+    ];
+    assert_eq!(run(program), Ok(7));
+
+    let err = run_err(vec![ix(Load, &[3, -1]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::InvalidAddress { pc: 0, opcode: Load, addr: -1 });
+    let program = vec![ix(Push, &[i64::MAX]), ix(StoreReg, &[3]), ix(Load, &[3, 1]), ix(Exit, &[])];
+    assert!(matches!(run_err(program), VmError::InvalidAddress { opcode: Load, .. }));
+}
+
+#[test]
+fn sized_loads_sign_extend_and_allow_unaligned_access() {
+    let program = vec![
+        ix(Push, &[0x1234_5678_9abc_def0]),
+        ix(Store64, &[1]),   // unaligned on purpose
+        ix(Load8, &[1]),     // 0xf0 -> -16
+        ix(Load16, &[3]),    // 0x9abc -> negative
+        ix(Load32, &[5]),    // 0x12345678
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new_with_memory(program, 16);
+    assert_eq!(context.run(false), Ok(0x1234_5678));
+    assert_eq!(context.stack(), &[-16, 0x9abc_u16 as i16 as i64]);
+}
+
+#[test]
+fn sized_access_at_the_last_valid_byte() {
+    let at = |opcode, addr| Context::new_with_memory(vec![ix(Push, &[-1]), ix(opcode, &[addr]), ix(Exit, &[0])], 8).run(false);
+    assert_eq!(at(Store8, 7), Ok(0));
+    assert_eq!(at(Store32, 4), Ok(0));
+    let err = at(Store32, 5).unwrap_err();
+    assert_eq!(err.root(), &VmError::OutOfBounds { pc: 1, opcode: Store32, addr: 5, size: 4, memory: 8 });
+    assert!(err.to_string().starts_with("Memory access out of bounds in Store32 Op: address 5, size 4, memory is 8 bytes"));
+    assert!(at(Store8, 8).is_err());
+}
+
+#[test]
+fn memory_limit_counts_distinct_cells() {
+    let config = Config { memory_limit: Some(4), ..Config::default() };
+    let mut program: Vec<_> = (0..4).flat_map(|addr| [ix(Push, &[addr]), ix(Store, &[addr])]).collect();
+    // overwriting is fine
+    program.extend([ix(Push, &[9]), ix(Store, &[2]), ix(Push, &[9]), ix(Store, &[4]), ix(Exit, &[0])]);
+
+    let mut context = Context::new_with_config(program, config);
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::MemoryLimit { pc: 11, limit: 4, addr: 4 });
+    assert_eq!(context.peek(2), Some(9));
+}
+
+#[test]
+fn memcpy_overlapping_regions_behave_like_memmove() {
+    let mut context = Context::new(vec![
+        ix(Push, &[11]), // dst
+        ix(Push, &[10]), // src
+        ix(Push, &[3]),  // count
+        ix(MemCpy, &[]),
+        ix(Exit, &[0]),
+    ]);
+    for (i, value) in [1, 2, 3].into_iter().enumerate() {
+        context.poke(10 + i, value);
+    }
+    context.run(false).unwrap();
+    let cells: Vec<_> = (10..14).map(|addr| context.peek(addr).unwrap()).collect();
+    assert_eq!(cells, vec![1, 1, 2, 3]);
+}
+
+#[test]
+fn bulk_ops_take_one_instruction_regardless_of_length() {
+    let steps = |count: i64| {
+        let program = vec![
+            ix(Push, &[0]), ix(Push, &[7]), ix(Push, &[count]), ix(MemSet, &[]),
+            ix(Push, &[5000]), ix(Push, &[0]), ix(Push, &[count]), ix(MemCpy, &[]),
+            ix(Load, &[5000 + count - 1]),
+            ix(Exit, &[]),
+        ];
+        let result = Context::execute(program, &[]).unwrap();
+        assert_eq!(result.value, 7);
+        result.steps
+    };
+    assert_eq!(steps(1000), steps(10));
+}
+
+#[test]
+fn bulk_ops_check_the_limit_before_writing() {
+    let config = Config { memory_limit: Some(10), ..Config::default() };
+    let mut context = Context::new_with_config(
+        vec![ix(Push, &[0]), ix(Push, &[1]), ix(Push, &[11]), ix(MemSet, &[]), ix(Exit, &[0])],
+        config,
+    );
+    assert!(matches!(context.run(false).unwrap_err().root(), VmError::MemoryLimit { .. }));
+    assert!(context.memory_snapshot().is_empty());
+}
+
+#[test]
+fn bulk_ops_reject_counts_past_the_cap() {
+    let count = MAX_BULK_CELLS as i64 + 1;
+    let err = run_err(vec![ix(Push, &[0]), ix(Push, &[1]), ix(Push, &[count]), ix(MemSet, &[]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 3, opcode: MemSet, value: count });
+    let err = run_err(vec![ix(Push, &[0]), ix(Push, &[1]), ix(Push, &[i64::MAX]), ix(MemCpy, &[]), ix(Exit, &[0])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 3, opcode: MemCpy, value: i64::MAX });
+}
+
+#[test]
+fn protected_ranges_reject_stores_at_both_edges() {
+    let store = |addr: i64| {
+        let mut context = Context::new(vec![ix(Push, &[1]), ix(Store, &[addr]), ix(Exit, &[0])]);
+        context.protect(10..20);
+        context.run(false)
+    };
+    assert_eq!(store(9), Ok(0));
+    assert_eq!(store(10).unwrap_err().root(), &VmError::ProtectedWrite { pc: 1, addr: 10, range: 10..20 });
+    assert!(store(19).is_err());
+    assert_eq!(store(20), Ok(0));
+}
+
+#[test]
+fn protection_survives_call_and_return() {
+    let mut context = Context::new(vec![
+        ix(Call, &[3]),
+        ix(Load, &[10]),
+        ix(Exit, &[]),
+        ix(Push, &[5]),
+        ix(Store, &[10]),
+        ix(Return, &[]),
+    ]);
+    context.poke(10, 42);
+    context.protect(10..11);
+    assert!(matches!(context.run(false).unwrap_err().root(), VmError::ProtectedWrite { pc: 4, addr: 10, .. }));
+    assert_eq!(context.peek(10), Some(42));
+}
+
+#[derive(Clone, Default)]
+struct Device {
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl MmioHandler for Device {
+    fn read(&mut self, addr: usize) -> Result<i64, String> {
+        self.log.lock().unwrap().push(format!("read {:#x}", addr));
+        Ok(77)
+    }
+
+    fn write(&mut self, addr: usize, value: i64) -> Result<(), String> {
+        if value < 0 {
+            return Err("device rejects negative values".to_string());
+        }
+        self.log.lock().unwrap().push(format!("write {:#x} {}", addr, value));
+        Ok(())
+    }
+}
+
+#[test]
+fn mmio_routes_loads_and_stores_to_the_handler() {
+    let device = Device::default();
+    let mut context = Context::new(vec![
+        ix(Load, &[0xff00]),
+        ix(Store, &[0xff01]),
+        ix(Push, &[0xff00]),
+        ix(LoadInd, &[]),
+        ix(Exit, &[]),
+    ]);
+    context.map_region(0xff00..0xff02, Box::new(device.clone())).unwrap();
+    assert_eq!(context.run(false), Ok(77));
+    assert_eq!(*device.log.lock().unwrap(), vec!["read 0xff00", "write 0xff01 77", "read 0xff00"]);
+    assert_eq!(context.peek(0xff01), None);
+}
+
+#[test]
+fn mmio_errors_carry_the_pc_and_overlaps_are_rejected() {
+    let mut context = Context::new(vec![ix(Push, &[-1]), ix(Store, &[0xff01]), ix(Exit, &[0])]);
+    context.map_region(0xff00..0xff02, Box::new(Device::default())).unwrap();
+    assert!(context.map_region(0xff01..0xff10, Box::new(Device::default())).is_err());
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::Mmio { pc: 1, addr: 0xff01, write: true, message: "device rejects negative values".to_string() });
+    assert!(err.to_string().starts_with("device rejects negative values (MMIO write at address 65281) at pc=1"), "{}", err);
+}
+
+#[test]
+fn bulk_ops_reach_the_handler_for_mapped_cells() {
+    let device = Device::default();
+    let mut context = Context::new(vec![
+        // 0xfeff..0xff02, the last two cells are the device's
+        ix(Push, &[0xfeff]), ix(Push, &[5]), ix(Push, &[3]), ix(MemSet, &[]),
+        ix(Push, &[0x10]), ix(Push, &[0xfeff]), ix(Push, &[3]), ix(MemCpy, &[]),
+        ix(Push, &[0xff01]), ix(Push, &[0x10]), ix(Push, &[1]), ix(MemCpy, &[]),
+        ix(Exit, &[0]),
+    ]);
+    context.map_region(0xff00..0xff02, Box::new(device.clone())).unwrap();
+    context.run(false).unwrap();
+    assert_eq!(
+        *device.log.lock().unwrap(),
+        vec!["write 0xff00 5", "write 0xff01 5", "read 0xff00", "read 0xff01", "write 0xff01 5"]
+    );
+    assert_eq!([0xfeff, 0xff00, 0x10, 0x11, 0x12].map(|addr| context.peek(addr)), [Some(5), None, Some(5), Some(77), Some(77)]);
+
+    // a device error stops the op at that cell
+    let mut context = Context::new(vec![ix(Push, &[0xff00]), ix(Push, &[-1]), ix(Push, &[2]), ix(MemSet, &[]), ix(Exit, &[0])]);
+    context.map_region(0xff00..0xff02, Box::new(Device::default())).unwrap();
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::Mmio { pc: 3, addr: 0xff00, write: true, message: "device rejects negative values".to_string() });
+}
+
+#[test]
+fn freed_heap_blocks_are_reused() {
+    let config = Config { heap: 1000..1010, ..Config::default() };
+    let program = vec![
+        ix(Push, &[4]),
+        ix(Alloc, &[]),  // 1000
+        ix(Push, &[4]),
+        ix(Alloc, &[]),  // 1004
+        ix(Swap, &[]),
+        ix(Free, &[]),   // free 1000
+        ix(Push, &[2]),
+        ix(Alloc, &[]),  // reuses 1000
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new_with_config(program, config);
+    assert_eq!(context.run(false), Ok(1000));
+    assert_eq!(context.stack(), &[1004]);
+}
+
+#[test]
+fn heap_exhaustion_and_bad_frees() {
+    let config = || Config { heap: 1000..1010, ..Config::default() };
+    let exhaust = vec![ix(Push, &[8]), ix(Alloc, &[]), ix(Push, &[3]), ix(Alloc, &[]), ix(Exit, &[])];
+    let err = Context::new_with_config(exhaust, config()).run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::OutOfHeap { pc: 3, size: 3, heap: 1000..1010 });
+
+    let double = vec![ix(Push, &[1]), ix(Alloc, &[]), ix(Dup, &[]), ix(Free, &[]), ix(Free, &[]), ix(Exit, &[0])];
+    let err = Context::new_with_config(double, config()).run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::InvalidFree { pc: 4, addr: 1000 });
+    let unknown = vec![ix(Push, &[1003]), ix(Free, &[]), ix(Exit, &[0])];
+    assert!(Context::new_with_config(unknown, config()).run(false).is_err());
+}
+
+#[test]
+fn data_segment_is_loaded_before_running() {
+    let program = Program::new(vec![ix(Push, &[4]), ix(StoreReg, &[1]), ix(Load, &[1, 100]), ix(Exit, &[])])
+        .with_data(100, vec![1, 1, 2, 6, 24, 120]);
+    let mut context = Context::load(program, Config::default()).unwrap();
+    assert_eq!(context.run(false), Ok(24));
+}
+
+#[test]
+fn overlapping_data_blocks_are_rejected() {
+    let program = Program::new(vec![ix(Exit, &[0])]).with_data(10, vec![1, 2, 3]).with_data(12, vec![4]);
+    let err = Context::load(program, Config::default()).err().unwrap();
+    assert_eq!(err, VmError::InvalidProgram("Data block at 12 overlaps data block 10..13".to_string()));
+}
+
+#[test]
+fn callee_writes_through_a_pointer_to_a_caller_local() {
+    let config = Config { memory_stack: Some(500), ..Config::default() };
+    let program = vec![
+        // caller: local x = 1 on the memory stack, pass &x
+        ix(Push, &[1]),
+        ix(PushM, &[]),
+        ix(LoadReg, &[10]),
+        ix(Call, &[6]),
+        ix(PopM, &[]),
+        ix(Exit, &[]),
+        // callee at 6: *p = 99
+        ix(Push, &[99]),
+        ix(Swap, &[]),
+        ix(StoreInd, &[]),
+        ix(Return, &[]),
+    ];
+    let mut context = Context::new_with_config(program, config);
+    assert_eq!(context.run(false), Ok(99));
+    assert_eq!(context.registers()[10], 500);
+}
+
+#[test]
+fn poke_peek_round_trip() {
+    let mut context = Context::new(vec![ix(Load, &[40]), ix(MulImm, &[2]), ix(Store, &[41]), ix(Exit, &[0])]);
+    context.poke(40, 21);
+    context.run(false).unwrap();
+    assert_eq!(context.peek(41), Some(42));
+    assert_eq!(context.memory_snapshot().into_iter().collect::<Vec<_>>(), vec![(40, 21), (41, 42)]);
+}
@@ -0,0 +1,98 @@
+// only what the crate offers without std, so this also passes with
+// `cargo test --no-default-features --test no_std`. The test crate itself is no_std as well
+#![no_std]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use beef::{
+    assemble, Channel, Context, Output, ProgramBuilder, RunOutcome, Scheduler, SharedBuffer, SharedMemory, TaskStatus, VmError,
+};
+
+// collects output without anything from std
+#[derive(Default)]
+struct Text(String);
+
+impl Output for Text {
+    fn write_text(&mut self, text: &str) -> Result<(), String> {
+        self.0.push_str(text);
+        Ok(())
+    }
+}
+
+#[test]
+fn the_interpreter_runs_without_std() {
+    let program = assemble(
+        "
+        push 10
+        storereg r1
+        push 1
+        storereg r0
+    loop:
+        loadreg r0
+        loadreg r1
+        mul
+        storereg r0
+        decreg r1
+        loadreg r1
+        push 1
+        jumpgt loop
+        loadreg r0
+        dup
+        print
+        store 100
+        load 100
+        push 3
+        alloc
+        pop
+        fpush 1.5
+        floattoint
+        add
+        timems
+        pop
+        push 120
+        printchar
+        exit
+        ",
+    )
+    .unwrap();
+    let buffer = SharedBuffer::default();
+    let mut context = Context::new(program);
+    context.set_output(Box::new(buffer.clone()));
+    assert_eq!(context.run(false), Ok(3_628_801));
+    assert_eq!(buffer.contents(), b"3628800\nx");
+
+    let program = ProgramBuilder::new().push(2).push(0).div().exit().build().unwrap();
+    let mut context = Context::new(program);
+    assert!(matches!(context.run(false).unwrap_err().root(), VmError::DivisionByZero { pc: 2, .. }));
+}
+
+#[test]
+fn host_plumbing_works_without_std() {
+    let mut context = Context::new(assemble("push 4\nsyscall 1 1\nprint\npush 0\nexit").unwrap());
+    context.register_host_fn(1, Box::new(|args| Ok(args[0] * 2)));
+    context.set_output(Box::new(Text::default()));
+    assert_eq!(context.run(false), Ok(0));
+    assert!(context.take_trace().is_none());
+
+    // channels and shared memory between contexts under the scheduler
+    let channel = Channel::new(2);
+    let memory = SharedMemory::new(1);
+    let mut producer = Context::new(assemble("push 5\nsend 0\npush 6\nsend 0\npush 7\nsend 0\npush 0\nexit").unwrap());
+    let mut consumer = Context::new(assemble("recv 0\nrecv 0\nrecv 0\nadd\nadd\natomicadd 500\npush 0\nexit").unwrap());
+    producer.attach_sender(0, &channel);
+    consumer.attach_receiver(0, &channel);
+    consumer.attach_shared(500, &memory).unwrap();
+    let mut scheduler = Scheduler::new(2);
+    scheduler.spawn(producer);
+    scheduler.spawn(consumer);
+    let finished: Vec<TaskStatus> = scheduler.run_all().into_iter().map(|(_, status)| status).collect();
+    assert_eq!(finished, [TaskStatus::Exited(0), TaskStatus::Exited(0)]);
+    assert_eq!(memory.get(0), Some(18));
+
+    let mut context = Context::new(assemble("loop:\njump loop").unwrap());
+    assert_eq!(context.run_for(10), Ok(RunOutcome::Paused));
+}
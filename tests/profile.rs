@@ -0,0 +1,51 @@
+mod common;
+
+use beef::{Context, OpCode::*};
+use common::factorial;
+
+#[test]
+fn factorial_profile() {
+    let mut context = Context::new(factorial(10));
+    assert_eq!(context.profile_report(), None);
+    context.enable_profiling();
+    assert_eq!(context.run(false), Ok(3_628_800));
+
+    let report = context.profile_report().unwrap();
+    assert_eq!(report.count(Mul), 9);
+    assert_eq!(report.count(Jump), 9);
+    assert_eq!(report.count(Exit), 1);
+    assert_eq!(report.count(Div), 0);
+    assert_eq!(report.total, context.steps());
+    assert_eq!(report.opcodes[0].opcode, LoadReg);
+
+    // the loop test runs once more than the body, then the body, then the setup and exit
+    let pcs: Vec<_> = report.pcs.iter().map(|stats| (stats.pc, stats.count)).collect();
+    assert_eq!(&pcs[..3], [(4, 10), (5, 10), (6, 10)]);
+    assert!(pcs[3..9].iter().all(|&(pc, count)| (7..=12).contains(&pc) && count == 9), "{:?}", pcs);
+
+    let text = report.to_string();
+    let lines: Vec<_> = text.lines().collect();
+    assert!(lines[1].trim_start().starts_with("28  31.46%"), "{}", text);
+    assert!(lines[1].ends_with("LoadReg"), "{}", text);
+    assert!(text.contains("hottest pcs:"), "{}", text);
+    assert!(text.contains("10  11.24%       4  LoadReg 1"), "{}", text);
+}
+
+#[test]
+fn counts_come_out_the_same_every_run() {
+    let report = || {
+        let mut context = Context::new(factorial(10));
+        context.enable_profiling();
+        context.run(false).unwrap();
+        let mut report = context.profile_report().unwrap();
+        // wall time is the only part allowed to differ
+        report.opcodes.iter_mut().for_each(|stats| stats.time = None);
+        report
+    };
+    let first = report();
+    assert_eq!(first, report());
+    // equally frequent opcodes keep opcode order instead of whichever happened to be slower
+    let tied: Vec<_> = first.opcodes.iter().filter(|stats| stats.count == 9).map(|stats| stats.opcode).collect();
+    assert_eq!(tied, [Mul, DecReg, Jump]);
+    assert!(!first.to_string().contains("time"), "{}", first);
+}
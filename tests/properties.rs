@@ -0,0 +1,267 @@
+mod common;
+
+use beef::{ArithMode, Config, Context, Instruction, OpCode, OpCode::*, VmError};
+use common::arbitrary::{check, value, Rng};
+use common::ix;
+
+// inputs per property, raise it when touching the interpreter loop
+const CASES: usize = 500;
+
+const MODES: [ArithMode; 3] = [ArithMode::Wrapping, ArithMode::Checked, ArithMode::Saturating];
+
+// push `values`, run `ops`, exit with the top of the stack, so Sub on &[a, b] is a - b.
+// Fused and plain have to agree
+fn eval(mode: ArithMode, values: &[i64], ops: &[Instruction]) -> Result<i64, VmError> {
+    let mut program: Vec<_> = values.iter().map(|&value| ix(Push, &[value])).collect();
+    program.extend_from_slice(ops);
+    program.push(ix(Exit, &[]));
+    let run = |superinstructions| {
+        let config = Config { arith_mode: mode, superinstructions, ..Config::default() };
+        Context::new_with_config(program.clone(), config).run(false).map_err(|err| err.root().clone())
+    };
+    let result = run(false);
+    assert_eq!(run(true), result, "superinstructions changed the result of {:?}", program);
+    result
+}
+
+// the stack left behind after pushing `values` and running `ops`
+fn stack_after(values: &[i64], ops: &[Instruction]) -> Result<Vec<i64>, VmError> {
+    let mut program: Vec<_> = values.iter().map(|&value| ix(Push, &[value])).collect();
+    program.extend_from_slice(ops);
+    program.push(ix(Exit, &[0]));
+    let mut context = Context::new(program);
+    context.run(false).map_err(|err| err.root().clone())?;
+    Ok(context.stack().to_vec())
+}
+
+fn expect<T: PartialEq + std::fmt::Debug>(found: T, expected: T) -> Result<(), String> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(format!("got {:?}, expected {:?}", found, expected))
+    }
+}
+
+fn pair(rng: &mut Rng) -> (i64, i64) {
+    (value(rng), value(rng))
+}
+
+fn overflow(pc: usize, opcode: OpCode, operands: &[i64]) -> VmError {
+    VmError::Overflow { pc, opcode, operands: operands.to_vec() }
+}
+
+#[test]
+fn add_sub_mul_follow_the_arith_mode() {
+    type Op = (OpCode, fn(i64, i64) -> i64, fn(i64, i64) -> Option<i64>, fn(i64, i64) -> i64);
+    let ops: [Op; 3] = [
+        (Add, i64::wrapping_add, i64::checked_add, i64::saturating_add),
+        (Sub, i64::wrapping_sub, i64::checked_sub, i64::saturating_sub),
+        (Mul, i64::wrapping_mul, i64::checked_mul, i64::saturating_mul),
+    ];
+    for (opcode, wrapping, checked, saturating) in ops {
+        for mode in MODES {
+            check(&format!("{:?} {:?}", opcode, mode), CASES, pair, |&(a, b)| {
+                let expected = match mode {
+                    ArithMode::Wrapping => Ok(wrapping(a, b)),
+                    ArithMode::Checked => checked(a, b).ok_or_else(|| overflow(2, opcode, &[a, b])),
+                    ArithMode::Saturating => Ok(saturating(a, b)),
+                };
+                expect(eval(mode, &[a, b], &[ix(opcode, &[])]), expected)
+            });
+        }
+    }
+}
+
+#[test]
+fn immediates_match_their_stack_forms() {
+    for (imm, opcode) in [(AddImm, Add), (SubImm, Sub), (MulImm, Mul)] {
+        for mode in MODES {
+            check(&format!("{:?} {:?}", imm, mode), CASES, pair, |&(a, b)| {
+                let expected = eval(mode, &[a, b], &[ix(opcode, &[])]).map_err(|err| match err {
+                    // one instruction shorter, so the pc moves back by one
+                    VmError::Overflow { operands, .. } => VmError::Overflow { pc: 1, opcode: imm, operands },
+                    err => err,
+                });
+                expect(eval(mode, &[a], &[ix(imm, &[b])]), expected)
+            });
+        }
+    }
+}
+
+#[test]
+fn div_and_mod_truncate_and_reject_zero() {
+    for mode in MODES {
+        check(&format!("Div Mod {:?}", mode), CASES, |rng| {
+            let (a, b) = pair(rng);
+            // a zero divisor and the one overflowing quotient come up on their own too rarely
+            match rng.below(8) {
+                0 => (a, 0),
+                1 => (i64::MIN, -1),
+                _ => (a, b),
+            }
+        }, |&(a, b)| {
+            for (opcode, checked) in [(Div, i64::checked_div as fn(i64, i64) -> Option<i64>), (Mod, i64::checked_rem)] {
+                let expected = match b {
+                    0 => Err(VmError::DivisionByZero { pc: 2 }),
+                    _ => checked(a, b).ok_or_else(|| overflow(2, opcode, &[a, b])),
+                };
+                expect(eval(mode, &[a, b], &[ix(opcode, &[])]), expected)?;
+            }
+            Ok(())
+        });
+    }
+    // the quotient and remainder put a back together
+    check("Div Mod identity", CASES, pair, |&(a, b)| {
+        if b == 0 || (a, b) == (i64::MIN, -1) {
+            return Ok(());
+        }
+        let (q, r) = (eval(ArithMode::Wrapping, &[a, b], &[ix(Div, &[])]), eval(ArithMode::Wrapping, &[a, b], &[ix(Mod, &[])]));
+        expect(q.unwrap().wrapping_mul(b).wrapping_add(r.unwrap()), a)
+    });
+}
+
+#[test]
+fn unary_ops() {
+    check("Neg Abs Not", CASES, value, |&a| {
+        expect(eval(ArithMode::Wrapping, &[a], &[ix(Neg, &[])]), a.checked_neg().ok_or_else(|| overflow(1, Neg, &[a])))?;
+        expect(eval(ArithMode::Wrapping, &[a], &[ix(Abs, &[])]), a.checked_abs().ok_or_else(|| overflow(1, Abs, &[a])))?;
+        expect(eval(ArithMode::Wrapping, &[a], &[ix(Not, &[])]), Ok(!a))?;
+        expect(eval(ArithMode::Wrapping, &[a], &[ix(Not, &[]), ix(Not, &[])]), Ok(a))
+    });
+}
+
+#[test]
+fn bitwise_min_max_and_comparisons() {
+    type Op = (OpCode, fn(i64, i64) -> i64);
+    let ops: [Op; 11] = [
+        (And, |a, b| a & b),
+        (Or, |a, b| a | b),
+        (Xor, |a, b| a ^ b),
+        (Min, i64::min),
+        (Max, i64::max),
+        (Eq, |a, b| (a == b) as i64),
+        (Ne, |a, b| (a != b) as i64),
+        (Lt, |a, b| (a < b) as i64),
+        (Le, |a, b| (a <= b) as i64),
+        (Gt, |a, b| (a > b) as i64),
+        (Ge, |a, b| (a >= b) as i64),
+    ];
+    for (opcode, reference) in ops {
+        check(&format!("{:?}", opcode), CASES, |rng| {
+            let (a, b) = pair(rng);
+            // equal operands are the interesting case for comparisons
+            if rng.chance(20) { (a, a) } else { (a, b) }
+        }, |&(a, b)| {
+            for mode in MODES {
+                expect(eval(mode, &[a, b], &[ix(opcode, &[])]), Ok(reference(a, b)))?;
+            }
+            Ok(())
+        });
+    }
+}
+
+#[test]
+fn unsigned_ops_match_u64() {
+    type Op = (OpCode, fn(u64, u64) -> u64);
+    let ops: [Op; 6] = [
+        (DivU, |a, b| a / b),
+        (ModU, |a, b| a % b),
+        (LtU, |a, b| (a < b) as u64),
+        (LeU, |a, b| (a <= b) as u64),
+        (GtU, |a, b| (a > b) as u64),
+        (GeU, |a, b| (a >= b) as u64),
+    ];
+    for (opcode, reference) in ops {
+        check(&format!("{:?}", opcode), CASES, pair, |&(a, b)| {
+            let expected = match (opcode, b) {
+                (DivU | ModU, 0) => Err(VmError::DivisionByZero { pc: 2 }),
+                _ => Ok(reference(a as u64, b as u64) as i64),
+            };
+            expect(eval(ArithMode::Checked, &[a, b], &[ix(opcode, &[])]), expected)
+        });
+    }
+}
+
+#[test]
+fn mul_hi_and_mul_make_the_full_product() {
+    check("MulHi", CASES, pair, |&(a, b)| {
+        let low = eval(ArithMode::Wrapping, &[a, b], &[ix(Mul, &[])]).unwrap();
+        let high = eval(ArithMode::Wrapping, &[a, b], &[ix(MulHi, &[])]).unwrap();
+        expect((high as i128) << 64 | low as u64 as i128, a as i128 * b as i128)?;
+        let high = eval(ArithMode::Wrapping, &[a, b], &[ix(MulHiU, &[])]).unwrap();
+        expect((high as u64 as u128) << 64 | low as u64 as u128, a as u64 as u128 * b as u64 as u128)
+    });
+}
+
+#[test]
+fn select_after_a_comparison_is_min_and_max() {
+    // a b -> a b a b Lt -> a b (a < b), then Select keeps a when it's the smaller one
+    let min = [ix(Over, &[]), ix(Over, &[]), ix(Lt, &[]), ix(Select, &[])];
+    let max = [ix(Over, &[]), ix(Over, &[]), ix(Gt, &[]), ix(Select, &[])];
+    check("Select min max", CASES, pair, |&(a, b)| {
+        expect(eval(ArithMode::Wrapping, &[a, b], &min), eval(ArithMode::Wrapping, &[a, b], &[ix(Min, &[])]))?;
+        expect(eval(ArithMode::Wrapping, &[a, b], &max), eval(ArithMode::Wrapping, &[a, b], &[ix(Max, &[])]))
+    });
+}
+
+#[test]
+fn shifts_take_amounts_below_64() {
+    type Op = (OpCode, fn(i64, u32) -> i64);
+    let ops: [Op; 3] = [(Shl, |a, n| a << n), (Shr, |a, n| ((a as u64) >> n) as i64), (Sar, |a, n| a >> n)];
+    for (opcode, reference) in ops {
+        check(&format!("{:?}", opcode), CASES, |rng| {
+            let amount = if rng.chance(80) { rng.below(64) as i64 } else { value(rng) };
+            (value(rng), amount)
+        }, |&(a, amount)| {
+            let expected = match u32::try_from(amount) {
+                Ok(n) if n < 64 => Ok(reference(a, n)),
+                _ => Err(VmError::InvalidOperand { pc: 2, opcode, value: amount }),
+            };
+            expect(eval(ArithMode::Wrapping, &[a, amount], &[ix(opcode, &[])]), expected)
+        });
+    }
+}
+
+#[test]
+fn stack_ops_rearrange_the_top() {
+    let values = |rng: &mut Rng| (0..3 + rng.below(4)).map(|_| value(rng)).collect::<Vec<_>>();
+    check("stack ops", CASES, values, |values| {
+        let n = values.len();
+        // the values under the top three, then `top`
+        let with = |top: &[i64]| values[..n - 3].iter().chain(top).copied().collect::<Vec<_>>();
+        let (a, b, c) = (values[n - 3], values[n - 2], values[n - 1]);
+        expect(stack_after(values, &[ix(Dup, &[])]), Ok(with(&[a, b, c, c])))?;
+        expect(stack_after(values, &[ix(Swap, &[])]), Ok(with(&[a, c, b])))?;
+        expect(stack_after(values, &[ix(Over, &[])]), Ok(with(&[a, b, c, b])))?;
+        expect(stack_after(values, &[ix(Rot, &[])]), Ok(with(&[b, c, a])))?;
+        expect(stack_after(values, &[ix(Rot, &[]), ix(Rot, &[]), ix(Rot, &[])]), Ok(values.clone()))?;
+        expect(stack_after(values, &[ix(Swap, &[]), ix(Swap, &[])]), Ok(values.clone()))?;
+        expect(stack_after(values, &[ix(Pop, &[])]), Ok(values[..n - 1].to_vec()))?;
+        for depth in 0..n {
+            let mut expected = values.clone();
+            expected.push(values[n - 1 - depth]);
+            expect(stack_after(values, &[ix(Pick, &[depth as i64])]), Ok(expected))?;
+        }
+        let err = stack_after(values, &[ix(Pick, &[n as i64])]);
+        expect(err, Err(VmError::StackUnderflow { pc: n, opcode: Pick, needed: n + 1, found: n }))
+    });
+}
+
+#[test]
+fn balanced_pushes_and_pops_leave_the_stack_alone() {
+    check("push pop", CASES, |rng| {
+        // a random interleaving of as many Pushes as Pops that never pops below the start
+        let (mut ops, mut open) = (Vec::new(), 0);
+        for _ in 0..rng.below(12) {
+            if open > 0 && rng.chance(50) {
+                ops.push(ix(Pop, &[]));
+                open -= 1;
+            } else {
+                ops.push(ix(Push, &[value(rng)]));
+                open += 1;
+            }
+        }
+        ops.extend((0..open).map(|_| ix(Pop, &[])));
+        (vec![value(rng), value(rng)], ops)
+    }, |(values, ops)| expect(stack_after(values, ops), Ok(values.clone())));
+}
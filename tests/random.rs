@@ -0,0 +1,92 @@
+mod common;
+
+#[cfg(feature = "std")]
+use beef::{replay, SharedBuffer};
+use beef::{Context, Instruction, OpCode::*, VmError};
+use common::{ix, run_err};
+
+// `count` draws of Rand, or of RandRange with `bound`, left on the stack
+fn draws(count: usize, bound: Option<i64>) -> Vec<Instruction> {
+    let mut program = Vec::new();
+    for _ in 0..count {
+        match bound {
+            Some(bound) => program.extend([ix(Push, &[bound]), ix(RandRange, &[])]),
+            None => program.push(ix(Rand, &[])),
+        }
+    }
+    program.push(ix(Halt, &[0]));
+    program
+}
+
+fn sequence(seed: Option<u64>, program: &[Instruction]) -> Vec<i64> {
+    let mut context = Context::new(program.to_vec());
+    if let Some(seed) = seed {
+        context.set_rng_seed(seed);
+    }
+    context.run(false).unwrap();
+    context.stack().to_vec()
+}
+
+#[test]
+fn the_same_seed_gives_the_same_sequence() {
+    let program = draws(64, None);
+    assert_eq!(sequence(Some(7), &program), sequence(Some(7), &program));
+    assert_ne!(sequence(Some(7), &program), sequence(Some(8), &program));
+    // no seed is a fixed seed, not entropy
+    assert_eq!(sequence(None, &program), sequence(None, &program));
+
+    let values = sequence(Some(7), &program);
+    assert!(values.windows(2).all(|pair| pair[0] != pair[1]), "{:?}", values);
+}
+
+#[test]
+fn rand_range_stays_in_bounds() {
+    let values = sequence(Some(1), &draws(2000, Some(7)));
+    assert!(values.iter().all(|value| (0..7).contains(value)), "{:?}", values);
+    for expected in 0..7 {
+        let hits = values.iter().filter(|&&value| value == expected).count();
+        assert!((200..400).contains(&hits), "{} came up {} times", expected, hits);
+    }
+
+    assert!(sequence(Some(2), &draws(50, Some(1))).iter().all(|&value| value == 0));
+    assert!(sequence(Some(3), &draws(50, Some(i64::MAX))).iter().all(|&value| value >= 0));
+
+    for bound in [0, -1, i64::MIN] {
+        let err = run_err(vec![ix(Push, &[bound]), ix(RandRange, &[]), ix(Exit, &[])]);
+        assert_eq!(err, VmError::InvalidOperand { pc: 1, opcode: RandRange, value: bound });
+    }
+}
+
+#[test]
+fn reset_snapshots_and_replays_keep_the_sequence() {
+    let program = draws(8, None);
+    let mut context = Context::new(program.clone());
+    context.set_rng_seed(99);
+    context.run(false).unwrap();
+    let first = context.stack().to_vec();
+    context.reset();
+    context.run(false).unwrap();
+    assert_eq!(context.stack(), first);
+
+    // a snapshot taken half way carries the generator state
+    context.reset();
+    assert!(context.run_for(4).is_ok());
+    let snapshot = context.snapshot();
+    context.run(false).unwrap();
+    context.restore(&snapshot).unwrap();
+    context.run(false).unwrap();
+    assert_eq!(context.stack(), first);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn recordings_with_a_custom_seed_replay() {
+    let program = draws(8, None);
+    let trace = SharedBuffer::default();
+    let mut context = Context::new(program.clone());
+    context.set_rng_seed(99);
+    context.record_trace(Box::new(trace.clone()));
+    context.run(false).unwrap();
+    context.stop_recording().unwrap();
+    assert_eq!(replay(program, trace.contents().as_slice()), Ok(9));
+}
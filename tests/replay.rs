@@ -0,0 +1,50 @@
+mod common;
+
+use beef::{replay, Context, ExecutionEvent, OpCode::*, ReplayError, SharedBuffer};
+use common::{factorial, ix};
+
+fn record(context: &mut Context) -> Vec<u8> {
+    let trace = SharedBuffer::default();
+    context.record_trace(Box::new(trace.clone()));
+    assert_eq!(context.run(false), Ok(120));
+    context.stop_recording().unwrap();
+    trace.contents()
+}
+
+#[test]
+fn a_recording_replays_against_its_program() {
+    let trace = record(&mut Context::new(factorial(5)));
+    assert_eq!(replay(factorial(5), &trace[..]), Ok(44));
+
+    // recording can start mid-run, replay picks up from the recorded pc, registers and stack
+    let mut context = Context::new(factorial(5));
+    context.run_for(20).unwrap();
+    let trace = record(&mut context);
+    assert_eq!(replay(factorial(5), &trace[..]), Ok(24));
+}
+
+#[test]
+fn replay_reports_the_first_divergence() {
+    let trace = record(&mut Context::new(factorial(5)));
+    let mut program = factorial(5);
+    program[5] = ix(Push, &[2]);
+
+    let Err(ReplayError::Diverged(divergence)) = replay(program, &trace[..]) else { panic!("expected a divergence") };
+    assert_eq!(divergence.step, 5);
+    assert_eq!(divergence.pc, 6);
+    assert_eq!(divergence.error, None);
+    assert!(divergence.expected.contains(&ExecutionEvent::StackPush(1)), "{:?}", divergence.expected);
+    assert!(divergence.found.contains(&ExecutionEvent::StackPush(2)), "{:?}", divergence.found);
+}
+
+#[test]
+fn broken_traces_are_rejected() {
+    assert_eq!(
+        replay(factorial(5), &b"nope"[..]),
+        Err(ReplayError::Corrupt { offset: 0, message: "not a beef trace (bad magic)".to_string() })
+    );
+
+    let trace = record(&mut Context::new(factorial(5)));
+    let err = replay(factorial(5), &trace[..trace.len() - 1]).unwrap_err();
+    assert!(matches!(&err, ReplayError::Corrupt { offset, .. } if *offset == trace.len() - 1), "{}", err);
+}
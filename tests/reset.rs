@@ -0,0 +1,54 @@
+mod common;
+
+use beef::{Config, Context, OpCode::*, Program, RunOutcome, StepOutcome, VmError};
+use common::{factorial, ix};
+
+#[test]
+fn reset_runs_the_program_again() {
+    let mut context = Context::new(factorial(5));
+    assert_eq!(context.run(false), Ok(120));
+    context.reset();
+    assert_eq!((context.pc(), context.steps(), context.stack()), (0, 0, &[][..]));
+    assert_eq!(context.registers(), [0; 11]);
+    assert_eq!(context.run(false), Ok(120));
+    assert_eq!(context.steps(), 44);
+}
+
+#[test]
+fn running_a_finished_program_is_an_error() {
+    let mut context = Context::new(factorial(5));
+    assert_eq!(context.run(false), Ok(120));
+    assert_eq!(context.run(false), Err(VmError::AlreadyFinished { value: 120 }));
+    assert_eq!(
+        context.run(false).unwrap_err().to_string(),
+        "Program already finished with 120, reset the context to run it again"
+    );
+    // the driving APIs keep reporting the result instead
+    assert_eq!(context.run_for(10), Ok(RunOutcome::Completed(120)));
+    assert_eq!(context.step(), Ok(StepOutcome::Exited(120)));
+}
+
+#[test]
+fn reset_restores_the_data_segment() {
+    // bumps the counter at 100 and pushes onto the memory stack, host pokes are cleared too
+    let program = Program::new(vec![
+        ix(Load, &[100]),
+        ix(AddImm, &[1]),
+        ix(Store, &[100]),
+        ix(Push, &[5]),
+        ix(PushM, &[]),
+        ix(Load, &[100]),
+        ix(Exit, &[]),
+    ])
+    .with_data(100, vec![41]);
+    let config = Config { memory_stack: Some(500), ..Config::default() };
+    let mut context = Context::load(program, config).unwrap();
+    context.poke(200, 1);
+    for _ in 0..2 {
+        assert_eq!(context.run(false), Ok(42));
+        assert_eq!(context.registers()[10], 499);
+        context.reset();
+        assert_eq!(context.memory_snapshot().into_iter().collect::<Vec<_>>(), [(100, 41)]);
+        assert_eq!(context.registers()[10], 500);
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use beef::{Context, OpCode::*, RunOutcome, SharedBuffer, StepOutcome, VmError};
+use common::{factorial, ix};
+
+fn state(context: &Context) -> (usize, u64, Vec<i64>, Vec<i64>) {
+    (context.pc(), context.steps(), context.stack().to_vec(), context.registers().to_vec())
+}
+
+#[test]
+fn stepping_back_lands_where_a_shorter_run_stops() {
+    let mut context = Context::new(factorial(5));
+    context.enable_reverse_stepping(8);
+    for _ in 0..20 {
+        assert_eq!(context.step(), Ok(StepOutcome::Continued));
+    }
+    for _ in 0..5 {
+        context.step_back().unwrap();
+    }
+
+    let mut fresh = Context::new(factorial(5));
+    assert_eq!(fresh.run_for(15), Ok(RunOutcome::Paused));
+    assert_eq!(state(&context), state(&fresh));
+
+    // and forward again from there
+    assert_eq!(context.run(false), Ok(120));
+    assert_eq!(context.steps(), 44);
+}
+
+#[test]
+fn catching_up_is_silent() {
+    let output = SharedBuffer::default();
+    let program = vec![ix(Push, &[1]), ix(Print, &[]), ix(Push, &[2]), ix(Print, &[]), ix(Exit, &[0])];
+    let mut context = Context::new(program);
+    context.set_output(Box::new(output.clone()));
+    context.add_breakpoint(1).unwrap();
+
+    assert_eq!(context.run_reversible(100), Ok(RunOutcome::Hit { pc: 1 }));
+    assert_eq!(context.run_for(3), Ok(RunOutcome::Paused));
+    assert_eq!(context.pc(), 4);
+    context.step_back().unwrap();
+    context.step_back().unwrap();
+    assert_eq!((context.pc(), context.stack()), (2, &[][..]));
+    assert_eq!(String::from_utf8(output.contents()).unwrap(), "1\n2\n");
+}
+
+#[test]
+fn stepping_back_needs_a_checkpoint() {
+    let mut context = Context::new(factorial(5));
+    context.run_for(3).unwrap();
+    assert_eq!(context.step_back(), Err(VmError::NoCheckpoint { steps: 3 }));
+
+    // interval 1 keeps a checkpoint per step, only the newest 64 survive
+    let mut context = Context::new(factorial(20));
+    context.enable_reverse_stepping(1);
+    context.run_for(100).unwrap();
+    for _ in 0..64 {
+        context.step_back().unwrap();
+    }
+    assert_eq!(context.steps(), 36);
+    assert_eq!(context.step_back(), Err(VmError::NoCheckpoint { steps: 36 }));
+    assert_eq!(context.steps(), 36);
+
+    let mut context = Context::new(factorial(5));
+    context.enable_reverse_stepping(4);
+    assert_eq!(context.step_back(), Err(VmError::NoCheckpoint { steps: 0 }));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use beef::{assemble, Context, OpCode::*, RunOutcome, Scheduler, TaskStatus, VmError};
+use common::ix;
+
+// counts r0 up to n and exits with it, 4 steps per iteration
+fn counter(n: i64) -> Context {
+    let program = assemble(&format!(
+        "
+        push 0
+        storereg r0
+    loop:
+        increg r0
+        loadreg r0
+        push {}
+        jumplt loop
+        loadreg r0
+        exit
+        ",
+        n
+    ))
+    .unwrap();
+    Context::new(program)
+}
+
+#[test]
+fn counters_finish_shortest_first_whatever_the_quantum() {
+    for quantum in [1, 3, 7, 50, 150] {
+        let mut scheduler = Scheduler::new(quantum);
+        let long = scheduler.spawn(counter(160));
+        let short = scheduler.spawn(counter(10));
+        let medium = scheduler.spawn(counter(40));
+        let finished = scheduler.run_all();
+        assert_eq!(
+            finished,
+            [(short, TaskStatus::Exited(10)), (medium, TaskStatus::Exited(40)), (long, TaskStatus::Exited(160))],
+            "quantum {}",
+            quantum
+        );
+        assert!(scheduler.is_empty());
+    }
+
+    // a quantum big enough for everything finishes them all in the first round, in spawn order
+    let mut scheduler = Scheduler::new(10_000);
+    scheduler.spawn(counter(160));
+    scheduler.spawn(counter(10));
+    let round = scheduler.round();
+    assert_eq!(round, [(0, TaskStatus::Exited(160)), (1, TaskStatus::Exited(10))]);
+}
+
+#[test]
+fn registers_can_be_inspected_between_rounds() {
+    let mut scheduler = Scheduler::new(10);
+    let a = scheduler.spawn(counter(100));
+    let b = scheduler.spawn(counter(5));
+    assert_eq!(scheduler.round(), [(a, TaskStatus::Running), (b, TaskStatus::Running)]);
+    // 2 setup steps then 4 a loop, the increments land at steps 3, 7, 11 and so on
+    assert_eq!(scheduler.get(a).unwrap().registers()[0], 2);
+
+    assert_eq!(scheduler.round(), [(a, TaskStatus::Running), (b, TaskStatus::Running)]);
+    assert_eq!(scheduler.round(), [(a, TaskStatus::Running), (b, TaskStatus::Exited(5))]);
+    let live: Vec<_> = scheduler.contexts().map(|(id, context)| (id, context.registers()[0])).collect();
+    assert_eq!(live, [(a, 7)]);
+    assert!(scheduler.get(b).is_none());
+
+    // the host can still step in
+    scheduler.get_mut(a).unwrap().set_register(0, 99).unwrap();
+    assert_eq!(scheduler.run_all(), [(a, TaskStatus::Exited(100))]);
+}
+
+#[test]
+fn yields_errors_and_breakpoints_are_reported() {
+    let mut scheduler = Scheduler::new(100);
+    let yielder = scheduler.spawn(Context::new(vec![
+        ix(Push, &[1]),
+        ix(Yield, &[1]),
+        ix(Push, &[2]),
+        ix(Yield, &[1]),
+        ix(Push, &[3]),
+        ix(Exit, &[]),
+    ]));
+    let failing = scheduler.spawn(Context::new(vec![ix(Add, &[]), ix(Exit, &[])]));
+    let mut paused = Context::new(vec![ix(Nop, &[]), ix(Push, &[4]), ix(Exit, &[])]);
+    paused.add_breakpoint(1).unwrap();
+    let paused = scheduler.spawn(paused);
+
+    let round = scheduler.round();
+    assert_eq!(round[0], (yielder, TaskStatus::Yielded(1)));
+    let (id, TaskStatus::Failed(error)) = &round[1] else { panic!("expected a failure, got {:?}", round[1]) };
+    assert_eq!(*id, failing);
+    assert!(matches!(error.root(), VmError::StackUnderflow { pc: 0, .. }), "{}", error);
+    assert_eq!(round[2], (paused, TaskStatus::Stopped(RunOutcome::Hit { pc: 1 })));
+    assert_eq!(scheduler.len(), 2);
+
+    assert_eq!(scheduler.round(), [(yielder, TaskStatus::Yielded(2)), (paused, TaskStatus::Exited(4))]);
+    assert_eq!(scheduler.round(), [(yielder, TaskStatus::Exited(3))]);
+    assert!(scheduler.round().is_empty());
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use beef::{assemble, Context, OpCode::*, Scheduler, SharedMemory, TaskStatus, VmError};
+use common::ix;
+
+// adds 1 to shared cell `addr` n times, exits with the last value it saw before its own add
+fn incrementer(addr: usize, n: i64) -> Context {
+    let program = assemble(&format!(
+        "
+        push {n}
+        storereg r1
+    loop:
+        push 1
+        atomicadd {addr}
+        storereg r0
+        decreg r1
+        loadreg r1
+        jumpnotzero loop
+        loadreg r0
+        exit
+        ",
+        n = n,
+        addr = addr,
+    ))
+    .unwrap();
+    Context::new(program)
+}
+
+#[test]
+fn two_contexts_share_a_counter() {
+    let counter = SharedMemory::new(4);
+    let mut scheduler = Scheduler::new(3);
+    // same cells, different addresses in each context
+    let mut a = incrementer(100, 500);
+    a.attach_shared(100, &counter).unwrap();
+    let mut b = incrementer(2000, 500);
+    b.attach_shared(2000, &counter).unwrap();
+    scheduler.spawn(a);
+    scheduler.spawn(b);
+
+    let finished = scheduler.run_all();
+    assert_eq!(counter.get(0), Some(1000));
+    // they took turns, so neither saw the counter only ever move by its own adds
+    let TaskStatus::Exited(last_a) = finished[0].1 else { panic!("{:?}", finished) };
+    assert!(last_a > 499 && last_a < 999, "{}", last_a);
+    assert_eq!(finished[1].1, TaskStatus::Exited(999));
+}
+
+#[test]
+fn loads_and_stores_in_the_range_are_shared_the_rest_private() {
+    let memory = SharedMemory::new(2);
+    let writer = vec![ix(Push, &[7]), ix(Store, &[11]), ix(Push, &[8]), ix(Store, &[5]), ix(Halt, &[0])];
+    let mut a = Context::new(writer);
+    a.attach_shared(10, &memory).unwrap();
+    a.run(false).unwrap();
+    assert_eq!(memory.contents(), [0, 7]);
+
+    let mut b = Context::new(vec![ix(Load, &[1]), ix(Load, &[5]), ix(Add, &[]), ix(Exit, &[])]);
+    b.attach_shared(0, &memory).unwrap();
+    assert_eq!(b.run(false), Ok(7));
+
+    // the region takes its space in the mapped ranges like any mmio
+    let mut c = Context::new(vec![]);
+    c.attach_shared(0, &memory).unwrap();
+    assert!(matches!(c.attach_shared(1, &SharedMemory::new(3)), Err(VmError::MmioOverlap { .. })));
+}
+
+#[test]
+fn bulk_ops_read_and_write_the_shared_cells() {
+    let memory = SharedMemory::new(3);
+    // 9..12 with the shared cells at 10 and 11
+    let mut a = Context::new(vec![ix(Push, &[9]), ix(Push, &[4]), ix(Push, &[3]), ix(MemSet, &[]), ix(Halt, &[0])]);
+    a.attach_shared(10, &memory).unwrap();
+    a.run(false).unwrap();
+    assert_eq!(memory.contents(), [4, 4, 0]);
+    assert_eq!(a.peek(9), Some(4));
+
+    let mut b = Context::new(vec![ix(Push, &[50]), ix(Push, &[0]), ix(Push, &[3]), ix(MemCpy, &[]), ix(Halt, &[0])]);
+    b.attach_shared(0, &memory).unwrap();
+    b.run(false).unwrap();
+    assert_eq!([50, 51, 52].map(|addr| b.peek(addr)), [Some(4), Some(4), Some(0)]);
+}
+
+#[test]
+fn compare_and_swap_only_stores_on_a_match() {
+    let memory = SharedMemory::new(1);
+    memory.set(0, 5);
+    let cas = |expected: i64, new: i64| {
+        let mut context = Context::new(vec![ix(Push, &[expected]), ix(Push, &[new]), ix(AtomicCas, &[40]), ix(Exit, &[])]);
+        context.attach_shared(40, &memory).unwrap();
+        context.run(false)
+    };
+    assert_eq!(cas(4, 9), Ok(5));
+    assert_eq!(memory.get(0), Some(5));
+    assert_eq!(cas(5, 9), Ok(5));
+    assert_eq!(memory.get(0), Some(9));
+
+    let mut context = Context::new(vec![ix(Push, &[1]), ix(AtomicAdd, &[3]), ix(Exit, &[])]);
+    context.attach_shared(0, &memory).unwrap();
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::NotShared { pc: 1, opcode: AtomicAdd, addr: 3 });
+    assert_eq!(err.root().to_string(), "AtomicAdd on address 3, which isn't shared memory");
+}
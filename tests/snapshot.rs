@@ -0,0 +1,85 @@
+mod common;
+
+#[cfg(feature = "json")]
+use beef::{Snapshot, REGISTER_COUNT};
+use beef::{Context, OpCode::*, RunOutcome, VmError};
+use common::{factorial, ix};
+
+#[test]
+fn restoring_a_snapshot_replays_the_rest_of_the_run() {
+    let mut context = Context::new(factorial(5));
+    assert_eq!(context.run_for(20), Ok(RunOutcome::Paused));
+    let snapshot = context.snapshot();
+    assert_eq!((snapshot.pc(), snapshot.steps()), (context.pc(), 20));
+
+    assert_eq!(context.run(false), Ok(120));
+    context.restore(&snapshot).unwrap();
+    assert_eq!((context.pc(), context.steps()), (snapshot.pc(), 20));
+    assert_eq!(context.run(false), Ok(120));
+    assert_eq!(context.steps(), 44);
+
+    // a clone restores just as well into a fresh context
+    let mut other = Context::new(factorial(5));
+    other.restore(&snapshot.clone()).unwrap();
+    assert_eq!(other.run(false), Ok(120));
+}
+
+#[test]
+fn snapshots_survive_errors() {
+    // r0 = 100 / r1, then store it to memory
+    let program = vec![
+        ix(Push, &[100]),
+        ix(LoadReg, &[1]),
+        ix(Div, &[]),
+        ix(Store, &[50]),
+        ix(Load, &[50]),
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new(program);
+    context.poke(50, 7);
+    let snapshot = context.snapshot();
+
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::DivisionByZero { pc: 2 });
+    context.restore(&snapshot).unwrap();
+    assert_eq!((context.pc(), context.stack(), context.peek(50)), (0, &[][..], Some(7)));
+
+    context.set_register(1, 4).unwrap();
+    assert_eq!(context.run(false), Ok(25));
+    context.restore(&snapshot).unwrap();
+    assert_eq!(context.peek(50), Some(7));
+}
+
+#[test]
+fn snapshots_only_restore_into_their_program() {
+    let snapshot = Context::new(factorial(5)).snapshot();
+    let mut context = Context::new(factorial(6));
+    let err = context.restore(&snapshot).unwrap_err();
+    assert!(matches!(err, VmError::SnapshotMismatch { snapshot: hash, .. } if hash == snapshot.program_hash()), "{}", err);
+    assert_eq!(context.run(false), Ok(720));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn snapshots_round_trip_through_json() {
+    let mut context = Context::new(factorial(5));
+    context.poke(7, -3);
+    assert_eq!(context.run_for(20), Ok(RunOutcome::Paused));
+    let json = context.snapshot().to_json();
+    assert!(json.contains(&format!("\"program_hash\": {},", context.snapshot().program_hash())), "{}", json);
+
+    let snapshot = Snapshot::from_json(&json).unwrap();
+    assert_eq!(snapshot.to_json(), json);
+    let mut other = Context::new(factorial(5));
+    other.restore(&snapshot).unwrap();
+    assert_eq!((other.pc(), other.steps(), other.peek(7)), (context.pc(), 20, Some(-3)));
+    assert_eq!(other.run(false), context.run(false));
+    assert_eq!(other.steps(), 44);
+
+    // the hash comes along, so the decoded snapshot still only fits its own program
+    let err = Context::new(factorial(6)).restore(&snapshot).unwrap_err();
+    assert!(matches!(err, VmError::SnapshotMismatch { .. }), "{}", err);
+    let err = Snapshot::from_json(&json.replace("\"registers\": [", "\"registers\": [1, ")).unwrap_err();
+    let expected = format!("registers: expected {} registers, found {}", REGISTER_COUNT, REGISTER_COUNT + 1);
+    assert_eq!(err.to_string(), expected);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use beef::{OpCode::*, VmError};
+use common::{ix, run, run_err};
+
+#[test]
+fn dup_and_swap() {
+    assert_eq!(run(vec![ix(Push, &[4]), ix(Dup, &[]), ix(Mul, &[]), ix(Exit, &[])]), Ok(16));
+    assert_eq!(run(vec![ix(Push, &[9]), ix(Push, &[2]), ix(Swap, &[]), ix(Sub, &[]), ix(Exit, &[])]), Ok(-7));
+}
+
+#[test]
+fn dup_and_swap_underflow() {
+    assert!(matches!(run_err(vec![ix(Dup, &[]), ix(Exit, &[])]), VmError::StackUnderflow { opcode: Dup, .. }));
+    assert!(matches!(run_err(vec![ix(Swap, &[]), ix(Exit, &[])]), VmError::StackUnderflow { .. }));
+    let err = run_err(vec![ix(Push, &[1]), ix(Swap, &[]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::StackUnderflow { pc: 1, opcode: Swap, needed: 2, found: 1 });
+    assert_eq!(err.to_string(), "Stack underflow => Swap Op needs 2 values, found 1");
+}
+
+#[test]
+fn over_rot_and_pick() {
+    assert_eq!(run(vec![ix(Push, &[1]), ix(Push, &[2]), ix(Over, &[]), ix(Exit, &[])]), Ok(1));
+    // 1 2 3 -> 2 3 1
+    assert_eq!(run(vec![ix(Push, &[1]), ix(Push, &[2]), ix(Push, &[3]), ix(Rot, &[]), ix(Exit, &[])]), Ok(1));
+    assert_eq!(run(vec![ix(Push, &[1]), ix(Push, &[2]), ix(Push, &[3]), ix(Pick, &[2]), ix(Exit, &[])]), Ok(1));
+    assert_eq!(run(vec![ix(Push, &[1]), ix(Pick, &[0]), ix(Add, &[]), ix(Exit, &[])]), Ok(2));
+}
+
+#[test]
+fn deep_access_errors_name_the_opcode() {
+    let err = run_err(vec![ix(Push, &[1]), ix(Over, &[]), ix(Exit, &[])]);
+    assert!(matches!(err, VmError::StackUnderflow { opcode: Over, needed: 2, .. }));
+    let err = run_err(vec![ix(Push, &[1]), ix(Rot, &[]), ix(Exit, &[])]);
+    assert!(matches!(err, VmError::StackUnderflow { opcode: Rot, needed: 3, .. }));
+    let err = run_err(vec![ix(Push, &[1]), ix(Pick, &[-1]), ix(Exit, &[])]);
+    assert_eq!(err, VmError::InvalidOperand { pc: 1, opcode: Pick, value: -1 });
+    let err = run_err(vec![ix(Push, &[1]), ix(Pick, &[1]), ix(Exit, &[])]);
+    assert!(matches!(err, VmError::StackUnderflow { opcode: Pick, needed: 2, found: 1, .. }));
+    assert!(run(vec![ix(Push, &[1]), ix(Pick, &[i64::MAX]), ix(Exit, &[])]).is_err());
+}
+
+#[test]
+fn three_way_max_without_registers() {
+    // max(b, c) first, then max(a, that), both with copy-compare-drop
+    let max3 = |a: i64, b: i64, c: i64| {
+        run(vec![
+            ix(Push, &[a]),
+            ix(Push, &[b]),
+            ix(Push, &[c]),
+            ix(Over, &[]),     // a b c b
+            ix(Over, &[]),     // a b c b c
+            ix(JumpLt, &[8]),  // b < c -> keep c
+            ix(Pop, &[]),      // a b
+            ix(Jump, &[10]),
+            ix(Swap, &[]),     // a c b
+            ix(Pop, &[]),      // a c
+            ix(Pick, &[1]),    // a m a
+            ix(Pick, &[1]),    // a m a m
+            ix(JumpLt, &[15]), // a < m -> keep m
+            ix(Pop, &[]),      // a
+            ix(Exit, &[]),
+            ix(Swap, &[]),     // m a
+            ix(Pop, &[]),      // m
+            ix(Exit, &[]),
+        ])
+    };
+    assert_eq!(max3(1, 2, 3), Ok(3));
+    assert_eq!(max3(3, 2, 1), Ok(3));
+    assert_eq!(max3(1, 3, 2), Ok(3));
+    assert_eq!(max3(-5, -9, -7), Ok(-5));
+}
+
+#[test]
+fn select_pops_condition_then_b_then_a() {
+    let select = |a: i64, b: i64, condition: i64| {
+        run(vec![ix(Push, &[a]), ix(Push, &[b]), ix(Push, &[condition]), ix(Select, &[]), ix(Exit, &[])])
+    };
+    assert_eq!(select(10, 20, 1), Ok(10));
+    assert_eq!(select(10, 20, 0), Ok(20));
+    // any nonzero condition picks a
+    assert_eq!(select(10, 20, -7), Ok(10));
+
+    // the two values under the condition are consumed, the rest of the stack is left alone
+    let program = vec![ix(Push, &[99]), ix(Push, &[1]), ix(Push, &[2]), ix(Push, &[0]), ix(Select, &[]), ix(Add, &[]), ix(Exit, &[])];
+    assert_eq!(run(program), Ok(101));
+    assert_eq!(
+        run_err(vec![ix(Push, &[1]), ix(Push, &[0]), ix(Select, &[]), ix(Exit, &[])]),
+        VmError::StackUnderflow { pc: 2, opcode: Select, needed: 3, found: 2 }
+    );
+}
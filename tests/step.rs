@@ -0,0 +1,74 @@
+mod common;
+
+use beef::{Context, OpCode::*, StepOutcome, VmError};
+use common::{factorial, ix};
+
+#[test]
+fn factorial_one_step_at_a_time() {
+    let mut context = Context::new(factorial(5));
+    // (pc executed, stack afterwards) for every step
+    let mut trace = Vec::new();
+    let value = loop {
+        let pc = context.pc();
+        let outcome = context.step().unwrap();
+        trace.push((pc, context.stack().to_vec()));
+        if let StepOutcome::Exited(value) = outcome {
+            break value;
+        }
+    };
+    assert_eq!(value, 120);
+    assert_eq!(trace.len(), 44);
+
+    // setup, then the first pass through the loop
+    assert_eq!(&trace[..12], [
+        (0, vec![5]),
+        (1, vec![]),
+        (2, vec![1]),
+        (3, vec![]),
+        (4, vec![5]),
+        (5, vec![5, 1]),
+        (6, vec![]),
+        (7, vec![1]),
+        (8, vec![1, 5]),
+        (9, vec![5]),
+        (10, vec![]),
+        (11, vec![]),
+    ]);
+    // the last test of r1 == 1 jumps out to Exit
+    assert_eq!(&trace[40..], [(4, vec![1]), (5, vec![1, 1]), (6, vec![]), (13, vec![])]);
+
+    assert_eq!(context.registers()[..2], [120, 1]);
+    assert_eq!(context.steps(), 44);
+    // a finished program stays finished
+    assert_eq!(context.step(), Ok(StepOutcome::Exited(120)));
+    assert_eq!(context.steps(), 44);
+}
+
+#[test]
+fn stepping_matches_running() {
+    let mut stepped = Context::new(factorial(7));
+    while stepped.step() == Ok(StepOutcome::Continued) {}
+    let mut ran = Context::new(factorial(7));
+    ran.run(false).unwrap();
+    assert_eq!((stepped.pc(), stepped.steps(), stepped.registers()), (ran.pc(), ran.steps(), ran.registers()));
+}
+
+#[test]
+fn step_shows_frames_and_errors() {
+    let mut context = Context::new(vec![ix(Push, &[6]), ix(CallN, &[3, 1]), ix(Exit, &[]), ix(Pop, &[]), ix(Return, &[])]);
+    context.step().unwrap();
+    context.step().unwrap();
+    assert_eq!(context.pc(), 3);
+    assert_eq!(context.call_stack().len(), 1);
+    assert_eq!(context.call_stack()[0].return_addr(), 2);
+    assert_eq!(context.call_stack()[0].locals(), [6]);
+
+    let err = context.step().unwrap_err();
+    assert_eq!(err.root(), &VmError::StackUnderflow { pc: 3, opcode: Pop, needed: 1, found: 0 });
+    assert_eq!(context.step().unwrap_err().root(), err.root());
+
+    let mut context = Context::new(vec![ix(Yield, &[]), ix(Nop, &[])]);
+    assert_eq!(context.step(), Ok(StepOutcome::Yielded(0)));
+    assert_eq!(context.step(), Ok(StepOutcome::Continued));
+    assert_eq!(context.step(), Err(VmError::NoExit));
+}
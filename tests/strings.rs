@@ -0,0 +1,66 @@
+mod common;
+
+use beef::{
+    assemble, assemble_program, disassemble_program, AsmErrorKind, Config, Context, OpCode::*, Program, SharedBuffer, VmError,
+};
+use common::ix;
+
+fn output(program: Program) -> String {
+    let buffer = SharedBuffer::default();
+    let mut context = Context::load(program, Config::default()).unwrap();
+    context.set_output(Box::new(buffer.clone()));
+    context.run(false).unwrap();
+    String::from_utf8(buffer.contents()).unwrap()
+}
+
+#[test]
+fn printstr_writes_table_entries() {
+    let program = assemble_program(
+        r#"
+        .string "usage: beef <file>\n"
+        printstr 0
+        printstr "; not a comment\n"
+        printstr "usage: beef <file>\n"   ; interned, reuses entry 0
+        push 0
+        exit
+        "#,
+        "usage.asm",
+    )
+    .unwrap();
+    assert_eq!(program.strings, ["usage: beef <file>\n", "; not a comment\n"]);
+    assert_eq!(program.instructions[..3], [ix(PrintStr, &[0]), ix(PrintStr, &[1]), ix(PrintStr, &[0])]);
+    assert_eq!(output(program), "usage: beef <file>\n; not a comment\nusage: beef <file>\n");
+}
+
+#[test]
+fn escapes_and_bad_literals() {
+    let program = assemble_program(r#"printstr "tab\tquote\"slash\\nul\0""#, "escapes.asm").unwrap();
+    assert_eq!(program.strings, ["tab\tquote\"slash\\nul\0"]);
+
+    for src in [r#"printstr "open"#, r#"printstr "bad \q""#, r#".string"#, r#".string 3"#] {
+        assert_eq!(assemble_program(src, "bad.asm").unwrap_err().kind, AsmErrorKind::InvalidString, "{}", src);
+    }
+    assert_eq!(assemble(r#"push "3""#).unwrap_err().kind, AsmErrorKind::InvalidOperand);
+}
+
+#[test]
+fn out_of_range_indices_fail_at_runtime() {
+    let mut program = Program::new(vec![ix(PrintStr, &[1]), ix(Exit, &[0])]);
+    program.strings = vec!["only one".to_string()];
+    let err = Context::load(program, Config::default()).unwrap().run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::InvalidOperand { pc: 0, opcode: PrintStr, value: 1 });
+}
+
+#[test]
+fn strings_survive_the_disassembler_and_json() {
+    let mut program = Program::new(vec![ix(PrintStr, &[1]), ix(PrintStr, &[0]), ix(Exit, &[0])]);
+    program.strings = vec!["line \"one\"\n".to_string(), "zwei ü\t".to_string()];
+
+    let text = disassemble_program(&program);
+    assert!(text.starts_with(".string \"line \\\"one\\\"\\n\"\n"), "{}", text);
+    let parsed = assemble_program(&text, "round.asm").unwrap();
+    assert_eq!((&parsed.instructions, &parsed.strings), (&program.instructions, &program.strings));
+
+    #[cfg(feature = "json")]
+    assert_eq!(Program::from_json(&program.to_json()).as_ref(), Ok(&program));
+}
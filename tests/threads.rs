@@ -0,0 +1,101 @@
+mod common;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use beef::{assemble, Config, Context, OpCode::*, RunOutcome, Scheduler, Snapshot, VmError, VmHandle};
+use common::ix;
+
+const WAIT: Duration = Duration::from_secs(10);
+
+fn is_send<T: std::marker::Send>() {}
+
+// counts r0 and r1 up forever, r0 first
+fn counter() -> Context {
+    let program = assemble("loop:\nincreg r0\nincreg r1\njump loop").unwrap();
+    Context::new_with_config(program, Config { stop_check_interval: 16, ..Config::default() })
+}
+
+// runs `context` on a new thread, handing its handle back first
+fn spawn(mut context: Context) -> (VmHandle, thread::JoinHandle<Result<RunOutcome, VmError>>) {
+    let (sender, receiver) = mpsc::channel();
+    let worker = thread::spawn(move || {
+        sender.send(context.handle()).unwrap();
+        context.resume()
+    });
+    (receiver.recv().unwrap(), worker)
+}
+
+#[test]
+fn contexts_can_move_between_threads() {
+    is_send::<Context>();
+    is_send::<Scheduler>();
+    is_send::<Snapshot>();
+    is_send::<VmHandle>();
+
+    let mut context = Context::new(common::factorial(5));
+    context.set_output(Box::new(std::io::sink()));
+    assert_eq!(thread::spawn(move || context.run(false)).join().unwrap(), Ok(120));
+}
+
+#[test]
+fn stop_from_another_thread() {
+    let (handle, worker) = spawn(counter());
+    handle.stop();
+    assert!(handle.is_stopped());
+    let err = worker.join().unwrap().unwrap_err();
+    assert!(matches!(err.root(), VmError::Interrupted { .. }), "{}", err);
+}
+
+#[test]
+fn snapshots_while_running_are_taken_between_instructions() {
+    let (handle, worker) = spawn(counter());
+    let first = handle.snapshot(WAIT).unwrap();
+    let second = handle.snapshot(WAIT).unwrap();
+    handle.stop();
+    worker.join().unwrap().unwrap_err();
+
+    assert!(second.steps() > first.steps());
+    for snapshot in [first, second] {
+        // r0 is counted first, so at any instruction boundary it's r1 or one ahead
+        let steps = snapshot.steps() as i64;
+        assert_eq!(&snapshot.registers()[..2], [(steps + 2) / 3, (steps + 1) / 3]);
+        assert_eq!(snapshot.pc() as i64, steps % 3);
+    }
+}
+
+#[test]
+fn breakpoints_set_from_another_thread_stop_the_run() {
+    let (handle, worker) = spawn(counter());
+    assert_eq!(handle.add_breakpoint(3), Err(VmError::InvalidBreakpoint { pc: 3, len: 3 }));
+    handle.add_breakpoint(2).unwrap();
+    assert_eq!(worker.join().unwrap(), Ok(RunOutcome::Hit { pc: 2 }));
+}
+
+#[test]
+fn idle_contexts_answer_when_polled() {
+    let mut context = Context::new(vec![ix(Push, &[1]), ix(Push, &[2]), ix(Exit, &[])]);
+    let handle = context.handle();
+    handle.add_breakpoint(1).unwrap();
+    handle.add_breakpoint(2).unwrap();
+    handle.clone().remove_breakpoint(1);
+    // nothing arrives until the context polls
+    assert!(context.list_breakpoints().is_empty());
+    assert!(handle.snapshot(Duration::ZERO).is_none());
+    context.poll_handle();
+    assert_eq!(context.list_breakpoints(), [2]);
+
+    let asker = {
+        let handle = handle.clone();
+        thread::spawn(move || handle.snapshot(WAIT))
+    };
+    while !asker.is_finished() {
+        context.poll_handle();
+        thread::yield_now();
+    }
+    assert_eq!(asker.join().unwrap().unwrap().pc(), 0);
+
+    drop(context);
+    assert!(handle.snapshot(WAIT).is_none());
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "std")]
+use beef::{replay, SharedBuffer};
+use beef::{Clock, Config, Context, OpCode::*};
+use common::{factorial, ix};
+
+// starts at `now`, every reading moves it `tick` further
+struct FakeClock {
+    now: Arc<AtomicI64>,
+    tick: i64,
+}
+
+impl Clock for FakeClock {
+    fn now_ms(&mut self) -> i64 {
+        self.now.fetch_add(self.tick, Ordering::Relaxed) + self.tick
+    }
+}
+
+#[test]
+fn steps_counts_everything_so_far_including_itself() {
+    let mut program = factorial(5);
+    let exit = program.len() - 1;
+    program[exit] = ix(Steps, &[]);
+    program.push(ix(Exit, &[]));
+    for superinstructions in [false, true] {
+        let mut context = Context::new_with_config(program.clone(), Config { superinstructions, ..Config::default() });
+        let steps = context.run(false).unwrap();
+        assert_eq!(steps as u64, context.steps() - 1);
+    }
+}
+
+#[test]
+fn time_comes_from_the_clock() {
+    let program = vec![ix(TimeMs, &[]), ix(TimeMs, &[]), ix(Swap, &[]), ix(Sub, &[]), ix(Exit, &[])];
+    let mut context = Context::new(program.clone());
+    let now = Arc::new(AtomicI64::new(1_000));
+    context.set_clock(Box::new(FakeClock { now: Arc::clone(&now), tick: 25 }));
+    assert_eq!(context.run(false), Ok(25));
+    assert_eq!(now.load(Ordering::Relaxed), 1_050);
+
+    // the default counts from the start of the run
+    let mut context = Context::new(vec![ix(TimeMs, &[]), ix(Exit, &[])]);
+    assert!((0..1_000).contains(&context.run(false).unwrap()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn replays_reuse_the_recorded_times() {
+    let program = vec![ix(TimeMs, &[]), ix(Print, &[]), ix(TimeMs, &[]), ix(Exit, &[])];
+    let trace = SharedBuffer::default();
+    let mut context = Context::new(program.clone());
+    context.set_output(Box::new(std::io::sink()));
+    context.set_clock(Box::new(FakeClock { now: Arc::new(AtomicI64::new(-5_000)), tick: 7_777 }));
+    context.record_trace(Box::new(trace.clone()));
+    assert_eq!(context.run(false), Ok(10_554));
+    context.stop_recording().unwrap();
+
+    assert_eq!(replay(program, trace.contents().as_slice()), Ok(4));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use beef::{Context, OpCode::*, TrapOutcome, VmError};
+use common::ix;
+
+const FAULT: i64 = 14;
+
+#[test]
+fn a_handler_fixes_up_memory_and_resumes() {
+    // lazy initialization: cell 100 reads as 0 until the first trap fills it in
+    let program = vec![
+        ix(Load, &[100]),
+        ix(JumpNotZero, &[4]),
+        ix(Trap, &[FAULT]),
+        ix(Jump, &[0]),
+        ix(Load, &[100]),
+        ix(Exit, &[]),
+    ];
+    let mut context = Context::new(program);
+    let mut faults = 0;
+    context.register_trap_handler(
+        FAULT,
+        Box::new(move |context, code| {
+            assert_eq!((context.pc(), code), (2, FAULT));
+            faults += 1;
+            context.poke(100, 42 * faults);
+            TrapOutcome::Resume
+        }),
+    );
+    assert_eq!(context.run(false), Ok(42));
+    assert_eq!(context.peek(100), Some(42));
+}
+
+#[test]
+fn resume_at_jumps_and_is_bounds_checked() {
+    let program = vec![ix(Trap, &[1]), ix(Push, &[1]), ix(Exit, &[]), ix(Push, &[2]), ix(Exit, &[])];
+    let mut context = Context::new(program.clone());
+    context.register_trap_handler(1, Box::new(|context, _| {
+        context.set_pc(1); // ignored, the outcome decides
+        TrapOutcome::ResumeAt(3)
+    }));
+    assert_eq!(context.run(false), Ok(2));
+
+    let mut context = Context::new(program);
+    context.register_trap_handler(1, Box::new(|_, _| TrapOutcome::ResumeAt(5)));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::JumpOutOfBounds { pc: 0, opcode: Trap, target: 5 });
+}
+
+#[test]
+fn a_handler_can_abort_the_run() {
+    let program = vec![ix(Push, &[7]), ix(Trap, &[3]), ix(Exit, &[])];
+    let mut context = Context::new(program.clone());
+    context.register_trap_handler(3, Box::new(|context, _| {
+        let value = context.stack()[0];
+        TrapOutcome::Abort(VmError::InvalidProgram(format!("guest gave up with {}", value)))
+    }));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::InvalidProgram("guest gave up with 7".to_string()));
+    assert_eq!(context.pc(), 1);
+
+    // handlers stay registered, so the same trap can fire again after a reset
+    context.reset();
+    assert!(context.run(false).is_err());
+}
+
+#[test]
+fn unregistered_codes_name_the_trap() {
+    let mut context = Context::new(vec![ix(Nop, &[]), ix(Trap, &[9]), ix(Exit, &[0])]);
+    context.register_trap_handler(8, Box::new(|_, _| TrapOutcome::Resume));
+    let err = context.run(false).unwrap_err();
+    assert_eq!(err.root(), &VmError::UnhandledTrap { pc: 1, code: 9 });
+    assert_eq!(err.root().to_string(), "Unhandled trap 9");
+    // the location wrapper adds the pc, once
+    assert!(err.to_string().starts_with("Unhandled trap 9 at pc=1 ("), "{}", err);
+    assert_eq!(err.to_string().matches("pc=").count(), 1, "{}", err);
+}
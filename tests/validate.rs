@@ -0,0 +1,159 @@
+mod common;
+
+use beef::{check_flow, validate, validate_strict, Context, OpCode::*, ValidationError, VmError};
+use common::{factorial, factorial_of_r1, ix};
+
+#[test]
+fn clean_programs_pass() {
+    assert_eq!(validate(&factorial(5)), Ok(()));
+    assert_eq!(validate(&factorial_of_r1()), Ok(()));
+    assert_eq!(validate(&[]), Ok(()));
+    let mut context = Context::new_validated(factorial(5)).unwrap();
+    assert_eq!(context.run(false), Ok(120));
+}
+
+#[test]
+fn operand_counts() {
+    let program = vec![ix(Push, &[]), ix(Add, &[1]), ix(Switch, &[]), ix(Exit, &[0, 1])];
+    assert_eq!(validate(&program), Err(vec![
+        ValidationError::OperandCount { pc: 0, opcode: Push, expected: 1..=1, found: 0 },
+        ValidationError::OperandCount { pc: 1, opcode: Add, expected: 0..=0, found: 1 },
+        ValidationError::OperandCount { pc: 2, opcode: Switch, expected: 1..=usize::MAX, found: 0 },
+        ValidationError::OperandCount { pc: 3, opcode: Exit, expected: 0..=1, found: 2 },
+    ]));
+}
+
+#[test]
+fn register_ranges() {
+    let program = vec![ix(LoadReg, &[11]), ix(AddReg, &[0, -1, 12]), ix(Store, &[20, 0]), ix(Store, &[20]), ix(Exit, &[10])];
+    let errors = validate(&program).unwrap_err();
+    assert_eq!(errors, vec![
+        ValidationError::InvalidRegister { pc: 0, opcode: LoadReg, register: 11 },
+        ValidationError::InvalidRegister { pc: 1, opcode: AddReg, register: -1 },
+        ValidationError::InvalidRegister { pc: 1, opcode: AddReg, register: 12 },
+        ValidationError::InvalidRegister { pc: 2, opcode: Store, register: 20 },
+    ]);
+    assert_eq!(errors[0].to_string(), "pc=0: Invalid register 11 for LoadReg");
+}
+
+#[test]
+fn jump_and_call_targets() {
+    let program = vec![
+        ix(Jump, &[4]),
+        ix(JumpEq, &[5]),
+        ix(Call, &[-1, 2]),
+        ix(JumpRel, &[-4]),
+        ix(Switch, &[0, 9, 4]),
+        ix(Exit, &[]),
+    ];
+    let errors = validate(&program).unwrap_err();
+    assert_eq!(errors, vec![
+        ValidationError::TargetOutOfBounds { pc: 2, opcode: Call, target: -1 },
+        ValidationError::TargetOutOfBounds { pc: 3, opcode: JumpRel, target: -1 },
+        ValidationError::TargetOutOfBounds { pc: 4, opcode: Switch, target: 9 },
+    ]);
+    assert_eq!(errors[2].to_string(), "pc=4: Switch target out of bounds: 9");
+}
+
+#[test]
+fn every_error_is_reported() {
+    // one of each, spread out, the way a typo deep in a program would look
+    let mut program = factorial(5);
+    program[9] = ix(Mul, &[3]);
+    program[10] = ix(StoreReg, &[42]);
+    program[12] = ix(Jump, &[400]);
+    let errors = Context::new_validated(program).err().unwrap();
+    let pcs: Vec<_> = errors.iter().map(|error| match error {
+        ValidationError::OperandCount { pc, .. } | ValidationError::InvalidRegister { pc, .. } | ValidationError::TargetOutOfBounds { pc, .. } => *pc,
+        ValidationError::FallsOffEnd { pc } => *pc,
+        ValidationError::Unreachable { pcs } => pcs.start,
+    }).collect();
+    assert_eq!(pcs, vec![9, 10, 12]);
+}
+
+#[test]
+fn run_verified_matches_run() {
+    for n in [1, 5, 20] {
+        let mut checked = Context::new(factorial(n));
+        let mut verified = Context::new(factorial(n));
+        assert_eq!(verified.run_verified(), checked.run(false));
+        assert_eq!((verified.steps(), verified.registers()), (checked.steps(), checked.registers()));
+    }
+
+    // data dependent checks stay on the fast path
+    let mut context = Context::new(vec![ix(Push, &[1]), ix(JumpEq, &[0]), ix(Exit, &[0])]);
+    let err = context.run_verified().unwrap_err();
+    assert_eq!(err.root(), &VmError::StackUnderflow { pc: 1, opcode: JumpEq, needed: 2, found: 1 });
+    assert!(err.to_string().contains("at pc=1 (Instruction { opcode: JumpEq, operands: [0] }), stack top: [1]"), "{}", err);
+}
+
+#[test]
+fn unverified_programs_never_run_unchecked() {
+    let program = vec![ix(Push, &[1]), ix(LoadReg, &[11]), ix(Jump, &[9]), ix(Exit, &[])];
+    let mut context = Context::new(program.clone());
+    let err = context.run_verified().unwrap_err();
+    assert_eq!(err, VmError::Unverified(validate(&program).unwrap_err()));
+    assert_eq!(
+        err.to_string(),
+        "Program failed verification: pc=1: Invalid register 11 for LoadReg; pc=2: Jump target out of bounds: 9"
+    );
+    // refused before anything ran, and asking again doesn't change that
+    assert_eq!((context.pc(), context.steps(), context.stack()), (0, 0, &[][..]));
+    assert!(matches!(context.run_verified(), Err(VmError::Unverified(_))));
+
+    // plain run still checks every step
+    assert_eq!(context.run(false).unwrap_err().root(), &VmError::InvalidRegister { pc: 1, index: 11 });
+}
+
+#[test]
+fn missing_exits_and_dead_code_are_warnings() {
+    assert_eq!(check_flow(&factorial(5)), []);
+    assert_eq!(validate_strict(&factorial(5)), Ok(()));
+
+    // prints and runs off the end
+    let program = vec![ix(Push, &[1]), ix(Print, &[])];
+    assert_eq!(check_flow(&program), [ValidationError::FallsOffEnd { pc: 1 }]);
+    assert_eq!(validate(&program), Ok(()));
+    assert_eq!(validate_strict(&program), Err(vec![ValidationError::FallsOffEnd { pc: 1 }]));
+
+    // the branch not taken at the end falls off too, and 3..6 is never reached
+    let program = vec![
+        ix(Push, &[0]),
+        ix(JumpZero, &[6]),
+        ix(Exit, &[0]),
+        ix(Push, &[2]),
+        ix(Print, &[]),
+        ix(Jump, &[3]),
+        ix(Push, &[0]),
+        ix(JumpNotZero, &[2]),
+    ];
+    let warnings = check_flow(&program);
+    assert_eq!(warnings, [ValidationError::Unreachable { pcs: 3..6 }, ValidationError::FallsOffEnd { pc: 7 }]);
+    let messages: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+    assert_eq!(messages, [
+        "pc=3..6: unreachable from pc 0",
+        "pc=7: execution can run past the end of the program, no Exit",
+    ]);
+
+    // strict mode reports the ordinary errors first
+    let program = vec![ix(LoadReg, &[11]), ix(Print, &[])];
+    assert_eq!(validate_strict(&program), Err(vec![
+        ValidationError::InvalidRegister { pc: 0, opcode: LoadReg, register: 11 },
+        ValidationError::FallsOffEnd { pc: 1 },
+    ]));
+}
+
+#[test]
+fn flow_checks_dont_reason_about_values() {
+    // 1 == 2 never holds, so this really loops forever, but structurally the Exit is reachable
+    // and nothing falls off the end. The check can't tell, by design
+    let program = vec![
+        ix(Push, &[1]),
+        ix(Push, &[2]),
+        ix(JumpEq, &[4]),
+        ix(Jump, &[0]),
+        ix(Exit, &[0]),
+    ];
+    assert_eq!(check_flow(&program), []);
+    assert_eq!(validate_strict(&program), Ok(()));
+}
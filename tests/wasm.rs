@@ -0,0 +1,94 @@
+mod common;
+
+use beef::{assemble, ExecutionEvent, OpCode::*, Program, VmStatus, WasmVm, RECENT_EVENTS};
+use common::{factorial, ix};
+
+fn json(source: &str) -> String {
+    Program::new(assemble(source).unwrap()).to_json()
+}
+
+#[test]
+fn step_through_a_json_program() {
+    let mut vm = WasmVm::from_json(&json("push 6\npush 7\nmul\ndup\nprint\nexit")).unwrap();
+    assert_eq!(vm.state().status, VmStatus::Ready);
+
+    let state = vm.step(2);
+    assert_eq!((state.pc, state.steps, state.stack.as_slice(), &state.status), (2, 2, &[6, 7][..], &VmStatus::Paused));
+    assert_eq!(state.events.last(), Some(&ExecutionEvent::StackPush(7)));
+
+    let state = vm.step(100);
+    assert_eq!(state.status, VmStatus::Exited(42));
+    assert_eq!(state.output, "42\n");
+    assert_eq!(state.events.last(), Some(&ExecutionEvent::Exit { value: 42 }));
+    // nothing left to run
+    assert_eq!(vm.step(5), state);
+}
+
+#[test]
+fn bytecode_loads_too() {
+    let bytes = Program::new(factorial(5)).to_bytes().unwrap();
+    let mut vm = WasmVm::from_bytes(&bytes).unwrap();
+    assert_eq!(vm.step(u32::MAX).status, VmStatus::Exited(120));
+    assert_eq!(vm.state().registers[0], 120);
+}
+
+#[test]
+fn bad_input_is_an_error_not_a_panic() {
+    let error = WasmVm::from_bytes(b"").err().unwrap();
+    assert!(error.contains("magic"), "{}", error);
+    assert!(WasmVm::from_bytes(&[0xff; 64]).is_err());
+    let mut truncated = Program::new(factorial(5)).to_bytes().unwrap();
+    truncated.truncate(truncated.len() - 3);
+    assert!(WasmVm::from_bytes(&truncated).is_err());
+    assert!(WasmVm::from_json("{\"instructions\": [{\"opcode\": \"Nope\"}]}").is_err());
+    assert!(WasmVm::from_json("not json").is_err());
+}
+
+#[test]
+fn failures_stick_until_reset() {
+    let mut vm = WasmVm::from_json(&json("push 1\nprint\npush 1\npush 0\ndiv\nexit")).unwrap();
+    let state = vm.step(10);
+    let VmStatus::Failed(message) = &state.status else { panic!("expected a failure, got {:?}", state.status) };
+    assert!(message.contains("pc=4"), "{}", message);
+    assert_eq!(vm.step(10), state);
+
+    vm.reset();
+    let state = vm.state();
+    assert_eq!((state.pc, state.status, state.output.as_str(), state.events.len()), (0, VmStatus::Ready, "", 0));
+    assert_eq!(vm.step(2).output, "1\n");
+}
+
+#[test]
+fn only_recent_events_are_kept() {
+    let mut vm = WasmVm::from_json(&Program::new(factorial(10)).to_json()).unwrap();
+    let state = vm.step(u32::MAX);
+    assert_eq!(state.status, VmStatus::Exited(3_628_800));
+    assert_eq!(state.events.len(), RECENT_EVENTS);
+    assert_eq!(state.events.last(), Some(&ExecutionEvent::Exit { value: 3_628_800 }));
+}
+
+#[test]
+fn state_serializes_to_json() {
+    let program = Program { strings: vec!["say \"hi\"\n".to_string()], ..Program::new(vec![ix(PrintStr, &[0]), ix(Push, &[-3]), ix(Exit, &[])]) };
+    let mut vm = WasmVm::from_json(&program.to_json()).unwrap();
+    vm.context_mut().set_register(2, 9).unwrap();
+    let json = vm.step(2).to_json();
+    let registers = format!("[0, 0, 9{}]", ", 0".repeat(beef::REGISTER_COUNT - 3));
+    assert_eq!(
+        json,
+        format!(
+            "{{\"pc\": 2, \"steps\": 2, \"stack\": [-3], \"registers\": {}, \"status\": {{\"kind\": \"paused\"}}, \
+             \"output\": \"say \\\"hi\\\"\\n\", \"events\": [\
+             {{\"kind\": \"instruction\", \"pc\": 0, \"opcode\": \"PrintStr\", \"operands\": [0]}}, \
+             {{\"kind\": \"instruction\", \"pc\": 1, \"opcode\": \"Push\", \"operands\": [-3]}}, \
+             {{\"kind\": \"push\", \"value\": -3}}]}}",
+            registers
+        )
+    );
+
+    let json = vm.step(1).to_json();
+    assert!(json.contains("\"status\": {\"kind\": \"exited\", \"value\": -3}"), "{}", json);
+    let mut vm = WasmVm::from_json(&Program::new(vec![ix(Add, &[])]).to_json()).unwrap();
+    let json = vm.step(1).to_json();
+    assert!(json.contains("\"status\": {\"kind\": \"failed\", \"message\": \""), "{}", json);
+}